@@ -0,0 +1,200 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Throughput of instruction packing, account packing and name validation, to
+//! catch a serialization change that slows the hot paths down.
+
+use std::time::Duration;
+
+use bitoku_sdk_agent_native::{
+    instruction::{BitokuInstructions, Request, MAX_PACKED_LEN},
+    policy::NamePolicyKind,
+    protocol::INSTRUCTIONS,
+    state::{validate_name, RequestData, RequestStatus, PREFIX_LEN, RESPONSE_LEN},
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+
+fn write_file() -> Request {
+    Request::WriteFile {
+        name: [b'n'; 128],
+        file_id: 1,
+        offset: 4096,
+        data: [b'd'; 512],
+    }
+}
+
+fn send_request() -> BitokuInstructions {
+    BitokuInstructions::SendRequest {
+        client_id: 0,
+        submission_ref: [0; 32],
+        request: write_file(),
+    }
+}
+
+//one instruction of every kind, in tag order
+fn every_instruction() -> Vec<BitokuInstructions> {
+    let key = Pubkey::new_from_array([7; 32]);
+    vec![
+        BitokuInstructions::InitBitoku,
+        BitokuInstructions::RegisterClient { compact: false },
+        BitokuInstructions::RemoveClient { client_id: 1 },
+        send_request(),
+        BitokuInstructions::GetVersion,
+        BitokuInstructions::RegisterClientFor { target: key },
+        BitokuInstructions::SetReservedPrefix {
+            index: 0,
+            prefix: [b'p'; PREFIX_LEN],
+        },
+        BitokuInstructions::PublishStats,
+        BitokuInstructions::UpdateRequestStatus {
+            client_id: 1,
+            status: RequestStatus::Completed,
+        },
+        BitokuInstructions::SetSupportedKinds {
+            supported_kinds: u32::MAX,
+        },
+        BitokuInstructions::WriteResponse {
+            client_id: 1,
+            data: [b'r'; 512],
+            len: 512,
+            status: RequestStatus::Completed,
+        },
+        BitokuInstructions::SetAgent { agent: key },
+        BitokuInstructions::SetNamePolicy {
+            policy: NamePolicyKind::Default,
+        },
+        BitokuInstructions::PublishLoad {
+            max_requests_per_epoch: 1000,
+            current_backlog: 10,
+        },
+        BitokuInstructions::ShrinkRequestAccount,
+        BitokuInstructions::SetAuthority { new_authority: key },
+        BitokuInstructions::ForceRemoveClient { client_id: 1 },
+        BitokuInstructions::SetPaused { paused: true },
+        BitokuInstructions::SubmitSignedRequest {
+            client_id: 1,
+            nonce: 1,
+            signer: key,
+            signature: [1; 64],
+            request: write_file(),
+        },
+        BitokuInstructions::CancelRequest { client_id: 1 },
+        BitokuInstructions::AddAgent { pubkey: key },
+        BitokuInstructions::RemoveAgent { pubkey: key },
+        BitokuInstructions::AcknowledgeRequest {
+            client_id: 1,
+            status: RequestStatus::Processing,
+        },
+        BitokuInstructions::OverwriteRequest {
+            client_id: 1,
+            submission_ref: [0; 32],
+            request: write_file(),
+        },
+        BitokuInstructions::ResetEpochCounter { client_id: 1 },
+        BitokuInstructions::SetClientPermissions {
+            client_id: 1,
+            permissions: 0b1111,
+        },
+        BitokuInstructions::MigrateAccount,
+        BitokuInstructions::UpdateAdmin { new_admin: key },
+    ]
+}
+
+fn request_data() -> RequestData {
+    RequestData {
+        is_initialized: true,
+        client_id: 1,
+        requester: Pubkey::new_from_array([7; 32]),
+        request: write_file(),
+        position: 4608,
+        max_written_offset: 4608,
+        sparse: false,
+        response_ready: false,
+        response: [0; RESPONSE_LEN],
+        submission_ref: [0; 32],
+        sequence: 1,
+        submitted_at: 1_700_000_000,
+        status: RequestStatus::Pending,
+        response_len: 0,
+        response_data: [0; 512],
+        nonce: 0,
+        bump: 255,
+        requests_this_epoch: 1,
+    }
+}
+
+fn instructions(c: &mut Criterion) {
+    let instruction = send_request();
+    c.bench_function("pack/SendRequest", |b| {
+        b.iter(|| black_box(&instruction).pack())
+    });
+    c.bench_function("pack_into/SendRequest", |b| {
+        let mut buf = [0u8; MAX_PACKED_LEN];
+        b.iter(|| black_box(&instruction).pack_into(&mut buf).unwrap())
+    });
+
+    let instructions = every_instruction();
+    let tags: Vec<usize> = instructions
+        .iter()
+        .map(|ix| ix.pack()[0] as usize)
+        .collect();
+    assert_eq!(tags, (0..INSTRUCTIONS.len()).collect::<Vec<_>>());
+
+    let mut group = c.benchmark_group("unpack");
+    for (instruction, variant) in instructions.iter().zip(INSTRUCTIONS) {
+        let bytes = instruction.pack();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(variant.name),
+            &bytes,
+            |b, bytes| b.iter(|| BitokuInstructions::unpack(black_box(bytes)).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn accounts(c: &mut Criterion) {
+    let data = request_data();
+    let mut account = vec![0u8; RequestData::LEN];
+    c.bench_function("RequestData/pack_into_slice", |b| {
+        b.iter(|| black_box(&data).pack_into_slice(&mut account))
+    });
+    c.bench_function("RequestData/unpack_from_slice", |b| {
+        b.iter(|| RequestData::unpack_from_slice(black_box(&account)).unwrap())
+    });
+}
+
+fn names(c: &mut Criterion) {
+    let name = [b'a'; 128];
+    c.bench_function("validate_name/128", |b| {
+        b.iter(|| validate_name(black_box(&name)))
+    });
+}
+
+//flag changes of 5% or more, at a 1% significance level
+fn config() -> Criterion {
+    Criterion::default()
+        .significance_level(0.01)
+        .noise_threshold(0.05)
+        .measurement_time(Duration::from_secs(3))
+}
+
+criterion_group! {
+    name = benches;
+    config = config();
+    targets = instructions, accounts, names
+}
+criterion_main!(benches);
@@ -0,0 +1,81 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Sets up a fresh deployment and registers its first client.
+//!
+//! ```text
+//! cargo run --example init_and_register -- <program id> <payer>
+//! ```
+//!
+//! Prints the instructions to sign with `payer` and send, in order, to the
+//! cluster the program is deployed on, e.g. a `solana-test-validator`. Once they
+//! landed, `registered_client_id` reads the client id back from the request
+//! account.
+
+use std::{env, process, str::FromStr};
+
+use bitoku_sdk_agent_native::{
+    instruction::{init_bitoku_auto, BitokuInstructionBuilder},
+    plan::{plan_registration, FeePrice},
+    state::RequestData,
+};
+use solana_program::{
+    instruction::Instruction, program_error::ProgramError, pubkey::Pubkey, rent::Rent,
+};
+
+/// `InitBitoku` followed by `RegisterClient`, both paid by `payer`, who becomes
+/// the deployment's authority and agent. `payer` must be the program's upgrade
+/// authority.
+pub fn setup_instructions(
+    program_id: Pubkey,
+    payer: Pubkey,
+) -> Result<Vec<Instruction>, ProgramError> {
+    Ok(vec![
+        init_bitoku_auto(payer, program_id)?,
+        BitokuInstructionBuilder::new(program_id, payer).register_client()?,
+    ])
+}
+
+/// Client id stored in the data of a request account.
+pub fn registered_client_id(data: &[u8]) -> Result<u8, ProgramError> {
+    Ok(RequestData::unpack_account(data)?.client_id)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [program_id, payer] = args.as_slice() else {
+        eprintln!("usage: init_and_register <program id> <payer>");
+        process::exit(2);
+    };
+    let program_id = Pubkey::from_str(program_id).expect("invalid program id");
+    let payer = Pubkey::from_str(payer).expect("invalid payer");
+
+    //the fees of a solana-test-validator without a priority fee
+    let fee_price = FeePrice {
+        lamports_per_signature: 5000,
+        compute_unit_price: 0,
+        compute_unit_limit: 200_000,
+    };
+    println!("{}", plan_registration(&Rent::default(), fee_price, false));
+    println!(
+        "request account: {}",
+        BitokuInstructionBuilder::new(program_id, payer).request()
+    );
+
+    for ix in setup_instructions(program_id, payer).expect("failed to build the instructions") {
+        println!("{:?}", ix);
+    }
+}
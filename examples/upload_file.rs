@@ -0,0 +1,102 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Uploads a local file in 512-byte chunks.
+//!
+//! ```text
+//! cargo run --example upload_file -- <program id> <payer> <client id> <bucket> <path> <file id>
+//! ```
+//!
+//! Prints the cost of the upload and the instructions to sign with `payer` and
+//! send one per transaction. The request account holds a single request, so
+//! each one has to be answered by the agent before the next is sent; see the
+//! `watch_requests` example.
+
+use std::{env, fs, path::Path, process, str::FromStr};
+
+use bitoku_sdk_agent_native::{
+    instruction::{request_address, send_create_bucket, send_create_file, send_write_at},
+    plan::{plan_upload, ChunkStrategy, FeePrice},
+};
+use solana_program::{instruction::Instruction, program_error::ProgramError, pubkey::Pubkey};
+
+/// Creates `bucket`, then an empty file `name` in it, then writes `data` to it
+/// with one `WriteAt` per chunk, as `ChunkStrategy::CreateThenWriteAt` plans it.
+pub fn upload_instructions(
+    program_id: Pubkey,
+    payer: Pubkey,
+    client_id: u8,
+    bucket: &str,
+    (name, file_id): (&str, u8),
+    data: &[u8],
+) -> Result<Vec<Instruction>, ProgramError> {
+    let (request, _) = request_address(&program_id, &payer);
+    let path = format!("{}/{}", bucket, name);
+
+    let mut instructions = vec![
+        send_create_bucket(payer, request, program_id, client_id, bucket)?,
+        send_create_file(payer, request, program_id, client_id, &path, false, &[])?,
+    ];
+    instructions.extend(send_write_at(
+        payer,
+        request,
+        program_id,
+        client_id,
+        &path,
+        file_id,
+        (0, data),
+    )?);
+
+    Ok(instructions)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [program_id, payer, client_id, bucket, path, file_id] = args.as_slice() else {
+        eprintln!("usage: upload_file <program id> <payer> <client id> <bucket> <path> <file id>");
+        process::exit(2);
+    };
+    let program_id = Pubkey::from_str(program_id).expect("invalid program id");
+    let payer = Pubkey::from_str(payer).expect("invalid payer");
+    let client_id: u8 = client_id.parse().expect("invalid client id");
+    let file_id: u8 = file_id.parse().expect("invalid file id");
+    let data = fs::read(path).expect("failed to read the file");
+    let name = Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .expect("invalid file name");
+
+    let fee_price = FeePrice {
+        lamports_per_signature: 5000,
+        compute_unit_price: 0,
+        compute_unit_limit: 200_000,
+    };
+    println!(
+        "{}",
+        plan_upload(
+            data.len() as u64,
+            ChunkStrategy::CreateThenWriteAt,
+            fee_price
+        )
+    );
+
+    let instructions =
+        upload_instructions(program_id, payer, client_id, bucket, (name, file_id), &data)
+            .expect("failed to build the instructions");
+    for ix in instructions {
+        println!("{:?}", ix);
+    }
+}
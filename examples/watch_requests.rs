@@ -0,0 +1,106 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Decodes the changes of a request account into events.
+//!
+//! ```text
+//! cargo run --example watch_requests -- <snapshot>...
+//! ```
+//!
+//! Each snapshot is the account data as delivered by an `accountSubscribe`
+//! notification, or saved with `solana account <request> --output-file`.
+
+use std::{env, fs};
+
+use bitoku_sdk_agent_native::{
+    instruction::Request,
+    state::{RequestData, RequestStatus},
+};
+use solana_program::program_error::ProgramError;
+
+/// A change between two snapshots of a request account.
+#[derive(Debug, PartialEq)]
+pub enum RequestEvent {
+    /// A new request was stored; requests stored between the two snapshots are
+    /// not seen.
+    Submitted {
+        sequence: u64,
+        request: Box<Request>,
+    },
+    StatusChanged {
+        sequence: u64,
+        status: RequestStatus,
+    },
+    /// The agent wrote a response with `WriteResponse`.
+    Responded { sequence: u64, data: Vec<u8> },
+}
+
+/// Turns successive snapshots of one request account into `RequestEvent`s.
+#[derive(Default)]
+pub struct RequestWatcher {
+    last: Option<RequestData>,
+}
+
+impl RequestWatcher {
+    pub fn update(&mut self, account_data: &[u8]) -> Result<Vec<RequestEvent>, ProgramError> {
+        let current = RequestData::unpack_account(account_data)?;
+        let mut events = Vec::new();
+
+        let (submitted, status, responded) = match &self.last {
+            Some(last) if last.sequence == current.sequence => (
+                false,
+                last.status != current.status,
+                last.response() != current.response(),
+            ),
+            //a fresh account holds no request yet
+            _ => (current.sequence > 0, current.sequence > 0, true),
+        };
+
+        if submitted {
+            events.push(RequestEvent::Submitted {
+                sequence: current.sequence,
+                request: Box::new(current.request.clone()),
+            });
+        }
+        //Pending is implied by the submission
+        if status && current.status != RequestStatus::Pending {
+            events.push(RequestEvent::StatusChanged {
+                sequence: current.sequence,
+                status: current.status,
+            });
+        }
+        if let Some(data) = current.response().filter(|_| responded) {
+            events.push(RequestEvent::Responded {
+                sequence: current.sequence,
+                data: data.to_vec(),
+            });
+        }
+
+        self.last = Some(current);
+        Ok(events)
+    }
+}
+
+fn main() {
+    let mut watcher = RequestWatcher::default();
+
+    for path in env::args().skip(1) {
+        let data = fs::read(&path).expect("failed to read the snapshot");
+        for event in watcher.update(&data).expect("not a request account") {
+            println!("{:?}", event);
+        }
+    }
+}
@@ -0,0 +1,9 @@
+//! Instruction data of any shape must unpack to `Ok` or `Err`, never panic.
+#![no_main]
+
+use bitoku_sdk_agent_native::instruction::BitokuInstructions;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = BitokuInstructions::unpack(data);
+});
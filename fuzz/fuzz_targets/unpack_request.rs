@@ -0,0 +1,9 @@
+//! Request bytes of any shape must unpack to `Ok` or `Err`, never panic.
+#![no_main]
+
+use bitoku_sdk_agent_native::instruction::unpack_request;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = unpack_request(data);
+});
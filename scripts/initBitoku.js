@@ -11,6 +11,12 @@ let bookkeeper = PublicKey.findProgramAddressSync(
   PROGRAM
 );
 
+// the wallet must be the program's upgrade authority
+let programData = PublicKey.findProgramAddressSync(
+  [PROGRAM.toBuffer()],
+  new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+);
+
 console.log(WALLET.publicKey.toBase58());
 
 const keys = [
@@ -34,6 +40,17 @@ const keys = [
     isSigner: false,
     isWritable: false,
   },
+  {
+    // admin
+    pubkey: WALLET.publicKey,
+    isSigner: true,
+    isWritable: false,
+  },
+  {
+    pubkey: programData[0],
+    isSigner: false,
+    isWritable: false,
+  },
 ];
 
 const data = Buffer.from(Int8Array.from([0]).buffer);
@@ -1,10 +1,22 @@
+import { createHash } from "node:crypto";
 import {
   PublicKey,
+  SystemProgram,
+  SYSVAR_CLOCK_PUBKEY,
+  SYSVAR_RENT_PUBKEY,
   TransactionInstruction,
-  Transaction,
 } from "@solana/web3.js";
 import { PROGRAM, CONNECTION, transaction, WALLET } from "./helper.js";
 
+// zero-padded name, as `str_to_name` builds it
+function toName(name) {
+  const buffer = Buffer.alloc(128);
+  buffer.write(name);
+  return buffer;
+}
+
+let name = "dir-2/file-1";
+
 let bookkeeper = PublicKey.findProgramAddressSync(
   [Buffer.from("bookkeeper")],
   PROGRAM
@@ -15,6 +27,19 @@ let request = PublicKey.findProgramAddressSync(
   PROGRAM
 );
 
+let clientInfo = PublicKey.findProgramAddressSync(
+  [Buffer.from("client_info"), WALLET.publicKey.toBuffer()],
+  PROGRAM
+);
+
+// writes lease the bucket: the name up to its first '/', hashed with sha256
+let bucket = toName(name.split("/")[0]);
+let lease = PublicKey.findProgramAddressSync(
+  [Buffer.from("lease"), createHash("sha256").update(bucket).digest()],
+  PROGRAM
+);
+
+// in the order of SEND_REQUEST_ACCOUNTS
 const keys = [
   {
     pubkey: WALLET.publicKey,
@@ -26,36 +51,59 @@ const keys = [
     isSigner: false,
     isWritable: true,
   },
+  {
+    pubkey: bookkeeper[0],
+    isSigner: false,
+    isWritable: false,
+  },
+  {
+    pubkey: SYSVAR_CLOCK_PUBKEY,
+    isSigner: false,
+    isWritable: false,
+  },
+  {
+    pubkey: clientInfo[0],
+    isSigner: false,
+    isWritable: false,
+  },
+  {
+    pubkey: SystemProgram.programId,
+    isSigner: false,
+    isWritable: false,
+  },
+  {
+    pubkey: SYSVAR_RENT_PUBKEY,
+    isSigner: false,
+    isWritable: false,
+  },
+  {
+    pubkey: lease[0],
+    isSigner: false,
+    isWritable: true,
+  },
 ];
 
-let name = "dir-2/file-1";
-let nameBuffer = Buffer.from(name);
-let zeroesBuffer = Buffer.from(
-  Array.from({ length: 128 - nameBuffer.length }, () => 0)
-);
-let namee = Buffer.concat([nameBuffer, zeroesBuffer]);
-
 let input = Buffer.from("test3");
+let fileData = Buffer.alloc(512);
+input.copy(fileData);
 
-let zeros = Buffer.from(Array.from({ length: 512 - input.length }, () => 0));
-
-let ip = Buffer.concat([input, zeros]);
-
+// Borsh, as `BitokuInstructions::pack` writes it: the variant index as a u8,
+// then each field in declaration order, arrays without a length prefix
 const data = Buffer.concat([
-  //enum for instruction : 3 for sending requests
-  Buffer.from(Int8Array.from([3]).buffer),
-  //  client_id
-  Buffer.from(Int8Array.from([0]).buffer),
-  //enum for request type : 0 for create bucket
-  Buffer.from(Int8Array.from([1]).buffer),
-  //name as buffer
-  namee,
-  //file id
-  Buffer.from(Int8Array.from([45]).buffer),
-  //position
-  Buffer.from(Uint8Array.of(...new BN(5).toArray("le", 8))),
-  // data
-  ip,
+  //SendRequest
+  Buffer.from([3]),
+  //client_id
+  Buffer.from([0]),
+  //submission_ref
+  Buffer.alloc(32),
+  //Request::CreateFile
+  Buffer.from([1]),
+  //name
+  toName(name),
+  //sparse
+  Buffer.from([0]),
+  //data
+  fileData,
 ]);
 
 const tx = new TransactionInstruction({
@@ -0,0 +1,809 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Byte-level audit of packed instructions and `RequestData` accounts, for
+//! checking encoders written outside this crate against the canonical layout.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+
+use crate::{
+    instruction::BitokuInstructions,
+    policy::{DefaultPolicy, NamePolicy, NamePolicyKind},
+    protocol::{FieldKind, Variant, INSTRUCTIONS, REQUESTS},
+    state::{
+        AccountType, RequestData, RequestStatus, CANCELLED_TAG, ENCODED_REQUEST_MAX_LEN,
+        RESPONSE_LEN,
+    },
+};
+
+/// Result of auditing one encoded value.
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuditReport {
+    /// Decoded variant, e.g. `SendRequest(WriteAt)`; `None` if the tag is unknown.
+    pub variant: Option<String>,
+    /// Every decoded field in byte order; together with `TrailingBytes` they
+    /// cover the whole input.
+    pub fields: Vec<AuditField>,
+    pub violations: Vec<Violation>,
+}
+
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuditField {
+    pub name: String,
+    pub offset: u32,
+    pub len: u32,
+    /// Decimal for integers, base58 for keys, text for names and hex otherwise.
+    pub value: String,
+}
+
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Violation {
+    /// The input ends inside `field`.
+    Truncated { field: String, offset: u32 },
+    /// Bytes left over after the last field.
+    TrailingBytes { offset: u32, len: u32 },
+    /// A non-zero byte where the layout requires zero padding.
+    NonZeroPadding { field: String, offset: u32 },
+    /// A name `DefaultPolicy` rejects.
+    InvalidName { field: String },
+    /// A tag or enum byte with no matching variant.
+    UnknownTag { field: String, value: u8 },
+    /// A value outside the canonical range, such as a bool other than 0 or 1.
+    InvalidValue { field: String },
+    /// The program's own decoder refused the input.
+    Rejected { error: String },
+}
+
+impl AuditReport {
+    /// Whether the input is a canonical encoding.
+    pub fn is_clean(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    U8,
+    Bool,
+    U16,
+    U32,
+    U64,
+    I64,
+    Key,
+    Name,
+    /// Fixed-size text padded with zeros after its first NUL.
+    Padded(usize),
+    Bytes(usize),
+    /// Request payload: the rest of the instruction, at most 512 bytes.
+    Data,
+}
+
+struct Auditor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    truncated: bool,
+    //variant of the request nested in the input, if any
+    request: Option<&'static str>,
+    report: AuditReport,
+}
+
+impl<'a> Auditor<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            truncated: false,
+            request: None,
+            report: AuditReport::default(),
+        }
+    }
+
+    fn violation(&mut self, violation: Violation) {
+        self.report.violations.push(violation);
+    }
+
+    //decodes the next field; `None` once the input has run out
+    fn field(&mut self, name: &str, kind: Kind) -> Option<&'a [u8]> {
+        if self.truncated {
+            return None;
+        }
+        let len = match kind {
+            Kind::U8 | Kind::Bool => 1,
+            Kind::U16 => 2,
+            Kind::U32 => 4,
+            Kind::U64 | Kind::I64 => 8,
+            Kind::Key => 32,
+            Kind::Name => 128,
+            Kind::Padded(len) | Kind::Bytes(len) => len,
+            Kind::Data => self.bytes.len().saturating_sub(self.pos).min(512),
+        };
+        let offset = self.pos;
+        let bytes = match self.bytes.get(offset..offset + len) {
+            Some(bytes) => bytes,
+            None => {
+                self.truncated = true;
+                self.violation(Violation::Truncated {
+                    field: name.to_string(),
+                    offset: offset as u32,
+                });
+                return None;
+            }
+        };
+        self.pos += len;
+
+        let value = match kind {
+            Kind::U8 => bytes[0].to_string(),
+            Kind::Bool => {
+                if bytes[0] > 1 {
+                    self.violation(Violation::InvalidValue {
+                        field: name.to_string(),
+                    });
+                }
+                (bytes[0] != 0).to_string()
+            }
+            Kind::U16 => u16::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            Kind::U32 => u32::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            Kind::U64 => u64::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            Kind::I64 => i64::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            Kind::Key => Pubkey::new_from_array(bytes.try_into().unwrap()).to_string(),
+            Kind::Name | Kind::Padded(_) => {
+                let text = self.padded(name, offset, bytes);
+                if matches!(kind, Kind::Name) && !DefaultPolicy.validate(bytes) {
+                    self.violation(Violation::InvalidName {
+                        field: name.to_string(),
+                    });
+                }
+                text
+            }
+            Kind::Bytes(_) | Kind::Data => hex(bytes),
+        };
+        self.report.fields.push(AuditField {
+            name: name.to_string(),
+            offset: offset as u32,
+            len: len as u32,
+            value,
+        });
+        Some(bytes)
+    }
+
+    //text before the first NUL, flagging anything but zeros after it
+    fn padded(&mut self, name: &str, offset: usize, bytes: &[u8]) -> String {
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        self.zeros(name, offset + len, &bytes[len..]);
+        match core::str::from_utf8(&bytes[..len]) {
+            Ok(text) => text.to_string(),
+            Err(_) => hex(&bytes[..len]),
+        }
+    }
+
+    fn zeros(&mut self, name: &str, offset: usize, bytes: &[u8]) {
+        if let Some(i) = bytes.iter().position(|&b| b != 0) {
+            self.violation(Violation::NonZeroPadding {
+                field: name.to_string(),
+                offset: (offset + i) as u32,
+            });
+        }
+    }
+
+    //decodes a tag and the fields of its variant, prefixing their names with
+    //`prefix`; returns the variant with the bytes of its fields in order
+    fn variant(
+        &mut self,
+        table: &'static [Variant],
+        tag_name: &str,
+        prefix: &str,
+        data: Kind,
+    ) -> Option<(&'static Variant, Vec<&'a [u8]>)> {
+        let tag = self.field(tag_name, Kind::U8)?[0];
+        let variant = match table.get(tag as usize) {
+            Some(variant) => variant,
+            None => {
+                self.violation(Violation::UnknownTag {
+                    field: tag_name.to_string(),
+                    value: tag,
+                });
+                return None;
+            }
+        };
+        let mut values = Vec::new();
+        for field in variant.fields {
+            let name = format!("{}{}", prefix, field.name);
+            let kind = match field.kind {
+                FieldKind::U8 | FieldKind::Enum(_) => Kind::U8,
+                FieldKind::Bool | FieldKind::Flag => Kind::Bool,
+                FieldKind::U16 => Kind::U16,
+                FieldKind::U32 => Kind::U32,
+                FieldKind::U64 => Kind::U64,
+                FieldKind::I64 => Kind::I64,
+                FieldKind::Key => Kind::Key,
+                FieldKind::Name => Kind::Name,
+                FieldKind::Text(len) => Kind::Padded(len),
+                FieldKind::Bytes(len) => Kind::Bytes(len),
+                FieldKind::Data => data,
+                FieldKind::Request => {
+                    self.request = self
+                        .variant(REQUESTS, "request.kind", "request.", data)
+                        .map(|(request, _)| request.name);
+                    continue;
+                }
+            };
+            //a flag may be left out at the end
+            if field.kind == FieldKind::Flag && self.pos == self.bytes.len() {
+                continue;
+            }
+            let value = match self.field(&name, kind) {
+                Some(value) => value,
+                None => break,
+            };
+            if let FieldKind::Enum(ty) = field.kind {
+                if !is_known(ty, value[0]) {
+                    self.violation(Violation::UnknownTag {
+                        field: name,
+                        value: value[0],
+                    });
+                }
+            }
+            values.push(value);
+        }
+        Some((variant, values))
+    }
+
+    fn finish(mut self) -> AuditReport {
+        if !self.truncated && self.pos < self.bytes.len() {
+            self.violation(Violation::TrailingBytes {
+                offset: self.pos as u32,
+                len: (self.bytes.len() - self.pos) as u32,
+            });
+        }
+        self.report
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Audits `bytes` as `BitokuInstructions` data. Names are checked against
+/// `DefaultPolicy`, whatever policy a deployment has chosen.
+pub fn audit_instruction(bytes: &[u8]) -> AuditReport {
+    let mut auditor = Auditor::new(bytes);
+
+    if let Some((variant, values)) = auditor.variant(INSTRUCTIONS, "tag", "", Kind::Data) {
+        if let (10, [_, data, len, ..]) = (variant.tag, &values[..]) {
+            response(&mut auditor, "data", 3, data, len);
+        }
+        auditor.report.variant = Some(match auditor.request {
+            Some(request) => format!("{}({})", variant.name, request),
+            None => variant.name.to_string(),
+        });
+    }
+
+    let mut report = auditor.finish();
+    if let Err(error) = BitokuInstructions::unpack(bytes) {
+        report.violations.push(Violation::Rejected {
+            error: error.to_string(),
+        });
+    }
+    report
+}
+
+/// Audits `bytes` as the data of a `RequestData` account.
+pub fn audit_request_account(bytes: &[u8]) -> AuditReport {
+    let mut auditor = Auditor::new(bytes);
+
+    if let Some(account_type) = auditor.field("account_type", Kind::U8) {
+        if account_type[0] > AccountType::RequestData as u8 {
+            auditor.violation(Violation::UnknownTag {
+                field: "account_type".to_string(),
+                value: account_type[0],
+            });
+        }
+    }
+    auditor.field("is_initialized", Kind::Bool);
+    auditor.field("client_id", Kind::U8);
+    auditor.field("requester", Kind::Key);
+    let area = auditor.pos;
+    if bytes.get(area) == Some(&CANCELLED_TAG) {
+        //`CancelRequest` leaves only the sentinel, followed by padding
+        auditor.field("request.kind", Kind::U8);
+        auditor.report.variant = Some("Cancelled".to_string());
+    } else if let Some((request, _)) =
+        auditor.variant(REQUESTS, "request.kind", "request.", Kind::Bytes(512))
+    {
+        auditor.report.variant = Some(request.name.to_string());
+    }
+    if !auditor.truncated {
+        //an unknown kind leaves the whole area unexplained
+        let end = area + ENCODED_REQUEST_MAX_LEN;
+        match bytes.get(auditor.pos..end) {
+            Some(padding) => {
+                let offset = auditor.pos;
+                auditor.zeros("request.padding", offset, padding);
+                auditor.report.fields.push(AuditField {
+                    name: "request.padding".to_string(),
+                    offset: offset as u32,
+                    len: padding.len() as u32,
+                    value: hex(padding),
+                });
+                auditor.pos = end;
+            }
+            None => {
+                auditor.truncated = true;
+                auditor.violation(Violation::Truncated {
+                    field: "request.padding".to_string(),
+                    offset: auditor.pos as u32,
+                });
+            }
+        }
+    }
+    auditor.field("position", Kind::U64);
+    auditor.field("max_written_offset", Kind::U64);
+    auditor.field("sparse", Kind::Bool);
+    auditor.field("response_ready", Kind::Bool);
+    auditor.field("response", Kind::Bytes(RESPONSE_LEN));
+    auditor.field("submission_ref", Kind::Bytes(32));
+    auditor.field("sequence", Kind::U64);
+    auditor.field("submitted_at", Kind::I64);
+    if let Some(status) = auditor.field("status", Kind::U8) {
+        if RequestStatus::from_u8(status[0]).is_err() {
+            auditor.violation(Violation::UnknownTag {
+                field: "status".to_string(),
+                value: status[0],
+            });
+        }
+    }
+    let len = auditor.field("response_len", Kind::U16);
+    let data = auditor.field("response_data", Kind::Bytes(512));
+    if let (Some(data), Some(len)) = (data, len) {
+        response(&mut auditor, "response_data", 0, data, len);
+    }
+    auditor.field("nonce", Kind::U64);
+    auditor.field("bump", Kind::U8);
+    auditor.field("requests_this_epoch", Kind::U64);
+
+    let mut report = auditor.finish();
+    if let Err(error) = RequestData::unpack_from_slice(bytes) {
+        report.violations.push(Violation::Rejected {
+            error: error.to_string(),
+        });
+    }
+    report
+}
+
+//whether `value` is a discriminant of the enum named `ty` in the protocol table
+fn is_known(ty: &str, value: u8) -> bool {
+    match ty {
+        "RequestStatus" => RequestStatus::from_u8(value).is_ok(),
+        "NamePolicyKind" => NamePolicyKind::from_u8(value).is_ok(),
+        _ => unreachable!("no decoder for {}", ty),
+    }
+}
+
+//checks a 512-byte response area against its length field; `gap` is the
+//distance from the end of `data` to wherever the auditor currently stands
+fn response(auditor: &mut Auditor, name: &str, gap: usize, data: &[u8], len: &[u8]) {
+    let len = u16::from_le_bytes(len.try_into().unwrap()) as usize;
+    if len > 512 {
+        auditor.violation(Violation::InvalidValue {
+            field: format!("{}.len", name),
+        });
+        return;
+    }
+    let offset = auditor.pos - gap - 512 + len;
+    auditor.zeros(name, offset, &data[len..]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::BitokuError::InvalidInstructionData;
+    use crate::instruction::test::every_request;
+    use crate::instruction::Request;
+    use crate::state::ACCOUNT_HEADER_LEN;
+    use solana_program::program_error::ProgramError;
+
+    fn instructions() -> Vec<BitokuInstructions> {
+        let mut instructions: Vec<BitokuInstructions> = every_request()
+            .into_iter()
+            .map(|request| BitokuInstructions::SendRequest {
+                client_id: 7,
+                submission_ref: [3; 32],
+                request,
+            })
+            .collect();
+        let mut response = [0u8; 512];
+        response[..4].copy_from_slice(b"done");
+        instructions.extend([
+            BitokuInstructions::InitBitoku,
+            BitokuInstructions::RegisterClient { compact: false },
+            BitokuInstructions::RegisterClient { compact: true },
+            BitokuInstructions::RemoveClient { client_id: 9 },
+            BitokuInstructions::GetVersion,
+            BitokuInstructions::RegisterClientFor {
+                target: Pubkey::new_unique(),
+            },
+            BitokuInstructions::SetReservedPrefix {
+                index: 1,
+                prefix: *b"system/\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0",
+            },
+            BitokuInstructions::PublishStats,
+            BitokuInstructions::UpdateRequestStatus {
+                client_id: 2,
+                status: RequestStatus::Failed,
+            },
+            BitokuInstructions::SetSupportedKinds {
+                supported_kinds: 0x5a5a,
+            },
+            BitokuInstructions::WriteResponse {
+                client_id: 4,
+                data: response,
+                len: 4,
+                status: RequestStatus::Completed,
+            },
+            BitokuInstructions::SetAgent {
+                agent: Pubkey::new_unique(),
+            },
+            BitokuInstructions::SetNamePolicy {
+                policy: NamePolicyKind::S3Compat,
+            },
+            BitokuInstructions::PublishLoad {
+                max_requests_per_epoch: 100,
+                current_backlog: 3,
+            },
+            BitokuInstructions::ShrinkRequestAccount,
+            BitokuInstructions::SetAuthority {
+                new_authority: Pubkey::new_unique(),
+            },
+            BitokuInstructions::ForceRemoveClient { client_id: 4 },
+            BitokuInstructions::SetPaused { paused: true },
+            BitokuInstructions::SubmitSignedRequest {
+                client_id: 7,
+                nonce: 5,
+                signer: Pubkey::new_unique(),
+                signature: [6; 64],
+                request: every_request()[16].clone(),
+            },
+            BitokuInstructions::CancelRequest { client_id: 3 },
+            BitokuInstructions::AddAgent {
+                pubkey: Pubkey::new_unique(),
+            },
+            BitokuInstructions::RemoveAgent {
+                pubkey: Pubkey::new_unique(),
+            },
+            BitokuInstructions::AcknowledgeRequest {
+                client_id: 2,
+                status: RequestStatus::Processing,
+            },
+            BitokuInstructions::OverwriteRequest {
+                client_id: 7,
+                submission_ref: [4; 32],
+                request: every_request()[1].clone(),
+            },
+            BitokuInstructions::ResetEpochCounter { client_id: 5 },
+            BitokuInstructions::SetClientPermissions {
+                client_id: 6,
+                permissions: 0b0101,
+            },
+            BitokuInstructions::MigrateAccount,
+            BitokuInstructions::UpdateAdmin {
+                new_admin: Pubkey::new_unique(),
+            },
+        ]);
+        instructions
+    }
+
+    //fields must tile the input with no gaps or overlaps
+    fn assert_covers(report: &AuditReport, len: usize) {
+        let mut next = 0;
+        for field in &report.fields {
+            assert_eq!(field.offset as usize, next, "{}", field.name);
+            next += field.len as usize;
+        }
+        assert_eq!(next, len);
+    }
+
+    fn send(request: Request) -> Vec<u8> {
+        BitokuInstructions::SendRequest {
+            client_id: 0,
+            submission_ref: [0; 32],
+            request,
+        }
+        .pack()
+    }
+
+    fn named(name: &[u8]) -> [u8; 128] {
+        let mut padded = [0u8; 128];
+        padded[..name.len()].copy_from_slice(name);
+        padded
+    }
+
+    #[test]
+    fn test_canonical_instructions_are_clean() {
+        for instruction in instructions() {
+            let packed = instruction.pack();
+            let report = audit_instruction(&packed);
+            assert!(report.is_clean(), "{:?}", report);
+            assert_covers(&report, packed.len());
+        }
+
+        let report = audit_instruction(&send(Request::WriteAt {
+            name: named(b"log"),
+            file_id: 1,
+            offset: 1024,
+            data: [0; 512],
+        }));
+        assert_eq!(report.variant.as_deref(), Some("SendRequest(WriteAt)"));
+        let field = |name: &str| {
+            report
+                .fields
+                .iter()
+                .find(|field| field.name == name)
+                .unwrap()
+                .value
+                .clone()
+        };
+        assert_eq!(field("request.name"), "log");
+        assert_eq!(field("request.offset"), "1024");
+    }
+
+    #[test]
+    fn test_corrupted_instructions() {
+        //legacy decoding accepts trailing bytes; Borsh and the audit do not
+        let mut packed = BitokuInstructions::RemoveClient { client_id: 1 }.pack();
+        packed.push(0);
+        let mut violations = vec![Violation::TrailingBytes { offset: 2, len: 1 }];
+        if !cfg!(feature = "legacy-wire") {
+            violations.push(Violation::Rejected {
+                error: ProgramError::from(InvalidInstructionData).to_string(),
+            });
+        }
+        assert_eq!(audit_instruction(&packed).violations, violations);
+
+        let mut name = named(b"bucket");
+        name[100] = b'x';
+        //the policy rejects a name whose padding isn't zeroed, too
+        let report = audit_instruction(&send(Request::CreateBucket { name }));
+        assert_eq!(
+            report.violations,
+            vec![
+                Violation::NonZeroPadding {
+                    field: "request.name".to_string(),
+                    offset: 35 + 100,
+                },
+                Violation::InvalidName {
+                    field: "request.name".to_string(),
+                },
+            ]
+        );
+
+        let report = audit_instruction(&send(Request::CreateBucket {
+            name: named(b"bad name"),
+        }));
+        assert_eq!(
+            report.violations,
+            vec![Violation::InvalidName {
+                field: "request.name".to_string(),
+            }]
+        );
+
+        let report = audit_instruction(&[99]);
+        assert_eq!(report.variant, None);
+        assert_eq!(
+            report.violations[0],
+            Violation::UnknownTag {
+                field: "tag".to_string(),
+                value: 99,
+            }
+        );
+        assert!(matches!(report.violations[1], Violation::Rejected { .. }));
+
+        let packed = BitokuInstructions::SetAgent {
+            agent: Pubkey::new_unique(),
+        }
+        .pack();
+        let report = audit_instruction(&packed[..20]);
+        assert_eq!(
+            report.violations[0],
+            Violation::Truncated {
+                field: "agent".to_string(),
+                offset: 1,
+            }
+        );
+        assert!(matches!(report.violations[1], Violation::Rejected { .. }));
+
+        let mut data = [0u8; 512];
+        data[..3].copy_from_slice(b"abc");
+        let packed = BitokuInstructions::WriteResponse {
+            client_id: 0,
+            data,
+            len: 2,
+            status: RequestStatus::Completed,
+        }
+        .pack();
+        assert_eq!(
+            audit_instruction(&packed).violations,
+            vec![Violation::NonZeroPadding {
+                field: "data".to_string(),
+                offset: 2 + 2,
+            }]
+        );
+
+        let mut packed = send(Request::CreateFile {
+            name: named(b"f"),
+            sparse: false,
+            data: [0; 512],
+        });
+        packed[35 + 128] = 2;
+        //legacy decoding reads any non-zero bool as true
+        let mut violations = vec![Violation::InvalidValue {
+            field: "request.sparse".to_string(),
+        }];
+        if !cfg!(feature = "legacy-wire") {
+            violations.push(Violation::Rejected {
+                error: ProgramError::from(InvalidInstructionData).to_string(),
+            });
+        }
+        assert_eq!(audit_instruction(&packed).violations, violations);
+        packed[35 + 128] = 0;
+        packed.push(1);
+        let report = audit_instruction(&packed);
+        assert_eq!(
+            report.violations[0],
+            Violation::TrailingBytes {
+                offset: packed.len() as u32 - 1,
+                len: 1,
+            }
+        );
+        assert!(matches!(report.violations[1], Violation::Rejected { .. }));
+    }
+
+    fn account(request: Request) -> Vec<u8> {
+        let data = RequestData {
+            is_initialized: true,
+            client_id: 3,
+            requester: Pubkey::new_unique(),
+            request,
+            position: 0,
+            max_written_offset: 0,
+            sparse: false,
+            response_ready: true,
+            response: [0; RESPONSE_LEN],
+            submission_ref: [0; 32],
+            sequence: 1,
+            submitted_at: 0,
+            status: RequestStatus::Completed,
+            response_len: 3,
+            response_data: {
+                let mut data = [0u8; 512];
+                data[..3].copy_from_slice(b"abc");
+                data
+            },
+            nonce: 2,
+            bump: 0,
+            requests_this_epoch: 0,
+        };
+        let mut bytes = vec![0u8; RequestData::LEN];
+        RequestData::pack(data, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_request_accounts() {
+        for request in every_request() {
+            let bytes = account(request);
+            let report = audit_request_account(&bytes);
+            assert!(report.is_clean(), "{:?}", report);
+            assert_covers(&report, bytes.len());
+        }
+
+        let bytes = account(Request::CreateBucket {
+            name: named(b"bucket"),
+        });
+        let corrupt = |at: usize, value: u8| {
+            let mut bytes = bytes.clone();
+            bytes[at] = value;
+            audit_request_account(&bytes).violations
+        };
+        //unpacking refuses the padding too
+        assert_eq!(
+            corrupt(ACCOUNT_HEADER_LEN + 33 + 1 + 128 + 5, 1),
+            vec![
+                Violation::NonZeroPadding {
+                    field: "request.padding".to_string(),
+                    offset: (ACCOUNT_HEADER_LEN + 33 + 1 + 128 + 5) as u32,
+                },
+                Violation::Rejected {
+                    error: ProgramError::InvalidAccountData.to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            corrupt(0, 7)[0],
+            Violation::UnknownTag {
+                field: "account_type".to_string(),
+                value: 7,
+            }
+        );
+        assert_eq!(
+            corrupt(RequestData::LEN - 532, 9)[0],
+            Violation::UnknownTag {
+                field: "status".to_string(),
+                value: 9,
+            }
+        );
+        assert_eq!(
+            corrupt(RequestData::LEN - 18, 1),
+            vec![Violation::NonZeroPadding {
+                field: "response_data".to_string(),
+                offset: RequestData::LEN as u32 - 18,
+            }]
+        );
+
+        let mut cancelled = RequestData::unpack(&bytes).unwrap();
+        cancelled.status = RequestStatus::Cancelled;
+        let mut cancelled_bytes = vec![0u8; RequestData::LEN];
+        RequestData::pack(cancelled, &mut cancelled_bytes).unwrap();
+        let report = audit_request_account(&cancelled_bytes);
+        assert!(report.is_clean(), "{:?}", report);
+        assert_eq!(report.variant.as_deref(), Some("Cancelled"));
+        assert_covers(&report, cancelled_bytes.len());
+        //the sentinel only ever goes with a cancelled status
+        cancelled_bytes[RequestData::LEN - 532] = RequestStatus::Pending as u8;
+        assert_eq!(
+            audit_request_account(&cancelled_bytes).violations,
+            vec![Violation::Rejected {
+                error: ProgramError::InvalidAccountData.to_string(),
+            }]
+        );
+
+        let report = audit_request_account(&bytes[..RequestData::LEN - 1]);
+        assert!(matches!(report.violations[0], Violation::Truncated { .. }));
+        let mut long = bytes.clone();
+        long.push(0);
+        assert_eq!(
+            audit_request_account(&long).violations,
+            vec![
+                Violation::TrailingBytes {
+                    offset: RequestData::LEN as u32,
+                    len: 1,
+                },
+                Violation::Rejected {
+                    error: ProgramError::InvalidAccountData.to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_report_serializes() {
+        let mut packed = BitokuInstructions::RemoveClient { client_id: 1 }.pack();
+        packed.push(0);
+        let report = audit_instruction(&packed);
+
+        let bytes = report.try_to_vec().unwrap();
+        assert_eq!(AuditReport::try_from_slice(&bytes).unwrap(), report);
+    }
+}
@@ -0,0 +1,166 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Corrupts valid packed accounts one byte at a time and truncates them at every
+//! length. Unpacking must then either fail with a `ProgramError` or give back an
+//! account that packs to the same bytes, apart from the corrupted one.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use solana_program::{program_error::ProgramError, program_pack::Pack, pubkey::Pubkey};
+
+use crate::{
+    instruction::test::every_request,
+    state::{
+        BookKeeper, RequestData, RequestStatus, COMPACT_BASE_LEN, MAX_AGENTS, PREFIX_LEN,
+        RESERVED_PREFIXES, RESPONSE_LEN,
+    },
+};
+
+const FLIPS: [u8; 3] = [0x01, 0x80, 0xff];
+
+fn request_data(request: crate::instruction::Request) -> RequestData {
+    let mut response_data = [0u8; 512];
+    response_data[..3].copy_from_slice(b"abc");
+    RequestData {
+        is_initialized: true,
+        client_id: 7,
+        requester: Pubkey::new_unique(),
+        request,
+        position: 5,
+        max_written_offset: 9,
+        sparse: true,
+        response_ready: true,
+        response: [3; RESPONSE_LEN],
+        submission_ref: [4; 32],
+        sequence: 6,
+        submitted_at: -8,
+        status: RequestStatus::Processing,
+        response_len: 3,
+        response_data,
+        nonce: 2,
+        bump: 254,
+        requests_this_epoch: 0,
+    }
+}
+
+fn bookkeeper() -> BookKeeper {
+    //client 3 registered, 255 released
+    let mut status = [0u8; 32];
+    status[0] = 1 << 3;
+    let mut free_slots = [0u8; 32];
+    free_slots[31] = 1 << 7;
+    let mut reserved_prefixes = [[0; PREFIX_LEN]; RESERVED_PREFIXES];
+    reserved_prefixes[0] = [b'p'; PREFIX_LEN];
+    BookKeeper {
+        is_initialized: true,
+        status,
+        next_id: 4,
+        authority: Pubkey::new_unique(),
+        reserved_prefixes,
+        supported_kinds: 0x8000_0001,
+        agent: Pubkey::new_unique(),
+        free_slots,
+        name_policy: 1,
+        max_requests_per_epoch: 0x0102_0304,
+        current_backlog: 0x0506,
+        paused: true,
+        bump: 254,
+        agents: [Pubkey::default(); MAX_AGENTS],
+        max_client_requests_per_epoch: 0,
+        last_heartbeat_slot: 0,
+        admin: Pubkey::new_unique(),
+    }
+}
+
+//unpacks every corruption of `valid`, which must fail cleanly or read back as an
+//account that packs over the corrupted bytes without changing any other byte
+fn assert_corruptions_handled<T>(
+    valid: &[u8],
+    unpack: impl Fn(&[u8]) -> Result<T, ProgramError>,
+    pack: impl Fn(T, &mut [u8]) -> Result<(), ProgramError>,
+) {
+    for pos in 0..valid.len() {
+        for flip in FLIPS {
+            let mut corrupted = valid.to_vec();
+            corrupted[pos] ^= flip;
+
+            let unpacked = catch_unwind(AssertUnwindSafe(|| unpack(&corrupted)))
+                .unwrap_or_else(|_| panic!("unpacking panicked on byte {} ^ {:#x}", pos, flip));
+            let Ok(unpacked) = unpacked else {
+                continue;
+            };
+
+            let mut repacked = corrupted.clone();
+            pack(unpacked, &mut repacked)
+                .unwrap_or_else(|e| panic!("repacking byte {} ^ {:#x}: {}", pos, flip, e));
+            let changed: Vec<usize> = (0..corrupted.len())
+                .filter(|&i| i != pos && repacked[i] != corrupted[i])
+                .collect();
+            assert!(
+                changed.is_empty(),
+                "byte {} ^ {:#x} read back as an account that packs differently at {:?}",
+                pos,
+                flip,
+                changed
+            );
+        }
+    }
+
+    for len in 0..valid.len() {
+        let unpacked = catch_unwind(AssertUnwindSafe(|| unpack(&valid[..len])))
+            .unwrap_or_else(|_| panic!("unpacking panicked when truncated to {}", len));
+        assert!(unpacked.is_err(), "truncated to {} but unpacked", len);
+    }
+}
+
+#[test]
+fn test_corrupted_request_accounts() {
+    for request in every_request() {
+        let mut valid = vec![0u8; RequestData::LEN];
+        RequestData::pack(request_data(request), &mut valid).unwrap();
+        assert_corruptions_handled(&valid, RequestData::unpack_from_slice, |data, dst| {
+            data.pack_into_slice(dst);
+            Ok(())
+        });
+    }
+}
+
+#[test]
+fn test_corrupted_compact_request_accounts() {
+    for request in every_request() {
+        let data = request_data(request);
+        let mut valid = vec![0u8; COMPACT_BASE_LEN];
+        RequestData::mark_compact(&mut valid);
+        valid.resize(data.compact_len(), 0);
+        data.pack_account(&mut valid).unwrap();
+        assert_corruptions_handled(
+            &valid,
+            RequestData::unpack_account,
+            RequestData::pack_account,
+        );
+    }
+}
+
+#[test]
+fn test_corrupted_bookkeeper() {
+    let mut valid = vec![0u8; BookKeeper::LEN];
+    BookKeeper::pack(bookkeeper(), &mut valid).unwrap();
+    assert_corruptions_handled(&valid, BookKeeper::unpack_from_slice, |data, dst| {
+        data.pack_into_slice(dst);
+        Ok(())
+    });
+}
@@ -0,0 +1,293 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Blocking RPC client for off-chain services, behind the `rpc` feature. Each
+//! call builds the transaction, signs it with the payer, sends it and reads back
+//! the account state it changed. `nonblocking` has the same client for async
+//! services, behind the `async-client` feature.
+
+use std::{
+    boxed::Box,
+    fmt, thread,
+    time::{Duration, Instant},
+    vec::Vec,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use solana_program::{
+    instruction::Instruction, program_error::ProgramError, program_pack::Pack, pubkey::Pubkey,
+};
+use solana_rpc_client::rpc_client::RpcClient;
+use solana_rpc_client_api::{
+    client_error::Error as RpcError, config::RpcSimulateTransactionConfig,
+    response::RpcSimulateTransactionResult,
+};
+use solana_sdk::{
+    signature::{Keypair, Signature, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use crate::{
+    error::BitokuError,
+    instruction::{
+        bookkeeper_address, get_version, register_client_auto, remove_client, request_address,
+        send_request, stats_snapshot_addresses, Request,
+    },
+    state::{BookKeeper, Capabilities, RequestData, RequestStatus, StatsSnapshot},
+};
+
+#[cfg(feature = "async-client")]
+pub mod nonblocking;
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The RPC call failed, or the transaction failed outside of an instruction.
+    Rpc(Box<RpcError>),
+    /// An instruction failed, an instruction couldn't be built, or an account
+    /// doesn't hold what the program writes to it.
+    Program(ProgramError),
+    /// `wait_for_status` gave up before the request got there.
+    Timeout,
+}
+
+impl ClientError {
+    /// The `BitokuError` an instruction failed with, if any.
+    pub fn bitoku_error(&self) -> Option<BitokuError> {
+        match self {
+            ClientError::Program(err) => BitokuError::from_program_error(err),
+            ClientError::Rpc(_) | ClientError::Timeout => None,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Rpc(err) => write!(f, "rpc error: {}", err),
+            ClientError::Program(err) => match BitokuError::from_program_error(err) {
+                Some(err) => write!(f, "program error: {}", err),
+                None => write!(f, "program error: {}", err),
+            },
+            ClientError::Timeout => write!(f, "timed out waiting for the request status"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Rpc(err) => Some(err.as_ref()),
+            ClientError::Program(err) => Some(err),
+            ClientError::Timeout => None,
+        }
+    }
+}
+
+impl From<RpcError> for ClientError {
+    fn from(err: RpcError) -> Self {
+        if let Some(TransactionError::InstructionError(_, err)) = err.get_transaction_error() {
+            if let Ok(err) = ProgramError::try_from(err) {
+                return ClientError::Program(err);
+            }
+        }
+        ClientError::Rpc(Box::new(err))
+    }
+}
+
+impl From<BitokuError> for ClientError {
+    fn from(err: BitokuError) -> Self {
+        ClientError::Program(err.into())
+    }
+}
+
+impl From<ProgramError> for ClientError {
+    fn from(err: ProgramError) -> Self {
+        ClientError::Program(err)
+    }
+}
+
+/// A `GetVersion` transaction to simulate, which needs neither a blockhash nor
+/// a signature.
+fn get_version_transaction(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+) -> Result<Transaction, ClientError> {
+    let (bookkeeper, _) = bookkeeper_address(program_id);
+    let ix = get_version(bookkeeper, *program_id)?;
+    Ok(Transaction::new_with_payer(&[ix], Some(payer)))
+}
+
+fn simulation_config() -> RpcSimulateTransactionConfig {
+    RpcSimulateTransactionConfig {
+        sig_verify: false,
+        replace_recent_blockhash: true,
+        ..RpcSimulateTransactionConfig::default()
+    }
+}
+
+/// The `Capabilities` logged by a simulated `GetVersion`.
+fn capabilities_from_simulation(
+    result: RpcSimulateTransactionResult,
+) -> Result<Capabilities, ClientError> {
+    if let Some(err) = result.err {
+        return Err(RpcError::from(err).into());
+    }
+    result
+        .logs
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|log| log.strip_prefix("Program data: "))
+        .filter_map(|data| STANDARD.decode(data).ok())
+        .find_map(|data| Capabilities::from_log_data(&data).ok())
+        .ok_or_else(|| ProgramError::InvalidAccountData.into())
+}
+
+/// Whether `wait_for_status` is done waiting for `status`: a request that ends up
+/// in another final status won't move again.
+fn reached(data: &RequestData, status: RequestStatus) -> bool {
+    data.status == status || data.status.is_final()
+}
+
+pub struct BitokuClient {
+    pub rpc: RpcClient,
+    pub program_id: Pubkey,
+    /// Signs and pays for every transaction; owner of the request account.
+    pub payer: Keypair,
+}
+
+impl BitokuClient {
+    pub fn new(rpc: RpcClient, program_id: Pubkey, payer: Keypair) -> Self {
+        Self {
+            rpc,
+            program_id,
+            payer,
+        }
+    }
+
+    /// The payer's request PDA.
+    pub fn request_address(&self) -> Pubkey {
+        request_address(&self.program_id, &self.payer.pubkey()).0
+    }
+
+    /// Registers the payer as a client and returns its new request account.
+    pub fn register(&self) -> Result<RequestData, ClientError> {
+        let (ix, _) = register_client_auto(self.payer.pubkey(), self.program_id)?;
+        self.submit(ix)?;
+        self.get_request_data()
+    }
+
+    /// Frees `client_id` and closes the payer's request account.
+    pub fn remove(&self, client_id: u8) -> Result<Signature, ClientError> {
+        let (bookkeeper, _) = bookkeeper_address(&self.program_id);
+        let ix = remove_client(
+            self.payer.pubkey(),
+            bookkeeper,
+            self.request_address(),
+            self.program_id,
+            client_id,
+        )?;
+        self.submit(ix)
+    }
+
+    /// Stores `request` for the agent and returns the request account holding it.
+    pub fn send(&self, request: Request) -> Result<RequestData, ClientError> {
+        let client_id = self.get_request_data()?.client_id;
+        let ix = send_request(
+            self.payer.pubkey(),
+            self.request_address(),
+            self.program_id,
+            client_id,
+            request,
+        )?;
+        self.submit(ix)?;
+        self.get_request_data()
+    }
+
+    pub fn get_request_data(&self) -> Result<RequestData, ClientError> {
+        let data = self.rpc.get_account_data(&self.request_address())?;
+        Ok(RequestData::unpack_account(&data)?)
+    }
+
+    /// Reads the request account every `interval` until the request is in `status`
+    /// or in any final status, and returns it. Fails with `ClientError::Timeout`
+    /// once `timeout` has passed.
+    pub fn wait_for_status(
+        &self,
+        status: RequestStatus,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Result<RequestData, ClientError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let data = self.get_request_data()?;
+            if reached(&data, status) {
+                return Ok(data);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(ClientError::Timeout);
+            }
+            thread::sleep(interval.min(left));
+        }
+    }
+
+    pub fn get_bookkeeper(&self) -> Result<BookKeeper, ClientError> {
+        let (bookkeeper, _) = bookkeeper_address(&self.program_id);
+        let data = self.rpc.get_account_data(&bookkeeper)?;
+        Ok(BookKeeper::unpack(&data)?)
+    }
+
+    /// What the deployed program supports, read from a simulated `GetVersion`,
+    /// so nothing is sent or paid for.
+    pub fn query_capabilities(&self) -> Result<Capabilities, ClientError> {
+        let transaction = get_version_transaction(&self.program_id, &self.payer.pubkey())?;
+        let result = self
+            .rpc
+            .simulate_transaction_with_config(&transaction, simulation_config())?;
+        capabilities_from_simulation(result.value)
+    }
+
+    /// The snapshots published in `epochs`, oldest first; epochs in which
+    /// nothing was published are skipped.
+    pub fn get_stats_snapshots(
+        &self,
+        epochs: impl IntoIterator<Item = u64>,
+    ) -> Result<Vec<StatsSnapshot>, ClientError> {
+        let keys: Vec<Pubkey> = stats_snapshot_addresses(&self.program_id, epochs)
+            .into_iter()
+            .map(|(_, key)| key)
+            .collect();
+        let mut snapshots = Vec::new();
+        //`getMultipleAccounts` takes at most 100 keys per call
+        for chunk in keys.chunks(100) {
+            for account in self.rpc.get_multiple_accounts(chunk)?.into_iter().flatten() {
+                snapshots.push(StatsSnapshot::unpack_unchecked(&account.data)?);
+            }
+        }
+        Ok(snapshots)
+    }
+
+    fn submit(&self, ix: Instruction) -> Result<Signature, ClientError> {
+        let blockhash = self.rpc.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&self.payer.pubkey()),
+            &[&self.payer],
+            blockhash,
+        );
+        Ok(self.rpc.send_and_confirm_transaction(&transaction)?)
+    }
+}
@@ -0,0 +1,219 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! `BitokuClient` over the nonblocking `RpcClient`, for services that run on
+//! tokio.
+
+use std::{time::Duration, vec::Vec};
+
+use solana_program::{instruction::Instruction, program_pack::Pack, pubkey::Pubkey};
+use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    signature::{Keypair, Signature, Signer},
+    transaction::Transaction,
+};
+use tokio::time::{self, Instant};
+
+use super::{
+    capabilities_from_simulation, get_version_transaction, reached, simulation_config, ClientError,
+};
+use crate::{
+    instruction::{
+        bookkeeper_address, register_client_auto, remove_client, request_address, send_request,
+        stats_snapshot_addresses, Request,
+    },
+    state::{BookKeeper, Capabilities, RequestData, RequestStatus, StatsSnapshot},
+};
+
+/// Async counterpart of `BitokuClient`.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), bitoku_sdk_agent_native::client::ClientError> {
+/// use std::time::Duration;
+///
+/// use bitoku_sdk_agent_native::{
+///     client::nonblocking::AsyncBitokuClient,
+///     instruction::Request,
+///     state::{str_to_name, RequestStatus},
+/// };
+/// use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+/// use solana_sdk::signature::Keypair;
+/// # let program_id = solana_sdk::pubkey::Pubkey::new_unique();
+///
+/// let rpc = RpcClient::new("http://127.0.0.1:8899".to_string());
+/// let client = AsyncBitokuClient::new(rpc, program_id, Keypair::new());
+/// client.register().await?;
+/// client
+///     .send(Request::CreateBucket { name: str_to_name("photos")? })
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncBitokuClient {
+    pub rpc: RpcClient,
+    pub program_id: Pubkey,
+    /// Signs and pays for every transaction; owner of the request account.
+    pub payer: Keypair,
+}
+
+impl AsyncBitokuClient {
+    pub fn new(rpc: RpcClient, program_id: Pubkey, payer: Keypair) -> Self {
+        Self {
+            rpc,
+            program_id,
+            payer,
+        }
+    }
+
+    /// The payer's request PDA.
+    pub fn request_address(&self) -> Pubkey {
+        request_address(&self.program_id, &self.payer.pubkey()).0
+    }
+
+    /// Registers the payer as a client and returns its new request account.
+    pub async fn register(&self) -> Result<RequestData, ClientError> {
+        let (ix, _) = register_client_auto(self.payer.pubkey(), self.program_id)?;
+        self.submit(ix).await?;
+        self.get_request_data().await
+    }
+
+    /// Frees `client_id` and closes the payer's request account.
+    pub async fn remove(&self, client_id: u8) -> Result<Signature, ClientError> {
+        let (bookkeeper, _) = bookkeeper_address(&self.program_id);
+        let ix = remove_client(
+            self.payer.pubkey(),
+            bookkeeper,
+            self.request_address(),
+            self.program_id,
+            client_id,
+        )?;
+        self.submit(ix).await
+    }
+
+    /// Stores `request` for the agent and returns the request account holding it.
+    pub async fn send(&self, request: Request) -> Result<RequestData, ClientError> {
+        let client_id = self.get_request_data().await?.client_id;
+        let ix = send_request(
+            self.payer.pubkey(),
+            self.request_address(),
+            self.program_id,
+            client_id,
+            request,
+        )?;
+        self.submit(ix).await?;
+        self.get_request_data().await
+    }
+
+    pub async fn get_request_data(&self) -> Result<RequestData, ClientError> {
+        let data = self.rpc.get_account_data(&self.request_address()).await?;
+        Ok(RequestData::unpack_account(&data)?)
+    }
+
+    /// Reads the request account every `interval` until the request is in `status`
+    /// or in any final status, and returns it. Fails with `ClientError::Timeout`
+    /// once `timeout` has passed.
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use bitoku_sdk_agent_native::{client::{nonblocking::AsyncBitokuClient, ClientError}, state::RequestStatus};
+    /// # async fn run(client: AsyncBitokuClient) -> Result<(), ClientError> {
+    /// let data = client
+    ///     .wait_for_status(
+    ///         RequestStatus::Completed,
+    ///         Duration::from_millis(500),
+    ///         Duration::from_secs(30),
+    ///     )
+    ///     .await?;
+    /// if data.status == RequestStatus::Failed {
+    ///     // the agent couldn't carry the request out
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn wait_for_status(
+        &self,
+        status: RequestStatus,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Result<RequestData, ClientError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let data = self.get_request_data().await?;
+            if reached(&data, status) {
+                return Ok(data);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(ClientError::Timeout);
+            }
+            time::sleep(interval.min(left)).await;
+        }
+    }
+
+    pub async fn get_bookkeeper(&self) -> Result<BookKeeper, ClientError> {
+        let (bookkeeper, _) = bookkeeper_address(&self.program_id);
+        let data = self.rpc.get_account_data(&bookkeeper).await?;
+        Ok(BookKeeper::unpack(&data)?)
+    }
+
+    /// What the deployed program supports, read from a simulated `GetVersion`,
+    /// so nothing is sent or paid for.
+    pub async fn query_capabilities(&self) -> Result<Capabilities, ClientError> {
+        let transaction = get_version_transaction(&self.program_id, &self.payer.pubkey())?;
+        let result = self
+            .rpc
+            .simulate_transaction_with_config(&transaction, simulation_config())
+            .await?;
+        capabilities_from_simulation(result.value)
+    }
+
+    /// The snapshots published in `epochs`, oldest first; epochs in which
+    /// nothing was published are skipped.
+    pub async fn get_stats_snapshots(
+        &self,
+        epochs: impl IntoIterator<Item = u64>,
+    ) -> Result<Vec<StatsSnapshot>, ClientError> {
+        let keys: Vec<Pubkey> = stats_snapshot_addresses(&self.program_id, epochs)
+            .into_iter()
+            .map(|(_, key)| key)
+            .collect();
+        let mut snapshots = Vec::new();
+        //`getMultipleAccounts` takes at most 100 keys per call
+        for chunk in keys.chunks(100) {
+            for account in self
+                .rpc
+                .get_multiple_accounts(chunk)
+                .await?
+                .into_iter()
+                .flatten()
+            {
+                snapshots.push(StatsSnapshot::unpack_unchecked(&account.data)?);
+            }
+        }
+        Ok(snapshots)
+    }
+
+    async fn submit(&self, ix: Instruction) -> Result<Signature, ClientError> {
+        let blockhash = self.rpc.get_latest_blockhash().await?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&self.payer.pubkey()),
+            &[&self.payer],
+            blockhash,
+        );
+        Ok(self.rpc.send_and_confirm_transaction(&transaction).await?)
+    }
+}
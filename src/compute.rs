@@ -0,0 +1,107 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Compute units to request per instruction with `set_compute_unit_limit`.
+//!
+//! The figures are estimates built from the runtime's syscall costs: 1,500 per
+//! PDA derivation attempt, about 2,500 per system program CPI, 100 per log line,
+//! plus the Borsh work on the accounts each instruction reads and writes. Each
+//! one has a 20% margin on top. They are upper bounds for a transaction that
+//! holds one Bitoku instruction, not measurements.
+
+use crate::instruction::BitokuInstructions;
+
+/// Derives the bookkeeper PDA and creates it through the system program.
+pub const INIT_BITOKU_CU: u32 = 12_000;
+/// Derives the request PDA, creates it, and updates the bookkeeper; also
+/// `RegisterClientFor`.
+pub const REGISTER_CLIENT_CU: u32 = 18_000;
+/// Frees the client id and closes the request account; also `ForceRemoveClient`.
+pub const REMOVE_CLIENT_CU: u32 = 11_000;
+/// Checks names and stores the request; a lease request creates the lease PDA.
+/// Also `OverwriteRequest`.
+pub const SEND_REQUEST_CU: u32 = 25_000;
+/// `SEND_REQUEST_CU` plus reading the ed25519 instruction from the instructions
+/// sysvar. The precompile's own cost is charged to its instruction.
+pub const SUBMIT_SIGNED_REQUEST_CU: u32 = 30_000;
+/// Derives the epoch's stats PDA and creates or updates it.
+pub const PUBLISH_STATS_CU: u32 = 15_000;
+/// Agent updates to a request account: `UpdateRequestStatus`, `WriteResponse`
+/// and `AcknowledgeRequest`.
+pub const AGENT_UPDATE_CU: u32 = 12_000;
+/// Updates to a single request account: `CancelRequest`, `ResetEpochCounter`,
+/// `SetClientPermissions` and `ShrinkRequestAccount`.
+pub const CLIENT_UPDATE_CU: u32 = 10_000;
+/// Authority and admin updates that only touch the bookkeeper, and agents
+/// publishing load.
+pub const BOOKKEEPER_UPDATE_CU: u32 = 6_000;
+/// Derives the account's PDA, tops up its rent and rewrites it in the current layout.
+pub const MIGRATE_ACCOUNT_CU: u32 = 20_000;
+/// Sets the return data and nothing else.
+pub const GET_VERSION_CU: u32 = 2_000;
+
+/// Compute units to budget for `ix`.
+pub fn estimate_cu(ix: &BitokuInstructions) -> u32 {
+    match ix {
+        BitokuInstructions::InitBitoku => INIT_BITOKU_CU,
+        BitokuInstructions::RegisterClient { .. }
+        | BitokuInstructions::RegisterClientFor { .. } => REGISTER_CLIENT_CU,
+        BitokuInstructions::RemoveClient { .. } | BitokuInstructions::ForceRemoveClient { .. } => {
+            REMOVE_CLIENT_CU
+        }
+        BitokuInstructions::SendRequest { .. } | BitokuInstructions::OverwriteRequest { .. } => {
+            SEND_REQUEST_CU
+        }
+        BitokuInstructions::SubmitSignedRequest { .. } => SUBMIT_SIGNED_REQUEST_CU,
+        BitokuInstructions::PublishStats => PUBLISH_STATS_CU,
+        BitokuInstructions::UpdateRequestStatus { .. }
+        | BitokuInstructions::WriteResponse { .. }
+        | BitokuInstructions::AcknowledgeRequest { .. } => AGENT_UPDATE_CU,
+        BitokuInstructions::CancelRequest { .. }
+        | BitokuInstructions::ResetEpochCounter { .. }
+        | BitokuInstructions::SetClientPermissions { .. }
+        | BitokuInstructions::ShrinkRequestAccount => CLIENT_UPDATE_CU,
+        BitokuInstructions::MigrateAccount => MIGRATE_ACCOUNT_CU,
+        BitokuInstructions::SetReservedPrefix { .. }
+        | BitokuInstructions::SetSupportedKinds { .. }
+        | BitokuInstructions::SetAgent { .. }
+        | BitokuInstructions::SetNamePolicy { .. }
+        | BitokuInstructions::PublishLoad { .. }
+        | BitokuInstructions::SetAuthority { .. }
+        | BitokuInstructions::UpdateAdmin { .. }
+        | BitokuInstructions::SetPaused { .. }
+        | BitokuInstructions::AddAgent { .. }
+        | BitokuInstructions::RemoveAgent { .. } => BOOKKEEPER_UPDATE_CU,
+        BitokuInstructions::GetVersion => GET_VERSION_CU,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction::test::every_instruction;
+
+    //the default limit of a transaction without a compute budget instruction
+    const DEFAULT_TX_LIMIT: u32 = 200_000;
+
+    #[test]
+    fn test_estimate_cu() {
+        for ix in every_instruction() {
+            let units = estimate_cu(&ix);
+            assert!(units > 0 && units < DEFAULT_TX_LIMIT, "{:?}", ix);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Requests signed off-chain by the owner of a client and submitted by a relayer
+//! that pays the fees. The relayer sends the `ed25519_instruction` of the envelope
+//! right before its `SubmitSignedRequest`; the ed25519 program checks the
+//! signature and the Bitoku program checks that it covers the envelope.
+//!
+//! The ed25519 instruction carries a copy of the signed message, so requests with
+//! a full 512-byte payload do not fit in one transaction with it.
+
+use alloc::{vec, vec::Vec};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use solana_program::{
+    ed25519_program, instruction::Instruction, program_error::ProgramError, pubkey::Pubkey,
+};
+
+use crate::{
+    error::BitokuError::{ClientMismatch, InvalidAccount, InvalidSignature, StaleNonce},
+    instruction::{pack_request, Request},
+    pda::find_request_pda,
+    state::{RequestData, ENCODED_REQUEST_MAX_LEN},
+};
+
+/// Start of every signed message, so that a signature over it can't be passed
+/// off as one over a transaction or another protocol's payload.
+pub const ENVELOPE_DOMAIN: &[u8] = b"bitoku:signed-request:v1";
+
+//ed25519 program instruction data: signature count and padding, one set of
+//offsets, then the public key, signature and message they point at
+const ED25519_OFFSETS_START: usize = 2;
+const ED25519_KEY_START: usize = ED25519_OFFSETS_START + 14;
+const ED25519_SIGNATURE_START: usize = ED25519_KEY_START + 32;
+const ED25519_MESSAGE_START: usize = ED25519_SIGNATURE_START + 64;
+
+/// The bytes the owner signs: `ENVELOPE_DOMAIN`, the program id, the packed
+/// request and the nonce as a little endian u64.
+pub fn signing_message(bitoku_agnet_program: &Pubkey, request: &Request, nonce: u64) -> Vec<u8> {
+    let mut packed = [0u8; ENCODED_REQUEST_MAX_LEN];
+    let len = pack_request(request, &mut packed).expect("the request area fits every request");
+
+    let mut message = Vec::with_capacity(ENVELOPE_DOMAIN.len() + 32 + len + 8);
+    message.extend_from_slice(ENVELOPE_DOMAIN);
+    message.extend_from_slice(bitoku_agnet_program.as_ref());
+    message.extend_from_slice(&packed[..len]);
+    message.extend_from_slice(&nonce.to_le_bytes());
+    message
+}
+
+/// A request signed by `signer`, the owner of the request account, for a
+/// relayer to submit with `SubmitSignedRequest`.
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignedRequestEnvelope {
+    pub request: Request,
+    pub client_id: u8,
+    /// Must exceed the nonce of the last envelope the request account accepted.
+    pub nonce: u64,
+    /// ed25519 signature of `signing_message` by `signer`.
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::data_serde"))]
+    pub signature: [u8; 64],
+    pub signer: Pubkey,
+}
+
+impl SignedRequestEnvelope {
+    pub fn new(
+        request: Request,
+        client_id: u8,
+        nonce: u64,
+        signer: Pubkey,
+        signature: [u8; 64],
+    ) -> Self {
+        Self {
+            request,
+            client_id,
+            nonce,
+            signature,
+            signer,
+        }
+    }
+
+    /// The message `signer` signs, see `signing_message`.
+    pub fn message(&self, bitoku_agnet_program: &Pubkey) -> Vec<u8> {
+        signing_message(bitoku_agnet_program, &self.request, self.nonce)
+    }
+
+    /// The ed25519 program instruction verifying the signature, which must come
+    /// right before the envelope's `SubmitSignedRequest` in the transaction.
+    pub fn ed25519_instruction(&self, bitoku_agnet_program: &Pubkey) -> Instruction {
+        let message = self.message(bitoku_agnet_program);
+
+        let mut data = Vec::with_capacity(ED25519_MESSAGE_START + message.len());
+        data.extend_from_slice(&[1, 0]);
+        for value in [
+            ED25519_SIGNATURE_START as u16,
+            u16::MAX,
+            ED25519_KEY_START as u16,
+            u16::MAX,
+            ED25519_MESSAGE_START as u16,
+            message.len() as u16,
+            u16::MAX,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(self.signer.as_ref());
+        data.extend_from_slice(&self.signature);
+        data.extend_from_slice(&message);
+
+        Instruction {
+            program_id: ed25519_program::id(),
+            accounts: vec![],
+            data,
+        }
+    }
+
+    /// Checks `ix` is the envelope's ed25519 instruction, as the program does with
+    /// the instruction preceding `SubmitSignedRequest`.
+    pub fn check_ed25519_instruction(
+        &self,
+        bitoku_agnet_program: &Pubkey,
+        ix: &Instruction,
+    ) -> Result<(), ProgramError> {
+        if ix.program_id != ed25519_program::id() {
+            return Err(InvalidSignature.into());
+        }
+        match parse_ed25519_instruction(&ix.data) {
+            Some((signer, signature, message))
+                if signer == self.signer
+                    && signature == self.signature
+                    && message == self.message(bitoku_agnet_program) =>
+            {
+                Ok(())
+            }
+            _ => Err(InvalidSignature.into()),
+        }
+    }
+
+    /// Checks the envelope against the signer's request account as the program
+    /// will: the account must belong to `signer`, be registered under `client_id`
+    /// and not have accepted `nonce` yet. The signature itself is left to the
+    /// ed25519 program.
+    pub fn check_request_account(
+        &self,
+        bitoku_agnet_program: &Pubkey,
+        request: &Pubkey,
+        request_account_data: &[u8],
+    ) -> Result<(), ProgramError> {
+        if find_request_pda(&self.signer, bitoku_agnet_program).0 != *request {
+            return Err(InvalidAccount.into());
+        }
+        let request_data = RequestData::unpack_account(request_account_data)?;
+        if !request_data.is_initialized {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        if request_data.client_id != self.client_id {
+            return Err(ClientMismatch.into());
+        }
+        if self.nonce <= request_data.nonce {
+            return Err(StaleNonce.into());
+        }
+        Ok(())
+    }
+}
+
+/// Public key, signature and message of an ed25519 program instruction that
+/// verifies one signature over data it carries itself, as `ed25519_instruction`
+/// builds them.
+pub fn parse_ed25519_instruction(data: &[u8]) -> Option<(Pubkey, [u8; 64], &[u8])> {
+    let offset = |i: usize| {
+        let start = ED25519_OFFSETS_START + 2 * i;
+        data.get(start..start + 2)
+            .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    if data.first() != Some(&1)
+        || offset(0)? as usize != ED25519_SIGNATURE_START
+        || offset(1)? != u16::MAX
+        || offset(2)? as usize != ED25519_KEY_START
+        || offset(3)? != u16::MAX
+        || offset(4)? as usize != ED25519_MESSAGE_START
+        || offset(6)? != u16::MAX
+    {
+        return None;
+    }
+    let message = data.get(ED25519_MESSAGE_START..ED25519_MESSAGE_START + offset(5)? as usize)?;
+    let signer = Pubkey::new_from_array(
+        data[ED25519_KEY_START..ED25519_SIGNATURE_START]
+            .try_into()
+            .unwrap(),
+    );
+    let signature = data[ED25519_SIGNATURE_START..ED25519_MESSAGE_START]
+        .try_into()
+        .unwrap();
+    Some((signer, signature, message))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ed25519_instruction_roundtrip() {
+        let program = Pubkey::new_unique();
+        let request = Request::DeleteBucket { name: [b'b'; 128] };
+        let envelope = SignedRequestEnvelope::new(request, 1, 2, Pubkey::new_unique(), [3; 64]);
+
+        let message = envelope.message(&program);
+        assert!(message.starts_with(ENVELOPE_DOMAIN));
+        assert_eq!(&message[ENVELOPE_DOMAIN.len()..][..32], program.as_ref());
+        assert_eq!(message[message.len() - 8..], 2u64.to_le_bytes());
+
+        let ix = envelope.ed25519_instruction(&program);
+        assert_eq!(ix.program_id, ed25519_program::id());
+        assert_eq!(
+            parse_ed25519_instruction(&ix.data),
+            Some((envelope.signer, envelope.signature, message.as_slice()))
+        );
+        assert_eq!(envelope.check_ed25519_instruction(&program, &ix), Ok(()));
+
+        //a bumped nonce needs a new signature
+        let bumped = SignedRequestEnvelope {
+            nonce: 3,
+            ..envelope.clone()
+        };
+        assert_eq!(
+            bumped.check_ed25519_instruction(&program, &ix),
+            Err(InvalidSignature.into())
+        );
+
+        //offsets pointing into other instructions are not followed
+        let mut data = ix.data.clone();
+        data[ED25519_OFFSETS_START + 2] = 0;
+        assert_eq!(parse_ed25519_instruction(&data), None);
+        assert_eq!(
+            parse_ed25519_instruction(&ix.data[..ix.data.len() - 1]),
+            None
+        );
+    }
+}
@@ -14,55 +14,341 @@
    limitations under the License.
 */
 
-use thiserror::Error;
+use core::fmt;
+
+use num_traits::FromPrimitive;
 
 use solana_program::{decode_error::DecodeError, program_error::ProgramError};
 
-#[derive(Error, Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BitokuError {
     //0
-    #[error("Instruction is not valid")]
     InvalidInstruction,
     //1
-    #[error("instruction_data is invalid")]
     InvalidInstructionData,
     //2
-    #[error("client limit reached")]
     NoAvailableClients,
     //3
-    #[error("numbers overflow")]
     Overflow,
     //4
-    #[error("client is not registered")]
     UnregisteredClient,
     //5
-    #[error("name is not valid")]
     InvalidName,
     //6
-    #[error("account is not valid")]
     InvalidAccount,
     //7
-    #[error("client is not valid")]
     InvalidClientId,
     //8
-    #[error("file id is not valid")]
     InvalidFileId,
     //9
-    #[error("provided position is not valid")]
     InvalidPosition,
     //10
-    #[error("client id mismatch")]
     ClientMismatch,
+    //11
+    EmptyName,
+    //12
+    BucketNotEmpty,
+    //13
+    Unauthorized,
+    //14
+    InvalidReadLength,
+    //15
+    ReservedName,
+    //16
+    InvalidStatusTransition,
+    //17
+    UnsupportedRequestKind,
+    //18
+    NoRequest,
+    //19
+    DataTooLarge,
+    //20
+    BufferTooSmall,
+    //21
+    AccountAlreadyInitialized,
+    //22
+    BucketLeased,
+    //23
+    ProgramPaused,
+    //24
+    StaleNonce,
+    //25
+    InvalidSignature,
+    //26
+    BitmapOverflow,
+    //27
+    UnauthorizedAgent,
+    //28
+    AgentLimitReached,
+    //29
+    RequestPending,
+    //30
+    /// A client reached `BookKeeper::max_client_requests_per_epoch`. It was asked
+    /// for as code 13, which `Unauthorized` already held. There is no separate
+    /// per-client `total_requests` counter: `RequestData::sequence` already
+    /// counts every request stored through the account.
+    RateLimitExceeded,
+    //31
+    /// A client's `ClientInfo::permissions` lack the bit its request needs. It
+    /// was asked for as code 14, which `InvalidReadLength` already held.
+    PermissionDenied,
+}
+
+impl BitokuError {
+    /// The code carried by `ProgramError::Custom` for this error.
+    pub fn code(&self) -> u32 {
+        self.clone() as u32
+    }
+
+    /// The error returned with `code`, if any.
+    pub fn from_code(code: u32) -> Option<BitokuError> {
+        use BitokuError::*;
+
+        Some(match code {
+            0 => InvalidInstruction,
+            1 => InvalidInstructionData,
+            2 => NoAvailableClients,
+            3 => Overflow,
+            4 => UnregisteredClient,
+            5 => InvalidName,
+            6 => InvalidAccount,
+            7 => InvalidClientId,
+            8 => InvalidFileId,
+            9 => InvalidPosition,
+            10 => ClientMismatch,
+            11 => EmptyName,
+            12 => BucketNotEmpty,
+            13 => Unauthorized,
+            14 => InvalidReadLength,
+            15 => ReservedName,
+            16 => InvalidStatusTransition,
+            17 => UnsupportedRequestKind,
+            18 => NoRequest,
+            19 => DataTooLarge,
+            20 => BufferTooSmall,
+            21 => AccountAlreadyInitialized,
+            22 => BucketLeased,
+            23 => ProgramPaused,
+            24 => StaleNonce,
+            25 => InvalidSignature,
+            26 => BitmapOverflow,
+            27 => UnauthorizedAgent,
+            28 => AgentLimitReached,
+            29 => RequestPending,
+            30 => RateLimitExceeded,
+            31 => PermissionDenied,
+            _ => return None,
+        })
+    }
+
+    /// The error a failed instruction returned, read back from the program error
+    /// of the transaction.
+    pub fn from_program_error(err: &ProgramError) -> Option<BitokuError> {
+        match err {
+            ProgramError::Custom(code) => Self::from_code(*code),
+            _ => None,
+        }
+    }
 }
 
+impl fmt::Display for BitokuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use BitokuError::*;
+
+        f.write_str(match self {
+            InvalidInstruction => "Instruction is not valid",
+            InvalidInstructionData => "instruction_data is invalid",
+            NoAvailableClients => "client limit reached",
+            Overflow => "numbers overflow",
+            UnregisteredClient => "client is not registered",
+            InvalidName => "name is not valid",
+            InvalidAccount => "account is not valid",
+            InvalidClientId => "client is not valid",
+            InvalidFileId => "file id is not valid",
+            InvalidPosition => "provided position is not valid",
+            ClientMismatch => "client id mismatch",
+            EmptyName => "name is empty",
+            BucketNotEmpty => "bucket is not empty",
+            Unauthorized => "signer is not authorized",
+            InvalidReadLength => "read length is not valid",
+            ReservedName => "name uses a reserved prefix",
+            InvalidStatusTransition => "request status transition is not allowed",
+            UnsupportedRequestKind => "request kind is not supported by the agent",
+            NoRequest => "no request has been submitted through this account",
+            DataTooLarge => "request payload is larger than 512 bytes",
+            BufferTooSmall => "buffer is too small for the packed instruction",
+            AccountAlreadyInitialized => "account is already initialized",
+            BucketLeased => "bucket is leased to another client",
+            ProgramPaused => "program is paused",
+            StaleNonce => "envelope nonce is not above the last one used",
+            InvalidSignature => "no matching ed25519 signature precedes the instruction",
+            BitmapOverflow => "element is outside the client bitmap",
+            UnauthorizedAgent => "signer is not a registered agent",
+            AgentLimitReached => "agent limit reached",
+            RequestPending => "previous request is still pending",
+            RateLimitExceeded => "client reached its request limit for this epoch",
+            PermissionDenied => "client lacks the permission for this request kind",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BitokuError {}
+
 impl From<BitokuError> for ProgramError {
     fn from(e: BitokuError) -> Self {
         ProgramError::Custom(e as u32)
     }
 }
 
-impl<T> DecodeError<T> for BitokuError {
+impl FromPrimitive for BitokuError {
+    fn from_i64(n: i64) -> Option<Self> {
+        u32::try_from(n).ok().and_then(Self::from_code)
+    }
+
+    fn from_u64(n: u64) -> Option<Self> {
+        u32::try_from(n).ok().and_then(Self::from_code)
+    }
+}
+
+impl DecodeError<BitokuError> for BitokuError {
+    fn decode_custom_error_to_enum(custom: u32) -> Option<BitokuError> {
+        Self::from_code(custom)
+    }
+
     fn type_of() -> &'static str {
         "BitokuError"
     }
 }
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(
+            ProgramError::from(BitokuError::EmptyName),
+            ProgramError::Custom(11)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::BucketNotEmpty),
+            ProgramError::Custom(12)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::InvalidReadLength),
+            ProgramError::Custom(14)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::ReservedName),
+            ProgramError::Custom(15)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::InvalidStatusTransition),
+            ProgramError::Custom(16)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::UnsupportedRequestKind),
+            ProgramError::Custom(17)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::NoRequest),
+            ProgramError::Custom(18)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::DataTooLarge),
+            ProgramError::Custom(19)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::BucketLeased),
+            ProgramError::Custom(22)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::ProgramPaused),
+            ProgramError::Custom(23)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::StaleNonce),
+            ProgramError::Custom(24)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::InvalidSignature),
+            ProgramError::Custom(25)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::BitmapOverflow),
+            ProgramError::Custom(26)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::UnauthorizedAgent),
+            ProgramError::Custom(27)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::AgentLimitReached),
+            ProgramError::Custom(28)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::RequestPending),
+            ProgramError::Custom(29)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::RateLimitExceeded),
+            ProgramError::Custom(30)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::PermissionDenied),
+            ProgramError::Custom(31)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::BufferTooSmall),
+            ProgramError::Custom(20)
+        );
+        assert_eq!(
+            ProgramError::from(BitokuError::AccountAlreadyInitialized),
+            ProgramError::Custom(21)
+        );
+    }
+
+    #[test]
+    fn test_error_codes_roundtrip() {
+        let errors: Vec<_> = (0..).map_while(BitokuError::from_code).collect();
+        assert_eq!(errors.last(), Some(&BitokuError::PermissionDenied));
+
+        for (code, error) in (0..).zip(errors) {
+            assert_eq!(error.code(), code);
+            let program_error = ProgramError::from(error.clone());
+            assert_eq!(
+                BitokuError::from_program_error(&program_error),
+                Some(error.clone())
+            );
+            assert_eq!(
+                <BitokuError as DecodeError<BitokuError>>::decode_custom_error_to_enum(code),
+                Some(error)
+            );
+        }
+
+        assert_eq!(BitokuError::from_code(u32::MAX), None);
+        assert_eq!(BitokuError::from_i64(-1), None);
+        assert_eq!(
+            BitokuError::from_program_error(&ProgramError::InvalidAccountData),
+            None
+        );
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let errors: Vec<_> = (0..).map_while(BitokuError::from_code).collect();
+        let messages: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+        for (i, message) in messages.iter().enumerate() {
+            assert!(!message.is_empty());
+            assert!(!messages[..i].contains(message), "{}", message);
+        }
+
+        assert_eq!(
+            BitokuError::RequestPending.to_string(),
+            "previous request is still pending"
+        );
+    }
+}
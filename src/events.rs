@@ -0,0 +1,172 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Events the processor logs with `sol_log_data`, one `Program data:` entry per
+//! event. Subscribers of `logsSubscribe` base64-decode the entry and pass it to
+//! `parse_event`. The entry logged by `GetVersion` is not an event; it is read
+//! with `Capabilities::from_log_data`.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{hash::hash, log::sol_log_data, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::instruction::Request;
+
+/// A client id was handed out by `RegisterClient` or `RegisterClientFor`.
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClientRegistered {
+    pub client_id: u8,
+    /// Owner of the request PDA.
+    pub requester: Pubkey,
+}
+
+/// A client id was freed by `RemoveClient` or `ForceRemoveClient`.
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClientRemoved {
+    pub client_id: u8,
+}
+
+/// A request was stored for the agent. Sequences of a client increase by one,
+/// so a gap is a request that was overwritten before it was seen.
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RequestSubmitted {
+    pub client_id: u8,
+    /// `Request::kind` of the stored request.
+    pub request_tag: u8,
+    /// `name_hash` of the stored request.
+    pub name_hash: [u8; 32],
+    /// `RequestData::sequence` of the stored request.
+    pub sequence: u64,
+}
+
+impl RequestSubmitted {
+    pub fn new(client_id: u8, request: &Request, sequence: u64) -> Self {
+        Self {
+            client_id,
+            request_tag: request.kind(),
+            name_hash: name_hash(request),
+            sequence,
+        }
+    }
+}
+
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BitokuEvent {
+    ClientRegistered(ClientRegistered),
+    ClientRemoved(ClientRemoved),
+    RequestSubmitted(RequestSubmitted),
+}
+
+impl BitokuEvent {
+    /// Logs the event as a single `sol_log_data` entry.
+    pub fn log(&self) -> Result<(), ProgramError> {
+        sol_log_data(&[&self.try_to_vec()?]);
+        Ok(())
+    }
+}
+
+impl From<ClientRegistered> for BitokuEvent {
+    fn from(event: ClientRegistered) -> Self {
+        BitokuEvent::ClientRegistered(event)
+    }
+}
+
+impl From<ClientRemoved> for BitokuEvent {
+    fn from(event: ClientRemoved) -> Self {
+        BitokuEvent::ClientRemoved(event)
+    }
+}
+
+impl From<RequestSubmitted> for BitokuEvent {
+    fn from(event: RequestSubmitted) -> Self {
+        BitokuEvent::RequestSubmitted(event)
+    }
+}
+
+/// Decodes a `Program data:` entry logged by the processor (already
+/// base64-decoded).
+pub fn parse_event(data: &[u8]) -> Result<BitokuEvent, ProgramError> {
+    BitokuEvent::try_from_slice(data).map_err(|_| ProgramError::InvalidAccountData)
+}
+
+/// SHA-256 of the text of the name `request` targets, without the NUL padding,
+/// so that an indexer can match it against a name it knows. Zero for
+/// `EmptyTrash`, which has no name.
+pub fn name_hash(request: &Request) -> [u8; 32] {
+    match request.name() {
+        Some(name) => {
+            let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            hash(&name[..len]).to_bytes()
+        }
+        None => [0; 32],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::str_to_name;
+
+    fn roundtrip(event: BitokuEvent) {
+        let logged = event.try_to_vec().unwrap();
+        assert_eq!(parse_event(&logged), Ok(event));
+    }
+
+    #[test]
+    fn test_client_registered_roundtrip() {
+        roundtrip(
+            ClientRegistered {
+                client_id: 7,
+                requester: Pubkey::new_unique(),
+            }
+            .into(),
+        );
+    }
+
+    #[test]
+    fn test_client_removed_roundtrip() {
+        roundtrip(ClientRemoved { client_id: 255 }.into());
+    }
+
+    #[test]
+    fn test_request_submitted_roundtrip() {
+        let request = Request::CreateBucket {
+            name: str_to_name("photos").unwrap(),
+        };
+        let event = RequestSubmitted::new(3, &request, 0x0102_0304_0506_0708);
+        assert_eq!(event.request_tag, 0);
+        assert_eq!(event.name_hash, hash(b"photos").to_bytes());
+        roundtrip(event.into());
+
+        let event = RequestSubmitted::new(3, &Request::EmptyTrash {}, 1);
+        assert_eq!(event.name_hash, [0; 32]);
+        roundtrip(event.into());
+    }
+
+    #[test]
+    fn test_parse_event_rejects_other_data() {
+        let logged = BitokuEvent::from(ClientRemoved { client_id: 1 })
+            .try_to_vec()
+            .unwrap();
+        assert!(parse_event(&logged[..1]).is_err());
+        assert!(parse_event(&[logged.as_slice(), &[0]].concat()).is_err());
+        assert!(parse_event(&[3]).is_err());
+        assert!(parse_event(&[]).is_err());
+    }
+}
@@ -14,69 +14,249 @@
    limitations under the License.
 */
 
+#[cfg(feature = "client")]
+use crate::envelope::SignedRequestEnvelope;
+use crate::error::BitokuError;
 use crate::error::BitokuError::{
-    InvalidClientId, InvalidFileId, InvalidInstruction, InvalidInstructionData, InvalidPosition,
+    BufferTooSmall, DataTooLarge, EmptyName, InvalidFileId, InvalidInstruction,
+    InvalidInstructionData, InvalidName, InvalidPosition, InvalidReadLength,
+};
+#[cfg(feature = "client")]
+use crate::error::BitokuError::{InvalidAccount, Overflow};
+#[cfg(feature = "legacy-wire")]
+use crate::legacy_wire;
+use crate::pda::find_stats_pda;
+#[cfg(feature = "client")]
+use crate::pda::{
+    find_bookkeeper_pda, find_client_info_pda, find_lease_pda, find_program_data_address,
+    find_request_pda,
+};
+use crate::policy::{NamePolicy, NamePolicyKind};
+use crate::protocol::{max_len, FieldKind, INSTRUCTIONS, REQUESTS};
+use crate::state::{
+    name_to_str, str_to_name, RequestStatus, ALL_PERMISSIONS, CHUNK_LEN, ENCODED_REQUEST_MAX_LEN,
+    MAX_LEASE_SLOTS, MAX_READ, PERMISSION_CREATE, PERMISSION_DELETE, PERMISSION_READ,
+    PERMISSION_WRITE, PREFIX_LEN, RESERVED_PREFIXES,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
 };
 use borsh::{BorshDeserialize, BorshSerialize};
+use core::{fmt, str::Utf8Error};
 use solana_program::{
-    instruction::{AccountMeta, Instruction},
-    program_error::ProgramError,
-    pubkey::Pubkey,
+    hash::hash, instruction::AccountMeta, program_error::ProgramError, pubkey::Pubkey,
 };
-use std::mem::size_of;
+#[cfg(feature = "client")]
+use solana_program::{instruction::Instruction, system_program, sysvar};
 
+/// `Display` and `Debug` print names as text and payloads as their length, e.g.
+/// `CreateBucket(name="photos")`.
 #[repr(C)]
-#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug, Clone)]
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Request {
     CreateBucket {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
         name: [u8; 128],
     },
+    /// `sparse` takes the byte that holds `file_id` in the other file requests,
+    /// so `data` sits at the same offset as in `AppendFile`.
     CreateFile {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
         name: [u8; 128],
+        sparse: bool,
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::data_serde"))]
         data: [u8; 512],
     },
+    /// Writes `data` at `offset`, so a non-sequential write needs no
+    /// `SetPosition` first.
     WriteFile {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
         name: [u8; 128],
         file_id: u8,
+        offset: u64,
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::data_serde"))]
         data: [u8; 512],
     },
     CloseFile {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
         name: [u8; 128],
         file_id: u8,
     },
     DeleteFile {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
         name: [u8; 128],
         file_id: u8,
     },
+    /// Deprecated: `WriteFile` carries its own offset. Kept so existing clients
+    /// keep working.
     SetPosition {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
         name: [u8; 128],
         file_id: u8,
         position: u64,
     },
     OpenFile {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
         name: [u8; 128],
         file_id: u8,
     },
+    /// `min_sequence` on read requests is the sequence of the last write the
+    /// answer must reflect; the agent holds the response until it has applied it.
     ReadFile {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+        file_id: u8,
+        min_sequence: u64,
+    },
+    /// Removes a bucket. The agent is expected to reject this with
+    /// `BitokuError::BucketNotEmpty` while the bucket still holds files.
+    DeleteBucket {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+    },
+    RenameFile {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+        file_id: u8,
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        new_name: [u8; 128],
+    },
+    CopyFile {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        src_name: [u8; 128],
+        src_file_id: u8,
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        dst_name: [u8; 128],
+    },
+    /// Asks the agent for a file's size and last write time, answered in
+    /// `RequestData::response` (see `STAT_SIZE_OFFSET`/`STAT_MTIME_OFFSET`).
+    StatFile {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+        file_id: u8,
+        min_sequence: u64,
+    },
+    TruncateFile {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+        file_id: u8,
+        new_length: u64,
+    },
+    /// Asks the agent for one page of the files in bucket `name`, answered in
+    /// `RequestData::response` (see `RequestData::list_entries`).
+    ListFiles {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+        page: u8,
+    },
+    /// `WriteFile` without the offset: the agent moves the write pointer to the
+    /// end of the file before writing `data`.
+    AppendFile {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+        file_id: u8,
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::data_serde"))]
+        data: [u8; 512],
+    },
+    /// Writes a chunk the agent already stores, identified by the sha256 of its
+    /// 512-byte padded contents (see `chunk_hash`), instead of carrying the data.
+    WriteByReference {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+        file_id: u8,
+        chunk_hash: [u8; 32],
+    },
+    /// Same layout and effect as `WriteFile`, which predates its offset field.
+    /// Kept for clients that used it to skip the `SetPosition` round trip.
+    WriteAt {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+        file_id: u8,
+        offset: u64,
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::data_serde"))]
+        data: [u8; 512],
+    },
+    /// Reads `len` bytes starting at `offset`; `len` must be in `1..=MAX_READ`.
+    ReadRange {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+        file_id: u8,
+        offset: u64,
+        len: u64,
+        min_sequence: u64,
+    },
+    /// Asks the agent whether `file_id` exists under `name`, answered in
+    /// `RequestData::response` (see `RequestData::exists_response`).
+    FileExists {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+        file_id: u8,
+    },
+    /// Moves a file to the trash, from where `RestoreFile` can bring it back until
+    /// `EmptyTrash` runs. Same layout as `DeleteFile`, which deletes immediately.
+    TrashFile {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+        file_id: u8,
+    },
+    RestoreFile {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
         name: [u8; 128],
         file_id: u8,
     },
+    /// Permanently deletes every trashed file; the only request without a name.
+    EmptyTrash {},
+    /// `ReadFile` limited to `length` bytes starting at `offset`; `length` must
+    /// be in `1..=MAX_READ`. Unlike `ReadRange` it carries no `min_sequence`.
+    ReadFileRange {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+        file_id: u8,
+        offset: u64,
+        length: u32,
+    },
+    /// Reserves writes to bucket `name` for the sending client for the next
+    /// `ttl_slots` slots, at most `MAX_LEASE_SLOTS`, renewing its own lease. Settled by the program in
+    /// `SendRequest` and never stored for the agent.
+    AcquireBucketLease {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+        ttl_slots: u32,
+    },
+    /// Gives up the sending client's lease on bucket `name`, or clears a lease
+    /// that has expired. Settled by the program like `AcquireBucketLease`.
+    ReleaseBucketLease {
+        #[cfg_attr(feature = "serde", serde(with = "crate::state::name_serde"))]
+        name: [u8; 128],
+    },
 }
 
+/// Account lists below are enforced through `BitokuInstructions::accounts`.
 #[derive(BorshSerialize,BorshDeserialize,Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[rustfmt::skip]
 pub enum BitokuInstructions {
     ///0. `[signer]` fee_payer account
     /// 1. `[writable]` bookkeeper PDA account
-    /// 2.`[]` system_program account
-    /// 3.`[]` sys_var program
+    /// 2. `[]` system_program account
+    /// 3. `[]` sys_var program
+    /// 4. `[signer]` admin account, stored as `BookKeeper::admin`
+    /// 5. `[]` the program's ProgramData account, whose upgrade authority
+    ///    must be the fee_payer
     InitBitoku,
     ///0. `[signer]` fee_payer account
     /// 1. `[writable]` bookkeeper PDA account
-    /// 2. `[]` request Pda account
-    /// 3.`[]` system_program account
-    ///  4.`[]` sys_var program
-    RegisterClient,
+    /// 2. `[writable]` request Pda account
+    /// 3. `[]` system_program account
+    /// 4. `[]` sys_var program
+    /// 5. `[writable]` ClientInfo Pda account of the fee_payer, created on its first registration
+    ///    `compact` selects the compact request account layout; it is encoded as an
+    ///    optional trailing byte, so a bare tag registers a full-layout account
+    RegisterClient{compact : bool},
 
     ///0. `[signer]` fee_payer account
     /// 1. `[writable]` bookkeeper PDA account
@@ -84,274 +264,4991 @@ pub enum BitokuInstructions {
     RemoveClient{client_id:u8},
 
     ///0. `[signer]` fee_payer account
-    /// 2. `[writable]` request Pda account
-    SendRequest{client_id : u8,request : Request}
+    /// 1. `[writable]` request Pda account
+    /// 2. `[]` bookkeeper PDA account
+    /// 3. `[]` clock sysvar
+    /// 4. `[]` ClientInfo Pda account of the fee_payer, holding its permissions
+    /// 5. `[]` system_program account, only used to grow a compact request account
+    /// 6. `[]` rent sysvar, only used to grow a compact request account
+    /// 7. `[writable]` lease PDA of `request.bucket()`, only used by write-type and lease requests
+    ///    `submission_ref` is an opaque client reference stored alongside the request;
+    ///    fails with `RequestPending` while the previous request is still `Pending`;
+    ///    a stored request is logged as a `BitokuEvent::RequestSubmitted`
+    SendRequest{client_id : u8,submission_ref : [u8; 32],request : Request},
+
+    ///0. `[]` bookkeeper PDA account
+    GetVersion,
+
+    ///0. `[signer]` authority account, pays for the request PDA
+    /// 1. `[writable]` bookkeeper PDA account
+    /// 2. `[writable]` request Pda account of `target`
+    /// 3. `[]` system_program account
+    /// 4. `[]` sys_var program
+    /// 5. `[writable]` ClientInfo Pda account of `target`, as in `RegisterClient`
+    RegisterClientFor{target : Pubkey},
+
+    ///0. `[signer]` authority account
+    /// 1. `[writable]` bookkeeper PDA account
+    ///    an all-zero `prefix` clears slot `index`
+    SetReservedPrefix{index : u8,prefix : [u8; PREFIX_LEN]},
+
+    ///0. `[signer]` authority or agent account, pays for a new snapshot PDA
+    /// 1. `[]` bookkeeper PDA account
+    /// 2. `[writable]` stats snapshot PDA of the current epoch
+    /// 3. `[]` system_program account
+    /// 4. `[]` clock sysvar
+    /// 5. `[]` sys_var program
+    PublishStats,
+
+    ///0. `[signer]` agent account, the primary agent or one added with `AddAgent`
+    /// 1. `[]` bookkeeper PDA account
+    /// 2. `[writable]` request Pda account of client `client_id`
+    UpdateRequestStatus{client_id : u8,status : RequestStatus},
+
+    ///0. `[signer]` agent account
+    /// 1. `[writable]` bookkeeper PDA account
+    SetSupportedKinds{supported_kinds : u32},
+
+    ///0. `[signer]` agent account
+    /// 1. `[]` bookkeeper PDA account
+    /// 2. `[writable]` request Pda account of client `client_id`
+    ///    only the first `len` bytes of `data` are meaningful; the request is left in
+    ///    `status`, see `RequestStatus::can_respond_with`
+    WriteResponse{client_id : u8,#[cfg_attr(feature = "serde", serde(with = "crate::state::data_serde"))] data : [u8; 512],len : u16,status : RequestStatus},
+
+    ///0. `[signer]` authority account
+    /// 1. `[writable]` bookkeeper PDA account
+    SetAgent{agent : Pubkey},
+
+    ///0. `[signer]` authority account
+    /// 1. `[writable]` bookkeeper PDA account
+    SetNamePolicy{policy : NamePolicyKind},
+
+    ///0. `[signer]` agent account
+    /// 1. `[writable]` bookkeeper PDA account
+    /// 2. `[]` clock sysvar
+    ///    also records the slot as `BookKeeper::last_heartbeat_slot`
+    PublishLoad{max_requests_per_epoch : u32,current_backlog : u16},
+
+    ///0. `[signer]` fee_payer account
+    /// 1. `[writable]` compact request Pda account of the fee_payer
+    /// 2. `[]` rent sysvar
+    ///    shrinks the account to fit its current request and refunds the excess rent
+    ShrinkRequestAccount,
+
+    ///0. `[signer]` authority account
+    /// 1. `[writable]` bookkeeper PDA account
+    ///    hands control of the bookkeeper over to `new_authority`
+    SetAuthority{new_authority : Pubkey},
+
+    ///0. `[signer]` authority account
+    /// 1. `[writable]` bookkeeper PDA account
+    /// 2. `[writable]` request Pda account of `owner`
+    /// 3. `[]` owner account, only its key is read
+    /// 4. `[writable]` destination of the request account's lamports
+    ///    `RemoveClient` without the owner's signature, for owners who lost their key
+    ForceRemoveClient{client_id : u8},
+
+    ///0. `[signer]` authority account
+    /// 1. `[writable]` bookkeeper PDA account
+    ///    while paused, `RegisterClient` and `SendRequest` fail; `RemoveClient` still works
+    SetPaused{paused : bool},
+
+    ///0. `[signer]` relayer account, pays for the transaction and any account growth
+    /// 1. `[]` instructions sysvar
+    /// 2. `[writable]` request Pda account of `signer`
+    /// 3. `[]` bookkeeper PDA account
+    /// 4. `[]` clock sysvar
+    /// 5. `[]` ClientInfo Pda account of `signer`
+    /// 6. `[]` system_program account, as in `SendRequest`
+    /// 7. `[]` rent sysvar, as in `SendRequest`
+    /// 8. `[writable]` lease PDA of `request.bucket()`, as in `SendRequest`
+    ///    `SendRequest` on behalf of `signer`, whose signature of the envelope (see
+    ///    `envelope::signing_message`) the preceding ed25519 program instruction checks
+    SubmitSignedRequest{client_id : u8,nonce : u64,signer : Pubkey,#[cfg_attr(feature = "serde", serde(with = "crate::state::data_serde"))] signature : [u8; 64],request : Request},
+
+    ///0. `[signer]` fee_payer account
+    /// 1. `[writable]` request Pda account of the fee_payer
+    ///    withdraws a request the agent hasn't picked up yet; it is left `Cancelled`, overwritten by the `CANCELLED_TAG` sentinel
+    CancelRequest{client_id : u8},
+
+    ///0. `[signer]` authority account
+    /// 1. `[writable]` bookkeeper PDA account
+    ///    registers `pubkey` as an additional agent, see `BookKeeper::agents`
+    AddAgent{pubkey : Pubkey},
+
+    ///0. `[signer]` authority account
+    /// 1. `[writable]` bookkeeper PDA account
+    ///    removes `pubkey` from the additional agents
+    RemoveAgent{pubkey : Pubkey},
+
+    ///0. `[signer]` agent account
+    /// 1. `[]` bookkeeper PDA account
+    /// 2. `[writable]` request Pda account of client `client_id`
+    ///    moves a `Pending` request to `Processing` once an agent has picked it up;
+    ///    any other `status` fails with `InvalidStatusTransition`
+    AcknowledgeRequest{client_id : u8,status : RequestStatus},
+
+    ///accounts as in `SendRequest`
+    /// `SendRequest` that replaces the previous request even while it is `Pending`
+    OverwriteRequest{client_id : u8,submission_ref : [u8; 32],request : Request},
+
+    ///0. `[signer]` authority account
+    /// 1. `[]` bookkeeper PDA account
+    /// 2. `[writable]` request Pda account of client `client_id`
+    ///    clears `RequestData::requests_this_epoch`, letting a client that reached
+    ///    `BookKeeper::max_client_requests_per_epoch` send again
+    ResetEpochCounter{client_id : u8},
+
+    ///0. `[signer]` admin account
+    /// 1. `[]` bookkeeper PDA account
+    /// 2. `[]` request Pda account of client `client_id`
+    /// 3. `[writable]` ClientInfo Pda account of the same client
+    ///    replaces `ClientInfo::permissions`; bits outside `ALL_PERMISSIONS` are rejected
+    ///    and any signer but the admin fails with `InvalidAccount`
+    SetClientPermissions{client_id : u8,permissions : u8},
+
+    ///0. `[signer]` fee_payer account, pays for the growth
+    /// 1. `[writable]` bookkeeper PDA or the fee_payer's request Pda account
+    /// 2. `[]` system_program account
+    /// 3. `[]` rent sysvar
+    /// 4. `[writable]` the fee_payer's ClientInfo Pda account, created with
+    ///    `ALL_PERMISSIONS` when migrating a request account that has none
+    /// 5. `[]` the program's ProgramData account
+    ///    rewrites an account from an older layout into the current one,
+    ///    filling the fields it lacks with their defaults; the first release's
+    ///    bookkeeper had no authority, so the fee_payer becomes its authority,
+    ///    agent and admin, and must be the program's upgrade authority
+    MigrateAccount,
+
+    ///0. `[signer]` admin account
+    /// 1. `[writable]` bookkeeper PDA account
+    ///    hands `BookKeeper::admin` over to `new_admin`; any other signer fails
+    ///    with `InvalidAccount`
+    UpdateAdmin{new_admin : Pubkey},
 }
 
 impl BitokuInstructions {
+    /// Decodes the Borsh encoding of an instruction, falling back to the legacy
+    /// decoder for input Borsh rejects when built with `legacy-wire`.
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-        let (tag, rest) = input.split_first().ok_or(InvalidInstructionData)?;
-
-        let (_, request): (&u8, &[u8]);
-        if *tag == 3 {
-            (_, request) = rest.split_first().ok_or(InvalidInstructionData)?;
-        } else {
-            request = &[0u8];
+        let tag = *input.first().ok_or(InvalidInstructionData)?;
+        if tag as usize >= INSTRUCTIONS.len() {
+            return Err(InvalidInstruction.into());
+        }
+        match Self::try_from_slice(input) {
+            Ok(instruction) => {
+                instruction.check_fields()?;
+                Ok(instruction)
+            }
+            #[cfg(feature = "legacy-wire")]
+            Err(_) => legacy_wire::unpack_instruction(input),
+            #[cfg(not(feature = "legacy-wire"))]
+            Err(_) => Err(InvalidInstructionData.into()),
         }
-        Ok(match tag {
-            0 => Self::InitBitoku {},
-            1 => Self::RegisterClient {},
-            2 => Self::RemoveClient {
-                client_id: unpack_client_id(rest)?,
-            },
-            3 => Self::SendRequest {
-                client_id: unpack_client_id(rest)?,
-                request: unpack_request(request)?,
-            },
-            _ => return Err(InvalidInstruction.into()),
-        })
     }
 
-    pub fn pack(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(size_of::<Self>());
+    //the checks Borsh can't express, failing as the legacy decoder does
+    pub(crate) fn check_fields(&self) -> Result<(), ProgramError> {
         match self {
-            Self::InitBitoku => {
-                buf.push(0);
+            Self::SendRequest { request, .. }
+            | Self::SubmitSignedRequest { request, .. }
+            | Self::OverwriteRequest { request, .. } => request.check_fields(),
+            Self::SetReservedPrefix { index, .. } if *index as usize >= RESERVED_PREFIXES => {
+                Err(InvalidInstructionData.into())
             }
-            Self::RegisterClient => {
-                buf.push(1);
+            Self::WriteResponse { len, .. } if *len > 512 => Err(InvalidInstructionData.into()),
+            Self::SetClientPermissions { permissions, .. }
+                if permissions & !ALL_PERMISSIONS != 0 =>
+            {
+                Err(InvalidInstructionData.into())
             }
+            _ => Ok(()),
+        }
+    }
 
-            Self::RemoveClient { client_id } => {
-                buf.push(2);
-                buf.extend_from_slice(&client_id.to_le_bytes());
-            }
-            Self::SendRequest { request, client_id } => {
-                buf.push(3);
-                buf.extend_from_slice(&client_id.to_le_bytes());
-                match request {
-                    Request::CreateBucket { name } => {
-                        buf.push(0);
-                        buf.extend_from_slice(name);
-                    }
-                    Request::CreateFile { name, data } => {
-                        buf.push(1);
-                        buf.extend_from_slice(name);
-                        buf.extend_from_slice(data);
-                    }
-                    Request::WriteFile {
-                        name,
-                        file_id,
-                        data,
-                    } => {
-                        buf.push(2);
-                        buf.extend_from_slice(name);
-                        buf.extend_from_slice(&file_id.to_le_bytes());
-                        buf.extend_from_slice(data);
-                    }
-                    Request::CloseFile { name, file_id } => {
-                        buf.push(3);
-                        buf.extend_from_slice(name);
-                        buf.extend_from_slice(&file_id.to_le_bytes());
-                    }
-                    Request::DeleteFile { name, file_id } => {
-                        buf.push(4);
-                        buf.extend_from_slice(name);
-                        buf.extend_from_slice(&file_id.to_le_bytes());
-                    }
-                    Request::SetPosition {
-                        name,
-                        file_id,
-                        position,
-                    } => {
-                        buf.push(5);
-                        buf.extend_from_slice(name);
-                        buf.extend_from_slice(&file_id.to_le_bytes());
-                        buf.extend_from_slice(&position.to_le_bytes())
-                    }
-                    Request::OpenFile { name, file_id } => {
-                        buf.push(6);
-                        buf.extend_from_slice(name);
-                        buf.extend_from_slice(&file_id.to_le_bytes());
-                    }
-                    Request::ReadFile { name, file_id } => {
-                        buf.push(7);
-                        buf.extend_from_slice(name);
-                        buf.extend_from_slice(&file_id.to_le_bytes());
-                    }
-                }
-            }
-        };
-        buf
+    /// Accounts this instruction expects, in order.
+    pub fn accounts(&self) -> &'static [AccountSpec] {
+        match self {
+            Self::InitBitoku => INIT_BITOKU_ACCOUNTS,
+            Self::RegisterClient { .. } => REGISTER_CLIENT_ACCOUNTS,
+            Self::RemoveClient { .. } => REMOVE_CLIENT_ACCOUNTS,
+            Self::SendRequest { .. } => SEND_REQUEST_ACCOUNTS,
+            Self::GetVersion => GET_VERSION_ACCOUNTS,
+            Self::RegisterClientFor { .. } => REGISTER_CLIENT_FOR_ACCOUNTS,
+            Self::SetReservedPrefix { .. } => SET_RESERVED_PREFIX_ACCOUNTS,
+            Self::PublishStats => PUBLISH_STATS_ACCOUNTS,
+            Self::UpdateRequestStatus { .. } => UPDATE_REQUEST_STATUS_ACCOUNTS,
+            Self::SetSupportedKinds { .. } => SET_SUPPORTED_KINDS_ACCOUNTS,
+            Self::WriteResponse { .. } => WRITE_RESPONSE_ACCOUNTS,
+            Self::SetAgent { .. } => SET_AGENT_ACCOUNTS,
+            Self::SetNamePolicy { .. } => SET_NAME_POLICY_ACCOUNTS,
+            Self::PublishLoad { .. } => PUBLISH_LOAD_ACCOUNTS,
+            Self::ShrinkRequestAccount => SHRINK_REQUEST_ACCOUNT_ACCOUNTS,
+            Self::SetAuthority { .. } => SET_AUTHORITY_ACCOUNTS,
+            Self::ForceRemoveClient { .. } => FORCE_REMOVE_CLIENT_ACCOUNTS,
+            Self::SetPaused { .. } => SET_PAUSED_ACCOUNTS,
+            Self::SubmitSignedRequest { .. } => SUBMIT_SIGNED_REQUEST_ACCOUNTS,
+            Self::CancelRequest { .. } => CANCEL_REQUEST_ACCOUNTS,
+            Self::AddAgent { .. } => ADD_AGENT_ACCOUNTS,
+            Self::RemoveAgent { .. } => REMOVE_AGENT_ACCOUNTS,
+            Self::AcknowledgeRequest { .. } => ACKNOWLEDGE_REQUEST_ACCOUNTS,
+            Self::ResetEpochCounter { .. } => RESET_EPOCH_COUNTER_ACCOUNTS,
+            Self::SetClientPermissions { .. } => SET_CLIENT_PERMISSIONS_ACCOUNTS,
+            Self::MigrateAccount => MIGRATE_ACCOUNT_ACCOUNTS,
+            Self::UpdateAdmin { .. } => UPDATE_ADMIN_ACCOUNTS,
+            Self::OverwriteRequest { .. } => SEND_REQUEST_ACCOUNTS,
+        }
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = [0u8; MAX_PACKED_LEN];
+        let len = self
+            .pack_into(&mut buf)
+            .expect("MAX_PACKED_LEN fits every instruction");
+        buf[..len].to_vec()
+    }
+
+    /// Writes the instruction into `buf` and returns the number of bytes used.
+    /// A buffer of `MAX_PACKED_LEN` bytes fits every instruction.
+    pub fn pack_into(&self, buf: &mut [u8]) -> Result<usize, BitokuError> {
+        serialize_into(self, buf)
     }
 }
 
-pub fn unpack_request(input: &[u8]) -> Result<Request, ProgramError> {
-    let (req, data) = input.split_first().ok_or(InvalidInstructionData)?;
+//Borsh encodes `value` into `buf`, returning the number of bytes used
+fn serialize_into(value: &impl BorshSerialize, buf: &mut [u8]) -> Result<usize, BitokuError> {
+    let capacity = buf.len();
+    let mut writer = buf;
+    value.serialize(&mut writer).map_err(|_| BufferTooSmall)?;
+    Ok(capacity - writer.len())
+}
 
-    let name = unpack_name(data)?;
+/// Longest packed instruction: a `SubmitSignedRequest` carrying a `WriteAt`.
+pub const MAX_PACKED_LEN: usize = max_len(INSTRUCTIONS);
 
-    Ok(match req {
-        0 => self::Request::CreateBucket { name },
-        1 => self::Request::CreateFile {
-            name,
-            data: unpack_data(data)?,
-        },
-        2 => self::Request::WriteFile {
-            name,
-            file_id: unpack_file_id(data)?,
-            data: unpack_data(data)?,
-        },
-        3 => self::Request::CloseFile {
-            name,
-            file_id: unpack_file_id(data)?,
-        },
-        4 => self::Request::DeleteFile {
-            name,
-            file_id: unpack_file_id(data)?,
-        },
-        5 => self::Request::SetPosition {
-            name,
-            file_id: unpack_file_id(data)?,
-            position: unpack_position(data)?,
-        },
-        6 => self::Request::OpenFile {
-            name,
-            file_id: unpack_file_id(data)?,
-        },
-        7 => self::Request::ReadFile {
-            name,
-            file_id: unpack_file_id(data)?,
-        },
-        _ => return Err(InvalidInstruction.into()),
-    })
+/// Writes `request` into `buf` and returns the number of bytes used.
+pub(crate) fn pack_request(request: &Request, buf: &mut [u8]) -> Result<usize, BitokuError> {
+    serialize_into(request, buf)
 }
 
-fn unpack_client_id(input: &[u8]) -> Result<u8, ProgramError> {
-    let id = input
-        .get(..1)
-        .and_then(|slice| slice.try_into().ok())
-        .map(u8::from_be_bytes)
-        .ok_or(InvalidClientId)?;
-    Ok(id)
+/// Unpacks a request from the fixed-size area of a `RequestData` account. The
+/// area is sized for `WriteAt`, so shorter requests are followed by zero padding.
+/// Padding that isn't zero is rejected, as it means the tag no longer matches the
+/// request that was stored.
+pub(crate) fn unpack_stored_request(area: &[u8]) -> Result<Request, ProgramError> {
+    let mut padding = area;
+    let request =
+        Request::deserialize(&mut padding).map_err(|_| ProgramError::InvalidAccountData)?;
+    if padding.iter().any(|&byte| byte != 0) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    request.check_fields()?;
+    Ok(request)
 }
 
-fn unpack_name(input: &[u8]) -> Result<[u8; 128], ProgramError> {
-    let name = input
-        .get(..128)
-        .and_then(|slice| slice.try_into().ok())
-        .unwrap();
-    Ok(name)
+//`data` zero padded to a request's data field
+fn data_field(data: &[u8]) -> Result<[u8; 512], BitokuError> {
+    if data.len() > CHUNK_LEN as usize {
+        return Err(DataTooLarge);
+    }
+    let mut field = [0u8; 512];
+    field[..data.len()].copy_from_slice(data);
+    Ok(field)
+}
+
+/// Decodes the Borsh encoding of a request, falling back to the legacy decoder
+/// for input Borsh rejects when built with `legacy-wire`.
+pub fn unpack_request(input: &[u8]) -> Result<Request, ProgramError> {
+    let tag = *input.first().ok_or(InvalidInstructionData)?;
+    if tag as usize >= REQUESTS.len() {
+        return Err(InvalidInstruction.into());
+    }
+    match Request::try_from_slice(input) {
+        Ok(request) => {
+            request.check_fields()?;
+            Ok(request)
+        }
+        #[cfg(feature = "legacy-wire")]
+        Err(_) => legacy_wire::unpack_request(input),
+        #[cfg(not(feature = "legacy-wire"))]
+        Err(_) => Err(InvalidInstructionData.into()),
+    }
 }
 
-fn unpack_file_id(input: &[u8]) -> Result<u8, ProgramError> {
-    let id = input
-        .get(128..129)
-        .and_then(|slice| slice.try_into().ok())
-        .map(u8::from_be_bytes)
-        .ok_or(InvalidFileId)?;
-    Ok(id)
+//the offset of an encoded `ReadFileRange` and the bytes after it
+fn read_range_offset(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
+    let (&tag, mut input) = input.split_first().ok_or(InvalidInstructionData)?;
+    if tag != 22 {
+        return Err(InvalidInstruction.into());
+    }
+    <[u8; 128]>::deserialize(&mut input).map_err(|_| InvalidInstructionData)?;
+    u8::deserialize(&mut input).map_err(|_| InvalidFileId)?;
+    let offset = u64::deserialize(&mut input).map_err(|_| InvalidPosition)?;
+    Ok((offset, input))
 }
 
-fn unpack_data(input: &[u8]) -> Result<[u8; 512], ProgramError> {
-    let data = input.get(129..).unwrap();
-    let mut padded_data = [0u8; 512];
-    padded_data[..data.len()].copy_from_slice(data);
-    Ok(padded_data)
+/// Offset of an encoded `ReadFileRange` (8 LE bytes at 129 after the tag),
+/// read without decoding the rest of the request.
+pub fn unpack_read_range_offset(input: &[u8]) -> Result<u64, ProgramError> {
+    Ok(read_range_offset(input)?.0)
 }
 
-fn unpack_position(input: &[u8]) -> Result<u64, ProgramError> {
-    let position = input
-        .get(129..137)
-        .and_then(|slice| slice.try_into().ok())
-        .map(u64::from_le_bytes)
-        .ok_or(InvalidPosition)?;
-    Ok(position)
+/// Length of an encoded `ReadFileRange` (4 LE bytes at 137 after the tag),
+/// checked as `unpack_request` checks it.
+pub fn unpack_read_range_length(input: &[u8]) -> Result<u32, ProgramError> {
+    let (_, mut input) = read_range_offset(input)?;
+    u32::deserialize(&mut input)
+        .ok()
+        .filter(|&length| length != 0 && length as u64 <= MAX_READ)
+        .ok_or_else(|| InvalidInstructionData.into())
 }
 
 impl Request {
-    pub fn name(&self) -> [u8; 128] {
+    /// Wire tag of the request, as written by `BitokuInstructions::pack`.
+    pub fn kind(&self) -> u8 {
         match self {
-            Request::CreateBucket { name } => *name,
-            Request::CreateFile { name, .. } => *name,
-            Request::WriteFile { name, .. } => *name,
-            Request::DeleteFile { name, .. } => *name,
-            Request::CloseFile { name, .. } => *name,
-            Request::SetPosition { name, .. } => *name,
-            Request::OpenFile { name, .. } => *name,
-            Request::ReadFile { name, .. } => *name,
+            Request::CreateBucket { .. } => 0,
+            Request::CreateFile { .. } => 1,
+            Request::WriteFile { .. } => 2,
+            Request::CloseFile { .. } => 3,
+            Request::DeleteFile { .. } => 4,
+            Request::SetPosition { .. } => 5,
+            Request::OpenFile { .. } => 6,
+            Request::ReadFile { .. } => 7,
+            Request::DeleteBucket { .. } => 8,
+            Request::RenameFile { .. } => 9,
+            Request::CopyFile { .. } => 10,
+            Request::StatFile { .. } => 11,
+            Request::TruncateFile { .. } => 12,
+            Request::ListFiles { .. } => 13,
+            Request::AppendFile { .. } => 14,
+            Request::WriteByReference { .. } => 15,
+            Request::WriteAt { .. } => 16,
+            Request::ReadRange { .. } => 17,
+            Request::FileExists { .. } => 18,
+            Request::TrashFile { .. } => 19,
+            Request::RestoreFile { .. } => 20,
+            Request::EmptyTrash {} => 21,
+            Request::ReadFileRange { .. } => 22,
+            Request::AcquireBucketLease { .. } => 23,
+            Request::ReleaseBucketLease { .. } => 24,
         }
     }
-}
 
-pub fn register_client(
-    fee_payer: Pubkey,
-    bookkeeper: Pubkey,
-    request: Pubkey,
-    system_program: Pubkey,
-    rent_sys_var: Pubkey,
-    bitoku_agnet_program: Pubkey,
-) -> Result<Instruction, ProgramError> {
-    let data = BitokuInstructions::RegisterClient {}.pack();
+    /// The name the request targets; `CopyFile` reports its source name and
+    /// `EmptyTrash` has none.
+    pub fn name(&self) -> Option<[u8; 128]> {
+        self.name_field().copied()
+    }
 
-    let accounts = vec![
-        AccountMeta::new(fee_payer, true),
-        AccountMeta::new(bookkeeper, false),
-        AccountMeta::new(request, false),
-        AccountMeta::new_readonly(system_program, false),
-        AccountMeta::new_readonly(rent_sys_var, false),
-    ];
+    /// `name` as text, up to its zero padding; `EmptyTrash` reads as "".
+    pub fn name_str(&self) -> Result<&str, Utf8Error> {
+        match self.name_field() {
+            Some(name) => name_to_str(name),
+            None => Ok(""),
+        }
+    }
 
-    Ok(Instruction {
-        program_id: bitoku_agnet_program,
-        accounts,
-        data,
-    })
-}
+    fn name_field(&self) -> Option<&[u8; 128]> {
+        match self {
+            Request::CreateBucket { name }
+            | Request::CreateFile { name, .. }
+            | Request::WriteFile { name, .. }
+            | Request::DeleteFile { name, .. }
+            | Request::CloseFile { name, .. }
+            | Request::SetPosition { name, .. }
+            | Request::OpenFile { name, .. }
+            | Request::ReadFile { name, .. }
+            | Request::DeleteBucket { name }
+            | Request::RenameFile { name, .. }
+            | Request::CopyFile { src_name: name, .. }
+            | Request::StatFile { name, .. }
+            | Request::TruncateFile { name, .. }
+            | Request::ListFiles { name, .. }
+            | Request::AppendFile { name, .. }
+            | Request::WriteByReference { name, .. }
+            | Request::WriteAt { name, .. }
+            | Request::ReadRange { name, .. }
+            | Request::FileExists { name, .. }
+            | Request::TrashFile { name, .. }
+            | Request::RestoreFile { name, .. }
+            | Request::ReadFileRange { name, .. }
+            | Request::AcquireBucketLease { name, .. }
+            | Request::ReleaseBucketLease { name } => Some(name),
+            Request::EmptyTrash {} => None,
+        }
+    }
 
-pub fn remove_client(
-    fee_payer: Pubkey,
-    bookkeeper: Pubkey,
-    request: Pubkey,
-    bitoku_agnet_program: Pubkey,
-    client_id: u8,
-) -> Result<Instruction, ProgramError> {
-    let data = BitokuInstructions::RemoveClient { client_id }.pack();
+    /// The request with tag `tag`, built from the fields most requests share, for
+    /// bindings that can't construct the enum. `name` fills the first name field
+    /// and `file_id` every file id; `data` is zero padded to 512 bytes and only
+    /// allowed for requests that carry data. Every other field is zero, so
+    /// requests that reject a zero there, such as `ReadRange`, can't be built
+    /// this way.
+    pub fn from_parts(tag: u8, name: &str, file_id: u8, data: &[u8]) -> Result<Self, ProgramError> {
+        let variant = REQUESTS.get(tag as usize).ok_or(InvalidInstruction)?;
+        if data.len() > CHUNK_LEN as usize {
+            return Err(DataTooLarge.into());
+        }
+        let mut name = Some(name);
+        let mut data = Some(data);
 
-    let accounts = vec![
-        AccountMeta::new(fee_payer, true),
-        AccountMeta::new(bookkeeper, false),
-        AccountMeta::new(request, false),
-    ];
+        let mut encoded = vec![tag];
+        for field in variant.fields {
+            match field.kind {
+                FieldKind::Name if name.is_some() => {
+                    encoded.extend_from_slice(&str_to_name(name.take().unwrap())?)
+                }
+                FieldKind::U8 if field.name.ends_with("file_id") => encoded.push(file_id),
+                FieldKind::Data => {
+                    let data = data.take().unwrap_or_default();
+                    encoded.extend_from_slice(data);
+                    encoded.resize(encoded.len() + CHUNK_LEN as usize - data.len(), 0);
+                }
+                kind => encoded.resize(encoded.len() + kind.size().unwrap_or(0), 0),
+            }
+        }
+        if matches!(data, Some(data) if !data.is_empty()) {
+            return Err(InvalidInstructionData.into());
+        }
+        unpack_request(&encoded)
+    }
 
-    Ok(Instruction {
-        program_id: bitoku_agnet_program,
-        accounts,
-        data,
-    })
-}
+    pub fn create_bucket(name: &str) -> Result<Self, BitokuError> {
+        Ok(Request::CreateBucket {
+            name: str_to_name(name)?,
+        })
+    }
 
-pub fn send_request(
-    fee_payer: Pubkey,
-    request: Pubkey,
-    bitoku_agnet_program: Pubkey,
-    client_id: u8,
-    req: Request,
-) -> Result<Instruction, ProgramError> {
-    let data = BitokuInstructions::SendRequest {
-        client_id,
-        request: req,
+    pub fn delete_bucket(name: &str) -> Result<Self, BitokuError> {
+        Ok(Request::DeleteBucket {
+            name: str_to_name(name)?,
+        })
     }
-    .pack();
 
-    let accounts = vec![
-        AccountMeta::new(fee_payer, true),
-        AccountMeta::new(request, false),
-    ];
+    /// A `CreateFile` that isn't sparse, holding up to 512 bytes of `data`.
+    pub fn create_file(name: &str, data: &[u8]) -> Result<Self, BitokuError> {
+        Ok(Request::CreateFile {
+            name: str_to_name(name)?,
+            sparse: false,
+            data: data_field(data)?,
+        })
+    }
 
-    Ok(Instruction {
-        program_id: bitoku_agnet_program,
-        accounts,
-        data,
-    })
+    pub fn write_file(
+        name: &str,
+        file_id: u8,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<Self, BitokuError> {
+        Ok(Request::WriteFile {
+            name: str_to_name(name)?,
+            file_id,
+            offset,
+            data: data_field(data)?,
+        })
+    }
+
+    pub fn append_file(name: &str, file_id: u8, data: &[u8]) -> Result<Self, BitokuError> {
+        Ok(Request::AppendFile {
+            name: str_to_name(name)?,
+            file_id,
+            data: data_field(data)?,
+        })
+    }
+
+    /// A `ReadFile` without a `min_sequence`.
+    pub fn read_file(name: &str, file_id: u8) -> Result<Self, BitokuError> {
+        Ok(Request::ReadFile {
+            name: str_to_name(name)?,
+            file_id,
+            min_sequence: 0,
+        })
+    }
+
+    pub fn close_file(name: &str, file_id: u8) -> Result<Self, BitokuError> {
+        Ok(Request::CloseFile {
+            name: str_to_name(name)?,
+            file_id,
+        })
+    }
+
+    pub fn delete_file(name: &str, file_id: u8) -> Result<Self, BitokuError> {
+        Ok(Request::DeleteFile {
+            name: str_to_name(name)?,
+            file_id,
+        })
+    }
+
+    pub fn rename_file(name: &str, file_id: u8, new_name: &str) -> Result<Self, BitokuError> {
+        Ok(Request::RenameFile {
+            name: str_to_name(name)?,
+            file_id,
+            new_name: str_to_name(new_name)?,
+        })
+    }
+
+    pub fn copy_file(src_name: &str, src_file_id: u8, dst_name: &str) -> Result<Self, BitokuError> {
+        Ok(Request::CopyFile {
+            src_name: str_to_name(src_name)?,
+            src_file_id,
+            dst_name: str_to_name(dst_name)?,
+        })
+    }
+
+    /// Whether the request changes stored data or the bucket/file namespace.
+    pub fn is_mutation(&self) -> bool {
+        match self {
+            Request::CreateBucket { .. }
+            | Request::CreateFile { .. }
+            | Request::WriteFile { .. }
+            | Request::DeleteFile { .. }
+            | Request::DeleteBucket { .. }
+            | Request::RenameFile { .. }
+            | Request::CopyFile { .. }
+            | Request::TruncateFile { .. }
+            | Request::AppendFile { .. }
+            | Request::WriteByReference { .. }
+            | Request::WriteAt { .. }
+            | Request::TrashFile { .. }
+            | Request::RestoreFile { .. }
+            | Request::EmptyTrash {} => true,
+            Request::CloseFile { .. }
+            | Request::SetPosition { .. }
+            | Request::OpenFile { .. }
+            | Request::ReadFile { .. }
+            | Request::StatFile { .. }
+            | Request::ListFiles { .. }
+            | Request::ReadRange { .. }
+            | Request::FileExists { .. }
+            | Request::ReadFileRange { .. }
+            | Request::AcquireBucketLease { .. }
+            | Request::ReleaseBucketLease { .. } => false,
+        }
+    }
+
+    /// Whether the request only queries stored data or metadata.
+    pub fn is_readonly(&self) -> bool {
+        match self {
+            Request::ReadFile { .. }
+            | Request::StatFile { .. }
+            | Request::ListFiles { .. }
+            | Request::ReadRange { .. }
+            | Request::FileExists { .. }
+            | Request::ReadFileRange { .. } => true,
+            Request::CreateBucket { .. }
+            | Request::CreateFile { .. }
+            | Request::WriteFile { .. }
+            | Request::CloseFile { .. }
+            | Request::DeleteFile { .. }
+            | Request::SetPosition { .. }
+            | Request::OpenFile { .. }
+            | Request::DeleteBucket { .. }
+            | Request::RenameFile { .. }
+            | Request::CopyFile { .. }
+            | Request::TruncateFile { .. }
+            | Request::AppendFile { .. }
+            | Request::WriteByReference { .. }
+            | Request::WriteAt { .. }
+            | Request::TrashFile { .. }
+            | Request::RestoreFile { .. }
+            | Request::EmptyTrash {}
+            | Request::AcquireBucketLease { .. }
+            | Request::ReleaseBucketLease { .. } => false,
+        }
+    }
+
+    /// The `PERMISSION_*` bit a client needs in `ClientInfo::permissions` to
+    /// send the request; 0 for `ReleaseBucketLease`, which any client may send
+    /// so that a lease is never stuck with a client that lost write access.
+    pub fn required_permission(&self) -> u8 {
+        match self {
+            Request::CreateBucket { .. }
+            | Request::CreateFile { .. }
+            | Request::CopyFile { .. } => PERMISSION_CREATE,
+            Request::WriteFile { .. }
+            | Request::RenameFile { .. }
+            | Request::TruncateFile { .. }
+            | Request::AppendFile { .. }
+            | Request::WriteByReference { .. }
+            | Request::WriteAt { .. }
+            | Request::RestoreFile { .. }
+            | Request::AcquireBucketLease { .. } => PERMISSION_WRITE,
+            Request::DeleteFile { .. }
+            | Request::DeleteBucket { .. }
+            | Request::TrashFile { .. }
+            | Request::EmptyTrash {} => PERMISSION_DELETE,
+            Request::CloseFile { .. }
+            | Request::SetPosition { .. }
+            | Request::OpenFile { .. }
+            | Request::ReadFile { .. }
+            | Request::StatFile { .. }
+            | Request::ListFiles { .. }
+            | Request::ReadRange { .. }
+            | Request::FileExists { .. }
+            | Request::ReadFileRange { .. } => PERMISSION_READ,
+            Request::ReleaseBucketLease { .. } => 0,
+        }
+    }
+
+    /// The file the request targets; `CopyFile` reports its source file.
+    pub fn file_id(&self) -> Option<u8> {
+        match self {
+            Request::WriteFile { file_id, .. }
+            | Request::CloseFile { file_id, .. }
+            | Request::DeleteFile { file_id, .. }
+            | Request::SetPosition { file_id, .. }
+            | Request::OpenFile { file_id, .. }
+            | Request::ReadFile { file_id, .. }
+            | Request::RenameFile { file_id, .. }
+            | Request::StatFile { file_id, .. }
+            | Request::TruncateFile { file_id, .. }
+            | Request::AppendFile { file_id, .. }
+            | Request::WriteByReference { file_id, .. }
+            | Request::WriteAt { file_id, .. }
+            | Request::ReadRange { file_id, .. }
+            | Request::FileExists { file_id, .. }
+            | Request::TrashFile { file_id, .. }
+            | Request::RestoreFile { file_id, .. }
+            | Request::ReadFileRange { file_id, .. } => Some(*file_id),
+            Request::CopyFile { src_file_id, .. } => Some(*src_file_id),
+            Request::CreateBucket { .. }
+            | Request::CreateFile { .. }
+            | Request::DeleteBucket { .. }
+            | Request::ListFiles { .. }
+            | Request::EmptyTrash {}
+            | Request::AcquireBucketLease { .. }
+            | Request::ReleaseBucketLease { .. } => None,
+        }
+    }
+
+    pub fn position(&self) -> Option<u64> {
+        match self {
+            Request::SetPosition { position, .. } => Some(*position),
+            _ => None,
+        }
+    }
+
+    pub fn data(&self) -> Option<&[u8; 512]> {
+        match self {
+            Request::CreateFile { data, .. }
+            | Request::WriteFile { data, .. }
+            | Request::AppendFile { data, .. }
+            | Request::WriteAt { data, .. } => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            Request::WriteFile { offset, .. }
+            | Request::WriteAt { offset, .. }
+            | Request::ReadRange { offset, .. }
+            | Request::ReadFileRange { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Request::ReadRange { len, .. } => Some(*len),
+            Request::ReadFileRange { length, .. } => Some(*length as u64),
+            _ => None,
+        }
+    }
+
+    /// Whether the request takes or gives up a `BucketLease`.
+    pub fn is_lease(&self) -> bool {
+        matches!(
+            self,
+            Request::AcquireBucketLease { .. } | Request::ReleaseBucketLease { .. }
+        )
+    }
+
+    /// The bucket whose lease the request is checked against: that of
+    /// `dst_name` for `CopyFile`, of `name` otherwise.
+    pub fn bucket(&self) -> Option<[u8; 128]> {
+        self.dst_name()
+            .or_else(|| self.name())
+            .map(|name| bucket_name(&name))
+    }
+
+    /// Whether the request reads a byte range rather than the whole file.
+    pub fn is_range_request(&self) -> bool {
+        matches!(
+            self,
+            Request::ReadRange { .. } | Request::ReadFileRange { .. }
+        )
+    }
+
+    pub fn min_sequence(&self) -> Option<u64> {
+        match self {
+            Request::ReadFile { min_sequence, .. }
+            | Request::StatFile { min_sequence, .. }
+            | Request::ReadRange { min_sequence, .. } => Some(*min_sequence),
+            _ => None,
+        }
+    }
+
+    pub fn new_length(&self) -> Option<u64> {
+        match self {
+            Request::TruncateFile { new_length, .. } => Some(*new_length),
+            _ => None,
+        }
+    }
+
+    pub fn new_name(&self) -> Option<[u8; 128]> {
+        match self {
+            Request::RenameFile { new_name, .. } => Some(*new_name),
+            _ => None,
+        }
+    }
+
+    pub fn dst_name(&self) -> Option<[u8; 128]> {
+        match self {
+            Request::CopyFile { dst_name, .. } => Some(*dst_name),
+            _ => None,
+        }
+    }
+
+    /// Checks every name the request carries against `policy`, as `SendRequest` does.
+    pub fn check_names(&self, policy: &dyn NamePolicy) -> Result<(), ProgramError> {
+        let name = self.name();
+        if name.is_some_and(|name| name[0] == 0) {
+            return Err(EmptyName.into());
+        }
+        for name in [name, self.new_name(), self.dst_name()]
+            .into_iter()
+            .flatten()
+        {
+            if !policy.validate(&name) {
+                return Err(InvalidName.into());
+            }
+        }
+        Ok(())
+    }
+
+    //the checks Borsh can't express, failing as the legacy decoder does
+    pub(crate) fn check_fields(&self) -> Result<(), ProgramError> {
+        match *self {
+            Request::ReadRange { offset, len, .. } => {
+                if len == 0 || len > MAX_READ {
+                    return Err(InvalidReadLength.into());
+                }
+                offset.checked_add(len).ok_or(InvalidPosition)?;
+            }
+            Request::ReadFileRange { offset, length, .. } => {
+                if length == 0 || length as u64 > MAX_READ {
+                    return Err(InvalidInstructionData.into());
+                }
+                offset.checked_add(length as u64).ok_or(InvalidPosition)?;
+            }
+            Request::AcquireBucketLease { ttl_slots, .. }
+                if ttl_slots == 0 || ttl_slots > MAX_LEASE_SLOTS =>
+            {
+                return Err(InvalidInstructionData.into())
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    //fields in wire order, read back from the packed request through `REQUESTS`
+    fn display_fields(&self) -> Vec<(&'static str, FieldValue)> {
+        let mut buf = [0u8; ENCODED_REQUEST_MAX_LEN];
+        let len = pack_request(self, &mut buf).expect("ENCODED_REQUEST_MAX_LEN fits every request");
+        let mut pos = 1;
+        REQUESTS[self.kind() as usize]
+            .fields
+            .iter()
+            .map(|field| {
+                let size = field.kind.size().unwrap_or(len - pos);
+                let bytes = &buf[pos..pos + size];
+                pos += size;
+                let mut number = [0u8; 8];
+                number[..size.min(8)].copy_from_slice(&bytes[..size.min(8)]);
+                let value = match field.kind {
+                    FieldKind::Name => {
+                        let name = bytes.try_into().unwrap();
+                        FieldValue::Name(match name_to_str(name) {
+                            Ok(text) => text.to_string(),
+                            Err(_) => String::from_utf8_lossy(name).into_owned(),
+                        })
+                    }
+                    FieldKind::Bool | FieldKind::Flag => FieldValue::Bool(bytes[0] != 0),
+                    FieldKind::I64 => FieldValue::Signed(i64::from_le_bytes(number)),
+                    FieldKind::U8
+                    | FieldKind::Enum(_)
+                    | FieldKind::U16
+                    | FieldKind::U32
+                    | FieldKind::U64 => FieldValue::Number(u64::from_le_bytes(number)),
+                    FieldKind::Data => FieldValue::Data(size),
+                    _ => FieldValue::Hex(bytes.iter().map(|b| format!("{:02x}", b)).collect()),
+                };
+                (field.name, value)
+            })
+            .collect()
+    }
+}
+
+//one field of a request as `Display` and `Debug` show it
+enum FieldValue {
+    Name(String),
+    Bool(bool),
+    Number(u64),
+    Signed(i64),
+    Hex(String),
+    Data(usize),
+}
+
+impl fmt::Debug for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "{:?}", name),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Number(value) => write!(f, "{}", value),
+            Self::Signed(value) => write!(f, "{}", value),
+            Self::Hex(value) => f.write_str(value),
+            Self::Data(len) => write!(f, "[{} bytes]", len),
+        }
+    }
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}(", REQUESTS[self.kind() as usize].name)?;
+        for (i, (name, value)) in self.display_fields().iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            match value {
+                FieldValue::Data(len) => write!(f, "{}_len={}", name, len)?,
+                value => write!(f, "{}={:?}", name, value)?,
+            }
+        }
+        f.write_str(")")
+    }
+}
+
+impl fmt::Debug for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct(REQUESTS[self.kind() as usize].name);
+        for (name, value) in &self.display_fields() {
+            debug.field(name, value);
+        }
+        debug.finish()
+    }
+}
+
+/// The bucket part of `name`, everything before its first `/`, zero padded.
+pub fn bucket_name(name: &[u8; 128]) -> [u8; 128] {
+    let len = name
+        .iter()
+        .position(|&b| b == b'/' || b == 0)
+        .unwrap_or(name.len());
+    let mut bucket = [0u8; 128];
+    bucket[..len].copy_from_slice(&name[..len]);
+    bucket
+}
+
+/// Signer and writable flags an instruction requires of one of its accounts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccountSpec {
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+impl AccountSpec {
+    pub fn meta(&self, pubkey: Pubkey) -> AccountMeta {
+        if self.is_writable {
+            AccountMeta::new(pubkey, self.is_signer)
+        } else {
+            AccountMeta::new_readonly(pubkey, self.is_signer)
+        }
+    }
+}
+
+const SIGNER: AccountSpec = AccountSpec {
+    is_signer: true,
+    is_writable: true,
+};
+const WRITABLE: AccountSpec = AccountSpec {
+    is_signer: false,
+    is_writable: true,
+};
+const READONLY: AccountSpec = AccountSpec {
+    is_signer: false,
+    is_writable: false,
+};
+const READONLY_SIGNER: AccountSpec = AccountSpec {
+    is_signer: true,
+    is_writable: false,
+};
+
+//account lists of each instruction, in the order the processor reads them
+pub const INIT_BITOKU_ACCOUNTS: &[AccountSpec] = &[
+    SIGNER,
+    WRITABLE,
+    READONLY,
+    READONLY,
+    READONLY_SIGNER,
+    READONLY,
+];
+pub const REGISTER_CLIENT_ACCOUNTS: &[AccountSpec] =
+    &[SIGNER, WRITABLE, WRITABLE, READONLY, READONLY, WRITABLE];
+pub const REMOVE_CLIENT_ACCOUNTS: &[AccountSpec] = &[SIGNER, WRITABLE, WRITABLE];
+pub const SEND_REQUEST_ACCOUNTS: &[AccountSpec] = &[
+    SIGNER, WRITABLE, READONLY, READONLY, READONLY, READONLY, READONLY, WRITABLE,
+];
+pub const GET_VERSION_ACCOUNTS: &[AccountSpec] = &[READONLY];
+pub const REGISTER_CLIENT_FOR_ACCOUNTS: &[AccountSpec] =
+    &[SIGNER, WRITABLE, WRITABLE, READONLY, READONLY, WRITABLE];
+pub const SET_RESERVED_PREFIX_ACCOUNTS: &[AccountSpec] = &[SIGNER, WRITABLE];
+pub const PUBLISH_STATS_ACCOUNTS: &[AccountSpec] =
+    &[SIGNER, READONLY, WRITABLE, READONLY, READONLY, READONLY];
+pub const UPDATE_REQUEST_STATUS_ACCOUNTS: &[AccountSpec] = &[SIGNER, READONLY, WRITABLE];
+pub const SET_SUPPORTED_KINDS_ACCOUNTS: &[AccountSpec] = &[SIGNER, WRITABLE];
+pub const WRITE_RESPONSE_ACCOUNTS: &[AccountSpec] = &[SIGNER, READONLY, WRITABLE];
+pub const SET_AGENT_ACCOUNTS: &[AccountSpec] = &[SIGNER, WRITABLE];
+pub const SET_NAME_POLICY_ACCOUNTS: &[AccountSpec] = &[SIGNER, WRITABLE];
+pub const PUBLISH_LOAD_ACCOUNTS: &[AccountSpec] = &[SIGNER, WRITABLE, READONLY];
+pub const SHRINK_REQUEST_ACCOUNT_ACCOUNTS: &[AccountSpec] = &[SIGNER, WRITABLE, READONLY];
+pub const SET_AUTHORITY_ACCOUNTS: &[AccountSpec] = &[SIGNER, WRITABLE];
+pub const FORCE_REMOVE_CLIENT_ACCOUNTS: &[AccountSpec] =
+    &[SIGNER, WRITABLE, WRITABLE, READONLY, WRITABLE];
+pub const SET_PAUSED_ACCOUNTS: &[AccountSpec] = &[SIGNER, WRITABLE];
+pub const SUBMIT_SIGNED_REQUEST_ACCOUNTS: &[AccountSpec] = &[
+    SIGNER, READONLY, WRITABLE, READONLY, READONLY, READONLY, READONLY, READONLY, WRITABLE,
+];
+pub const CANCEL_REQUEST_ACCOUNTS: &[AccountSpec] = &[SIGNER, WRITABLE];
+pub const ADD_AGENT_ACCOUNTS: &[AccountSpec] = &[SIGNER, WRITABLE];
+pub const REMOVE_AGENT_ACCOUNTS: &[AccountSpec] = &[SIGNER, WRITABLE];
+pub const ACKNOWLEDGE_REQUEST_ACCOUNTS: &[AccountSpec] = &[SIGNER, READONLY, WRITABLE];
+pub const RESET_EPOCH_COUNTER_ACCOUNTS: &[AccountSpec] = &[SIGNER, READONLY, WRITABLE];
+pub const SET_CLIENT_PERMISSIONS_ACCOUNTS: &[AccountSpec] = &[SIGNER, READONLY, READONLY, WRITABLE];
+pub const MIGRATE_ACCOUNT_ACCOUNTS: &[AccountSpec] =
+    &[SIGNER, WRITABLE, READONLY, READONLY, WRITABLE, READONLY];
+pub const UPDATE_ADMIN_ACCOUNTS: &[AccountSpec] = &[SIGNER, WRITABLE];
+
+#[cfg(feature = "client")]
+fn account_metas(specs: &[AccountSpec], keys: &[Pubkey]) -> Vec<AccountMeta> {
+    let mut accounts = Vec::with_capacity(specs.len());
+    fill_account_metas(specs, keys, &mut accounts);
+    accounts
+}
+
+#[cfg(feature = "client")]
+fn fill_account_metas(specs: &[AccountSpec], keys: &[Pubkey], accounts: &mut Vec<AccountMeta>) {
+    debug_assert_eq!(specs.len(), keys.len());
+    accounts.clear();
+    accounts.extend(specs.iter().zip(keys).map(|(spec, key)| spec.meta(*key)));
+}
+
+/// Address of the program's `BookKeeper` account.
+#[cfg(feature = "client")]
+pub fn bookkeeper_address(bitoku_agnet_program: &Pubkey) -> (Pubkey, u8) {
+    find_bookkeeper_pda(bitoku_agnet_program)
+}
+
+/// Address of the `ClientInfo` account `RegisterClient` creates for `owner`.
+#[cfg(feature = "client")]
+pub fn client_info_address(bitoku_agnet_program: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    find_client_info_pda(owner, bitoku_agnet_program)
+}
+
+/// Address of the request account `RegisterClient` creates for `owner`.
+#[cfg(feature = "client")]
+pub fn request_address(bitoku_agnet_program: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    find_request_pda(owner, bitoku_agnet_program)
+}
+
+/// Address of the `BucketLease` of `req`'s bucket. Requests without a name get
+/// the lease of the empty bucket name, which the program never reads.
+#[cfg(feature = "client")]
+pub fn lease_address(bitoku_agnet_program: &Pubkey, req: &Request) -> (Pubkey, u8) {
+    find_lease_pda(&req.bucket().unwrap_or([0; 128]), bitoku_agnet_program)
+}
+
+#[cfg(feature = "client")]
+pub fn init_bitoku(
+    fee_payer: Pubkey,
+    bookkeeper: Pubkey,
+    system_program: Pubkey,
+    rent_sys_var: Pubkey,
+    admin: Pubkey,
+    program_data: Pubkey,
+    bitoku_agnet_program: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let (bookkeeper_key, _) = find_bookkeeper_pda(&bitoku_agnet_program);
+    if bookkeeper != bookkeeper_key
+        || program_data != find_program_data_address(&bitoku_agnet_program)
+    {
+        return Err(InvalidAccount.into());
+    }
+
+    let data = BitokuInstructions::InitBitoku {}.pack();
+
+    let accounts = account_metas(
+        INIT_BITOKU_ACCOUNTS,
+        &[
+            fee_payer,
+            bookkeeper,
+            system_program,
+            rent_sys_var,
+            admin,
+            program_data,
+        ],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+/// Like `init_bitoku`, deriving the bookkeeper and ProgramData, using the
+/// system program and rent sysvar ids and making the fee_payer the admin.
+#[cfg(feature = "client")]
+pub fn init_bitoku_auto(
+    fee_payer: Pubkey,
+    bitoku_agnet_program: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    init_bitoku(
+        fee_payer,
+        bookkeeper,
+        system_program::id(),
+        sysvar::rent::id(),
+        fee_payer,
+        find_program_data_address(&bitoku_agnet_program),
+        bitoku_agnet_program,
+    )
+}
+
+#[cfg(feature = "client")]
+pub fn register_client(
+    fee_payer: Pubkey,
+    bookkeeper: Pubkey,
+    request: Pubkey,
+    system_program: Pubkey,
+    rent_sys_var: Pubkey,
+    bitoku_agnet_program: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::RegisterClient { compact: false }.pack();
+
+    let accounts = account_metas(
+        REGISTER_CLIENT_ACCOUNTS,
+        &[
+            fee_payer,
+            bookkeeper,
+            request,
+            system_program,
+            rent_sys_var,
+            client_info_address(&bitoku_agnet_program, &fee_payer).0,
+        ],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+/// Like `register_client`, but the request account is created with the compact
+/// layout, which grows with the stored request instead of reserving the full payload.
+#[cfg(feature = "client")]
+pub fn register_compact_client(
+    fee_payer: Pubkey,
+    bookkeeper: Pubkey,
+    request: Pubkey,
+    system_program: Pubkey,
+    rent_sys_var: Pubkey,
+    bitoku_agnet_program: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::RegisterClient { compact: true }.pack();
+
+    let accounts = account_metas(
+        REGISTER_CLIENT_ACCOUNTS,
+        &[
+            fee_payer,
+            bookkeeper,
+            request,
+            system_program,
+            rent_sys_var,
+            client_info_address(&bitoku_agnet_program, &fee_payer).0,
+        ],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+/// Like `register_client`, deriving every account from `fee_payer`; also returns
+/// the request account so the caller can pass it to `send_request`.
+#[cfg(feature = "client")]
+pub fn register_client_auto(
+    fee_payer: Pubkey,
+    bitoku_agnet_program: Pubkey,
+) -> Result<(Instruction, Pubkey), ProgramError> {
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+    let (request, _) = request_address(&bitoku_agnet_program, &fee_payer);
+
+    let ix = register_client(
+        fee_payer,
+        bookkeeper,
+        request,
+        system_program::id(),
+        sysvar::rent::id(),
+        bitoku_agnet_program,
+    )?;
+
+    Ok((ix, request))
+}
+
+#[cfg(feature = "client")]
+pub fn register_client_for(
+    authority: Pubkey,
+    bookkeeper: Pubkey,
+    request: Pubkey,
+    system_program: Pubkey,
+    rent_sys_var: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    target: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::RegisterClientFor { target }.pack();
+
+    let accounts = account_metas(
+        REGISTER_CLIENT_FOR_ACCOUNTS,
+        &[
+            authority,
+            bookkeeper,
+            request,
+            system_program,
+            rent_sys_var,
+            client_info_address(&bitoku_agnet_program, &target).0,
+        ],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn remove_client(
+    fee_payer: Pubkey,
+    bookkeeper: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::RemoveClient { client_id }.pack();
+
+    let accounts = account_metas(REMOVE_CLIENT_ACCOUNTS, &[fee_payer, bookkeeper, request]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn send_request(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    req: Request,
+) -> Result<Instruction, ProgramError> {
+    send_request_with_ref(
+        fee_payer,
+        request,
+        bitoku_agnet_program,
+        client_id,
+        [0; 32],
+        req,
+    )
+}
+
+/// Like `send_request`, but rejects names `policy` would refuse on-chain before
+/// building the instruction.
+#[cfg(feature = "client")]
+pub fn send_request_with_policy(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    req: Request,
+    policy: &dyn NamePolicy,
+) -> Result<Instruction, ProgramError> {
+    req.check_names(policy)?;
+    send_request(fee_payer, request, bitoku_agnet_program, client_id, req)
+}
+
+#[cfg(feature = "client")]
+pub fn send_request_with_ref(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    submission_ref: [u8; 32],
+    req: Request,
+) -> Result<Instruction, ProgramError> {
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+    let (lease, _) = lease_address(&bitoku_agnet_program, &req);
+
+    let data = BitokuInstructions::SendRequest {
+        client_id,
+        submission_ref,
+        request: req,
+    }
+    .pack();
+
+    let accounts = account_metas(
+        SEND_REQUEST_ACCOUNTS,
+        &[
+            fee_payer,
+            request,
+            bookkeeper,
+            sysvar::clock::id(),
+            client_info_address(&bitoku_agnet_program, &fee_payer).0,
+            system_program::id(),
+            sysvar::rent::id(),
+            lease,
+        ],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+/// Like `send_request`, but replaces the previous request even if the agent
+/// hasn't acknowledged it yet.
+#[cfg(feature = "client")]
+pub fn overwrite_request(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    req: Request,
+) -> Result<Instruction, ProgramError> {
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+    let (lease, _) = lease_address(&bitoku_agnet_program, &req);
+
+    let data = BitokuInstructions::OverwriteRequest {
+        client_id,
+        submission_ref: [0; 32],
+        request: req,
+    }
+    .pack();
+
+    let accounts = account_metas(
+        SEND_REQUEST_ACCOUNTS,
+        &[
+            fee_payer,
+            request,
+            bookkeeper,
+            sysvar::clock::id(),
+            client_info_address(&bitoku_agnet_program, &fee_payer).0,
+            system_program::id(),
+            sysvar::rent::id(),
+            lease,
+        ],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+/// Like `send_request_with_ref`, but rebuilds `ix` in place so that senders
+/// reusing one `Instruction` do not allocate once its buffers have grown.
+#[cfg(feature = "client")]
+pub fn send_request_into(
+    ix: &mut Instruction,
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    submission_ref: [u8; 32],
+    req: Request,
+) -> Result<(), ProgramError> {
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+    let (lease, _) = lease_address(&bitoku_agnet_program, &req);
+
+    ix.program_id = bitoku_agnet_program;
+    fill_account_metas(
+        SEND_REQUEST_ACCOUNTS,
+        &[
+            fee_payer,
+            request,
+            bookkeeper,
+            sysvar::clock::id(),
+            client_info_address(&bitoku_agnet_program, &fee_payer).0,
+            system_program::id(),
+            sysvar::rent::id(),
+            lease,
+        ],
+        &mut ix.accounts,
+    );
+    ix.data.resize(MAX_PACKED_LEN, 0);
+    let len = BitokuInstructions::SendRequest {
+        client_id,
+        submission_ref,
+        request: req,
+    }
+    .pack_into(&mut ix.data)?;
+    ix.data.truncate(len);
+
+    Ok(())
+}
+
+/// Like `send_request`, deriving the request account from `fee_payer`.
+#[cfg(feature = "client")]
+pub fn send_request_auto(
+    fee_payer: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    req: Request,
+) -> Result<(Instruction, Pubkey), ProgramError> {
+    let (request, _) = request_address(&bitoku_agnet_program, &fee_payer);
+
+    let ix = send_request(fee_payer, request, bitoku_agnet_program, client_id, req)?;
+
+    Ok((ix, request))
+}
+
+/// Builds the client instructions of one fee payer against one deployment of
+/// the program, deriving the bookkeeper and request accounts itself.
+#[cfg(feature = "client")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BitokuInstructionBuilder {
+    program_id: Pubkey,
+    fee_payer: Pubkey,
+}
+
+#[cfg(feature = "client")]
+impl BitokuInstructionBuilder {
+    pub fn new(program_id: Pubkey, fee_payer: Pubkey) -> Self {
+        Self {
+            program_id,
+            fee_payer,
+        }
+    }
+
+    /// The request account `register_client` creates for the fee payer.
+    pub fn request(&self) -> Pubkey {
+        request_address(&self.program_id, &self.fee_payer).0
+    }
+
+    pub fn register_client(&self) -> Result<Instruction, ProgramError> {
+        register_client(
+            self.fee_payer,
+            bookkeeper_address(&self.program_id).0,
+            self.request(),
+            system_program::id(),
+            sysvar::rent::id(),
+            self.program_id,
+        )
+    }
+
+    pub fn remove_client(&self, client_id: u8) -> Result<Instruction, ProgramError> {
+        remove_client(
+            self.fee_payer,
+            bookkeeper_address(&self.program_id).0,
+            self.request(),
+            self.program_id,
+            client_id,
+        )
+    }
+
+    pub fn send_request(&self, client_id: u8, req: Request) -> Result<Instruction, ProgramError> {
+        send_request(
+            self.fee_payer,
+            self.request(),
+            self.program_id,
+            client_id,
+            req,
+        )
+    }
+
+    pub fn overwrite_request(
+        &self,
+        client_id: u8,
+        req: Request,
+    ) -> Result<Instruction, ProgramError> {
+        overwrite_request(
+            self.fee_payer,
+            self.request(),
+            self.program_id,
+            client_id,
+            req,
+        )
+    }
+
+    pub fn cancel_request(&self, client_id: u8) -> Result<Instruction, ProgramError> {
+        cancel_request(self.fee_payer, self.program_id, client_id)
+    }
+}
+
+#[cfg(feature = "client")]
+pub fn set_reserved_prefix(
+    authority: Pubkey,
+    bookkeeper: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    index: u8,
+    prefix: &str,
+) -> Result<Instruction, ProgramError> {
+    let bytes = prefix.as_bytes();
+    if bytes.len() > PREFIX_LEN {
+        return Err(InvalidName.into());
+    }
+    if index as usize >= RESERVED_PREFIXES {
+        return Err(InvalidInstructionData.into());
+    }
+
+    let mut padded_prefix = [0u8; PREFIX_LEN];
+    padded_prefix[..bytes.len()].copy_from_slice(bytes);
+
+    let data = BitokuInstructions::SetReservedPrefix {
+        index,
+        prefix: padded_prefix,
+    }
+    .pack();
+
+    let accounts = account_metas(SET_RESERVED_PREFIX_ACCOUNTS, &[authority, bookkeeper]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn set_supported_kinds(
+    agent: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    supported_kinds: u32,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::SetSupportedKinds { supported_kinds }.pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    let accounts = account_metas(SET_SUPPORTED_KINDS_ACCOUNTS, &[agent, bookkeeper]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn update_request_status(
+    agent: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    status: RequestStatus,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::UpdateRequestStatus { client_id, status }.pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    let accounts = account_metas(
+        UPDATE_REQUEST_STATUS_ACCOUNTS,
+        &[agent, bookkeeper, request],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn acknowledge_request(
+    agent: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    status: RequestStatus,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::AcknowledgeRequest { client_id, status }.pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    let accounts = account_metas(ACKNOWLEDGE_REQUEST_ACCOUNTS, &[agent, bookkeeper, request]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn reset_epoch_counter(
+    authority: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::ResetEpochCounter { client_id }.pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    let accounts = account_metas(
+        RESET_EPOCH_COUNTER_ACCOUNTS,
+        &[authority, bookkeeper, request],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+/// Replaces the permissions of `owner`'s client `client_id`, signed by the admin.
+#[cfg(feature = "client")]
+pub fn set_client_permissions(
+    admin: Pubkey,
+    owner: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    permissions: u8,
+) -> Result<Instruction, ProgramError> {
+    if permissions & !ALL_PERMISSIONS != 0 {
+        return Err(InvalidInstructionData.into());
+    }
+
+    let data = BitokuInstructions::SetClientPermissions {
+        client_id,
+        permissions,
+    }
+    .pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+    let (request, _) = request_address(&bitoku_agnet_program, &owner);
+    let (client_info, _) = client_info_address(&bitoku_agnet_program, &owner);
+
+    let accounts = account_metas(
+        SET_CLIENT_PERMISSIONS_ACCOUNTS,
+        &[admin, bookkeeper, request, client_info],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn write_response(
+    agent: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    status: RequestStatus,
+    response: &[u8],
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::WriteResponse {
+        client_id,
+        data: pad_data(response)?,
+        len: response.len() as u16,
+        status,
+    }
+    .pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    let accounts = account_metas(WRITE_RESPONSE_ACCOUNTS, &[agent, bookkeeper, request]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn set_agent(
+    authority: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    agent: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::SetAgent { agent }.pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    let accounts = account_metas(SET_AGENT_ACCOUNTS, &[authority, bookkeeper]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn set_name_policy(
+    authority: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    policy: NamePolicyKind,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::SetNamePolicy { policy }.pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    let accounts = account_metas(SET_NAME_POLICY_ACCOUNTS, &[authority, bookkeeper]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn publish_load(
+    agent: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    max_requests_per_epoch: u32,
+    current_backlog: u16,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::PublishLoad {
+        max_requests_per_epoch,
+        current_backlog,
+    }
+    .pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    let accounts = account_metas(
+        PUBLISH_LOAD_ACCOUNTS,
+        &[agent, bookkeeper, sysvar::clock::id()],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn shrink_request_account(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::ShrinkRequestAccount.pack();
+
+    let accounts = account_metas(
+        SHRINK_REQUEST_ACCOUNT_ACCOUNTS,
+        &[fee_payer, request, sysvar::rent::id()],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn migrate_account(
+    fee_payer: Pubkey,
+    account: Pubkey,
+    bitoku_agnet_program: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::MigrateAccount.pack();
+
+    let (client_info, _) = client_info_address(&bitoku_agnet_program, &fee_payer);
+
+    let accounts = account_metas(
+        MIGRATE_ACCOUNT_ACCOUNTS,
+        &[
+            fee_payer,
+            account,
+            system_program::id(),
+            sysvar::rent::id(),
+            client_info,
+            find_program_data_address(&bitoku_agnet_program),
+        ],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn set_authority(
+    authority: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    new_authority: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::SetAuthority { new_authority }.pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    let accounts = account_metas(SET_AUTHORITY_ACCOUNTS, &[authority, bookkeeper]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn update_admin(
+    admin: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    new_admin: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::UpdateAdmin { new_admin }.pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    let accounts = account_metas(UPDATE_ADMIN_ACCOUNTS, &[admin, bookkeeper]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+/// Frees the id of `owner`'s client and closes its request account into
+/// `destination`, signed by the bookkeeper authority instead of the owner.
+#[cfg(feature = "client")]
+pub fn force_remove_client(
+    authority: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    owner: Pubkey,
+    destination: Pubkey,
+    client_id: u8,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::ForceRemoveClient { client_id }.pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+    let (request, _) = request_address(&bitoku_agnet_program, &owner);
+
+    let accounts = account_metas(
+        FORCE_REMOVE_CLIENT_ACCOUNTS,
+        &[authority, bookkeeper, request, owner, destination],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+/// Pauses or resumes the program; only the bookkeeper authority may sign it.
+#[cfg(feature = "client")]
+pub fn set_paused(
+    authority: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    paused: bool,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::SetPaused { paused }.pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    let accounts = account_metas(SET_PAUSED_ACCOUNTS, &[authority, bookkeeper]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+/// Submits `envelope` with `relayer` paying. Send it right after
+/// `envelope.ed25519_instruction`, in the same transaction.
+#[cfg(feature = "client")]
+pub fn submit_signed_request(
+    relayer: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    envelope: &SignedRequestEnvelope,
+) -> Result<Instruction, ProgramError> {
+    let (request, _) = request_address(&bitoku_agnet_program, &envelope.signer);
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+    let (lease, _) = lease_address(&bitoku_agnet_program, &envelope.request);
+
+    let data = BitokuInstructions::SubmitSignedRequest {
+        client_id: envelope.client_id,
+        nonce: envelope.nonce,
+        signer: envelope.signer,
+        signature: envelope.signature,
+        request: envelope.request.clone(),
+    }
+    .pack();
+
+    let accounts = account_metas(
+        SUBMIT_SIGNED_REQUEST_ACCOUNTS,
+        &[
+            relayer,
+            sysvar::instructions::id(),
+            request,
+            bookkeeper,
+            sysvar::clock::id(),
+            client_info_address(&bitoku_agnet_program, &envelope.signer).0,
+            system_program::id(),
+            sysvar::rent::id(),
+            lease,
+        ],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+/// Withdraws the pending request of `fee_payer`'s client `client_id`.
+#[cfg(feature = "client")]
+pub fn cancel_request(
+    fee_payer: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::CancelRequest { client_id }.pack();
+
+    let (request, _) = request_address(&bitoku_agnet_program, &fee_payer);
+
+    let accounts = account_metas(CANCEL_REQUEST_ACCOUNTS, &[fee_payer, request]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn add_agent(
+    authority: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    pubkey: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::AddAgent { pubkey }.pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    let accounts = account_metas(ADD_AGENT_ACCOUNTS, &[authority, bookkeeper]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn remove_agent(
+    authority: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    pubkey: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::RemoveAgent { pubkey }.pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+
+    let accounts = account_metas(REMOVE_AGENT_ACCOUNTS, &[authority, bookkeeper]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+/// Address of the `StatsSnapshot` published for `epoch`.
+pub fn stats_snapshot_address(bitoku_agnet_program: &Pubkey, epoch: u64) -> (Pubkey, u8) {
+    find_stats_pda(epoch, bitoku_agnet_program)
+}
+
+/// Snapshot addresses for each of `epochs`, for fetching the history with
+/// `getMultipleAccounts`; epochs in which nothing was published have no account.
+#[cfg(feature = "client")]
+pub fn stats_snapshot_addresses(
+    bitoku_agnet_program: &Pubkey,
+    epochs: impl IntoIterator<Item = u64>,
+) -> Vec<(u64, Pubkey)> {
+    epochs
+        .into_iter()
+        .map(|epoch| (epoch, stats_snapshot_address(bitoku_agnet_program, epoch).0))
+        .collect()
+}
+
+/// `epoch` must be the cluster's current epoch when the instruction executes.
+#[cfg(feature = "client")]
+pub fn publish_stats(
+    publisher: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    epoch: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::PublishStats {}.pack();
+
+    let (bookkeeper, _) = bookkeeper_address(&bitoku_agnet_program);
+    let (stats, _) = stats_snapshot_address(&bitoku_agnet_program, epoch);
+
+    let accounts = account_metas(
+        PUBLISH_STATS_ACCOUNTS,
+        &[
+            publisher,
+            bookkeeper,
+            stats,
+            system_program::id(),
+            sysvar::clock::id(),
+            sysvar::rent::id(),
+        ],
+    );
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn get_version(
+    bookkeeper: Pubkey,
+    bitoku_agnet_program: Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = BitokuInstructions::GetVersion {}.pack();
+
+    let accounts = account_metas(GET_VERSION_ACCOUNTS, &[bookkeeper]);
+
+    Ok(Instruction {
+        program_id: bitoku_agnet_program,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(feature = "client")]
+pub fn send_create_bucket(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    name: &str,
+) -> Result<Instruction, ProgramError> {
+    send_request(
+        fee_payer,
+        request,
+        bitoku_agnet_program,
+        client_id,
+        Request::CreateBucket {
+            name: pad_name(name)?,
+        },
+    )
+}
+
+/// Creates file `name` holding up to 512 bytes of `data`; `send_write_at` writes
+/// the rest.
+#[cfg(feature = "client")]
+pub fn send_create_file(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    name: &str,
+    sparse: bool,
+    data: &[u8],
+) -> Result<Instruction, ProgramError> {
+    send_request(
+        fee_payer,
+        request,
+        bitoku_agnet_program,
+        client_id,
+        Request::CreateFile {
+            name: pad_name(name)?,
+            sparse,
+            data: pad_data(data)?,
+        },
+    )
+}
+
+#[cfg(feature = "client")]
+pub fn send_delete_bucket(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    name: &str,
+) -> Result<Instruction, ProgramError> {
+    send_request(
+        fee_payer,
+        request,
+        bitoku_agnet_program,
+        client_id,
+        Request::DeleteBucket {
+            name: pad_name(name)?,
+        },
+    )
+}
+
+/// Reserves writes to bucket `name` for client `client_id` for `ttl_slots` slots.
+#[cfg(feature = "client")]
+pub fn send_acquire_bucket_lease(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    name: &str,
+    ttl_slots: u32,
+) -> Result<Instruction, ProgramError> {
+    send_request(
+        fee_payer,
+        request,
+        bitoku_agnet_program,
+        client_id,
+        Request::AcquireBucketLease {
+            name: pad_name(name)?,
+            ttl_slots,
+        },
+    )
+}
+
+#[cfg(feature = "client")]
+pub fn send_release_bucket_lease(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    name: &str,
+) -> Result<Instruction, ProgramError> {
+    send_request(
+        fee_payer,
+        request,
+        bitoku_agnet_program,
+        client_id,
+        Request::ReleaseBucketLease {
+            name: pad_name(name)?,
+        },
+    )
+}
+
+/// Moves the file to the trash; use `send_delete_file_permanent` to skip it.
+#[cfg(feature = "client")]
+pub fn send_delete_file(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    name: &str,
+    file_id: u8,
+) -> Result<Instruction, ProgramError> {
+    send_request(
+        fee_payer,
+        request,
+        bitoku_agnet_program,
+        client_id,
+        Request::TrashFile {
+            name: pad_name(name)?,
+            file_id,
+        },
+    )
+}
+
+#[cfg(feature = "client")]
+pub fn send_delete_file_permanent(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    name: &str,
+    file_id: u8,
+) -> Result<Instruction, ProgramError> {
+    send_request(
+        fee_payer,
+        request,
+        bitoku_agnet_program,
+        client_id,
+        Request::DeleteFile {
+            name: pad_name(name)?,
+            file_id,
+        },
+    )
+}
+
+#[cfg(feature = "client")]
+pub fn send_restore_file(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    name: &str,
+    file_id: u8,
+) -> Result<Instruction, ProgramError> {
+    send_request(
+        fee_payer,
+        request,
+        bitoku_agnet_program,
+        client_id,
+        Request::RestoreFile {
+            name: pad_name(name)?,
+            file_id,
+        },
+    )
+}
+
+#[cfg(feature = "client")]
+pub fn send_empty_trash(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+) -> Result<Instruction, ProgramError> {
+    send_request(
+        fee_payer,
+        request,
+        bitoku_agnet_program,
+        client_id,
+        Request::EmptyTrash {},
+    )
+}
+
+#[cfg(feature = "client")]
+pub fn send_write_by_reference(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    name: &str,
+    file_id: u8,
+    chunk: &[u8],
+) -> Result<Instruction, ProgramError> {
+    send_request(
+        fee_payer,
+        request,
+        bitoku_agnet_program,
+        client_id,
+        Request::WriteByReference {
+            name: pad_name(name)?,
+            file_id,
+            chunk_hash: chunk_hash(&pad_data(chunk)?),
+        },
+    )
+}
+
+/// Splits `data` into 512-byte `WriteAt` requests starting at `offset`; the last
+/// chunk is zero-padded.
+#[cfg(feature = "client")]
+pub fn send_write_at(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    name: &str,
+    file_id: u8,
+    (offset, data): (u64, &[u8]),
+) -> Result<Vec<Instruction>, ProgramError> {
+    let name = pad_name(name)?;
+
+    data.chunks(512)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let offset = offset.checked_add(i as u64 * 512).ok_or(Overflow)?;
+            send_request(
+                fee_payer,
+                request,
+                bitoku_agnet_program,
+                client_id,
+                Request::WriteAt {
+                    name,
+                    file_id,
+                    offset,
+                    data: pad_data(chunk)?,
+                },
+            )
+        })
+        .collect()
+}
+
+/// Writes `data` to the file from `start`: a `SetPosition` to `start`, then one
+/// `WriteFile` per 512-byte chunk at its own offset, the last chunk zero-padded.
+///
+/// Each instruction stores a request, and the agent has to pick it up before the
+/// next is accepted, so send them one transaction at a time, in order; the
+/// length of the result is the number of transactions. Empty `data` is rejected.
+#[cfg(feature = "client")]
+#[allow(clippy::too_many_arguments)]
+pub fn write_file_chunked(
+    fee_payer: Pubkey,
+    request: Pubkey,
+    bitoku_agnet_program: Pubkey,
+    client_id: u8,
+    name: &str,
+    file_id: u8,
+    data: &[u8],
+    start: u64,
+) -> Result<Vec<Instruction>, ProgramError> {
+    if data.is_empty() {
+        return Err(InvalidInstructionData.into());
+    }
+    let name = pad_name(name)?;
+    let send = |req| send_request(fee_payer, request, bitoku_agnet_program, client_id, req);
+
+    let mut ixs = vec![send(Request::SetPosition {
+        name,
+        file_id,
+        position: start,
+    })?];
+    for (i, chunk) in data.chunks(512).enumerate() {
+        let offset = start.checked_add(i as u64 * 512).ok_or(Overflow)?;
+        ixs.push(send(Request::WriteFile {
+            name,
+            file_id,
+            offset,
+            data: pad_data(chunk)?,
+        })?);
+    }
+    Ok(ixs)
+}
+
+/// Content hash identifying a stored chunk in `WriteByReference`.
+pub fn chunk_hash(data: &[u8; 512]) -> [u8; 32] {
+    hash(data).to_bytes()
+}
+
+#[cfg(feature = "client")]
+fn pad_name(name: &str) -> Result<[u8; 128], ProgramError> {
+    let bytes = name.as_bytes();
+    if bytes.len() > 128 {
+        return Err(InvalidName.into());
+    }
+
+    let mut padded_name = [0u8; 128];
+    padded_name[..bytes.len()].copy_from_slice(bytes);
+    Ok(padded_name)
+}
+
+#[cfg(feature = "client")]
+fn pad_data(data: &[u8]) -> Result<[u8; 512], ProgramError> {
+    if data.len() > 512 {
+        return Err(InvalidInstructionData.into());
+    }
+
+    let mut padded_data = [0u8; 512];
+    padded_data[..data.len()].copy_from_slice(data);
+    Ok(padded_data)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+
+    use super::*;
+    #[cfg(feature = "client")]
+    use crate::error::BitokuError::InvalidClientId;
+
+    //one of each `Request` variant in tag order, with no field left at a default
+    //value so that a shifted or dropped byte changes the result
+    pub(crate) fn every_request() -> Vec<Request> {
+        let name = [b'n'; 128];
+        let other = [b'o'; 128];
+        let mut data = [0u8; 512];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = (i % 251) as u8 + 1;
+        }
+
+        vec![
+            Request::CreateBucket { name },
+            Request::CreateFile {
+                name,
+                sparse: true,
+                data,
+            },
+            Request::WriteFile {
+                name,
+                file_id: 2,
+                offset: 2 * 512,
+                data,
+            },
+            Request::CloseFile { name, file_id: 3 },
+            Request::DeleteFile { name, file_id: 4 },
+            Request::SetPosition {
+                name,
+                file_id: 5,
+                position: 0x0102_0304_0506_0708,
+            },
+            Request::OpenFile { name, file_id: 6 },
+            Request::ReadFile {
+                name,
+                file_id: 7,
+                min_sequence: 0x1112_1314_1516_1718,
+            },
+            Request::DeleteBucket { name },
+            Request::RenameFile {
+                name,
+                file_id: 9,
+                new_name: other,
+            },
+            Request::CopyFile {
+                src_name: name,
+                src_file_id: 10,
+                dst_name: other,
+            },
+            Request::StatFile {
+                name,
+                file_id: 11,
+                min_sequence: 0x2122_2324_2526_2728,
+            },
+            Request::TruncateFile {
+                name,
+                file_id: 12,
+                new_length: 0x3132_3334_3536_3738,
+            },
+            Request::ListFiles { name, page: 13 },
+            Request::AppendFile {
+                name,
+                file_id: 14,
+                data,
+            },
+            Request::WriteByReference {
+                name,
+                file_id: 15,
+                chunk_hash: chunk_hash(&data),
+            },
+            Request::WriteAt {
+                name,
+                file_id: 16,
+                offset: 0x4142_4344_4546_4748,
+                data,
+            },
+            Request::ReadRange {
+                name,
+                file_id: 17,
+                offset: 0x5152_5354_5556_5758,
+                len: MAX_READ,
+                min_sequence: 0x6162_6364_6566_6768,
+            },
+            Request::FileExists { name, file_id: 18 },
+            Request::TrashFile { name, file_id: 19 },
+            Request::RestoreFile { name, file_id: 20 },
+            Request::EmptyTrash {},
+            Request::ReadFileRange {
+                name,
+                file_id: 22,
+                offset: 0x7172_7374_7576_7778,
+                length: MAX_READ as u32,
+            },
+            Request::AcquireBucketLease {
+                name,
+                ttl_slots: MAX_LEASE_SLOTS,
+            },
+            Request::ReleaseBucketLease { name },
+        ]
+    }
+
+    //one of each `BitokuInstructions` variant, with a `SendRequest` for every request
+    pub(crate) fn every_instruction() -> Vec<BitokuInstructions> {
+        let mut instructions: Vec<BitokuInstructions> = every_request()
+            .into_iter()
+            .map(|request| BitokuInstructions::SendRequest {
+                client_id: 7,
+                submission_ref: [3; 32],
+                request,
+            })
+            .collect();
+        instructions.extend([
+            BitokuInstructions::InitBitoku,
+            BitokuInstructions::RegisterClient { compact: false },
+            BitokuInstructions::RegisterClient { compact: true },
+            BitokuInstructions::RemoveClient { client_id: 9 },
+            BitokuInstructions::GetVersion,
+            BitokuInstructions::RegisterClientFor {
+                target: Pubkey::new_unique(),
+            },
+            BitokuInstructions::SetReservedPrefix {
+                index: 1,
+                prefix: [b'p'; PREFIX_LEN],
+            },
+            BitokuInstructions::PublishStats,
+            BitokuInstructions::UpdateRequestStatus {
+                client_id: 2,
+                status: RequestStatus::Completed,
+            },
+            BitokuInstructions::SetSupportedKinds {
+                supported_kinds: 0x5a5a,
+            },
+            BitokuInstructions::WriteResponse {
+                client_id: 4,
+                data: [b'r'; 512],
+                len: 512,
+                status: RequestStatus::Failed,
+            },
+            BitokuInstructions::SetAgent {
+                agent: Pubkey::new_unique(),
+            },
+            BitokuInstructions::SetNamePolicy {
+                policy: NamePolicyKind::Utf8,
+            },
+            BitokuInstructions::PublishLoad {
+                max_requests_per_epoch: 1000,
+                current_backlog: 12,
+            },
+            BitokuInstructions::ShrinkRequestAccount,
+            BitokuInstructions::SetAuthority {
+                new_authority: Pubkey::new_unique(),
+            },
+            BitokuInstructions::ForceRemoveClient { client_id: 9 },
+            BitokuInstructions::SetPaused { paused: true },
+            BitokuInstructions::SubmitSignedRequest {
+                client_id: 7,
+                nonce: 5,
+                signer: Pubkey::new_unique(),
+                signature: [6; 64],
+                request: every_request()[16].clone(),
+            },
+            BitokuInstructions::CancelRequest { client_id: 3 },
+            BitokuInstructions::AddAgent {
+                pubkey: Pubkey::new_unique(),
+            },
+            BitokuInstructions::RemoveAgent {
+                pubkey: Pubkey::new_unique(),
+            },
+            BitokuInstructions::AcknowledgeRequest {
+                client_id: 2,
+                status: RequestStatus::Processing,
+            },
+            BitokuInstructions::OverwriteRequest {
+                client_id: 7,
+                submission_ref: [4; 32],
+                request: every_request()[1].clone(),
+            },
+            BitokuInstructions::ResetEpochCounter { client_id: 5 },
+            BitokuInstructions::SetClientPermissions {
+                client_id: 6,
+                permissions: PERMISSION_READ | PERMISSION_WRITE,
+            },
+            BitokuInstructions::MigrateAccount,
+            BitokuInstructions::UpdateAdmin {
+                new_admin: Pubkey::new_from_array([8; 32]),
+            },
+        ]);
+        instructions
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_request_json_roundtrip() {
+        let mut data = [0u8; 512];
+        data[..5].copy_from_slice(b"hello");
+        let request = Request::WriteFile {
+            name: crate::state::str_to_name("bucket/a.txt").unwrap(),
+            file_id: 3,
+            offset: 1024,
+            data,
+        };
+
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains(r#""name":"bucket/a.txt""#), "{}", json);
+        assert!(json.contains(r#""data":"aGVsbG8AAAAA"#), "{}", json);
+        let decoded: Request = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, request);
+
+        //names go through the same check as the builders
+        let invalid = json.replace("bucket/a.txt", r"bucket/\u0000");
+        assert!(serde_json::from_str::<Request>(&invalid).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_every_request_json_roundtrip() {
+        for request in every_request() {
+            let json = serde_json::to_string(&request).unwrap();
+            let decoded: Request = serde_json::from_str(&json).unwrap();
+            assert_eq!(decoded, request, "{}", json);
+        }
+
+        //a name filling all 128 bytes comes out whole, with no padding to trim
+        let request = Request::CreateBucket { name: [b'n'; 128] };
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(
+            json,
+            format!(r#"{{"CreateBucket":{{"name":"{}"}}}}"#, "n".repeat(128))
+        );
+        let long = json.replace("nnnn", "nnnnn");
+        assert!(serde_json::from_str::<Request>(&long).is_err());
+    }
+
+    #[test]
+    fn test_every_request_roundtrip() {
+        let requests = every_request();
+        let kinds: Vec<u8> = requests.iter().map(Request::kind).collect();
+        assert_eq!(kinds, (0..crate::state::REQUEST_KINDS).collect::<Vec<_>>());
+
+        for request in requests {
+            let packed = BitokuInstructions::SendRequest {
+                client_id: 1,
+                submission_ref: [0x5a; 32],
+                request: request.clone(),
+            }
+            .pack();
+            match BitokuInstructions::unpack(&packed).unwrap() {
+                BitokuInstructions::SendRequest {
+                    client_id,
+                    submission_ref,
+                    request: unpacked,
+                } => {
+                    assert_eq!(client_id, 1);
+                    assert_eq!(submission_ref, [0x5a; 32]);
+                    assert_eq!(unpacked, request);
+                }
+                _ => panic!("unexpected instruction"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_request_display() {
+        let mut name = [0u8; 128];
+        name[..5].copy_from_slice(b"hello");
+        let bucket = Request::CreateBucket { name };
+        assert_eq!(bucket.to_string(), "CreateBucket(name=\"hello\")");
+        assert_eq!(format!("{:?}", bucket), "CreateBucket { name: \"hello\" }");
+
+        let mut path = [0u8; 128];
+        path[..4].copy_from_slice(b"path");
+        let write = Request::WriteFile {
+            name: path,
+            file_id: 3,
+            offset: 8,
+            data: [7; 512],
+        };
+        assert_eq!(
+            write.to_string(),
+            "WriteFile(name=\"path\", file_id=3, offset=8, data_len=512)"
+        );
+        assert_eq!(
+            format!("{:?}", write),
+            "WriteFile { name: \"path\", file_id: 3, offset: 8, data: [512 bytes] }"
+        );
+        assert_eq!(Request::EmptyTrash {}.to_string(), "EmptyTrash()");
+
+        //no variant dumps a raw array
+        for request in every_request() {
+            for text in [request.to_string(), format!("{:?}", request)] {
+                assert!(text.len() < 400, "{}", text);
+                assert!(
+                    !text.contains(", 0,") && !text.contains("[110,"),
+                    "{}",
+                    text
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_write_file_offset_roundtrip() {
+        let mut data = [0u8; 512];
+        data[511] = 0xdd;
+        let request = Request::WriteFile {
+            name: pad_name("log").unwrap(),
+            file_id: 3,
+            offset: u64::MAX - 1,
+            data,
+        };
+        let packed = BitokuInstructions::SendRequest {
+            client_id: 1,
+            submission_ref: [0; 32],
+            request: request.clone(),
+        }
+        .pack();
+
+        //the request's fields start after its tag at 34
+        let fields = &packed[35..];
+        assert_eq!(&fields[129..137], &(u64::MAX - 1).to_le_bytes());
+        assert_eq!(&fields[137..649], &data);
+        assert_eq!(unpack_request(&packed[34..]).unwrap(), request);
+        assert_eq!(request.offset(), Some(u64::MAX - 1));
+
+        let mut area = [0u8; ENCODED_REQUEST_MAX_LEN];
+        assert_eq!(pack_request(&request, &mut area).unwrap(), 1 + 137 + 512);
+        assert_eq!(unpack_stored_request(&area).unwrap(), request);
+    }
+
+    #[test]
+    fn test_delete_bucket_roundtrip() {
+        let mut name = [0u8; 128];
+        name[..6].copy_from_slice(b"bucket");
+
+        let instruction = BitokuInstructions::SendRequest {
+            client_id: 4,
+            submission_ref: [0; 32],
+            request: Request::DeleteBucket { name },
+        };
+        let unpacked = BitokuInstructions::unpack(&instruction.pack()).unwrap();
+
+        match unpacked {
+            BitokuInstructions::SendRequest {
+                client_id, request, ..
+            } => {
+                assert_eq!(client_id, 4);
+                assert_eq!(request, Request::DeleteBucket { name });
+            }
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[test]
+    fn test_rename_file_roundtrip() {
+        let mut name = [0u8; 128];
+        name[..6].copy_from_slice(b"file-1");
+        let mut new_name = [0u8; 128];
+        new_name[..6].copy_from_slice(b"file-2");
+
+        let request = Request::RenameFile {
+            name,
+            file_id: 7,
+            new_name,
+        };
+        let instruction = BitokuInstructions::SendRequest {
+            client_id: 1,
+            submission_ref: [0; 32],
+            request: request.clone(),
+        };
+        let packed = instruction.pack();
+        assert_eq!(packed.len(), 3 + 32 + 128 + 1 + 128);
+
+        match BitokuInstructions::unpack(&packed).unwrap() {
+            BitokuInstructions::SendRequest { request: r, .. } => {
+                assert_eq!(r, request);
+                assert_eq!(r.name(), Some(name));
+                assert_eq!(r.new_name(), Some(new_name));
+            }
+            _ => panic!("unexpected instruction"),
+        }
+
+        assert_eq!(Request::DeleteBucket { name }.new_name(), None);
+    }
+
+    #[test]
+    fn test_copy_file_roundtrip() {
+        let mut src_name = [0u8; 128];
+        src_name[..12].copy_from_slice(b"dir-1/file-1");
+        let mut dst_name = [0u8; 128];
+        dst_name[..12].copy_from_slice(b"dir-2/file-1");
+
+        let request = Request::CopyFile {
+            src_name,
+            src_file_id: 3,
+            dst_name,
+        };
+        let instruction = BitokuInstructions::SendRequest {
+            client_id: 5,
+            submission_ref: [0; 32],
+            request: request.clone(),
+        };
+
+        match BitokuInstructions::unpack(&instruction.pack()).unwrap() {
+            BitokuInstructions::SendRequest {
+                client_id,
+                request: r,
+                ..
+            } => {
+                assert_eq!(client_id, 5);
+                assert_eq!(r, request);
+                assert_eq!(r.name(), Some(src_name));
+                assert_eq!(r.dst_name(), Some(dst_name));
+                assert_eq!(r.new_name(), None);
+            }
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[test]
+    fn test_stat_file_roundtrip() {
+        let mut name = [0u8; 128];
+        name[..4].copy_from_slice(b"stat");
+        let request = Request::StatFile {
+            name,
+            file_id: 2,
+            min_sequence: 7,
+        };
+
+        let instruction = BitokuInstructions::SendRequest {
+            client_id: 0,
+            submission_ref: [0; 32],
+            request: request.clone(),
+        };
+        match BitokuInstructions::unpack(&instruction.pack()).unwrap() {
+            BitokuInstructions::SendRequest { request: r, .. } => assert_eq!(r, request),
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_submission_ref_roundtrip() {
+        let submission_ref = [7u8; 32];
+        let ix = send_request_with_ref(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            1,
+            submission_ref,
+            Request::CreateBucket { name: [b'a'; 128] },
+        )
+        .unwrap();
+
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::SendRequest {
+                submission_ref: r, ..
+            } => assert_eq!(r, submission_ref),
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[test]
+    fn test_truncate_file_roundtrip() {
+        let mut name = [0u8; 128];
+        name[..5].copy_from_slice(b"trunc");
+
+        for new_length in [0, 4096, u64::MAX] {
+            let request = Request::TruncateFile {
+                name,
+                file_id: 6,
+                new_length,
+            };
+            let instruction = BitokuInstructions::SendRequest {
+                client_id: 2,
+                submission_ref: [0; 32],
+                request: request.clone(),
+            };
+
+            match BitokuInstructions::unpack(&instruction.pack()).unwrap() {
+                BitokuInstructions::SendRequest { request: r, .. } => {
+                    assert_eq!(r, request);
+                    assert_eq!(r.new_length(), Some(new_length));
+                }
+                _ => panic!("unexpected instruction"),
+            }
+        }
+
+        assert_eq!(
+            Request::StatFile {
+                name,
+                file_id: 6,
+                min_sequence: 0,
+            }
+            .new_length(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_list_files_roundtrip() {
+        let mut name = [0u8; 128];
+        name[..6].copy_from_slice(b"bucket");
+        let request = Request::ListFiles { name, page: 3 };
+
+        let instruction = BitokuInstructions::SendRequest {
+            client_id: 0,
+            submission_ref: [0; 32],
+            request: request.clone(),
+        };
+        match BitokuInstructions::unpack(&instruction.pack()).unwrap() {
+            BitokuInstructions::SendRequest { request: r, .. } => assert_eq!(r, request),
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_register_client_for_roundtrip() {
+        let target = Pubkey::new_unique();
+        let ix = register_client_for(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            target,
+        )
+        .unwrap();
+
+        assert!(ix.accounts[0].is_signer);
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::RegisterClientFor { target: t } => assert_eq!(t, target),
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_set_reserved_prefix_roundtrip() {
+        let authority = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let ix =
+            set_reserved_prefix(authority, Pubkey::new_unique(), program, 3, "system/").unwrap();
+
+        assert!(ix.accounts[0].is_signer);
+        let mut prefix = [0u8; PREFIX_LEN];
+        prefix[..7].copy_from_slice(b"system/");
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::SetReservedPrefix { index, prefix: p } => {
+                assert_eq!(index, 3);
+                assert_eq!(p, prefix);
+            }
+            _ => panic!("unexpected instruction"),
+        }
+
+        let full = "p".repeat(PREFIX_LEN);
+        assert!(set_reserved_prefix(authority, authority, program, 0, &full).is_ok());
+        assert_eq!(
+            set_reserved_prefix(
+                authority,
+                authority,
+                program,
+                0,
+                &"p".repeat(PREFIX_LEN + 1)
+            ),
+            Err(InvalidName.into())
+        );
+
+        let mut data = ix.data.clone();
+        data[1] = RESERVED_PREFIXES as u8;
+        assert_eq!(
+            BitokuInstructions::unpack(&data).err(),
+            Some(InvalidInstructionData.into())
+        );
+        assert!(BitokuInstructions::unpack(&ix.data[..ix.data.len() - 1]).is_err());
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_init_bitoku_accounts() {
+        let fee_payer = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let ix = init_bitoku_auto(fee_payer, program).unwrap();
+
+        let (bookkeeper, _) = Pubkey::find_program_address(&["bookkeeper".as_ref()], &program);
+        assert_eq!(ix.program_id, program);
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(fee_payer, true),
+                AccountMeta::new(bookkeeper, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(fee_payer, true),
+                AccountMeta::new_readonly(find_program_data_address(&program), false),
+            ]
+        );
+        assert!(matches!(
+            BitokuInstructions::unpack(&ix.data).unwrap(),
+            BitokuInstructions::InitBitoku
+        ));
+    }
+
+    #[test]
+    fn test_pack_into_matches_pack() {
+        let instructions = every_instruction();
+        let mut longest = 0;
+        for instruction in &instructions {
+            let packed = instruction.pack();
+            let mut buf = [0xffu8; MAX_PACKED_LEN];
+            let len = instruction.pack_into(&mut buf).unwrap();
+            assert_eq!(&buf[..len], packed.as_slice());
+
+            let mut exact = vec![0u8; len];
+            assert_eq!(instruction.pack_into(&mut exact), Ok(len));
+            assert_eq!(
+                instruction.pack_into(&mut exact[..len - 1]),
+                Err(BitokuError::BufferTooSmall)
+            );
+            longest = longest.max(len);
+        }
+        assert_eq!(longest, MAX_PACKED_LEN);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_send_request_into_reuses_buffers() {
+        let fee_payer = Pubkey::new_unique();
+        let request = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut ix = Instruction {
+            program_id: Pubkey::default(),
+            accounts: Vec::new(),
+            data: Vec::new(),
+        };
+
+        let mut data_ptr = None;
+        for request_kind in every_request() {
+            let expected = send_request_with_ref(
+                fee_payer,
+                request,
+                program,
+                5,
+                [1; 32],
+                request_kind.clone(),
+            )
+            .unwrap();
+            send_request_into(
+                &mut ix,
+                fee_payer,
+                request,
+                program,
+                5,
+                [1; 32],
+                request_kind,
+            )
+            .unwrap();
+            assert_eq!(ix, expected);
+
+            //the data buffer is sized for the longest instruction on first use
+            //and never reallocated afterwards
+            assert_eq!(*data_ptr.get_or_insert(ix.data.as_ptr()), ix.data.as_ptr());
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_init_bitoku_roundtrip() {
+        let fee_payer = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let (bookkeeper, _) = bookkeeper_address(&program);
+        let program_data = find_program_data_address(&program);
+
+        let ix = init_bitoku(
+            fee_payer,
+            bookkeeper,
+            system_program::id(),
+            sysvar::rent::id(),
+            fee_payer,
+            program_data,
+            program,
+        )
+        .unwrap();
+        assert_eq!(ix, init_bitoku_auto(fee_payer, program).unwrap());
+        assert!(matches!(
+            BitokuInstructions::unpack(&ix.data).unwrap(),
+            BitokuInstructions::InitBitoku
+        ));
+        assert_eq!(ix.data, BitokuInstructions::InitBitoku {}.pack());
+
+        assert_eq!(
+            init_bitoku(
+                fee_payer,
+                Pubkey::new_unique(),
+                system_program::id(),
+                sysvar::rent::id(),
+                fee_payer,
+                program_data,
+                program,
+            ),
+            Err(InvalidAccount.into())
+        );
+        //the ProgramData of another program holds another upgrade authority
+        assert_eq!(
+            init_bitoku(
+                fee_payer,
+                bookkeeper,
+                system_program::id(),
+                sysvar::rent::id(),
+                fee_payer,
+                Pubkey::new_unique(),
+                program,
+            ),
+            Err(InvalidAccount.into())
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_update_request_status_roundtrip() {
+        let request = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let ix = update_request_status(
+            Pubkey::new_unique(),
+            request,
+            program,
+            9,
+            RequestStatus::Failed,
+        )
+        .unwrap();
+
+        assert!(ix.accounts[0].is_signer);
+        assert_eq!(ix.accounts[2], AccountMeta::new(request, false));
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::UpdateRequestStatus { client_id, status } => {
+                assert_eq!(client_id, 9);
+                assert_eq!(status, RequestStatus::Failed);
+            }
+            _ => panic!("unexpected instruction"),
+        }
+
+        assert_eq!(
+            BitokuInstructions::unpack(&[8, 9, 5]).err(),
+            Some(InvalidInstructionData.into())
+        );
+        assert!(BitokuInstructions::unpack(&[8, 9]).is_err());
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_write_response_roundtrip() {
+        let request = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let ix = write_response(
+            Pubkey::new_unique(),
+            request,
+            program,
+            2,
+            RequestStatus::Processing,
+            b"contents",
+        )
+        .unwrap();
+
+        assert!(ix.accounts[0].is_signer);
+        assert_eq!(ix.accounts[2], AccountMeta::new(request, false));
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::WriteResponse {
+                client_id,
+                data,
+                len,
+                status,
+            } => {
+                assert_eq!(client_id, 2);
+                assert_eq!(len, 8);
+                assert_eq!(status, RequestStatus::Processing);
+                assert_eq!(&data[..8], b"contents");
+                assert!(data[8..].iter().all(|&b| b == 0));
+            }
+            _ => panic!("unexpected instruction"),
+        }
+
+        let mut too_long = ix.data.clone();
+        too_long[514..516].copy_from_slice(&513u16.to_le_bytes());
+        assert_eq!(
+            BitokuInstructions::unpack(&too_long).err(),
+            Some(InvalidInstructionData.into())
+        );
+        let mut unknown_status = ix.data.clone();
+        unknown_status[516] = 5;
+        assert_eq!(
+            BitokuInstructions::unpack(&unknown_status).err(),
+            Some(InvalidInstructionData.into())
+        );
+        assert!(BitokuInstructions::unpack(&ix.data[..516]).is_err());
+        assert!(write_response(
+            Pubkey::new_unique(),
+            request,
+            program,
+            2,
+            RequestStatus::Completed,
+            &[0; 513]
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_set_agent_roundtrip() {
+        let agent = Pubkey::new_unique();
+        let ix = set_agent(Pubkey::new_unique(), Pubkey::new_unique(), agent).unwrap();
+
+        assert!(ix.accounts[0].is_signer);
+        assert!(ix.accounts[1].is_writable);
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::SetAgent { agent: unpacked } => assert_eq!(unpacked, agent),
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_publish_load_roundtrip() {
+        let agent = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let ix = publish_load(agent, program, 0x0a0b_0c0d, 0x0e0f).unwrap();
+
+        let (bookkeeper, _) = bookkeeper_address(&program);
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(agent, true),
+                AccountMeta::new(bookkeeper, false),
+                AccountMeta::new_readonly(sysvar::clock::id(), false),
+            ]
+        );
+        assert_eq!(ix.data, [13, 0x0d, 0x0c, 0x0b, 0x0a, 0x0f, 0x0e]);
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::PublishLoad {
+                max_requests_per_epoch,
+                current_backlog,
+            } => {
+                assert_eq!(max_requests_per_epoch, 0x0a0b_0c0d);
+                assert_eq!(current_backlog, 0x0e0f);
+            }
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[test]
+    fn test_unpack_truncated_input() {
+        let name = [b't'; 128];
+        let instructions = [
+            BitokuInstructions::RemoveClient { client_id: 1 },
+            BitokuInstructions::RegisterClientFor {
+                target: Pubkey::new_unique(),
+            },
+            BitokuInstructions::SetReservedPrefix {
+                index: 0,
+                prefix: [b'p'; PREFIX_LEN],
+            },
+            BitokuInstructions::UpdateRequestStatus {
+                client_id: 1,
+                status: RequestStatus::Completed,
+            },
+            BitokuInstructions::SetSupportedKinds { supported_kinds: 1 },
+            BitokuInstructions::WriteResponse {
+                client_id: 1,
+                data: [b'r'; 512],
+                len: 512,
+                status: RequestStatus::Completed,
+            },
+            BitokuInstructions::SetAgent {
+                agent: Pubkey::new_unique(),
+            },
+            BitokuInstructions::PublishLoad {
+                max_requests_per_epoch: 1,
+                current_backlog: 1,
+            },
+            BitokuInstructions::SendRequest {
+                client_id: 1,
+                submission_ref: [0; 32],
+                request: Request::CreateBucket { name },
+            },
+            BitokuInstructions::SendRequest {
+                client_id: 1,
+                submission_ref: [0; 32],
+                request: Request::WriteAt {
+                    name,
+                    file_id: 1,
+                    offset: 512,
+                    data: [b'd'; 512],
+                },
+            },
+            BitokuInstructions::SendRequest {
+                client_id: 1,
+                submission_ref: [0; 32],
+                request: Request::ReadRange {
+                    name,
+                    file_id: 1,
+                    offset: 0,
+                    len: 1,
+                    min_sequence: 0,
+                },
+            },
+        ];
+
+        for instruction in instructions {
+            let packed = instruction.pack();
+            let mut padded = packed.clone();
+            padded.resize(700, 0xff);
+            for len in 0..=700 {
+                //must return, never panic
+                let result = BitokuInstructions::unpack(&padded[..len]);
+                if len < packed.len().min(35 + 128) {
+                    assert!(result.is_err(), "{:?} cut to {}", instruction, len);
+                }
+            }
+        }
+
+        //a SendRequest that stops right after the request tag
+        let mut short = vec![3, 1];
+        short.extend_from_slice(&[0; 32]);
+        short.push(0);
+        assert_eq!(
+            BitokuInstructions::unpack(&short).err(),
+            Some(InvalidInstructionData.into())
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_set_name_policy_roundtrip() {
+        let ix = set_name_policy(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            NamePolicyKind::S3Compat,
+        )
+        .unwrap();
+
+        assert!(ix.accounts[0].is_signer);
+        assert!(ix.accounts[1].is_writable);
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::SetNamePolicy { policy } => {
+                assert_eq!(policy, NamePolicyKind::S3Compat)
+            }
+            _ => panic!("unexpected instruction"),
+        }
+        assert_eq!(
+            BitokuInstructions::unpack(&[12, 3]).err(),
+            Some(InvalidInstructionData.into())
+        );
+        assert!(BitokuInstructions::unpack(&[12]).is_err());
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_send_request_with_policy() {
+        let mut name = [0u8; 128];
+        name[..8].copy_from_slice(b"file(1)/");
+        let mut new_name = [0u8; 128];
+        new_name[..6].copy_from_slice(b"file 2");
+        let rename = |new_name| Request::RenameFile {
+            name,
+            file_id: 1,
+            new_name,
+        };
+        let build = |req, policy: &dyn NamePolicy| {
+            send_request_with_policy(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                0,
+                req,
+                policy,
+            )
+        };
+
+        assert_eq!(
+            build(Request::CreateBucket { name }, &NamePolicyKind::Default).err(),
+            Some(InvalidName.into())
+        );
+        assert!(build(Request::CreateBucket { name }, &NamePolicyKind::Utf8).is_ok());
+        assert_eq!(
+            build(
+                Request::CreateBucket { name: [0; 128] },
+                &NamePolicyKind::Utf8
+            )
+            .err(),
+            Some(EmptyName.into())
+        );
+        //every name the request carries is checked
+        assert_eq!(
+            build(rename(new_name), &NamePolicyKind::S3Compat).err(),
+            Some(InvalidName.into())
+        );
+        assert!(build(rename(new_name), &NamePolicyKind::Utf8).is_ok());
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_publish_stats_accounts() {
+        let authority = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let ix = publish_stats(authority, program, 12).unwrap();
+
+        let (stats, _) =
+            Pubkey::find_program_address(&["stats".as_ref(), &12u64.to_le_bytes()], &program);
+        assert!(ix.accounts[0].is_signer);
+        assert_eq!(ix.accounts[2], AccountMeta::new(stats, false));
+        assert!(matches!(
+            BitokuInstructions::unpack(&ix.data).unwrap(),
+            BitokuInstructions::PublishStats
+        ));
+
+        let history = stats_snapshot_addresses(&program, 11..13);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1], (12, stats));
+        assert_ne!(history[0].1, stats);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_auto_builders_derive_accounts() {
+        let fee_payer = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let (bookkeeper, _) = Pubkey::find_program_address(&["bookkeeper".as_ref()], &program);
+        let (request, _) =
+            Pubkey::find_program_address(&["request".as_ref(), fee_payer.as_ref()], &program);
+
+        let (ix, request_key) = register_client_auto(fee_payer, program).unwrap();
+        assert_eq!(request_key, request);
+        assert_eq!(
+            ix,
+            register_client(
+                fee_payer,
+                bookkeeper,
+                request,
+                system_program::id(),
+                sysvar::rent::id(),
+                program
+            )
+            .unwrap()
+        );
+
+        let req = Request::CreateBucket { name: [b'b'; 128] };
+        let (ix, request_key) = send_request_auto(fee_payer, program, 3, req.clone()).unwrap();
+        assert_eq!(request_key, request);
+        assert_eq!(
+            ix,
+            send_request(fee_payer, request, program, 3, req).unwrap()
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_instruction_builder_matches_free_functions() {
+        let fee_payer = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let (bookkeeper, _) = Pubkey::find_program_address(&["bookkeeper".as_ref()], &program);
+        let (request, _) =
+            Pubkey::find_program_address(&["request".as_ref(), fee_payer.as_ref()], &program);
+        let builder = BitokuInstructionBuilder::new(program, fee_payer);
+        assert_eq!(builder.request(), request);
+
+        let register = register_client(
+            fee_payer,
+            bookkeeper,
+            request,
+            system_program::id(),
+            sysvar::rent::id(),
+            program,
+        )
+        .unwrap();
+        assert_eq!(builder.register_client().unwrap(), register);
+
+        let remove = remove_client(fee_payer, bookkeeper, request, program, 9).unwrap();
+        assert_eq!(builder.remove_client(9).unwrap(), remove);
+
+        for req in every_request() {
+            assert_eq!(
+                builder.send_request(4, req.clone()).unwrap(),
+                send_request(fee_payer, request, program, 4, req).unwrap()
+            );
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_send_request_passes_sysvar_accounts() {
+        let program = Pubkey::new_unique();
+        let fee_payer = Pubkey::new_unique();
+        let ix = send_request(
+            fee_payer,
+            Pubkey::new_unique(),
+            program,
+            0,
+            Request::CreateBucket { name: [b'b'; 128] },
+        )
+        .unwrap();
+
+        let (bookkeeper, _) = Pubkey::find_program_address(&["bookkeeper".as_ref()], &program);
+        assert_eq!(ix.accounts[2], AccountMeta::new_readonly(bookkeeper, false));
+        assert_eq!(
+            ix.accounts[3],
+            AccountMeta::new_readonly(sysvar::clock::id(), false)
+        );
+        assert_eq!(
+            ix.accounts[4],
+            AccountMeta::new_readonly(client_info_address(&program, &fee_payer).0, false)
+        );
+        assert_eq!(
+            ix.accounts[5],
+            AccountMeta::new_readonly(system_program::id(), false)
+        );
+        assert_eq!(
+            ix.accounts[6],
+            AccountMeta::new_readonly(sysvar::rent::id(), false)
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_register_client_layout_flag() {
+        let keys: Vec<Pubkey> = (0..6).map(|_| Pubkey::new_unique()).collect();
+        let full = register_client(keys[0], keys[1], keys[2], keys[3], keys[4], keys[5]).unwrap();
+        let compact =
+            register_compact_client(keys[0], keys[1], keys[2], keys[3], keys[4], keys[5]).unwrap();
+        assert_eq!(full.accounts, compact.accounts);
+        assert_eq!(full.data, [1, 0]);
+        assert_eq!(compact.data, [1, 1]);
+
+        //a bare tag is the legacy encoding of a full-layout registration
+        let mut encodings = vec![(&[1, 0][..], false), (&[1, 1], true)];
+        if cfg!(feature = "legacy-wire") {
+            encodings.push((&[1], false));
+        } else {
+            assert!(BitokuInstructions::unpack(&[1]).is_err());
+        }
+        for (data, expected) in encodings {
+            match BitokuInstructions::unpack(data).unwrap() {
+                BitokuInstructions::RegisterClient { compact } => assert_eq!(compact, expected),
+                _ => panic!("unexpected instruction"),
+            }
+        }
+        assert_eq!(
+            BitokuInstructions::unpack(&[1, 2]).err(),
+            Some(InvalidInstructionData.into())
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_shrink_request_account_roundtrip() {
+        let fee_payer = Pubkey::new_unique();
+        let request = Pubkey::new_unique();
+        let ix = shrink_request_account(fee_payer, request, Pubkey::new_unique()).unwrap();
+
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(fee_payer, true),
+                AccountMeta::new(request, false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+            ]
+        );
+        assert_eq!(ix.data, [14]);
+        assert!(matches!(
+            BitokuInstructions::unpack(&ix.data).unwrap(),
+            BitokuInstructions::ShrinkRequestAccount
+        ));
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_migrate_account_roundtrip() {
+        let fee_payer = Pubkey::new_unique();
+        let account = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let ix = migrate_account(fee_payer, account, program).unwrap();
+
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(fee_payer, true),
+                AccountMeta::new(account, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new(client_info_address(&program, &fee_payer).0, false),
+                AccountMeta::new_readonly(find_program_data_address(&program), false),
+            ]
+        );
+        assert_eq!(ix.data, [26]);
+        assert!(matches!(
+            BitokuInstructions::unpack(&ix.data).unwrap(),
+            BitokuInstructions::MigrateAccount
+        ));
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_force_remove_client_roundtrip() {
+        let authority = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let ix = force_remove_client(authority, program, owner, destination, 7).unwrap();
+
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(authority, true),
+                AccountMeta::new(bookkeeper_address(&program).0, false),
+                AccountMeta::new(request_address(&program, &owner).0, false),
+                AccountMeta::new_readonly(owner, false),
+                AccountMeta::new(destination, false),
+            ]
+        );
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::ForceRemoveClient { client_id } => assert_eq!(client_id, 7),
+            _ => panic!("unexpected instruction"),
+        }
+        //legacy decoding names the missing field
+        let missing = if cfg!(feature = "legacy-wire") {
+            InvalidClientId
+        } else {
+            InvalidInstructionData
+        };
+        assert_eq!(
+            BitokuInstructions::unpack(&ix.data[..1]).err(),
+            Some(missing.into())
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_set_authority_roundtrip() {
+        let authority = Pubkey::new_unique();
+        let new_authority = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let ix = set_authority(authority, program, new_authority).unwrap();
+
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(authority, true),
+                AccountMeta::new(bookkeeper_address(&program).0, false),
+            ]
+        );
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::SetAuthority { new_authority: key } => {
+                assert_eq!(key, new_authority)
+            }
+            _ => panic!("unexpected instruction"),
+        }
+        assert_eq!(
+            BitokuInstructions::unpack(&ix.data[..32]).err(),
+            Some(InvalidInstructionData.into())
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_update_admin_roundtrip() {
+        let admin = Pubkey::new_unique();
+        let new_admin = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let ix = update_admin(admin, program, new_admin).unwrap();
+
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(admin, true),
+                AccountMeta::new(bookkeeper_address(&program).0, false),
+            ]
+        );
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::UpdateAdmin { new_admin: key } => assert_eq!(key, new_admin),
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_set_paused_roundtrip() {
+        let authority = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+
+        for paused in [true, false] {
+            let ix = set_paused(authority, program, paused).unwrap();
+            assert_eq!(
+                ix.accounts,
+                vec![
+                    AccountMeta::new(authority, true),
+                    AccountMeta::new(bookkeeper_address(&program).0, false),
+                ]
+            );
+            match BitokuInstructions::unpack(&ix.data).unwrap() {
+                BitokuInstructions::SetPaused { paused: value } => assert_eq!(value, paused),
+                _ => panic!("unexpected instruction"),
+            }
+        }
+        assert_eq!(
+            BitokuInstructions::unpack(&[17]).err(),
+            Some(InvalidInstructionData.into())
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_submit_signed_request_roundtrip() {
+        let relayer = Pubkey::new_unique();
+        let signer = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let req = Request::CreateBucket { name: [b'b'; 128] };
+        let envelope = SignedRequestEnvelope::new(req.clone(), 3, 9, signer, [5; 64]);
+        let ix = submit_signed_request(relayer, program, &envelope).unwrap();
+
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(relayer, true),
+                AccountMeta::new_readonly(sysvar::instructions::id(), false),
+                AccountMeta::new(request_address(&program, &signer).0, false),
+                AccountMeta::new_readonly(bookkeeper_address(&program).0, false),
+                AccountMeta::new_readonly(sysvar::clock::id(), false),
+                AccountMeta::new_readonly(client_info_address(&program, &signer).0, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new(lease_address(&program, &req).0, false),
+            ]
+        );
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::SubmitSignedRequest {
+                client_id,
+                nonce,
+                signer: key,
+                signature,
+                request,
+            } => {
+                assert_eq!(
+                    SignedRequestEnvelope::new(request, client_id, nonce, key, signature),
+                    envelope
+                );
+            }
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_add_remove_agent_roundtrip() {
+        let authority = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let agent = Pubkey::new_unique();
+        let accounts = vec![
+            AccountMeta::new(authority, true),
+            AccountMeta::new(bookkeeper_address(&program).0, false),
+        ];
+
+        let ix = add_agent(authority, program, agent).unwrap();
+        assert_eq!(ix.accounts, accounts);
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::AddAgent { pubkey } => assert_eq!(pubkey, agent),
+            _ => panic!("unexpected instruction"),
+        }
+
+        let ix = remove_agent(authority, program, agent).unwrap();
+        assert_eq!(ix.accounts, accounts);
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::RemoveAgent { pubkey } => assert_eq!(pubkey, agent),
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_overwrite_and_acknowledge_roundtrip() {
+        let fee_payer = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let request = request_address(&program, &fee_payer).0;
+        let mut name = [0u8; 128];
+        name[..6].copy_from_slice(b"bucket");
+        let bucket = Request::CreateBucket { name };
+
+        let ix = overwrite_request(fee_payer, request, program, 3, bucket.clone()).unwrap();
+        let send = send_request(fee_payer, request, program, 3, bucket.clone()).unwrap();
+        assert_eq!(ix.accounts, send.accounts);
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::OverwriteRequest {
+                client_id,
+                submission_ref,
+                request,
+            } => {
+                assert_eq!((client_id, submission_ref), (3, [0; 32]));
+                assert_eq!(request, bucket);
+            }
+            _ => panic!("unexpected instruction"),
+        }
+        assert_eq!(
+            BitokuInstructionBuilder::new(program, fee_payer).overwrite_request(3, bucket),
+            Ok(ix)
+        );
+
+        let agent = Pubkey::new_unique();
+        let ix =
+            acknowledge_request(agent, request, program, 3, RequestStatus::Processing).unwrap();
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(agent, true),
+                AccountMeta::new_readonly(bookkeeper_address(&program).0, false),
+                AccountMeta::new(request, false),
+            ]
+        );
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::AcknowledgeRequest { client_id, status } => {
+                assert_eq!(client_id, 3);
+                assert_eq!(status, RequestStatus::Processing);
+            }
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_reset_epoch_counter_roundtrip() {
+        let authority = Pubkey::new_unique();
+        let request = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+
+        let ix = reset_epoch_counter(authority, request, program, 4).unwrap();
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(authority, true),
+                AccountMeta::new_readonly(bookkeeper_address(&program).0, false),
+                AccountMeta::new(request, false),
+            ]
+        );
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::ResetEpochCounter { client_id } => assert_eq!(client_id, 4),
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_set_client_permissions_roundtrip() {
+        let admin = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+
+        let ix = set_client_permissions(admin, owner, program, 4, PERMISSION_READ).unwrap();
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(admin, true),
+                AccountMeta::new_readonly(bookkeeper_address(&program).0, false),
+                AccountMeta::new_readonly(request_address(&program, &owner).0, false),
+                AccountMeta::new(client_info_address(&program, &owner).0, false),
+            ]
+        );
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::SetClientPermissions {
+                client_id,
+                permissions,
+            } => assert_eq!((client_id, permissions), (4, PERMISSION_READ)),
+            _ => panic!("unexpected instruction"),
+        }
+
+        //only the four known bits can be granted
+        assert_eq!(
+            set_client_permissions(admin, owner, program, 4, 0b10000),
+            Err(InvalidInstructionData.into())
+        );
+        assert_eq!(
+            BitokuInstructions::unpack(&[25, 4, 0b10000]).err(),
+            Some(InvalidInstructionData.into())
+        );
+    }
+
+    #[test]
+    fn test_required_permission() {
+        for request in every_request() {
+            let bit = request.required_permission();
+            assert!(bit.count_ones() <= 1 && bit & !ALL_PERMISSIONS == 0);
+            if request.is_readonly() {
+                assert_eq!(bit, PERMISSION_READ, "{}", request);
+            }
+            if request.is_mutation() {
+                assert_ne!(bit, PERMISSION_READ, "{}", request);
+            }
+        }
+        let release = Request::ReleaseBucketLease { name: [b'b'; 128] };
+        assert_eq!(release.required_permission(), 0);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_cancel_request_roundtrip() {
+        let fee_payer = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+
+        let ix = cancel_request(fee_payer, program, 6).unwrap();
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(fee_payer, true),
+                AccountMeta::new(request_address(&program, &fee_payer).0, false),
+            ]
+        );
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::CancelRequest { client_id } => assert_eq!(client_id, 6),
+            _ => panic!("unexpected instruction"),
+        }
+        let missing = if cfg!(feature = "legacy-wire") {
+            InvalidClientId
+        } else {
+            InvalidInstructionData
+        };
+        assert_eq!(
+            BitokuInstructions::unpack(&[19]).err(),
+            Some(missing.into())
+        );
+        assert_eq!(
+            BitokuInstructionBuilder::new(program, fee_payer).cancel_request(6),
+            Ok(ix)
+        );
+    }
+
+    #[test]
+    fn test_append_file_roundtrip() {
+        let mut data = [0u8; 512];
+        data[..5].copy_from_slice(b"tail!");
+        let request = Request::AppendFile {
+            name: [b'a'; 128],
+            file_id: 8,
+            data,
+        };
+
+        let instruction = BitokuInstructions::SendRequest {
+            client_id: 3,
+            submission_ref: [0; 32],
+            request: request.clone(),
+        };
+        match BitokuInstructions::unpack(&instruction.pack()).unwrap() {
+            BitokuInstructions::SendRequest { request: r, .. } => assert_eq!(r, request),
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[test]
+    fn test_request_from_parts() {
+        let photos = str_to_name("photos").unwrap();
+        assert_eq!(
+            Request::from_parts(0, "photos", 0, &[]),
+            Ok(Request::CreateBucket { name: photos })
+        );
+
+        let mut data = [0u8; 512];
+        data[..3].copy_from_slice(b"abc");
+        assert_eq!(
+            Request::from_parts(2, "photos", 4, b"abc"),
+            Ok(Request::WriteFile {
+                name: photos,
+                file_id: 4,
+                offset: 0,
+                data,
+            })
+        );
+        assert_eq!(
+            Request::from_parts(10, "photos", 4, &[]),
+            Ok(Request::CopyFile {
+                src_name: photos,
+                src_file_id: 4,
+                dst_name: [0; 128],
+            })
+        );
+        assert_eq!(
+            Request::from_parts(21, "ignored", 0, &[]),
+            Ok(Request::EmptyTrash {})
+        );
+
+        assert_eq!(
+            Request::from_parts(REQUESTS.len() as u8, "photos", 0, &[]),
+            Err(InvalidInstruction.into())
+        );
+        assert_eq!(
+            Request::from_parts(2, "photos", 4, &[1; 513]),
+            Err(DataTooLarge.into())
+        );
+        //data for a request that has nowhere to put it
+        assert_eq!(
+            Request::from_parts(0, "photos", 0, b"abc"),
+            Err(InvalidInstructionData.into())
+        );
+        assert_eq!(Request::from_parts(0, "", 0, &[]), Err(InvalidName.into()));
+        //ReadRange rejects a zero length
+        assert!(Request::from_parts(17, "photos", 1, &[]).is_err());
+    }
+
+    #[test]
+    fn test_request_constructors() {
+        for len in [1, 127, 128] {
+            let name = "n".repeat(len);
+            let request = Request::create_bucket(&name).unwrap();
+            assert_eq!(request.name_str(), Ok(name.as_str()));
+        }
+        assert_eq!(Request::create_bucket(&"n".repeat(129)), Err(InvalidName));
+        assert_eq!(Request::create_bucket(""), Err(InvalidName));
+        assert_eq!(Request::create_bucket("a b"), Err(InvalidName));
+        assert_eq!(Request::create_bucket("a\0b"), Err(InvalidName));
+
+        for len in [0, 511, 512] {
+            let bytes: Vec<u8> = (0..len).map(|i| (i % 251) as u8 + 1).collect();
+            let request = Request::write_file("f", 2, 1024, &bytes).unwrap();
+            let data = request.data().unwrap();
+            assert_eq!(&data[..len], &bytes[..]);
+            assert!(data[len..].iter().all(|&b| b == 0));
+            assert_eq!(request.file_id(), Some(2));
+            assert_eq!(request.offset(), Some(1024));
+        }
+        assert_eq!(Request::write_file("f", 2, 0, &[1; 513]), Err(DataTooLarge));
+        assert_eq!(Request::create_file("f", &[1; 513]), Err(DataTooLarge));
+        assert_eq!(Request::append_file("f", 2, &[1; 513]), Err(DataTooLarge));
+
+        let photos = str_to_name("photos").unwrap();
+        let other = str_to_name("other").unwrap();
+        assert_eq!(
+            Request::create_file("photos", b"abc"),
+            Ok(Request::CreateFile {
+                name: photos,
+                sparse: false,
+                data: data_field(b"abc").unwrap(),
+            })
+        );
+        assert_eq!(
+            Request::rename_file("photos", 3, "other"),
+            Ok(Request::RenameFile {
+                name: photos,
+                file_id: 3,
+                new_name: other,
+            })
+        );
+        assert_eq!(
+            Request::copy_file("photos", 3, "other"),
+            Ok(Request::CopyFile {
+                src_name: photos,
+                src_file_id: 3,
+                dst_name: other,
+            })
+        );
+        assert_eq!(Request::rename_file("photos", 3, "a b"), Err(InvalidName));
+        assert_eq!(
+            Request::read_file("photos", 3),
+            Ok(Request::ReadFile {
+                name: photos,
+                file_id: 3,
+                min_sequence: 0,
+            })
+        );
+        assert_eq!(
+            Request::delete_bucket("photos"),
+            Ok(Request::DeleteBucket { name: photos })
+        );
+        assert_eq!(
+            Request::close_file("photos", 1),
+            Ok(Request::CloseFile {
+                name: photos,
+                file_id: 1
+            })
+        );
+        assert_eq!(
+            Request::delete_file("photos", 1),
+            Ok(Request::DeleteFile {
+                name: photos,
+                file_id: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_name_str() {
+        assert_eq!(Request::EmptyTrash {}.name_str(), Ok(""));
+        let request = Request::CloseFile {
+            name: [0xff; 128],
+            file_id: 1,
+        };
+        assert!(request.name_str().is_err());
+        assert_eq!(request.file_id(), Some(1));
+        assert_eq!(request.data(), None);
+    }
+
+    #[test]
+    fn test_is_mutation() {
+        let name = [b'a'; 128];
+        let data = [0u8; 512];
+        let cases = [
+            (Request::CreateBucket { name }, true),
+            (
+                Request::CreateFile {
+                    name,
+                    sparse: false,
+                    data,
+                },
+                true,
+            ),
+            (
+                Request::WriteFile {
+                    name,
+                    file_id: 1,
+                    offset: 0,
+                    data,
+                },
+                true,
+            ),
+            (Request::CloseFile { name, file_id: 1 }, false),
+            (Request::DeleteFile { name, file_id: 1 }, true),
+            (
+                Request::SetPosition {
+                    name,
+                    file_id: 1,
+                    position: 0,
+                },
+                false,
+            ),
+            (Request::OpenFile { name, file_id: 1 }, false),
+            (
+                Request::ReadFile {
+                    name,
+                    file_id: 1,
+                    min_sequence: 0,
+                },
+                false,
+            ),
+            (Request::DeleteBucket { name }, true),
+            (
+                Request::RenameFile {
+                    name,
+                    file_id: 1,
+                    new_name: name,
+                },
+                true,
+            ),
+            (
+                Request::CopyFile {
+                    src_name: name,
+                    src_file_id: 1,
+                    dst_name: name,
+                },
+                true,
+            ),
+            (
+                Request::StatFile {
+                    name,
+                    file_id: 1,
+                    min_sequence: 0,
+                },
+                false,
+            ),
+            (
+                Request::TruncateFile {
+                    name,
+                    file_id: 1,
+                    new_length: 0,
+                },
+                true,
+            ),
+            (Request::ListFiles { name, page: 0 }, false),
+            (
+                Request::AppendFile {
+                    name,
+                    file_id: 1,
+                    data,
+                },
+                true,
+            ),
+            (
+                Request::WriteByReference {
+                    name,
+                    file_id: 1,
+                    chunk_hash: [0; 32],
+                },
+                true,
+            ),
+            (
+                Request::WriteAt {
+                    name,
+                    file_id: 1,
+                    offset: 0,
+                    data: [0; 512],
+                },
+                true,
+            ),
+            (
+                Request::ReadRange {
+                    name,
+                    file_id: 1,
+                    offset: 0,
+                    len: 1,
+                    min_sequence: 0,
+                },
+                false,
+            ),
+            (Request::FileExists { name, file_id: 1 }, false),
+            (Request::TrashFile { name, file_id: 1 }, true),
+            (Request::RestoreFile { name, file_id: 1 }, true),
+            (Request::EmptyTrash {}, true),
+            (
+                Request::ReadFileRange {
+                    name,
+                    file_id: 1,
+                    offset: 0,
+                    length: 1,
+                },
+                false,
+            ),
+        ];
+
+        for (request, expected) in cases {
+            assert_eq!(request.is_mutation(), expected, "{:?}", request.name());
+        }
+    }
+
+    #[test]
+    fn test_is_readonly() {
+        let name = [b'a'; 128];
+        let data = [0u8; 512];
+        let cases = [
+            (Request::CreateBucket { name }, false),
+            (
+                Request::CreateFile {
+                    name,
+                    sparse: false,
+                    data,
+                },
+                false,
+            ),
+            (
+                Request::WriteFile {
+                    name,
+                    file_id: 1,
+                    offset: 0,
+                    data,
+                },
+                false,
+            ),
+            (Request::CloseFile { name, file_id: 1 }, false),
+            (Request::DeleteFile { name, file_id: 1 }, false),
+            (
+                Request::SetPosition {
+                    name,
+                    file_id: 1,
+                    position: 0,
+                },
+                false,
+            ),
+            (Request::OpenFile { name, file_id: 1 }, false),
+            (
+                Request::ReadFile {
+                    name,
+                    file_id: 1,
+                    min_sequence: 0,
+                },
+                true,
+            ),
+            (Request::DeleteBucket { name }, false),
+            (
+                Request::RenameFile {
+                    name,
+                    file_id: 1,
+                    new_name: name,
+                },
+                false,
+            ),
+            (
+                Request::CopyFile {
+                    src_name: name,
+                    src_file_id: 1,
+                    dst_name: name,
+                },
+                false,
+            ),
+            (
+                Request::StatFile {
+                    name,
+                    file_id: 1,
+                    min_sequence: 0,
+                },
+                true,
+            ),
+            (
+                Request::TruncateFile {
+                    name,
+                    file_id: 1,
+                    new_length: 0,
+                },
+                false,
+            ),
+            (Request::ListFiles { name, page: 0 }, true),
+            (
+                Request::AppendFile {
+                    name,
+                    file_id: 1,
+                    data,
+                },
+                false,
+            ),
+            (
+                Request::WriteByReference {
+                    name,
+                    file_id: 1,
+                    chunk_hash: [0; 32],
+                },
+                false,
+            ),
+            (
+                Request::WriteAt {
+                    name,
+                    file_id: 1,
+                    offset: 0,
+                    data,
+                },
+                false,
+            ),
+            (
+                Request::ReadRange {
+                    name,
+                    file_id: 1,
+                    offset: 0,
+                    len: 1,
+                    min_sequence: 0,
+                },
+                true,
+            ),
+            (Request::FileExists { name, file_id: 1 }, true),
+            (Request::TrashFile { name, file_id: 1 }, false),
+            (Request::RestoreFile { name, file_id: 1 }, false),
+            (Request::EmptyTrash {}, false),
+            (
+                Request::ReadFileRange {
+                    name,
+                    file_id: 1,
+                    offset: 0,
+                    length: 1,
+                },
+                true,
+            ),
+        ];
+
+        for (request, expected) in cases {
+            assert_eq!(request.is_readonly(), expected, "{:?}", request.name());
+            assert!(!(request.is_readonly() && request.is_mutation()));
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_kind_matches_wire_tag() {
+        let name = [b'k'; 128];
+        let requests = [
+            Request::CreateBucket { name },
+            Request::OpenFile { name, file_id: 1 },
+            Request::ListFiles { name, page: 0 },
+            Request::ReadRange {
+                name,
+                file_id: 1,
+                offset: 0,
+                len: 1,
+                min_sequence: 0,
+            },
+            Request::FileExists { name, file_id: 1 },
+        ];
+
+        for request in requests {
+            let packed = BitokuInstructions::SendRequest {
+                client_id: 0,
+                submission_ref: [0; 32],
+                request: request.clone(),
+            }
+            .pack();
+            assert_eq!(packed[34], request.kind());
+            assert!(request.kind() < crate::state::REQUEST_KINDS);
+        }
+
+        let ix = set_supported_kinds(Pubkey::new_unique(), Pubkey::new_unique(), 0b101).unwrap();
+        assert!(ix.accounts[0].is_signer);
+        match BitokuInstructions::unpack(&ix.data).unwrap() {
+            BitokuInstructions::SetSupportedKinds { supported_kinds } => {
+                assert_eq!(supported_kinds, 0b101)
+            }
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    /// Deterministic spread of valid `(offset, len)` pairs, edges first.
+    fn read_ranges() -> impl Iterator<Item = (u64, u64)> {
+        let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+        let edges = [
+            (0, 1),
+            (0, MAX_READ),
+            (u64::MAX - MAX_READ, MAX_READ),
+            (u64::MAX - 1, 1),
+        ];
+        edges.into_iter().chain((0..256).map(move |_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let len = seed % MAX_READ + 1;
+            (seed.wrapping_mul(31) % (u64::MAX - len), len)
+        }))
+    }
+
+    #[test]
+    fn test_read_range_roundtrip() {
+        for (offset, len) in read_ranges() {
+            let request = Request::ReadRange {
+                name: [b'r'; 128],
+                file_id: (offset % 256) as u8,
+                offset,
+                len,
+                min_sequence: len,
+            };
+            let instruction = BitokuInstructions::SendRequest {
+                client_id: 1,
+                submission_ref: [0; 32],
+                request: request.clone(),
+            };
+            match BitokuInstructions::unpack(&instruction.pack()).unwrap() {
+                BitokuInstructions::SendRequest { request: r, .. } => {
+                    assert_eq!(r, request);
+                    assert_eq!(r.offset(), Some(offset));
+                    assert_eq!(r.len(), Some(len));
+                    assert_eq!(r.min_sequence(), Some(len));
+                }
+                _ => panic!("unexpected instruction"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_range_validation() {
+        let encode = |offset: u64, len: u64| {
+            let mut input = vec![17u8];
+            input.extend_from_slice(&[b'r'; 128]);
+            input.push(1);
+            input.extend_from_slice(&offset.to_le_bytes());
+            input.extend_from_slice(&len.to_le_bytes());
+            input.extend_from_slice(&9u64.to_le_bytes());
+            input
+        };
+
+        assert_eq!(unpack_request(&encode(0, 0)), Err(InvalidReadLength.into()));
+        assert_eq!(
+            unpack_request(&encode(0, MAX_READ + 1)),
+            Err(InvalidReadLength.into())
+        );
+        assert_eq!(
+            unpack_request(&encode(u64::MAX, 1)),
+            Err(InvalidPosition.into())
+        );
+
+        //legacy decoding names the first missing field
+        let input = encode(0, 1);
+        for (len, legacy_error) in [
+            (input.len() - 1, InvalidInstructionData),
+            (input.len() - 9, InvalidReadLength),
+            (134, InvalidPosition),
+        ] {
+            let error = if cfg!(feature = "legacy-wire") {
+                legacy_error
+            } else {
+                InvalidInstructionData
+            };
+            assert_eq!(unpack_request(&input[..len]), Err(error.into()));
+        }
+    }
+
+    #[test]
+    fn test_read_file_range_roundtrip() {
+        for (offset, len) in read_ranges() {
+            let request = Request::ReadFileRange {
+                name: [b'r'; 128],
+                file_id: (offset % 256) as u8,
+                offset,
+                length: len as u32,
+            };
+            let mut encoded = [0u8; ENCODED_REQUEST_MAX_LEN];
+            let used = pack_request(&request, &mut encoded).unwrap();
+            assert_eq!(used, 1 + 128 + 1 + 8 + 4);
+            assert_eq!(encoded[1 + 129..1 + 137], offset.to_le_bytes());
+            assert_eq!(encoded[1 + 137..1 + 141], (len as u32).to_le_bytes());
+            assert_eq!(unpack_read_range_offset(&encoded[..used]), Ok(offset));
+            assert_eq!(unpack_read_range_length(&encoded[..used]), Ok(len as u32));
+
+            let decoded = unpack_request(&encoded[..used]).unwrap();
+            assert_eq!(decoded, request);
+            assert!(decoded.is_range_request());
+            assert_eq!(decoded.offset(), Some(offset));
+            assert_eq!(decoded.len(), Some(len));
+            assert_eq!(decoded.min_sequence(), None);
+        }
+
+        assert!(!Request::ReadFile {
+            name: [b'r'; 128],
+            file_id: 1,
+            min_sequence: 0,
+        }
+        .is_range_request());
+    }
+
+    #[test]
+    fn test_lease_ttl_bounds() {
+        let encode = |ttl_slots: u32| {
+            let mut input = vec![23u8];
+            input.extend_from_slice(&[b'l'; 128]);
+            input.extend_from_slice(&ttl_slots.to_le_bytes());
+            input
+        };
+
+        for ttl_slots in [1, MAX_LEASE_SLOTS] {
+            assert_eq!(
+                unpack_request(&encode(ttl_slots)),
+                Ok(Request::AcquireBucketLease {
+                    name: [b'l'; 128],
+                    ttl_slots
+                })
+            );
+        }
+        for ttl_slots in [0, MAX_LEASE_SLOTS + 1, u32::MAX] {
+            assert_eq!(
+                unpack_request(&encode(ttl_slots)),
+                Err(InvalidInstructionData.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_file_range_validation() {
+        let encode = |offset: u64, length: u32| {
+            let mut input = vec![22u8];
+            input.extend_from_slice(&[b'r'; 128]);
+            input.push(1);
+            input.extend_from_slice(&offset.to_le_bytes());
+            input.extend_from_slice(&length.to_le_bytes());
+            input
+        };
+
+        for input in [encode(0, 0), encode(0, MAX_READ as u32 + 1)] {
+            assert_eq!(unpack_request(&input), Err(InvalidInstructionData.into()));
+            assert_eq!(
+                unpack_read_range_length(&input),
+                Err(InvalidInstructionData.into())
+            );
+            assert_eq!(unpack_read_range_offset(&input), Ok(0));
+        }
+        assert_eq!(
+            unpack_request(&encode(u64::MAX, 1)),
+            Err(InvalidPosition.into())
+        );
+
+        let input = encode(7, 1);
+        assert_eq!(
+            unpack_request(&input[..input.len() - 1]),
+            Err(InvalidInstructionData.into())
+        );
+        assert_eq!(
+            unpack_read_range_offset(&input[..137]),
+            Err(InvalidPosition.into())
+        );
+        let mut read_file = input;
+        read_file[0] = Request::ReadFile {
+            name: [0; 128],
+            file_id: 0,
+            min_sequence: 0,
+        }
+        .kind();
+        assert_eq!(
+            unpack_read_range_offset(&read_file),
+            Err(InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_min_sequence_encoding() {
+        let name = [b's'; 128];
+        for request in [
+            Request::ReadFile {
+                name,
+                file_id: 1,
+                min_sequence: u64::MAX,
+            },
+            Request::StatFile {
+                name,
+                file_id: 1,
+                min_sequence: 42,
+            },
+        ] {
+            let instruction = BitokuInstructions::SendRequest {
+                client_id: 1,
+                submission_ref: [0; 32],
+                request: request.clone(),
+            };
+            let packed = instruction.pack();
+            match BitokuInstructions::unpack(&packed).unwrap() {
+                BitokuInstructions::SendRequest { request: r, .. } => assert_eq!(r, request),
+                _ => panic!("unexpected instruction"),
+            }
+            assert_eq!(
+                BitokuInstructions::unpack(&packed[..packed.len() - 1]).err(),
+                Some(InvalidInstructionData.into())
+            );
+        }
+
+        let write = Request::WriteFile {
+            name,
+            file_id: 1,
+            offset: 0,
+            data: [0; 512],
+        };
+        assert_eq!(write.min_sequence(), None);
+    }
+
+    #[test]
+    fn test_offset_and_len_accessors() {
+        let name = [b'a'; 128];
+        let write_at = Request::WriteAt {
+            name,
+            file_id: 1,
+            offset: 1024,
+            data: [0; 512],
+        };
+        assert_eq!(write_at.offset(), Some(1024));
+        assert_eq!(write_at.len(), None);
+
+        let read = Request::ReadFile {
+            name,
+            file_id: 1,
+            min_sequence: 0,
+        };
+        assert_eq!(read.offset(), None);
+        assert_eq!(read.len(), None);
+    }
+
+    #[test]
+    fn test_file_id_position_and_data_accessors() {
+        let requests = every_request();
+        let data = requests[1].data().copied().unwrap();
+        for request in &requests {
+            let kind = request.kind();
+            //every_request numbers each file id after its tag
+            let file_id = match kind {
+                0 | 1 | 8 | 13 | 21 | 23 | 24 => None,
+                _ => Some(kind),
+            };
+            let position = match kind {
+                5 => Some(0x0102_0304_0506_0708),
+                _ => None,
+            };
+            let expected_data = match kind {
+                1 | 2 | 14 | 16 => Some(&data),
+                _ => None,
+            };
+            assert_eq!(request.file_id(), file_id, "kind {}", kind);
+            assert_eq!(request.position(), position, "kind {}", kind);
+            assert_eq!(request.data(), expected_data, "kind {}", kind);
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_lease_builders() {
+        let fee_payer = Pubkey::new_unique();
+        let request = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut shared = [0u8; 128];
+        shared[..6].copy_from_slice(b"shared");
+        let (lease, _) = find_lease_pda(&shared, &program);
+
+        assert_eq!(bucket_name(&shared), shared);
+        let mut file = shared;
+        file[6..12].copy_from_slice(b"/dir/a");
+        assert_eq!(bucket_name(&file), shared);
+
+        let acquire =
+            send_acquire_bucket_lease(fee_payer, request, program, 1, "shared", 20).unwrap();
+        let release = send_release_bucket_lease(fee_payer, request, program, 1, "shared").unwrap();
+        let write = send_request(
+            fee_payer,
+            request,
+            program,
+            1,
+            Request::CopyFile {
+                src_name: [b'o'; 128],
+                src_file_id: 1,
+                dst_name: file,
+            },
+        )
+        .unwrap();
+        for ix in [&acquire, &release, &write] {
+            assert_eq!(ix.accounts[7], AccountMeta::new(lease, false));
+        }
+
+        match BitokuInstructions::unpack(&acquire.data).unwrap() {
+            BitokuInstructions::SendRequest { request, .. } => {
+                assert!(request.is_lease());
+                assert_eq!(
+                    request,
+                    Request::AcquireBucketLease {
+                        name: shared,
+                        ttl_slots: 20
+                    }
+                );
+            }
+            _ => panic!("unexpected instruction"),
+        }
+        assert_eq!(Request::EmptyTrash {}.bucket(), None);
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_delete_file_defaults_to_trash() {
+        let fee_payer = Pubkey::new_unique();
+        let request = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut name = [0u8; 128];
+        name[..8].copy_from_slice(b"dir/file");
+
+        let cases = [
+            (
+                send_delete_file(fee_payer, request, program, 1, "dir/file", 3),
+                Request::TrashFile { name, file_id: 3 },
+            ),
+            (
+                send_delete_file_permanent(fee_payer, request, program, 1, "dir/file", 3),
+                Request::DeleteFile { name, file_id: 3 },
+            ),
+            (
+                send_restore_file(fee_payer, request, program, 1, "dir/file", 3),
+                Request::RestoreFile { name, file_id: 3 },
+            ),
+            (
+                send_empty_trash(fee_payer, request, program, 1),
+                Request::EmptyTrash {},
+            ),
+        ];
+        for (ix, expected) in cases {
+            assert_eq!(
+                ix.unwrap(),
+                send_request(fee_payer, request, program, 1, expected).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty_trash_is_nameless() {
+        let request = Request::EmptyTrash {};
+        let packed = BitokuInstructions::SendRequest {
+            client_id: 2,
+            submission_ref: [0; 32],
+            request: request.clone(),
+        }
+        .pack();
+        assert_eq!(packed.len(), 1 + 1 + 32 + 1);
+        assert_eq!(packed[34], 21);
+
+        assert_eq!(request.name(), None);
+        assert_eq!(request.file_id(), None);
+        assert_eq!(request.check_names(&NamePolicyKind::Default), Ok(()));
+        //legacy decoding ignores anything after the tag, as it does for other kinds
+        assert_eq!(
+            unpack_request(&[21, 0xff, 0xff]).ok(),
+            cfg!(feature = "legacy-wire").then_some(request)
+        );
+
+        //the trash requests share DeleteFile's layout
+        let delete = BitokuInstructions::SendRequest {
+            client_id: 2,
+            submission_ref: [0; 32],
+            request: Request::DeleteFile {
+                name: [b'd'; 128],
+                file_id: 5,
+            },
+        }
+        .pack();
+        let mut trash = delete.clone();
+        trash[34] = 19;
+        match BitokuInstructions::unpack(&trash).unwrap() {
+            BitokuInstructions::SendRequest { request, .. } => assert_eq!(
+                request,
+                Request::TrashFile {
+                    name: [b'd'; 128],
+                    file_id: 5
+                }
+            ),
+            _ => panic!("unexpected instruction"),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_write_by_reference() {
+        let fee_payer = Pubkey::new_unique();
+        let request = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let chunk = b"shared chunk";
+
+        let by_reference =
+            send_write_by_reference(fee_payer, request, program, 1, "dir/file", 2, chunk).unwrap();
+
+        let mut data = [0u8; 512];
+        data[..chunk.len()].copy_from_slice(chunk);
+        match BitokuInstructions::unpack(&by_reference.data).unwrap() {
+            BitokuInstructions::SendRequest { request: r, .. } => {
+                assert_eq!(
+                    r,
+                    Request::WriteByReference {
+                        name: pad_name("dir/file").unwrap(),
+                        file_id: 2,
+                        chunk_hash: chunk_hash(&data),
+                    }
+                );
+            }
+            _ => panic!("unexpected instruction"),
+        }
+
+        let write = send_request(
+            fee_payer,
+            request,
+            program,
+            1,
+            Request::WriteFile {
+                name: pad_name("dir/file").unwrap(),
+                file_id: 2,
+                offset: 0,
+                data,
+            },
+        )
+        .unwrap();
+        assert_eq!(write.data.len() - by_reference.data.len(), 8 + 512 - 32);
+
+        let truncated = &by_reference.data[..by_reference.data.len() - 1];
+        assert!(BitokuInstructions::unpack(truncated).is_err());
+
+        assert!(
+            send_write_by_reference(fee_payer, request, program, 1, "f", 2, &[0; 513]).is_err()
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_send_write_at() {
+        let fee_payer = Pubkey::new_unique();
+        let request = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let data: Vec<u8> = (0..1100).map(|i| i as u8).collect();
+
+        let ixs = send_write_at(fee_payer, request, program, 1, "f", 2, (4096, &data)).unwrap();
+        assert_eq!(ixs.len(), 3);
+
+        for (i, ix) in ixs.iter().enumerate() {
+            let chunk = &data[i * 512..data.len().min((i + 1) * 512)];
+            match BitokuInstructions::unpack(&ix.data).unwrap() {
+                BitokuInstructions::SendRequest {
+                    request:
+                        Request::WriteAt {
+                            file_id,
+                            offset,
+                            data,
+                            ..
+                        },
+                    ..
+                } => {
+                    assert_eq!(file_id, 2);
+                    assert_eq!(offset, 4096 + i as u64 * 512);
+                    assert_eq!(&data[..chunk.len()], chunk);
+                    assert!(data[chunk.len()..].iter().all(|&b| b == 0));
+                }
+                _ => panic!("unexpected instruction"),
+            }
+        }
+
+        assert!(
+            send_write_at(fee_payer, request, program, 1, "f", 2, (0, &[]))
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            send_write_at(fee_payer, request, program, 1, "f", 2, (u64::MAX, &data)),
+            Err(Overflow.into())
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_write_file_chunked() {
+        let fee_payer = Pubkey::new_unique();
+        let request = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+
+        for (len, chunks) in [(1, 1), (511, 1), (512, 1), (513, 2), (1024, 2), (1100, 3)] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8 + 1).collect();
+            let ixs =
+                write_file_chunked(fee_payer, request, program, 1, "f", 2, &data, 4096).unwrap();
+            assert_eq!(ixs.len(), 1 + chunks);
+
+            let mut requests = ixs
+                .iter()
+                .map(|ix| match BitokuInstructions::unpack(&ix.data) {
+                    Ok(BitokuInstructions::SendRequest { request, .. }) => request,
+                    _ => panic!("unexpected instruction"),
+                });
+            match requests.next().unwrap() {
+                Request::SetPosition {
+                    file_id, position, ..
+                } => {
+                    assert_eq!(file_id, 2);
+                    assert_eq!(position, 4096);
+                }
+                _ => panic!("expected SetPosition first"),
+            }
+
+            let mut reassembled = Vec::new();
+            for (i, request) in requests.enumerate() {
+                match request {
+                    Request::WriteFile {
+                        file_id,
+                        offset,
+                        data,
+                        ..
+                    } => {
+                        assert_eq!(file_id, 2);
+                        assert_eq!(offset, 4096 + i as u64 * 512);
+                        reassembled.extend_from_slice(&data);
+                    }
+                    _ => panic!("expected WriteFile"),
+                }
+            }
+            //only the final chunk is padded
+            assert_eq!(reassembled.len() % 512, 0);
+            assert_eq!(&reassembled[..len], &data[..]);
+            assert!(reassembled[len..].iter().all(|&b| b == 0));
+        }
+
+        assert_eq!(
+            write_file_chunked(fee_payer, request, program, 1, "f", 2, &[], 0),
+            Err(InvalidInstructionData.into())
+        );
+        assert_eq!(
+            write_file_chunked(fee_payer, request, program, 1, "f", 2, &[1; 513], u64::MAX),
+            Err(Overflow.into())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "legacy-wire")]
+    fn test_write_at_missing_offset() {
+        let mut input = vec![16u8];
+        input.extend_from_slice(&[b'f'; 128]);
+        input.push(2);
+        input.extend_from_slice(&[0; 4]);
+
+        assert_eq!(unpack_request(&input), Err(InvalidPosition.into()));
+    }
+
+    #[test]
+    #[cfg(feature = "legacy-wire")]
+    fn test_data_payload_limit() {
+        use crate::error::BitokuError::DataTooLarge;
+
+        let encode = |len: usize| {
+            let mut input = vec![2u8];
+            input.extend_from_slice(&[b'f'; 128]);
+            input.push(3);
+            input.extend_from_slice(&0u64.to_le_bytes());
+            input.resize(input.len() + len, b'd');
+            input
+        };
+
+        for len in [0, 511, 512] {
+            match unpack_request(&encode(len)).unwrap() {
+                Request::WriteFile { data, .. } => {
+                    assert!(data[..len].iter().all(|&b| b == b'd'));
+                    assert!(data[len..].iter().all(|&b| b == 0));
+                }
+                other => panic!("unexpected request {:?}", other),
+            }
+        }
+        for len in [513, 4096] {
+            assert_eq!(unpack_request(&encode(len)), Err(DataTooLarge.into()));
+        }
+
+        let mut write_at = vec![16u8];
+        write_at.extend_from_slice(&[b'f'; 128]);
+        write_at.push(3);
+        write_at.extend_from_slice(&0u64.to_le_bytes());
+        write_at.extend_from_slice(&[b'd'; 513]);
+        assert_eq!(unpack_request(&write_at), Err(DataTooLarge.into()));
+        assert!(unpack_request(&write_at[..write_at.len() - 1]).is_ok());
+
+        //the name and file id must still be there
+        assert_eq!(unpack_request(&encode(0)[..129]), Err(InvalidFileId.into()));
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_send_delete_bucket() {
+        let fee_payer = Pubkey::new_unique();
+        let request = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+
+        let ix = send_delete_bucket(fee_payer, request, program, 2, "dir-1").unwrap();
+
+        let mut name = [0u8; 128];
+        name[..5].copy_from_slice(b"dir-1");
+        let expected = send_request(
+            fee_payer,
+            request,
+            program,
+            2,
+            Request::DeleteBucket { name },
+        )
+        .unwrap();
+        assert_eq!(ix, expected);
+
+        let long_name = "a".repeat(129);
+        assert_eq!(
+            send_delete_bucket(fee_payer, request, program, 2, &long_name),
+            Err(InvalidName.into())
+        );
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn test_send_create_file() {
+        let fee_payer = Pubkey::new_unique();
+        let request = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+
+        let ix = send_create_bucket(fee_payer, request, program, 1, "docs").unwrap();
+        let mut bucket = [0u8; 128];
+        bucket[..4].copy_from_slice(b"docs");
+        let expected = send_request(
+            fee_payer,
+            request,
+            program,
+            1,
+            Request::CreateBucket { name: bucket },
+        )
+        .unwrap();
+        assert_eq!(ix, expected);
+
+        let ix = send_create_file(fee_payer, request, program, 1, "docs/a", true, b"hi").unwrap();
+        let mut name = [0u8; 128];
+        name[..6].copy_from_slice(b"docs/a");
+        let mut data = [0u8; 512];
+        data[..2].copy_from_slice(b"hi");
+        let expected = send_request(
+            fee_payer,
+            request,
+            program,
+            1,
+            Request::CreateFile {
+                name,
+                sparse: true,
+                data,
+            },
+        )
+        .unwrap();
+        assert_eq!(ix, expected);
+
+        assert_eq!(
+            send_create_file(fee_payer, request, program, 1, "docs/a", false, &[1; 513]),
+            Err(InvalidInstructionData.into())
+        );
+    }
 }
@@ -0,0 +1,533 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! The instruction decoder from before the wire format moved to Borsh, kept for
+//! the `legacy-wire` feature. It walks the `protocol` tables and accepts
+//! everything Borsh does, decoding it the same way, plus the looser encodings
+//! older clients send: `data` cut short, the `compact` flag of `RegisterClient`
+//! left out, any non-zero byte as a true `bool`, and bytes after the last field.
+
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+use crate::{
+    error::BitokuError::{
+        DataTooLarge, InvalidClientId, InvalidFileId, InvalidInstruction, InvalidInstructionData,
+        InvalidName, InvalidPosition, InvalidReadLength,
+    },
+    instruction::{BitokuInstructions, Request},
+    policy::NamePolicyKind,
+    protocol::{Field, FieldKind, Variant, INSTRUCTIONS, REQUESTS},
+    state::{RequestStatus, ALL_PERMISSIONS, MAX_LEASE_SLOTS, MAX_READ, RESERVED_PREFIXES},
+};
+
+/// `BitokuInstructions::unpack` as it was before Borsh.
+pub(crate) fn unpack_instruction(input: &[u8]) -> Result<BitokuInstructions, ProgramError> {
+    let (tag, mut input) = Decoder::new(input, INSTRUCTIONS)?;
+
+    Ok(match tag {
+        0 => BitokuInstructions::InitBitoku {},
+        1 => BitokuInstructions::RegisterClient {
+            compact: input.flag("compact")?,
+        },
+        2 => BitokuInstructions::RemoveClient {
+            client_id: input.u8("client_id").ok_or(InvalidClientId)?,
+        },
+        3 => BitokuInstructions::SendRequest {
+            client_id: input.u8("client_id").ok_or(InvalidClientId)?,
+            submission_ref: input
+                .bytes("submission_ref")
+                .ok_or(InvalidInstructionData)?,
+            request: unpack_request(input.request("request").ok_or(InvalidInstructionData)?)?,
+        },
+        4 => BitokuInstructions::GetVersion {},
+        5 => BitokuInstructions::RegisterClientFor {
+            target: input.key("target").ok_or(InvalidInstructionData)?,
+        },
+        6 => BitokuInstructions::SetReservedPrefix {
+            index: input
+                .u8("index")
+                .filter(|&index| (index as usize) < RESERVED_PREFIXES)
+                .ok_or(InvalidInstructionData)?,
+            prefix: input.text("prefix").ok_or(InvalidInstructionData)?,
+        },
+        7 => BitokuInstructions::PublishStats {},
+        8 => BitokuInstructions::UpdateRequestStatus {
+            client_id: input.u8("client_id").ok_or(InvalidClientId)?,
+            status: input
+                .enum_u8("status", "RequestStatus")
+                .and_then(|status| RequestStatus::from_u8(status).ok())
+                .ok_or(InvalidInstructionData)?,
+        },
+        9 => BitokuInstructions::SetSupportedKinds {
+            supported_kinds: input.u32("supported_kinds").ok_or(InvalidInstructionData)?,
+        },
+        10 => BitokuInstructions::WriteResponse {
+            client_id: input.u8("client_id").ok_or(InvalidClientId)?,
+            data: input.bytes("data").ok_or(InvalidInstructionData)?,
+            len: input
+                .u16("len")
+                .filter(|&len| len <= 512)
+                .ok_or(InvalidInstructionData)?,
+            status: input
+                .enum_u8("status", "RequestStatus")
+                .and_then(|status| RequestStatus::from_u8(status).ok())
+                .ok_or(InvalidInstructionData)?,
+        },
+        11 => BitokuInstructions::SetAgent {
+            agent: input.key("agent").ok_or(InvalidInstructionData)?,
+        },
+        12 => BitokuInstructions::SetNamePolicy {
+            policy: input
+                .enum_u8("policy", "NamePolicyKind")
+                .and_then(|policy| NamePolicyKind::from_u8(policy).ok())
+                .ok_or(InvalidInstructionData)?,
+        },
+        13 => BitokuInstructions::PublishLoad {
+            max_requests_per_epoch: input
+                .u32("max_requests_per_epoch")
+                .ok_or(InvalidInstructionData)?,
+            current_backlog: input.u16("current_backlog").ok_or(InvalidInstructionData)?,
+        },
+        14 => BitokuInstructions::ShrinkRequestAccount,
+        15 => BitokuInstructions::SetAuthority {
+            new_authority: input.key("new_authority").ok_or(InvalidInstructionData)?,
+        },
+        16 => BitokuInstructions::ForceRemoveClient {
+            client_id: input.u8("client_id").ok_or(InvalidClientId)?,
+        },
+        17 => BitokuInstructions::SetPaused {
+            paused: input.bool("paused").ok_or(InvalidInstructionData)?,
+        },
+        18 => BitokuInstructions::SubmitSignedRequest {
+            client_id: input.u8("client_id").ok_or(InvalidClientId)?,
+            nonce: input.u64("nonce").ok_or(InvalidInstructionData)?,
+            signer: input.key("signer").ok_or(InvalidInstructionData)?,
+            signature: input.bytes("signature").ok_or(InvalidInstructionData)?,
+            request: unpack_request(input.request("request").ok_or(InvalidInstructionData)?)?,
+        },
+        19 => BitokuInstructions::CancelRequest {
+            client_id: input.u8("client_id").ok_or(InvalidClientId)?,
+        },
+        20 => BitokuInstructions::AddAgent {
+            pubkey: input.key("pubkey").ok_or(InvalidInstructionData)?,
+        },
+        21 => BitokuInstructions::RemoveAgent {
+            pubkey: input.key("pubkey").ok_or(InvalidInstructionData)?,
+        },
+        22 => BitokuInstructions::AcknowledgeRequest {
+            client_id: input.u8("client_id").ok_or(InvalidClientId)?,
+            status: input
+                .enum_u8("status", "RequestStatus")
+                .and_then(|status| RequestStatus::from_u8(status).ok())
+                .ok_or(InvalidInstructionData)?,
+        },
+        23 => BitokuInstructions::OverwriteRequest {
+            client_id: input.u8("client_id").ok_or(InvalidClientId)?,
+            submission_ref: input
+                .bytes("submission_ref")
+                .ok_or(InvalidInstructionData)?,
+            request: unpack_request(input.request("request").ok_or(InvalidInstructionData)?)?,
+        },
+        24 => BitokuInstructions::ResetEpochCounter {
+            client_id: input.u8("client_id").ok_or(InvalidClientId)?,
+        },
+        25 => BitokuInstructions::SetClientPermissions {
+            client_id: input.u8("client_id").ok_or(InvalidClientId)?,
+            permissions: input
+                .u8("permissions")
+                .filter(|&permissions| permissions & !ALL_PERMISSIONS == 0)
+                .ok_or(InvalidInstructionData)?,
+        },
+        26 => BitokuInstructions::MigrateAccount,
+        27 => BitokuInstructions::UpdateAdmin {
+            new_admin: input.key("new_admin").ok_or(InvalidInstructionData)?,
+        },
+        _ => return Err(InvalidInstruction.into()),
+    })
+}
+
+/// `unpack_request` as it was before Borsh.
+pub(crate) fn unpack_request(input: &[u8]) -> Result<Request, ProgramError> {
+    let (tag, mut input) = Decoder::new(input, REQUESTS)?;
+
+    if tag == 21 {
+        return Ok(Request::EmptyTrash {});
+    }
+    //CopyFile names its fields after the source and the destination
+    if tag == 10 {
+        return Ok(Request::CopyFile {
+            src_name: input.name("src_name").ok_or(InvalidInstructionData)?,
+            src_file_id: input.u8("src_file_id").ok_or(InvalidFileId)?,
+            dst_name: input.name("dst_name").ok_or(InvalidName)?,
+        });
+    }
+    let name = input.name("name").ok_or(InvalidInstructionData)?;
+
+    Ok(match tag {
+        0 => Request::CreateBucket { name },
+        1 => Request::CreateFile {
+            name,
+            sparse: input.bool("sparse").ok_or(InvalidInstructionData)?,
+            data: input.data("data")?,
+        },
+        2 => Request::WriteFile {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+            offset: input.u64("offset").ok_or(InvalidPosition)?,
+            data: input.data("data")?,
+        },
+        3 => Request::CloseFile {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+        },
+        4 => Request::DeleteFile {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+        },
+        5 => Request::SetPosition {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+            position: input.u64("position").ok_or(InvalidPosition)?,
+        },
+        6 => Request::OpenFile {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+        },
+        7 => Request::ReadFile {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+            min_sequence: input.u64("min_sequence").ok_or(InvalidInstructionData)?,
+        },
+        8 => Request::DeleteBucket { name },
+        9 => Request::RenameFile {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+            new_name: input.name("new_name").ok_or(InvalidName)?,
+        },
+        11 => Request::StatFile {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+            min_sequence: input.u64("min_sequence").ok_or(InvalidInstructionData)?,
+        },
+        12 => Request::TruncateFile {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+            new_length: input.u64("new_length").ok_or(InvalidInstructionData)?,
+        },
+        13 => Request::ListFiles {
+            name,
+            page: input.u8("page").ok_or(InvalidInstructionData)?,
+        },
+        14 => Request::AppendFile {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+            data: input.data("data")?,
+        },
+        15 => Request::WriteByReference {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+            chunk_hash: input.bytes("chunk_hash").ok_or(InvalidInstructionData)?,
+        },
+        16 => Request::WriteAt {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+            offset: input.u64("offset").ok_or(InvalidPosition)?,
+            data: input.data("data")?,
+        },
+        17 => {
+            let file_id = input.u8("file_id").ok_or(InvalidFileId)?;
+            let offset = input.u64("offset").ok_or(InvalidPosition)?;
+            let len = input
+                .u64("len")
+                .filter(|&len| len != 0 && len <= MAX_READ)
+                .ok_or(InvalidReadLength)?;
+            offset.checked_add(len).ok_or(InvalidPosition)?;
+            Request::ReadRange {
+                name,
+                file_id,
+                offset,
+                len,
+                min_sequence: input.u64("min_sequence").ok_or(InvalidInstructionData)?,
+            }
+        }
+        18 => Request::FileExists {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+        },
+        19 => Request::TrashFile {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+        },
+        20 => Request::RestoreFile {
+            name,
+            file_id: input.u8("file_id").ok_or(InvalidFileId)?,
+        },
+        22 => {
+            let file_id = input.u8("file_id").ok_or(InvalidFileId)?;
+            let offset = input.u64("offset").ok_or(InvalidPosition)?;
+            let length = read_range_length(&mut input)?;
+            offset.checked_add(length as u64).ok_or(InvalidPosition)?;
+            Request::ReadFileRange {
+                name,
+                file_id,
+                offset,
+                length,
+            }
+        }
+        23 => Request::AcquireBucketLease {
+            name,
+            ttl_slots: input
+                .u32("ttl_slots")
+                .filter(|&ttl_slots| ttl_slots != 0 && ttl_slots <= MAX_LEASE_SLOTS)
+                .ok_or(InvalidInstructionData)?,
+        },
+        24 => Request::ReleaseBucketLease { name },
+        _ => return Err(InvalidInstruction.into()),
+    })
+}
+
+fn read_range_length(input: &mut Decoder) -> Result<u32, ProgramError> {
+    input
+        .u32("length")
+        .filter(|&length| length != 0 && length as u64 <= MAX_READ)
+        .ok_or_else(|| InvalidInstructionData.into())
+}
+
+//the next table field, which must be the one the caller is about to handle
+fn next_field(fields: &mut core::slice::Iter<'static, Field>, name: &str, kind: FieldKind) {
+    let field = fields.next();
+    debug_assert!(
+        field.is_some_and(|field| field.name == name && field.kind == kind),
+        "{} ({:?}) does not match the protocol table ({:?})",
+        name,
+        kind,
+        field
+    );
+}
+
+/// Reads one variant of a table, checking every field against it. Fields come
+/// back as `None` once the input runs out, leaving the error to the caller.
+struct Decoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+    fields: core::slice::Iter<'static, Field>,
+}
+
+impl<'a> Decoder<'a> {
+    /// Reads the tag and returns it with a decoder for the rest of the variant.
+    fn new(input: &'a [u8], table: &'static [Variant]) -> Result<(u8, Self), ProgramError> {
+        let tag = *input.first().ok_or(InvalidInstructionData)?;
+        let variant = table.get(tag as usize).ok_or(InvalidInstruction)?;
+        let decoder = Self {
+            input,
+            pos: 1,
+            fields: variant.fields.iter(),
+        };
+        Ok((tag, decoder))
+    }
+
+    fn field(&mut self, name: &str, kind: FieldKind) -> Option<&'a [u8]> {
+        next_field(&mut self.fields, name, kind);
+        let len = kind.size().unwrap();
+        let bytes = self.input.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self, name: &str) -> Option<u8> {
+        self.field(name, FieldKind::U8).map(|bytes| bytes[0])
+    }
+
+    /// Any non-zero byte reads as true.
+    fn bool(&mut self, name: &str) -> Option<bool> {
+        self.field(name, FieldKind::Bool).map(|bytes| bytes[0] != 0)
+    }
+
+    fn u16(&mut self, name: &str) -> Option<u16> {
+        self.field(name, FieldKind::U16)
+            .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u32(&mut self, name: &str) -> Option<u32> {
+        self.field(name, FieldKind::U32)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self, name: &str) -> Option<u64> {
+        self.field(name, FieldKind::U64)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn key(&mut self, name: &str) -> Option<Pubkey> {
+        self.field(name, FieldKind::Key)
+            .map(|bytes| Pubkey::new_from_array(bytes.try_into().unwrap()))
+    }
+
+    fn name(&mut self, name: &str) -> Option<[u8; 128]> {
+        self.field(name, FieldKind::Name)
+            .map(|bytes| bytes.try_into().unwrap())
+    }
+
+    fn text<const N: usize>(&mut self, name: &str) -> Option<[u8; N]> {
+        self.field(name, FieldKind::Text(N))
+            .map(|bytes| bytes.try_into().unwrap())
+    }
+
+    fn bytes<const N: usize>(&mut self, name: &str) -> Option<[u8; N]> {
+        self.field(name, FieldKind::Bytes(N))
+            .map(|bytes| bytes.try_into().unwrap())
+    }
+
+    fn enum_u8(&mut self, name: &str, ty: &'static str) -> Option<u8> {
+        self.field(name, FieldKind::Enum(ty)).map(|bytes| bytes[0])
+    }
+
+    /// Absent or 0 is false and 1 is true; anything else is rejected.
+    fn flag(&mut self, name: &str) -> Result<bool, ProgramError> {
+        next_field(&mut self.fields, name, FieldKind::Flag);
+        let flag = match self.input.get(self.pos) {
+            None => return Ok(false),
+            Some(0) => false,
+            Some(1) => true,
+            Some(_) => return Err(InvalidInstructionData.into()),
+        };
+        self.pos += 1;
+        Ok(flag)
+    }
+
+    fn data(&mut self, name: &str) -> Result<[u8; 512], ProgramError> {
+        next_field(&mut self.fields, name, FieldKind::Data);
+        let data = self.input.get(self.pos..).ok_or(InvalidInstructionData)?;
+        if data.len() > 512 {
+            return Err(DataTooLarge.into());
+        }
+        let mut padded = [0u8; 512];
+        padded[..data.len()].copy_from_slice(data);
+        self.pos = self.input.len();
+        Ok(padded)
+    }
+
+    /// The rest of the input, where a nested request starts.
+    fn request(&mut self, name: &str) -> Option<&'a [u8]> {
+        next_field(&mut self.fields, name, FieldKind::Request);
+        let rest = self.input.get(self.pos..)?;
+        self.pos = self.input.len();
+        Some(rest)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use borsh::{BorshDeserialize, BorshSerialize};
+
+    use super::*;
+    use crate::instruction::test::{every_instruction, every_request};
+
+    const FLIPS: [u8; 3] = [0x01, 0x80, 0xff];
+
+    //every single-byte corruption and every truncation of `valid`
+    fn corruptions(valid: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+        let flipped = (0..valid.len()).flat_map(move |pos| {
+            FLIPS.into_iter().map(move |flip| {
+                let mut corrupted = valid.to_vec();
+                corrupted[pos] ^= flip;
+                corrupted
+            })
+        });
+        flipped.chain((0..valid.len()).map(|len| valid[..len].to_vec()))
+    }
+
+    //what Borsh makes of `input`: `None` if it doesn't decode, otherwise the
+    //value or the error of the checks that follow
+    fn borsh_instruction(input: &[u8]) -> Option<Result<Vec<u8>, ProgramError>> {
+        let instruction = BitokuInstructions::try_from_slice(input).ok()?;
+        Some(instruction.check_fields().map(|()| instruction.pack()))
+    }
+
+    fn borsh_request(input: &[u8]) -> Option<Result<Request, ProgramError>> {
+        let request = Request::try_from_slice(input).ok()?;
+        Some(request.check_fields().map(|()| request))
+    }
+
+    #[test]
+    fn test_decoders_agree_on_borsh_encodings() {
+        for instruction in every_instruction() {
+            let packed = instruction.try_to_vec().unwrap();
+            assert_eq!(packed, instruction.pack());
+            let legacy = unpack_instruction(&packed).unwrap();
+            assert_eq!(legacy.pack(), packed, "{:?}", instruction);
+        }
+        for request in every_request() {
+            let packed = request.try_to_vec().unwrap();
+            assert_eq!(unpack_request(&packed), Ok(request));
+        }
+    }
+
+    #[test]
+    fn test_decoders_agree_on_corrupted_encodings() {
+        for instruction in every_instruction() {
+            for input in corruptions(&instruction.pack()) {
+                if let Some(borsh) = borsh_instruction(&input) {
+                    let legacy = unpack_instruction(&input).map(|legacy| legacy.pack());
+                    assert_eq!(legacy, borsh, "{:?} decoded differently", input);
+                    let unpacked = BitokuInstructions::unpack(&input).map(|ix| ix.pack());
+                    assert_eq!(unpacked, borsh);
+                }
+            }
+        }
+        for request in every_request() {
+            for input in corruptions(&request.try_to_vec().unwrap()) {
+                if let Some(borsh) = borsh_request(&input) {
+                    assert_eq!(unpack_request(&input), borsh, "{:?}", input);
+                    assert_eq!(crate::instruction::unpack_request(&input), borsh);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_legacy_only_encodings() {
+        let full = BitokuInstructions::RegisterClient { compact: false }.pack();
+        let write = BitokuInstructions::SendRequest {
+            client_id: 1,
+            submission_ref: [2; 32],
+            request: every_request()[2].clone(),
+        }
+        .pack();
+        let mut short_data = write.clone();
+        short_data.truncate(write.len() - 500);
+        //data runs to the end of the input, so only fixed-size fields can be followed
+        let mut trailing = BitokuInstructions::RemoveClient { client_id: 1 }.pack();
+        trailing.push(0);
+        let mut sparse = BitokuInstructions::SendRequest {
+            client_id: 1,
+            submission_ref: [2; 32],
+            request: every_request()[1].clone(),
+        }
+        .pack();
+        //the sparse flag of CreateFile
+        sparse[1 + 1 + 32 + 1 + 128] = 2;
+
+        for input in [&full[..1], &short_data, &trailing, &sparse] {
+            assert!(borsh_instruction(input).is_none());
+            let legacy = unpack_instruction(input).unwrap().pack();
+            assert_eq!(
+                BitokuInstructions::unpack(input).map(|ix| ix.pack()).ok(),
+                cfg!(feature = "legacy-wire").then_some(legacy)
+            );
+        }
+    }
+}
@@ -14,9 +14,57 @@
    limitations under the License.
 */
 
-#[cfg(not(feature = "no-entrypoint"))]
+//! Native Solana program and client library for the Bitoku agent.
+//!
+//! The crate only uses `core` and `alloc`, so the account layouts, instruction
+//! encodings and checks can be shared with `no_std` tooling. Everything else sits
+//! behind features, all on by default:
+//!
+//! - `program`: the processor and, unless `no-entrypoint` is set, the entrypoint.
+//! - `client`: the instruction builders in [`instruction`] and the [`pool`] of
+//!   registered clients.
+//! - `std`: `std::error::Error` for the error types.
+//! - `legacy-wire`: the instruction encodings that predate Borsh.
+//!
+//! `serde` derives serde on the public types, `rpc` adds `client::BitokuClient`,
+//! a blocking client over `RpcClient`, `async-client` adds its async counterpart
+//! `client::nonblocking::AsyncBitokuClient`, and `wasm` exports request packing to
+//! JavaScript on `wasm32` targets, see `src/wasm.rs`.
+//!
+//! `--no-default-features` leaves the core layer: [`state`], the instruction
+//! enums with their packing, [`error`], [`events`], [`pda`], [`policy`], [`envelope`],
+//! [`plan`], [`audit`], [`migration`], [`compute`] and [`protocol`].
+//! `solana-program` itself still links `std` on host targets.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod audit;
+#[cfg(all(test, feature = "program"))]
+mod chaos;
+#[cfg(feature = "rpc")]
+pub mod client;
+pub mod compute;
+#[cfg(all(feature = "program", not(feature = "no-entrypoint")))]
 pub mod entrypoint;
+pub mod envelope;
 pub mod error;
+pub mod events;
 pub mod instruction;
+#[cfg(any(test, feature = "legacy-wire"))]
+mod legacy_wire;
+pub mod migration;
+pub mod pda;
+pub mod plan;
+pub mod policy;
+#[cfg(feature = "client")]
+pub mod pool;
+#[cfg(feature = "program")]
 pub mod processor;
+pub mod protocol;
 pub mod state;
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+pub mod wasm;
@@ -0,0 +1,694 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Checks for code that moves live accounts between layouts, and the upgrades
+//! `MigrateAccount` applies to accounts stored in an older one. The baseline,
+//! v0 and appended-field tables below are frozen: a change to `RequestData` or
+//! `BookKeeper` that moves a field fails their tests instead of silently
+//! misreading existing accounts.
+
+use core::fmt;
+
+use alloc::{borrow::Cow, vec, vec::Vec};
+
+use solana_program::{program_error::ProgramError, program_pack::Pack, pubkey::Pubkey};
+
+use crate::instruction::{pack_request, Request};
+use crate::pda::{find_bookkeeper_pda, find_request_pda};
+use crate::policy::NamePolicyKind;
+use crate::state::{
+    AccountType, BookKeeper, RequestData, ACCOUNT_HEADER_LEN, ACCOUNT_VERSION, ALL_REQUEST_KINDS,
+    DEFAULT_MAX_CLIENT_REQUESTS_PER_EPOCH, MAX_AGENTS, PREFIX_LEN, RESERVED_PREFIXES,
+};
+
+/// Where a field sits in the full layout of an account, header included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FieldLayout {
+    pub name: &'static str,
+    pub offset: usize,
+    pub len: usize,
+}
+
+const fn at(name: &'static str, offset: usize, len: usize) -> FieldLayout {
+    FieldLayout { name, offset, len }
+}
+
+/// Layout of the 675-byte `RequestData` accounts of the first release, which
+/// had no header. `request` held the tag, the name, then the file id and the
+/// data or position, and each request was written over the last one without
+/// clearing it.
+pub const REQUEST_DATA_BASELINE: &[FieldLayout] = &[
+    at("client_id", 0, 1),
+    at("requester", 1, 32),
+    at("request", 33, 642),
+];
+
+/// Layout of the 33-byte `BookKeeper` account of the first release, which had
+/// no header.
+pub const BOOKKEEPER_BASELINE: &[FieldLayout] = &[at("status", 0, 32), at("next_id", 32, 1)];
+
+/// Full layout of `RequestData` accounts as of wire format 0.
+pub const REQUEST_DATA_V0: &[FieldLayout] = &[
+    at("account_type", 0, 1),
+    at("is_initialized", 1, 1),
+    at("client_id", 2, 1),
+    at("requester", 3, 32),
+    at("request", 35, 650),
+    at("position", 685, 8),
+    at("max_written_offset", 693, 8),
+    at("sparse", 701, 1),
+    at("response_ready", 702, 1),
+    at("response", 703, 256),
+    at("submission_ref", 959, 32),
+    at("sequence", 991, 8),
+    at("submitted_at", 999, 8),
+    at("status", 1007, 1),
+    at("response_len", 1008, 2),
+    at("response_data", 1010, 512),
+];
+
+/// Layout of the `BookKeeper` account as of wire format 0.
+pub const BOOKKEEPER_V0: &[FieldLayout] = &[
+    at("account_type", 0, 1),
+    at("is_initialized", 1, 1),
+    at("status", 2, 32),
+    at("next_id", 34, 1),
+    at("authority", 35, 32),
+    at("reserved_prefixes", 67, 128),
+    at("supported_kinds", 195, 4),
+    at("agent", 199, 32),
+    at("free_slots", 231, 32),
+    at("name_policy", 263, 1),
+    at("max_requests_per_epoch", 264, 4),
+    at("current_backlog", 268, 2),
+];
+
+/// Fields appended to `RequestData` accounts since wire format 0, oldest first.
+/// An account may end after any of them, or right after `REQUEST_DATA_V0`; the
+/// last one ends the current layout.
+pub const REQUEST_DATA_APPENDED: &[FieldLayout] = &[
+    at("nonce", 1522, 8),
+    at("bump", 1530, 1),
+    at("requests_this_epoch", 1531, 8),
+];
+
+/// Fields appended to the `BookKeeper` account since wire format 0, as in
+/// `REQUEST_DATA_APPENDED`.
+pub const BOOKKEEPER_APPENDED: &[FieldLayout] = &[
+    at("paused", 270, 1),
+    at("bump", 271, 1),
+    at("agents", 272, 128),
+    at("max_client_requests_per_epoch", 400, 8),
+    at("last_heartbeat_slot", 408, 8),
+    at("admin", 416, 32),
+];
+
+/// A field whose bytes differ between two versions of an account. Bytes missing
+/// from a truncated account come back empty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MigrationError {
+    UnknownLayout,
+    FieldChanged(&'static str),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::UnknownLayout => f.write_str("account is not in any known layout"),
+            MigrationError::FieldChanged(field) => {
+                write!(f, "field {} did not survive the migration", field)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MigrationError {}
+
+impl From<MigrationError> for ProgramError {
+    fn from(_: MigrationError) -> Self {
+        ProgramError::InvalidAccountData
+    }
+}
+
+fn field<'a>(bytes: &'a [u8], layout: &FieldLayout) -> &'a [u8] {
+    bytes
+        .get(layout.offset..layout.offset + layout.len)
+        .unwrap_or_default()
+}
+
+fn named(layouts: &'static [FieldLayout], name: &str) -> &'static FieldLayout {
+    layouts.iter().find(|layout| layout.name == name).unwrap()
+}
+
+//length of an account that ends after the last of `layouts`
+fn end(layouts: &[FieldLayout]) -> usize {
+    layouts
+        .last()
+        .map_or(0, |layout| layout.offset + layout.len)
+}
+
+//whether an account of `len` bytes, header included, ends after one of the fields
+fn is_known_len(len: usize, v0: &[FieldLayout], appended: &[FieldLayout]) -> bool {
+    v0.iter()
+        .last()
+        .into_iter()
+        .chain(appended)
+        .any(|layout| layout.offset + layout.len == len)
+}
+
+//whether the appended field `name` was added after an account of `len` bytes was laid out
+fn lacks(appended: &[FieldLayout], len: usize, name: &str) -> bool {
+    appended
+        .iter()
+        .any(|layout| layout.name == name && layout.offset >= len)
+}
+
+/// Whether `src` is a bookkeeper stored by the first release, which
+/// `upgrade_bookkeeper` hands to the fee_payer.
+pub fn is_baseline_bookkeeper(src: &[u8]) -> bool {
+    src.len() == end(BOOKKEEPER_BASELINE)
+}
+
+/// The bookkeeper stored in `src` in the current layout or an older one, with
+/// the fields added since set as `InitBitoku` sets them. The first release's
+/// bookkeeper had no authority, so `fee_payer` becomes its authority, agent and
+/// admin, as it would for `InitBitoku`; `MigrateAccount` only lets the program's
+/// upgrade authority do that.
+pub fn upgrade_bookkeeper(
+    src: &[u8],
+    fee_payer: &Pubkey,
+    program_id: &Pubkey,
+) -> Result<BookKeeper, MigrationError> {
+    if is_baseline_bookkeeper(src) {
+        return Ok(upgrade_baseline_bookkeeper(src, fee_payer, program_id));
+    }
+    if !is_known_len(src.len(), BOOKKEEPER_V0, BOOKKEEPER_APPENDED) {
+        return Err(MigrationError::UnknownLayout);
+    }
+    let mut full = src.to_vec();
+    full.resize(BookKeeper::LEN, 0);
+    let mut bookkeeper = BookKeeper::unpack(&full).map_err(|_| MigrationError::UnknownLayout)?;
+
+    if lacks(BOOKKEEPER_APPENDED, src.len(), "bump") {
+        bookkeeper.bump = find_bookkeeper_pda(program_id).1;
+    }
+    if lacks(
+        BOOKKEEPER_APPENDED,
+        src.len(),
+        "max_client_requests_per_epoch",
+    ) {
+        bookkeeper.max_client_requests_per_epoch = DEFAULT_MAX_CLIENT_REQUESTS_PER_EPOCH;
+    }
+    if lacks(BOOKKEEPER_APPENDED, src.len(), "admin") {
+        bookkeeper.admin = bookkeeper.authority;
+    }
+    Ok(bookkeeper)
+}
+
+//the first release only kept the registered ids and the next fresh one, so the
+//ids below it that aren't registered are the ones RemoveClient freed
+fn upgrade_baseline_bookkeeper(src: &[u8], fee_payer: &Pubkey, program_id: &Pubkey) -> BookKeeper {
+    let mut bookkeeper = BookKeeper {
+        is_initialized: true,
+        status: field(src, named(BOOKKEEPER_BASELINE, "status"))
+            .try_into()
+            .unwrap(),
+        next_id: field(src, named(BOOKKEEPER_BASELINE, "next_id"))[0],
+        authority: *fee_payer,
+        reserved_prefixes: [[0; PREFIX_LEN]; RESERVED_PREFIXES],
+        supported_kinds: ALL_REQUEST_KINDS,
+        agent: *fee_payer,
+        free_slots: [0; 32],
+        name_policy: NamePolicyKind::Default as u8,
+        max_requests_per_epoch: 0,
+        current_backlog: 0,
+        paused: false,
+        bump: find_bookkeeper_pda(program_id).1,
+        agents: [Pubkey::default(); MAX_AGENTS],
+        max_client_requests_per_epoch: DEFAULT_MAX_CLIENT_REQUESTS_PER_EPOCH,
+        last_heartbeat_slot: 0,
+        admin: *fee_payer,
+    };
+    for id in 0..bookkeeper.next_id {
+        if !bookkeeper.is_registered(id) {
+            bookkeeper.unregister_client(id);
+        }
+    }
+    bookkeeper
+}
+
+//a request account of the first release in the v0 full layout. Only the bytes the
+//tag says belong to the request are read, the rest may be left from an earlier
+//one; a write went to the file's position, which the account didn't keep, so it
+//comes back with offset 0
+fn baseline_request_data(src: &[u8]) -> Result<RequestData, MigrationError> {
+    let area = field(src, named(REQUEST_DATA_BASELINE, "request"));
+    let name = area[1..129].try_into().unwrap();
+    let file_id = area[129];
+    let data = area[130..642].try_into().unwrap();
+    let request = match area[0] {
+        0 => Request::CreateBucket { name },
+        1 => Request::CreateFile {
+            name,
+            sparse: false,
+            data,
+        },
+        2 => Request::WriteFile {
+            name,
+            file_id,
+            offset: 0,
+            data,
+        },
+        3 => Request::CloseFile { name, file_id },
+        4 => Request::DeleteFile { name, file_id },
+        5 => Request::SetPosition {
+            name,
+            file_id,
+            position: u64::from_le_bytes(area[130..138].try_into().unwrap()),
+        },
+        6 => Request::OpenFile { name, file_id },
+        7 => Request::ReadFile {
+            name,
+            file_id,
+            min_sequence: 0,
+        },
+        _ => return Err(MigrationError::UnknownLayout),
+    };
+
+    let mut full = vec![0; RequestData::LEN];
+    full[..ACCOUNT_HEADER_LEN].copy_from_slice(&[AccountType::RequestData as u8, ACCOUNT_VERSION]);
+    for name in ["client_id", "requester"] {
+        let (old, new) = (
+            named(REQUEST_DATA_BASELINE, name),
+            named(REQUEST_DATA_V0, name),
+        );
+        full[new.offset..new.offset + new.len].copy_from_slice(field(src, old));
+    }
+    let new = named(REQUEST_DATA_V0, "request");
+    pack_request(&request, &mut full[new.offset..new.offset + new.len])
+        .map_err(|_| MigrationError::UnknownLayout)?;
+    RequestData::unpack(&full).map_err(|_| MigrationError::UnknownLayout)
+}
+
+//a request account in an older full layout, prefixed with the header if it
+//predates it; accounts from before the header are otherwise in the full layout
+fn with_header(src: &[u8]) -> Option<Cow<'_, [u8]>> {
+    if is_known_len(src.len(), REQUEST_DATA_V0, REQUEST_DATA_APPENDED) {
+        return Some(Cow::Borrowed(src));
+    }
+    if !is_known_len(
+        src.len() + ACCOUNT_HEADER_LEN,
+        REQUEST_DATA_V0,
+        REQUEST_DATA_APPENDED,
+    ) {
+        return None;
+    }
+    let mut full = Vec::with_capacity(RequestData::LEN);
+    full.extend_from_slice(&[AccountType::RequestData as u8, ACCOUNT_VERSION]);
+    full.extend_from_slice(src);
+    Some(Cow::Owned(full))
+}
+
+/// The request account of `owner` stored in `src` in any current layout or an
+/// older full one, with or without the header, with the fields added since set
+/// as `RegisterClient` sets them.
+pub fn upgrade_request_data(
+    src: &[u8],
+    owner: &Pubkey,
+    program_id: &Pubkey,
+) -> Result<RequestData, MigrationError> {
+    let current = RequestData::unpack_account(src).ok();
+    if let Some(request_data) = current.filter(|data| data.is_initialized) {
+        return Ok(request_data);
+    }
+    if src.len() == end(REQUEST_DATA_BASELINE) {
+        let mut request_data = baseline_request_data(src)?;
+        request_data.bump = find_request_pda(owner, program_id).1;
+        return Ok(request_data);
+    }
+
+    let older = with_header(src).ok_or(MigrationError::UnknownLayout)?;
+    let len = older.len();
+    let mut full = older.into_owned();
+    full.resize(RequestData::LEN, 0);
+    let mut request_data = RequestData::unpack(&full).map_err(|_| MigrationError::UnknownLayout)?;
+
+    if lacks(REQUEST_DATA_APPENDED, len, "bump") {
+        request_data.bump = find_request_pda(owner, program_id).1;
+    }
+    Ok(request_data)
+}
+
+/// Fields of the request account `old_bytes`, stored in any layout, that
+/// writing `new` in the full layout would change.
+pub fn diff_layouts(old_bytes: &[u8], new: &RequestData) -> Vec<FieldChange> {
+    let baseline = match old_bytes.len() == end(REQUEST_DATA_BASELINE) {
+        true => baseline_request_data(old_bytes).ok(),
+        false => None,
+    };
+    let old = match baseline {
+        Some(old) => old.full_layout().into(),
+        None => RequestData::full_bytes(old_bytes).unwrap_or(old_bytes.into()),
+    };
+    let new = new.full_layout();
+    REQUEST_DATA_V0
+        .iter()
+        .filter(|layout| field(&old, layout) != field(&new, layout))
+        .map(|layout| FieldChange {
+            field: layout.name,
+            old: field(&old, layout).to_vec(),
+            new: field(&new, layout).to_vec(),
+        })
+        .collect()
+}
+
+/// Checks that every field of the request account `old_bytes` reads back
+/// byte-for-byte from `migrated_bytes`, whatever layout either is stored in.
+pub fn verify_migration(old_bytes: &[u8], migrated_bytes: &[u8]) -> Result<(), MigrationError> {
+    let migrated =
+        RequestData::full_bytes(migrated_bytes).map_err(|_| MigrationError::UnknownLayout)?;
+    let old = if old_bytes.len() == end(REQUEST_DATA_BASELINE) {
+        //the fields the first release had are only moved by the header
+        for name in ["client_id", "requester"] {
+            let (old, new) = (
+                named(REQUEST_DATA_BASELINE, name),
+                named(REQUEST_DATA_V0, name),
+            );
+            if field(old_bytes, old) != field(&migrated, new) {
+                return Err(MigrationError::FieldChanged(name));
+            }
+        }
+        baseline_request_data(old_bytes)?.full_layout().into()
+    } else {
+        match with_header(old_bytes) {
+            Some(old) if !RequestData::is_compact(old_bytes) => old,
+            _ => RequestData::full_bytes(old_bytes).map_err(|_| MigrationError::UnknownLayout)?,
+        }
+    };
+    for layout in REQUEST_DATA_V0 {
+        if field(&old, layout).len() != layout.len
+            || field(&old, layout) != field(&migrated, layout)
+        {
+            return Err(MigrationError::FieldChanged(layout.name));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction::Request;
+    use crate::state::{RequestStatus, LEGACY_REQUEST_LEN, MAX_AGENTS};
+
+    //fields must tile the start of the account with no gaps or overlaps; fields
+    //added since v0 are appended after them
+    fn assert_tiles(layouts: &[FieldLayout], len: usize) {
+        let mut next = 0;
+        for layout in layouts {
+            assert_eq!(layout.offset, next, "{}", layout.name);
+            next += layout.len;
+        }
+        assert!(next <= len);
+    }
+
+    //a field name and a change to that field alone
+    type Edit = (&'static str, fn(&mut RequestData));
+
+    fn request_data() -> RequestData {
+        RequestData {
+            is_initialized: true,
+            client_id: 7,
+            requester: Pubkey::new_unique(),
+            request: Request::CreateBucket { name: [b'b'; 128] },
+            position: 1,
+            max_written_offset: 2,
+            sparse: true,
+            response_ready: true,
+            response: [3; 256],
+            submission_ref: [4; 32],
+            sequence: 5,
+            submitted_at: 6,
+            status: RequestStatus::Completed,
+            response_len: 7,
+            response_data: [8; 512],
+            nonce: 0,
+            bump: 0,
+            requests_this_epoch: 0,
+        }
+    }
+
+    #[test]
+    fn test_v0_layouts_frozen() {
+        assert_tiles(REQUEST_DATA_V0, RequestData::LEN);
+        assert_tiles(BOOKKEEPER_V0, BookKeeper::LEN);
+
+        //every field of the current layout lands where v0 put it
+        let data = request_data();
+        let packed = data.full_layout();
+        let edits: [Edit; 6] = [
+            ("client_id", |data| data.client_id += 1),
+            ("request", |data| data.request = Request::EmptyTrash {}),
+            ("sparse", |data| data.sparse = false),
+            ("sequence", |data| data.sequence += 1),
+            ("status", |data| data.status = RequestStatus::Failed),
+            ("response_data", |data| data.response_data[511] = 0),
+        ];
+        for (name, edit) in edits {
+            let mut edited = data.clone();
+            edit(&mut edited);
+            let changes = diff_layouts(&packed, &edited);
+            assert_eq!(changes.len(), 1, "{}", name);
+            assert_eq!(changes[0].field, name);
+        }
+        assert_eq!(diff_layouts(&packed, &data), vec![]);
+
+        let mut bookkeeper = vec![0u8; BookKeeper::LEN];
+        let authority = Pubkey::new_unique();
+        BookKeeper::pack(
+            BookKeeper {
+                is_initialized: true,
+                status: [1; 32],
+                next_id: 2,
+                authority,
+                reserved_prefixes: [[3; 32]; 4],
+                supported_kinds: 4,
+                agent: authority,
+                free_slots: [5; 32],
+                name_policy: 1,
+                max_requests_per_epoch: 6,
+                current_backlog: 7,
+                paused: false,
+                bump: 0,
+                agents: [Pubkey::default(); MAX_AGENTS],
+                max_client_requests_per_epoch: 0,
+                last_heartbeat_slot: 0,
+                admin: authority,
+            },
+            &mut bookkeeper,
+        )
+        .unwrap();
+        let value = |name| {
+            field(
+                &bookkeeper,
+                BOOKKEEPER_V0.iter().find(|l| l.name == name).unwrap(),
+            )
+        };
+        assert_eq!(value("authority"), authority.as_ref());
+        assert_eq!(value("agent"), authority.as_ref());
+        assert_eq!(value("next_id"), [2]);
+        assert_eq!(value("current_backlog"), 7u16.to_le_bytes());
+    }
+
+    #[test]
+    fn test_appended_layouts_frozen() {
+        //appended fields pick up where v0 ends and end the current layout
+        for (v0, appended, len) in [
+            (REQUEST_DATA_V0, REQUEST_DATA_APPENDED, RequestData::LEN),
+            (BOOKKEEPER_V0, BOOKKEEPER_APPENDED, BookKeeper::LEN),
+        ] {
+            let mut next = v0.last().map(|l| l.offset + l.len).unwrap();
+            for layout in appended {
+                assert_eq!(layout.offset, next, "{}", layout.name);
+                next += layout.len;
+            }
+            assert_eq!(next, len);
+        }
+
+        let mut data = request_data();
+        data.nonce = 1;
+        data.bump = 2;
+        data.requests_this_epoch = 3;
+        let packed = data.full_layout();
+        let value = |name| {
+            field(
+                &packed,
+                REQUEST_DATA_APPENDED
+                    .iter()
+                    .find(|l| l.name == name)
+                    .unwrap(),
+            )
+        };
+        assert_eq!(value("nonce"), 1u64.to_le_bytes());
+        assert_eq!(value("bump"), [2]);
+        assert_eq!(value("requests_this_epoch"), 3u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_upgrade_request_data() {
+        let owner = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+        let mut data = request_data();
+        data.bump = find_request_pda(&owner, &program_id).1;
+        let full = data.full_layout();
+
+        assert_eq!(
+            upgrade_request_data(&full, &owner, &program_id)
+                .unwrap()
+                .full_layout(),
+            full
+        );
+        for len in [1522, 1530, 1531] {
+            for older in [&full[..len], &full[ACCOUNT_HEADER_LEN..len]] {
+                let upgraded = upgrade_request_data(older, &owner, &program_id).unwrap();
+                assert_eq!(upgraded.request, data.request);
+                assert_eq!(upgraded.bump, data.bump);
+                assert_eq!(verify_migration(older, &upgraded.full_layout()), Ok(()));
+            }
+        }
+        assert_eq!(
+            upgrade_request_data(&full[..1000], &owner, &program_id).err(),
+            Some(MigrationError::UnknownLayout)
+        );
+        assert_eq!(
+            upgrade_bookkeeper(&[0; 300], &owner, &program_id).err(),
+            Some(MigrationError::UnknownLayout)
+        );
+    }
+
+    #[test]
+    fn test_upgrade_baseline_accounts() {
+        assert_tiles(REQUEST_DATA_BASELINE, 675);
+        assert_tiles(BOOKKEEPER_BASELINE, 33);
+        let owner = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+
+        //a WriteFile, as the first release packed it
+        let mut baseline = vec![0u8; 675];
+        baseline[0] = 7;
+        baseline[1..33].copy_from_slice(owner.as_ref());
+        baseline[33] = 2;
+        baseline[34..37].copy_from_slice(b"b/f");
+        baseline[162] = 3;
+        baseline[163..166].copy_from_slice(b"abc");
+        let upgraded = upgrade_request_data(&baseline, &owner, &program_id).unwrap();
+        let mut data = [0; 512];
+        data[..3].copy_from_slice(b"abc");
+        let mut name = [0; 128];
+        name[..3].copy_from_slice(b"b/f");
+        assert_eq!(
+            upgraded.request,
+            Request::WriteFile {
+                name,
+                file_id: 3,
+                offset: 0,
+                data,
+            }
+        );
+        assert!(upgraded.is_initialized);
+        assert_eq!((upgraded.client_id, upgraded.requester), (7, owner));
+        assert_eq!(upgraded.bump, find_request_pda(&owner, &program_id).1);
+        assert_eq!((upgraded.sequence, upgraded.is_pending()), (0, false));
+        let full = upgraded.full_layout();
+        assert_eq!(verify_migration(&baseline, &full), Ok(()));
+        assert_eq!(diff_layouts(&baseline, &upgraded), vec![]);
+        let mut corrupted = full.clone();
+        corrupted[3] ^= 1;
+        assert_eq!(
+            verify_migration(&baseline, &corrupted),
+            Err(MigrationError::FieldChanged("requester"))
+        );
+
+        //a SetPosition left the write's data behind its position
+        baseline[33] = 5;
+        baseline[163..171].copy_from_slice(&9u64.to_le_bytes());
+        let upgraded = upgrade_request_data(&baseline, &owner, &program_id).unwrap();
+        assert_eq!(
+            upgraded.request,
+            Request::SetPosition {
+                name,
+                file_id: 3,
+                position: 9,
+            }
+        );
+
+        baseline[33] = 8;
+        assert_eq!(
+            upgrade_request_data(&baseline, &owner, &program_id).err(),
+            Some(MigrationError::UnknownLayout)
+        );
+
+        //ids 0 and 2 registered, 1 removed
+        let mut baseline = [0u8; 33];
+        baseline[0] = 0b101;
+        baseline[32] = 3;
+        let fee_payer = Pubkey::new_unique();
+        let upgraded = upgrade_bookkeeper(&baseline, &fee_payer, &program_id).unwrap();
+        assert!(upgraded.is_initialized);
+        assert_eq!(upgraded.registered_clients().collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(upgraded.free_slots[0], 0b10);
+        assert_eq!(upgraded.next_id, 3);
+        assert_eq!(upgraded.first_free(), Some(1));
+        assert_eq!(
+            (upgraded.authority, upgraded.agent, upgraded.admin),
+            (fee_payer, fee_payer, fee_payer)
+        );
+        assert_eq!(upgraded.bump, find_bookkeeper_pda(&program_id).1);
+        assert_eq!(upgraded.supported_kinds, ALL_REQUEST_KINDS);
+    }
+
+    #[test]
+    fn test_verify_migration() {
+        let data = request_data();
+        let full = data.full_layout();
+        let legacy = &full[2..];
+        assert_eq!(legacy.len(), LEGACY_REQUEST_LEN);
+
+        //headerless to full layout, and back
+        assert_eq!(verify_migration(legacy, &full), Ok(()));
+        assert_eq!(verify_migration(&full, legacy), Ok(()));
+        assert_eq!(diff_layouts(legacy, &data), vec![]);
+
+        let mut corrupted = full.clone();
+        corrupted[991] ^= 1;
+        assert_eq!(
+            verify_migration(legacy, &corrupted),
+            Err(MigrationError::FieldChanged("sequence"))
+        );
+        assert_eq!(
+            verify_migration(&full, &full[..1000]),
+            Err(MigrationError::FieldChanged("submitted_at"))
+        );
+    }
+}
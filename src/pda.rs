@@ -0,0 +1,143 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use solana_program::{
+    bpf_loader_upgradeable, hash::hash, program_error::ProgramError, pubkey::Pubkey,
+};
+
+use crate::error::BitokuError::InvalidAccount;
+
+/// Seed of the `BookKeeper` PDA.
+pub const BOOKKEEPER_SEED: &[u8] = b"bookkeeper";
+/// First seed of a client's request PDA; the second is the owner's key.
+pub const REQUEST_SEED: &[u8] = b"request";
+/// First seed of a client's `ClientInfo` PDA; the second is the owner's key.
+pub const CLIENT_INFO_SEED: &[u8] = b"client_info";
+/// First seed of a bucket's lease PDA; the second is the sha256 of the bucket name.
+pub const LEASE_SEED: &[u8] = b"lease";
+
+/// First seed of a `StatsSnapshot` PDA; the second is the epoch as a little endian u64.
+pub const STATS_SEED: &[u8] = b"stats";
+
+/// Address and bump of the program's `BookKeeper` account.
+pub fn find_bookkeeper_pda(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[BOOKKEEPER_SEED], program_id)
+}
+
+/// Address and bump of the request account `RegisterClient` creates for `fee_payer`.
+pub fn find_request_pda(fee_payer: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[REQUEST_SEED, fee_payer.as_ref()], program_id)
+}
+
+/// Address and bump of the `ClientInfo` account `RegisterClient` creates for `owner`.
+pub fn find_client_info_pda(owner: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CLIENT_INFO_SEED, owner.as_ref()], program_id)
+}
+
+/// Address of the `BookKeeper` PDA for the bump stored in it. Cheaper than
+/// `find_bookkeeper_pda`, which tries every bump from 255 down.
+pub fn bookkeeper_pda(program_id: &Pubkey, bump: u8) -> Result<Pubkey, ProgramError> {
+    Pubkey::create_program_address(&[BOOKKEEPER_SEED, &[bump]], program_id)
+        .map_err(|_| InvalidAccount.into())
+}
+
+/// Address of `owner`'s request PDA for the bump stored in it.
+pub fn request_pda(owner: &Pubkey, bump: u8, program_id: &Pubkey) -> Result<Pubkey, ProgramError> {
+    Pubkey::create_program_address(&[REQUEST_SEED, owner.as_ref(), &[bump]], program_id)
+        .map_err(|_| InvalidAccount.into())
+}
+
+/// Address of `owner`'s `ClientInfo` PDA for the bump stored in it.
+pub fn client_info_pda(
+    owner: &Pubkey,
+    bump: u8,
+    program_id: &Pubkey,
+) -> Result<Pubkey, ProgramError> {
+    Pubkey::create_program_address(&[CLIENT_INFO_SEED, owner.as_ref(), &[bump]], program_id)
+        .map_err(|_| InvalidAccount.into())
+}
+
+/// Address and bump of the `BucketLease` account of the zero-padded `bucket` name.
+pub fn find_lease_pda(bucket: &[u8; 128], program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[LEASE_SEED, hash(bucket).as_ref()], program_id)
+}
+
+/// Address and bump of the `StatsSnapshot` account published for `epoch`.
+pub fn find_stats_pda(epoch: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[STATS_SEED, &epoch.to_le_bytes()], program_id)
+}
+
+/// Address of the ProgramData account the upgradeable loader keeps for
+/// `program_id`, which records its upgrade authority.
+pub fn find_program_data_address(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id()).0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pdas_match_processor_seeds() {
+        let program_id = Pubkey::new_unique();
+        let fee_payer = Pubkey::new_unique();
+
+        assert_eq!(
+            find_bookkeeper_pda(&program_id),
+            Pubkey::find_program_address(&["bookkeeper".as_ref()], &program_id)
+        );
+        assert_eq!(
+            find_request_pda(&fee_payer, &program_id),
+            Pubkey::find_program_address(&["request".as_ref(), fee_payer.as_ref()], &program_id)
+        );
+        assert_ne!(
+            find_request_pda(&fee_payer, &program_id).0,
+            find_request_pda(&Pubkey::new_unique(), &program_id).0
+        );
+        assert_eq!(
+            find_client_info_pda(&fee_payer, &program_id),
+            Pubkey::find_program_address(
+                &["client_info".as_ref(), fee_payer.as_ref()],
+                &program_id
+            )
+        );
+        assert_ne!(
+            find_lease_pda(&[b'a'; 128], &program_id).0,
+            find_lease_pda(&[b'b'; 128], &program_id).0
+        );
+        assert_eq!(
+            find_stats_pda(12, &program_id),
+            Pubkey::find_program_address(&["stats".as_ref(), &12u64.to_le_bytes()], &program_id)
+        );
+    }
+
+    #[test]
+    fn test_stored_bumps_derive_same_address() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let (bookkeeper, bump) = find_bookkeeper_pda(&program_id);
+        assert_eq!(bookkeeper_pda(&program_id, bump), Ok(bookkeeper));
+        let (request, bump) = find_request_pda(&owner, &program_id);
+        assert_eq!(request_pda(&owner, bump, &program_id), Ok(request));
+        assert_ne!(
+            request_pda(&Pubkey::new_unique(), bump, &program_id),
+            Ok(request)
+        );
+        let (client_info, bump) = find_client_info_pda(&owner, &program_id);
+        assert_eq!(client_info_pda(&owner, bump, &program_id), Ok(client_info));
+    }
+}
@@ -0,0 +1,247 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Off-chain cost estimates for uploads and registrations.
+//!
+//! A request account holds one request at a time, so every request is its own
+//! transaction. The rent sysvar and the fee rate are left to the caller to
+//! fetch from its RPC node.
+
+use core::fmt;
+
+use solana_program::{native_token::lamports_to_sol, program_pack::Pack, rent::Rent};
+
+use crate::state::{ClientInfo, RequestData, CHUNK_LEN, COMPACT_BASE_LEN};
+
+/// How an upload is split into requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChunkStrategy {
+    /// A `CreateFile` carrying the first chunk, then a `WriteFile` per further chunk.
+    CreateThenWrite,
+    /// An empty `CreateFile`, then a `WriteAt` per chunk as `send_write_at` builds them.
+    CreateThenWriteAt,
+}
+
+/// What a transaction costs, as quoted by the cluster.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeePrice {
+    pub lamports_per_signature: u64,
+    /// Priority fee, in micro-lamports per compute unit.
+    pub compute_unit_price: u64,
+    /// Compute units requested per transaction.
+    pub compute_unit_limit: u32,
+}
+
+impl FeePrice {
+    /// Fee of a transaction with one signature: the base fee plus the priority
+    /// fee, rounded up to the lamport as the runtime does.
+    pub fn per_transaction(&self) -> u64 {
+        let priority = (self.compute_unit_price as u128 * self.compute_unit_limit as u128)
+            .div_ceil(1_000_000)
+            .min(u64::MAX as u128) as u64;
+        self.lamports_per_signature.saturating_add(priority)
+    }
+}
+
+/// Cost breakdown of uploading one file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UploadPlan {
+    pub data_len: u64,
+    pub strategy: ChunkStrategy,
+    /// 512-byte chunks the data is split into; the last one is zero-padded.
+    pub chunks: u64,
+    pub transactions: u64,
+    pub fee_per_transaction: u64,
+    pub fees: u64,
+}
+
+/// Cost breakdown of registering a client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RegistrationPlan {
+    /// Size the request account is created with.
+    pub account_len: usize,
+    /// Lamports moved into the request account and the client's `ClientInfo`
+    /// account to keep both rent exempt.
+    pub rent: u64,
+    pub transactions: u64,
+    pub fees: u64,
+}
+
+impl RegistrationPlan {
+    /// Lamports the fee payer needs for the registration.
+    pub fn total(&self) -> u64 {
+        self.rent.saturating_add(self.fees)
+    }
+}
+
+/// Requests and fees needed to upload `data_len` bytes split with `strategy`.
+pub fn plan_upload(data_len: u64, strategy: ChunkStrategy, fee_price: FeePrice) -> UploadPlan {
+    let chunks = data_len.div_ceil(CHUNK_LEN);
+    let transactions = match strategy {
+        //the first chunk rides along with CreateFile, which is sent even for no data
+        ChunkStrategy::CreateThenWrite => chunks.max(1),
+        ChunkStrategy::CreateThenWriteAt => chunks + 1,
+    };
+    let fee_per_transaction = fee_price.per_transaction();
+
+    UploadPlan {
+        data_len,
+        strategy,
+        chunks,
+        transactions,
+        fee_per_transaction,
+        fees: fee_per_transaction.saturating_mul(transactions),
+    }
+}
+
+/// Rent and fees of a `RegisterClient`, for a compact or a full request account.
+pub fn plan_registration(rent: &Rent, fee_price: FeePrice, compact: bool) -> RegistrationPlan {
+    let account_len = if compact {
+        COMPACT_BASE_LEN
+    } else {
+        RequestData::LEN
+    };
+
+    RegistrationPlan {
+        account_len,
+        rent: rent
+            .minimum_balance(account_len)
+            .saturating_add(rent.minimum_balance(ClientInfo::LEN)),
+        transactions: 1,
+        fees: fee_price.per_transaction(),
+    }
+}
+
+impl fmt::Display for UploadPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "upload of {} bytes: {} chunks in {} transactions, {} lamports in fees ({} SOL)",
+            self.data_len,
+            self.chunks,
+            self.transactions,
+            self.fees,
+            lamports_to_sol(self.fees)
+        )
+    }
+}
+
+impl fmt::Display for RegistrationPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "registration: {} transaction, {} lamports in fees and {} lamports of rent \
+             for a {}-byte request account and its ClientInfo ({} SOL)",
+            self.transactions,
+            self.fees,
+            self.rent,
+            self.account_len,
+            lamports_to_sol(self.total())
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    //5000 lamports base fee and 1000 micro-lamports for each of 200k units: 5200 a transaction
+    const PRICE: FeePrice = FeePrice {
+        lamports_per_signature: 5000,
+        compute_unit_price: 1000,
+        compute_unit_limit: 200_000,
+    };
+
+    #[test]
+    fn test_plan_upload() {
+        let empty = plan_upload(0, ChunkStrategy::CreateThenWrite, PRICE);
+        assert_eq!((empty.chunks, empty.transactions, empty.fees), (0, 1, 5200));
+        let empty = plan_upload(0, ChunkStrategy::CreateThenWriteAt, PRICE);
+        assert_eq!((empty.chunks, empty.transactions, empty.fees), (0, 1, 5200));
+
+        let chunk = plan_upload(512, ChunkStrategy::CreateThenWrite, PRICE);
+        assert_eq!((chunk.chunks, chunk.transactions, chunk.fees), (1, 1, 5200));
+        let chunk = plan_upload(512, ChunkStrategy::CreateThenWriteAt, PRICE);
+        assert_eq!(
+            (chunk.chunks, chunk.transactions, chunk.fees),
+            (1, 2, 10_400)
+        );
+        assert_eq!(
+            plan_upload(513, ChunkStrategy::CreateThenWrite, PRICE).chunks,
+            2
+        );
+
+        //10 MiB is 20480 chunks
+        let large = plan_upload(10 << 20, ChunkStrategy::CreateThenWrite, PRICE);
+        assert_eq!(large.chunks, 20_480);
+        assert_eq!(large.transactions, 20_480);
+        assert_eq!(large.fees, 106_496_000);
+        assert_eq!(
+            large.to_string(),
+            "upload of 10485760 bytes: 20480 chunks in 20480 transactions, \
+             106496000 lamports in fees (0.106496 SOL)"
+        );
+        let large = plan_upload(10 << 20, ChunkStrategy::CreateThenWriteAt, PRICE);
+        assert_eq!((large.transactions, large.fees), (20_481, 106_501_200));
+    }
+
+    #[test]
+    fn test_priority_fee_rounds_up() {
+        let price = FeePrice {
+            lamports_per_signature: 5000,
+            compute_unit_price: 1,
+            compute_unit_limit: 1,
+        };
+        assert_eq!(price.per_transaction(), 5001);
+        let price = FeePrice {
+            compute_unit_price: 0,
+            ..price
+        };
+        assert_eq!(price.per_transaction(), 5000);
+    }
+
+    #[test]
+    fn test_plan_registration() {
+        let rent = Rent::default();
+        let full = plan_registration(&rent, PRICE, false);
+        assert_eq!(full.account_len, RequestData::LEN);
+        //the default rent: 3480 lamports per byte-year over two years, with 128 bytes of
+        //overhead for each of the request and ClientInfo accounts
+        assert_eq!(
+            full.rent,
+            (RequestData::LEN as u64 + 128 + ClientInfo::LEN as u64 + 128) * 3480 * 2
+        );
+        assert_eq!(full.total(), full.rent + 5200);
+
+        let compact = plan_registration(&rent, PRICE, true);
+        assert_eq!(compact.account_len, COMPACT_BASE_LEN);
+        assert!(compact.rent < full.rent);
+        assert_eq!(
+            compact.to_string(),
+            format!(
+                "registration: 1 transaction, 5200 lamports in fees and {} lamports of rent \
+                 for a {}-byte request account and its ClientInfo ({} SOL)",
+                compact.rent,
+                COMPACT_BASE_LEN,
+                lamports_to_sol(compact.rent + 5200)
+            )
+        );
+    }
+}
@@ -0,0 +1,195 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::program_error::ProgramError;
+
+/// Rules a deployment applies to bucket and file names.
+pub trait NamePolicy {
+    /// Whether `name`, already cut at its first zero byte and non-empty, is allowed.
+    fn accepts(&self, name: &[u8]) -> bool;
+
+    /// Whether the zero-padded `name` is allowed; empty names, names longer than
+    /// 128 bytes and names with anything but zeros after the first zero never are.
+    fn validate(&self, name: &[u8]) -> bool {
+        if name.len() > 128 {
+            return false;
+        }
+        let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        len != 0 && name[len..].iter().all(|&b| b == 0) && self.accepts(&name[..len])
+    }
+}
+
+/// ASCII letters, digits and `. / _ + -`.
+pub struct DefaultPolicy;
+
+/// The characters S3 documents as safe in object keys, with `/` as the
+/// delimiter; keys may not start with `/` or contain empty segments.
+pub struct S3CompatPolicy;
+
+/// Any valid UTF-8 without control characters.
+pub struct Utf8Policy;
+
+impl NamePolicy for DefaultPolicy {
+    fn accepts(&self, name: &[u8]) -> bool {
+        name.iter()
+            .all(|&b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'/' | b'_' | b'+' | b'-'))
+    }
+}
+
+impl NamePolicy for S3CompatPolicy {
+    fn accepts(&self, name: &[u8]) -> bool {
+        name.iter().all(|&b| {
+            b.is_ascii_alphanumeric()
+                || matches!(
+                    b,
+                    b'/' | b'!' | b'-' | b'_' | b'.' | b'*' | b'\'' | b'(' | b')'
+                )
+        }) && name
+            .split(|&b| b == b'/')
+            .all(|segment| !segment.is_empty())
+    }
+}
+
+impl NamePolicy for Utf8Policy {
+    fn accepts(&self, name: &[u8]) -> bool {
+        match core::str::from_utf8(name) {
+            Ok(name) => !name.chars().any(char::is_control),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Policy selected on-chain through `BookKeeper::name_policy`.
+#[repr(u8)]
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NamePolicyKind {
+    Default = 0,
+    S3Compat = 1,
+    Utf8 = 2,
+}
+
+impl NamePolicyKind {
+    pub fn from_u8(value: u8) -> Result<Self, ProgramError> {
+        Ok(match value {
+            0 => Self::Default,
+            1 => Self::S3Compat,
+            2 => Self::Utf8,
+            _ => return Err(ProgramError::InvalidAccountData),
+        })
+    }
+}
+
+impl NamePolicy for NamePolicyKind {
+    fn accepts(&self, name: &[u8]) -> bool {
+        match self {
+            Self::Default => DefaultPolicy.accepts(name),
+            Self::S3Compat => S3CompatPolicy.accepts(name),
+            Self::Utf8 => Utf8Policy.accepts(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(policy: &dyn NamePolicy, cases: &[(&[u8], bool)]) {
+        for (name, expected) in cases {
+            assert_eq!(
+                policy.validate(name),
+                *expected,
+                "{:?}",
+                String::from_utf8_lossy(name)
+            );
+        }
+    }
+
+    #[test]
+    fn test_default_policy() {
+        check(
+            &DefaultPolicy,
+            &[
+                (b"bucket/file-1.txt", true),
+                (b"a+b_c", true),
+                (b"/leading", true),
+                (b"", false),
+                (b"\0tail", false),
+                (b"file\0\0\0", true),
+                (b"file\0hidden tail!", false),
+                (b"file\0\0\x01", false),
+                (&[0u8; 128], false),
+                (&[b'n', 0xff, 0xfe], false),
+                (b"with space", false),
+                (b"file(1)", false),
+                ("donn\u{e9}es".as_bytes(), false),
+                (&[b'a'; 129], false),
+                (&[b'a'; 128], true),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_s3_compat_policy() {
+        check(
+            &S3CompatPolicy,
+            &[
+                (b"bucket/file(1)!.txt", true),
+                (b"it's/*", true),
+                (b"a+b", false),
+                (b"/leading", false),
+                (b"trailing/", false),
+                (b"double//slash", false),
+                (b"with space", false),
+                ("donn\u{e9}es".as_bytes(), false),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_utf8_policy() {
+        check(
+            &Utf8Policy,
+            &[
+                ("donn\u{e9}es/\u{1f4c1} file".as_bytes(), true),
+                (b"with space", true),
+                (b"tab\there", false),
+                (&[0xff, 0xfe], false),
+                (&[b'a', 0xc3], false),
+                (b"", false),
+                ("caf\u{e9}\0\u{e9}".as_bytes(), false),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_policy_kind() {
+        for kind in [
+            NamePolicyKind::Default,
+            NamePolicyKind::S3Compat,
+            NamePolicyKind::Utf8,
+        ] {
+            assert_eq!(NamePolicyKind::from_u8(kind as u8).unwrap(), kind);
+        }
+        assert_eq!(
+            NamePolicyKind::from_u8(3).err(),
+            Some(ProgramError::InvalidAccountData)
+        );
+        assert!(NamePolicyKind::S3Compat.validate(b"file(1)"));
+        assert!(!NamePolicyKind::Default.validate(b"file(1)"));
+    }
+}
@@ -0,0 +1,204 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Off-chain bookkeeping for a service that drives many client registrations.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{instruction::Instruction, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::error::BitokuError::UnregisteredClient;
+use crate::instruction::{
+    bookkeeper_address, register_client_auto, remove_client, request_address, send_request, Request,
+};
+use crate::state::RequestData;
+
+/// What the pool knows about one authority.
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PooledClient {
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::pubkey_serde"))]
+    pub request: Pubkey,
+    pub bump: u8,
+    /// `None` until the registration has been confirmed with `confirm`.
+    pub client_id: Option<u8>,
+}
+
+/// The next instruction an authority needs signed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PoolStep {
+    /// The authority has no confirmed registration yet; submit this, `confirm`,
+    /// then call `send_for` again for the request, which wasn't built.
+    Register(Instruction),
+    Send(Instruction),
+}
+
+/// Registrations of many authorities against one program, keyed by authority.
+///
+/// The pool only builds instructions; signing and submitting them is left to
+/// the caller. Its borsh encoding is the snapshot a service persists across
+/// restarts.
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClientPool {
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::pubkey_serde"))]
+    pub program: Pubkey,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::pubkey_map_serde"))]
+    clients: BTreeMap<Pubkey, PooledClient>,
+}
+
+impl ClientPool {
+    pub fn new(program: Pubkey) -> Self {
+        Self {
+            program,
+            clients: BTreeMap::new(),
+        }
+    }
+
+    pub fn from_snapshot(snapshot: &[u8]) -> Result<Self, ProgramError> {
+        Self::try_from_slice(snapshot).map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    pub fn snapshot(&self) -> Vec<u8> {
+        self.try_to_vec().unwrap()
+    }
+
+    pub fn client(&self, authority: &Pubkey) -> Option<&PooledClient> {
+        self.clients.get(authority)
+    }
+
+    pub fn authorities(&self) -> impl Iterator<Item = &Pubkey> {
+        self.clients.keys()
+    }
+
+    /// Records the client id `RegisterClient` assigned, read from the data of
+    /// the authority's request account, `client(authority).request`.
+    pub fn confirm(
+        &mut self,
+        authority: &Pubkey,
+        request_account: &[u8],
+    ) -> Result<u8, ProgramError> {
+        let data = RequestData::unpack_account(request_account)?;
+        if !data.is_initialized {
+            return Err(UnregisteredClient.into());
+        }
+        self.entry(authority).client_id = Some(data.client_id);
+        Ok(data.client_id)
+    }
+
+    /// Sends `request` for `authority`, or registers it first if the pool
+    /// has no confirmed client id for it.
+    ///
+    /// `SendRequest` needs the id `RegisterClient` assigns, so a `Register` step
+    /// carries only the registration: once it has landed and `confirm` has read
+    /// the id, the caller must call `send_for` again with the same request.
+    pub fn send_for(
+        &mut self,
+        authority: &Pubkey,
+        request: Request,
+    ) -> Result<PoolStep, ProgramError> {
+        let program = self.program;
+        let client = *self.entry(authority);
+        match client.client_id {
+            Some(client_id) => Ok(PoolStep::Send(send_request(
+                *authority,
+                client.request,
+                program,
+                client_id,
+                request,
+            )?)),
+            None => Ok(PoolStep::Register(
+                register_client_auto(*authority, program)?.0,
+            )),
+        }
+    }
+
+    /// Forgets `authority` and returns the `RemoveClient` that releases its id.
+    pub fn remove(&mut self, authority: &Pubkey) -> Result<Instruction, ProgramError> {
+        let client_id = self
+            .client(authority)
+            .and_then(|client| client.client_id)
+            .ok_or(UnregisteredClient)?;
+        let client = self.clients.remove(authority).unwrap();
+        let (bookkeeper, _) = bookkeeper_address(&self.program);
+        remove_client(
+            *authority,
+            bookkeeper,
+            client.request,
+            self.program,
+            client_id,
+        )
+    }
+
+    fn entry(&mut self, authority: &Pubkey) -> &mut PooledClient {
+        let program = self.program;
+        self.clients.entry(*authority).or_insert_with(|| {
+            let (request, bump) = request_address(&program, authority);
+            PooledClient {
+                request,
+                bump,
+                client_id: None,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let program = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let mut pool = ClientPool::new(program);
+        let bucket = Request::CreateBucket { name: [1; 128] };
+
+        assert!(matches!(
+            pool.send_for(&authority, bucket).unwrap(),
+            PoolStep::Register(_)
+        ));
+        assert_eq!(
+            pool.client(&authority).unwrap().request,
+            request_address(&program, &authority).0
+        );
+        assert_eq!(pool.remove(&authority), Err(UnregisteredClient.into()));
+
+        let restored = ClientPool::from_snapshot(&pool.snapshot()).unwrap();
+        assert_eq!(restored, pool);
+        assert!(ClientPool::from_snapshot(&[0; 3]).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_pool_json_roundtrip() {
+        let mut pool = ClientPool::new(Pubkey::new_unique());
+        let (confirmed, unconfirmed) = (Pubkey::new_unique(), Pubkey::new_unique());
+        pool.entry(&confirmed).client_id = Some(4);
+        pool.entry(&unconfirmed);
+
+        //authorities are object keys, so they are written as base58 text
+        let json = serde_json::to_string(&pool).unwrap();
+        assert!(json.contains(&format!("\"{}\":", confirmed)));
+        let decoded: ClientPool = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, pool);
+
+        let invalid = json.replace(&confirmed.to_string(), "not-a-key");
+        assert!(serde_json::from_str::<ClientPool>(&invalid).is_err());
+    }
+}
@@ -14,29 +14,62 @@
    limitations under the License.
 */
 
+use alloc::{format, vec};
+
+use borsh::BorshSerialize;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
     entrypoint::ProgramResult,
+    hash::hash,
+    log::sol_log_data,
     msg,
-    program::invoke_signed,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
     program_memory::sol_memset,
     program_pack::Pack,
     pubkey::Pubkey,
-    system_instruction::create_account,
-    sysvar::{rent::Rent, Sysvar},
+    system_instruction::{create_account, transfer},
+    sysvar::{self, clock::Clock, instructions::get_instruction_relative, rent::Rent, Sysvar},
 };
 
 use crate::{
+    envelope::SignedRequestEnvelope,
     error::BitokuError::{
-        ClientMismatch, InvalidAccount, InvalidName, NoAvailableClients, Overflow,
-        UnregisteredClient,
+        self, AccountAlreadyInitialized, AgentLimitReached, BucketLeased, ClientMismatch,
+        InvalidAccount, InvalidInstructionData, InvalidSignature, InvalidStatusTransition,
+        NoAvailableClients, NoRequest, Overflow, PermissionDenied, ProgramPaused,
+        RateLimitExceeded, RequestPending, ReservedName, StaleNonce, Unauthorized,
+        UnauthorizedAgent, UnregisteredClient, UnsupportedRequestKind,
     },
+    events::{BitokuEvent, ClientRegistered, ClientRemoved, RequestSubmitted},
     instruction::{BitokuInstructions, Request},
-    state::{addel, delel, isel, validate_name, BookKeeper, RequestData},
+    migration::{is_baseline_bookkeeper, upgrade_bookkeeper, upgrade_request_data},
+    pda::{
+        bookkeeper_pda, client_info_pda, find_bookkeeper_pda, find_client_info_pda, find_lease_pda,
+        find_program_data_address, find_request_pda, find_stats_pda, request_pda, BOOKKEEPER_SEED,
+        CLIENT_INFO_SEED, LEASE_SEED, REQUEST_SEED, STATS_SEED,
+    },
+    policy::NamePolicyKind,
+    state::{
+        name_to_str, BookKeeper, BucketLease, Capabilities, ClientInfo, RequestData, RequestStatus,
+        StatsSnapshot, ALL_PERMISSIONS, ALL_REQUEST_KINDS, COMPACT_BASE_LEN,
+        DEFAULT_MAX_CLIENT_REQUESTS_PER_EPOCH, MAX_AGENTS, PREFIX_LEN, RESERVED_PREFIXES,
+        RESPONSE_LEN,
+    },
 };
 
+#[cfg(debug_assertions)]
+use crate::migration::verify_migration;
+
 pub struct Processor;
 
+//logs which value overflowed, as the error alone doesn't say
+fn overflow(value: &str) -> BitokuError {
+    msg!("Error: Overflow: {}", value);
+    Overflow
+}
+
 impl Processor {
     pub fn process(
         program_id: &Pubkey,
@@ -50,9 +83,9 @@ impl Processor {
                 msg!("Instruction : InitBitoku");
                 Self::process_init_bitoku(accounts, program_id)
             }
-            BitokuInstructions::RegisterClient => {
+            BitokuInstructions::RegisterClient { compact } => {
                 msg!("Instruction : RegisterClient");
-                self::Processor::process_register_client(accounts, program_id)
+                self::Processor::process_register_client(accounts, program_id, compact)
             }
 
             BitokuInstructions::RemoveClient { client_id } => {
@@ -60,9 +93,185 @@ impl Processor {
                 self::Processor::process_remove_client(accounts, program_id, client_id)
             }
 
-            BitokuInstructions::SendRequest { request, client_id } => {
+            BitokuInstructions::SendRequest {
+                request,
+                client_id,
+                submission_ref,
+            } => {
                 msg!("Instruction : SendRequest");
-                self::Processor::process_send_request(accounts, program_id, request, client_id)
+                self::Processor::process_send_request(
+                    accounts,
+                    program_id,
+                    request,
+                    client_id,
+                    submission_ref,
+                    false,
+                )
+            }
+
+            BitokuInstructions::OverwriteRequest {
+                request,
+                client_id,
+                submission_ref,
+            } => {
+                msg!("Instruction : OverwriteRequest");
+                self::Processor::process_send_request(
+                    accounts,
+                    program_id,
+                    request,
+                    client_id,
+                    submission_ref,
+                    true,
+                )
+            }
+
+            BitokuInstructions::GetVersion => {
+                msg!("Instruction : GetVersion");
+                self::Processor::process_get_version(accounts, program_id)
+            }
+
+            BitokuInstructions::RegisterClientFor { target } => {
+                msg!("Instruction : RegisterClientFor");
+                self::Processor::process_register_client_for(accounts, program_id, target)
+            }
+
+            BitokuInstructions::SetReservedPrefix { index, prefix } => {
+                msg!("Instruction : SetReservedPrefix");
+                self::Processor::process_set_reserved_prefix(accounts, program_id, index, prefix)
+            }
+
+            BitokuInstructions::PublishStats => {
+                msg!("Instruction : PublishStats");
+                self::Processor::process_publish_stats(accounts, program_id)
+            }
+
+            BitokuInstructions::SetSupportedKinds { supported_kinds } => {
+                msg!("Instruction : SetSupportedKinds");
+                self::Processor::process_set_supported_kinds(accounts, program_id, supported_kinds)
+            }
+
+            BitokuInstructions::UpdateRequestStatus { client_id, status } => {
+                msg!("Instruction : UpdateRequestStatus");
+                self::Processor::process_update_request_status(
+                    accounts, program_id, client_id, status,
+                )
+            }
+
+            BitokuInstructions::WriteResponse {
+                client_id,
+                data,
+                len,
+                status,
+            } => {
+                msg!("Instruction : WriteResponse");
+                self::Processor::process_write_response(
+                    accounts, program_id, client_id, data, len, status,
+                )
+            }
+
+            BitokuInstructions::SetAgent { agent } => {
+                msg!("Instruction : SetAgent");
+                self::Processor::process_set_agent(accounts, program_id, agent)
+            }
+
+            BitokuInstructions::SetAuthority { new_authority } => {
+                msg!("Instruction : SetAuthority");
+                self::Processor::process_set_authority(accounts, program_id, new_authority)
+            }
+
+            BitokuInstructions::ForceRemoveClient { client_id } => {
+                msg!("Instruction : ForceRemoveClient");
+                self::Processor::process_force_remove_client(accounts, program_id, client_id)
+            }
+
+            BitokuInstructions::SetPaused { paused } => {
+                msg!("Instruction : SetPaused");
+                self::Processor::process_set_paused(accounts, program_id, paused)
+            }
+
+            BitokuInstructions::SubmitSignedRequest {
+                client_id,
+                nonce,
+                signer,
+                signature,
+                request,
+            } => {
+                msg!("Instruction : SubmitSignedRequest");
+                let envelope =
+                    SignedRequestEnvelope::new(request, client_id, nonce, signer, signature);
+                self::Processor::process_submit_signed_request(accounts, program_id, envelope)
+            }
+
+            BitokuInstructions::SetNamePolicy { policy } => {
+                msg!("Instruction : SetNamePolicy");
+                self::Processor::process_set_name_policy(accounts, program_id, policy)
+            }
+
+            BitokuInstructions::PublishLoad {
+                max_requests_per_epoch,
+                current_backlog,
+            } => {
+                msg!("Instruction : PublishLoad");
+                self::Processor::process_publish_load(
+                    accounts,
+                    program_id,
+                    max_requests_per_epoch,
+                    current_backlog,
+                )
+            }
+
+            BitokuInstructions::ShrinkRequestAccount => {
+                msg!("Instruction : ShrinkRequestAccount");
+                self::Processor::process_shrink_request_account(accounts, program_id)
+            }
+
+            BitokuInstructions::CancelRequest { client_id } => {
+                msg!("Instruction : CancelRequest");
+                self::Processor::process_cancel_request(accounts, program_id, client_id)
+            }
+
+            BitokuInstructions::AddAgent { pubkey } => {
+                msg!("Instruction : AddAgent");
+                self::Processor::process_add_agent(accounts, program_id, pubkey)
+            }
+
+            BitokuInstructions::RemoveAgent { pubkey } => {
+                msg!("Instruction : RemoveAgent");
+                self::Processor::process_remove_agent(accounts, program_id, pubkey)
+            }
+
+            BitokuInstructions::AcknowledgeRequest { client_id, status } => {
+                msg!("Instruction : AcknowledgeRequest");
+                self::Processor::process_acknowledge_request(
+                    accounts, program_id, client_id, status,
+                )
+            }
+
+            BitokuInstructions::ResetEpochCounter { client_id } => {
+                msg!("Instruction : ResetEpochCounter");
+                self::Processor::process_reset_epoch_counter(accounts, program_id, client_id)
+            }
+
+            BitokuInstructions::SetClientPermissions {
+                client_id,
+                permissions,
+            } => {
+                msg!("Instruction : SetClientPermissions");
+                self::Processor::process_set_client_permissions(
+                    accounts,
+                    program_id,
+                    client_id,
+                    permissions,
+                )
+            }
+
+            BitokuInstructions::MigrateAccount => {
+                msg!("Instruction : MigrateAccount");
+                self::Processor::process_migrate_account(accounts, program_id)
+            }
+            BitokuInstructions::UpdateAdmin { new_admin } => {
+                msg!("Instruction : UpdateAdmin");
+                self::Processor::process_update_admin(accounts, program_id, new_admin)
             }
         }
     }
@@ -74,16 +283,38 @@ impl Processor {
         let bookkeeper = next_account_info(account_iter)?;
         let system_program = next_account_info(account_iter)?;
         let rent_sysvar_account = next_account_info(account_iter)?;
+        let admin = next_account_info(account_iter)?;
+        let program_data = next_account_info(account_iter)?;
+
+        //the fee_payer becomes the authority, so it has to prove it holds the key,
+        //and only whoever deployed the program may claim the bookkeeper
+        Self::require_upgrade_authority(program_id, fee_payer, program_data)?;
+        //the admin can take the authority back, so it must consent to the role
+        Self::require_signer(admin)?;
 
         let rent = Rent::from_account_info(rent_sysvar_account)?;
 
-        let (bookkeeper_key, _bump) =
-            Pubkey::find_program_address(&["bookkeeper".as_ref()], program_id);
+        let (bookkeeper_key, bump) = find_bookkeeper_pda(program_id);
 
         if bookkeeper_key != *bookkeeper.key {
+            msg!(
+                "Error: InvalidAccount: expected bookkeeper {}, got {}",
+                bookkeeper_key,
+                bookkeeper.key
+            );
             return Err(InvalidAccount.into());
         };
 
+        //the system program refuses to create the account again, hiding why, so
+        //anything already written to it is rejected first
+        if bookkeeper.try_borrow_data()?.iter().any(|&byte| byte != 0) {
+            msg!(
+                "Error: AccountAlreadyInitialized: bookkeeper {}",
+                bookkeeper.key
+            );
+            return Err(AccountAlreadyInitialized.into());
+        }
+
         //creating Bookkeeper account
         let init_bookkeeper = create_account(
             &fee_payer.key,
@@ -100,12 +331,39 @@ impl Processor {
                 fee_payer.clone(),
                 bookkeeper.clone(),
             ],
-            &[&["bookkeeper".as_ref(), &[_bump]]],
+            &[&[BOOKKEEPER_SEED, &[bump]]],
         )?;
+
+        let bookkeeper_data = BookKeeper {
+            is_initialized: true,
+            status: [0; 32],
+            next_id: 0,
+            authority: *fee_payer.key,
+            reserved_prefixes: [[0; PREFIX_LEN]; RESERVED_PREFIXES],
+            //kinds added by later program upgrades stay off until the agent enables them
+            supported_kinds: ALL_REQUEST_KINDS,
+            agent: *fee_payer.key,
+            free_slots: [0; 32],
+            name_policy: NamePolicyKind::Default as u8,
+            max_requests_per_epoch: 0,
+            current_backlog: 0,
+            paused: false,
+            bump,
+            agents: [Pubkey::default(); MAX_AGENTS],
+            max_client_requests_per_epoch: DEFAULT_MAX_CLIENT_REQUESTS_PER_EPOCH,
+            last_heartbeat_slot: 0,
+            admin: *admin.key,
+        };
+        BookKeeper::pack(bookkeeper_data, &mut bookkeeper.try_borrow_mut_data()?)?;
+
         Ok(())
     }
 
-    fn process_register_client(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    fn process_register_client(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        compact: bool,
+    ) -> ProgramResult {
         let accounts_iter = &mut accounts.iter();
 
         let fee_payer = next_account_info(accounts_iter)?;
@@ -113,20 +371,92 @@ impl Processor {
         let request = next_account_info(accounts_iter)?;
         let system_program = next_account_info(accounts_iter)?;
         let rent_sys_var = next_account_info(accounts_iter)?;
+        let client_info = next_account_info(accounts_iter)?;
 
-        let rent = Rent::from_account_info(rent_sys_var)?;
+        if !fee_payer.is_signer {
+            msg!(
+                "Error: MissingRequiredSignature: {} did not sign",
+                fee_payer.key
+            );
+            return Err(ProgramError::MissingRequiredSignature);
+        }
 
-        let (bookkeeper_key, _bump) =
-            Pubkey::find_program_address(&["bookkeeper".as_ref()], program_id);
+        if Self::load_bookkeeper(program_id, bookkeeper)?.paused {
+            msg!("Error: ProgramPaused: registrations are suspended");
+            return Err(ProgramPaused.into());
+        }
 
-        if bookkeeper_key != *bookkeeper.key {
-            return Err(InvalidAccount.into());
+        Self::create_client(
+            program_id,
+            fee_payer,
+            fee_payer.key,
+            bookkeeper,
+            request,
+            client_info,
+            system_program,
+            rent_sys_var,
+            compact,
+        )
+    }
+
+    fn process_register_client_for(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        target: Pubkey,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+        let request = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+        let rent_sys_var = next_account_info(accounts_iter)?;
+        let client_info = next_account_info(accounts_iter)?;
+
+        Self::require_authority(program_id, authority, bookkeeper)?;
+
+        Self::create_client(
+            program_id,
+            authority,
+            &target,
+            bookkeeper,
+            request,
+            client_info,
+            system_program,
+            rent_sys_var,
+            false,
+        )
+    }
+
+    //creates the request PDA of `owner`, paid by `fee_payer`, and assigns it the next client id
+    //a compact account starts with room for an empty request and grows in SendRequest
+    #[allow(clippy::too_many_arguments)]
+    fn create_client<'a>(
+        program_id: &Pubkey,
+        fee_payer: &AccountInfo<'a>,
+        owner: &Pubkey,
+        bookkeeper: &AccountInfo<'a>,
+        request: &AccountInfo<'a>,
+        client_info: &AccountInfo<'a>,
+        system_program: &AccountInfo<'a>,
+        rent_sys_var: &AccountInfo<'a>,
+        compact: bool,
+    ) -> ProgramResult {
+        let rent = Rent::from_account_info(rent_sys_var)?;
+        let space = if compact {
+            COMPACT_BASE_LEN
+        } else {
+            RequestData::LEN
         };
 
-        let (request_key, bump) =
-            Pubkey::find_program_address(&["request".as_ref(), fee_payer.key.as_ref()], program_id);
+        let (request_key, bump) = find_request_pda(owner, program_id);
 
         if request_key != *request.key {
+            msg!(
+                "Error: InvalidAccount: expected request {}, got {}",
+                request_key,
+                request.key
+            );
             return Err(InvalidAccount.into());
         };
 
@@ -134,35 +464,125 @@ impl Processor {
         let init_request = create_account(
             &fee_payer.key,
             &request_key,
-            rent.minimum_balance(RequestData::LEN),
-            RequestData::LEN as u64,
+            rent.minimum_balance(space),
+            space as u64,
             &program_id,
         );
 
         invoke_signed(
             &init_request,
             &[system_program.clone(), fee_payer.clone(), request.clone()],
-            &[&["request".as_ref(), fee_payer.key.as_ref(), &[bump]]],
+            &[&[REQUEST_SEED, owner.as_ref(), &[bump]]],
         )?;
 
         //getting bookkeeper data from pda
         let mut bookkeeper_data = BookKeeper::unpack_unchecked(&bookkeeper.try_borrow_data()?)?;
-        let my_id = bookkeeper_data.next_id;
-        addel(&mut bookkeeper_data.status, my_id);
+        if !bookkeeper_data.is_initialized {
+            msg!("Error: UninitializedAccount: bookkeeper {}", bookkeeper.key);
+            return Err(ProgramError::UninitializedAccount);
+        }
+        //handing out an id freed by RemoveClient before a fresh one
+        let my_id = bookkeeper_data.first_free().ok_or_else(|| {
+            msg!("Error: NoAvailableClients: all 256 client ids are registered");
+            NoAvailableClients
+        })?;
+        bookkeeper_data.next_id = bookkeeper_data.next_id.max(my_id.saturating_add(1));
+        bookkeeper_data.register_client(my_id);
+        if compact {
+            RequestData::mark_compact(&mut request.try_borrow_mut_data()?);
+        }
         //getting request_data from pda
-        let mut request_data = RequestData::unpack_unchecked(&request.try_borrow_data()?)?;
+        let mut request_data = RequestData::unpack_account(&request.try_borrow_data()?)?;
+        if request_data.is_initialized {
+            msg!(
+                "Error: AccountAlreadyInitialized: request account {}",
+                request.key
+            );
+            return Err(AccountAlreadyInitialized.into());
+        }
 
+        request_data.is_initialized = true;
         request_data.client_id = my_id;
-        if bookkeeper_data.next_id == 255 {
-            return Err(NoAvailableClients.into());
-        }
+        request_data.bump = bump;
 
-        bookkeeper_data.next_id += 1;
+        Self::create_client_info(
+            program_id,
+            fee_payer,
+            owner,
+            client_info,
+            system_program,
+            &rent,
+        )?;
 
         BookKeeper::pack(bookkeeper_data, &mut bookkeeper.try_borrow_mut_data()?)?;
-        RequestData::pack(request_data, &mut request.try_borrow_mut_data()?)?;
+        request_data.pack_account(&mut request.try_borrow_mut_data()?)?;
 
-        Ok(())
+        BitokuEvent::from(ClientRegistered {
+            client_id: my_id,
+            requester: *owner,
+        })
+        .log()
+    }
+
+    //creates the ClientInfo PDA of `owner` with every permission on its first
+    //registration; a client registering again keeps the permissions it had
+    fn create_client_info<'a>(
+        program_id: &Pubkey,
+        fee_payer: &AccountInfo<'a>,
+        owner: &Pubkey,
+        client_info: &AccountInfo<'a>,
+        system_program: &AccountInfo<'a>,
+        rent: &Rent,
+    ) -> ProgramResult {
+        let (client_info_key, bump) = find_client_info_pda(owner, program_id);
+
+        if client_info_key != *client_info.key {
+            msg!(
+                "Error: InvalidAccount: expected client info {}, got {}",
+                client_info_key,
+                client_info.key
+            );
+            return Err(InvalidAccount.into());
+        };
+
+        if client_info.data_is_empty() {
+            let init_client_info = create_account(
+                fee_payer.key,
+                &client_info_key,
+                rent.minimum_balance(ClientInfo::LEN),
+                ClientInfo::LEN as u64,
+                program_id,
+            );
+
+            invoke_signed(
+                &init_client_info,
+                &[
+                    system_program.clone(),
+                    fee_payer.clone(),
+                    client_info.clone(),
+                ],
+                &[&[CLIENT_INFO_SEED, owner.as_ref(), &[bump]]],
+            )?;
+        }
+        if client_info.owner != program_id {
+            msg!(
+                "Error: InvalidAccount: {} is owned by {}",
+                client_info.key,
+                client_info.owner
+            );
+            return Err(InvalidAccount.into());
+        }
+
+        if ClientInfo::unpack_unchecked(&client_info.try_borrow_data()?)?.is_initialized {
+            return Ok(());
+        }
+        let client_info_data = ClientInfo {
+            is_initialized: true,
+            owner: *owner,
+            permissions: ALL_PERMISSIONS,
+            bump,
+        };
+        ClientInfo::pack(client_info_data, &mut client_info.try_borrow_mut_data()?)
     }
 
     fn process_remove_client(
@@ -175,28 +595,301 @@ impl Processor {
         let bookkeeper = next_account_info(accounts_iter)?;
         let request = next_account_info(accounts_iter)?;
 
-        let (bookkeeper_key, _bump) =
-            Pubkey::find_program_address(&["bookkeeper".as_ref()], program_id);
+        if !fee_payer.is_signer {
+            msg!(
+                "Error: MissingRequiredSignature: {} did not sign",
+                fee_payer.key
+            );
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let bookkeeper_data = Self::load_bookkeeper(program_id, bookkeeper)?;
+        let request_data = Self::load_request(program_id, fee_payer.key, request)?;
 
-        if bookkeeper_key != *bookkeeper.key {
+        Self::release_client(
+            bookkeeper,
+            bookkeeper_data,
+            request,
+            request_data,
+            fee_payer,
+            client_id,
+        )
+    }
+
+    fn process_force_remove_client(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        client_id: u8,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let authority = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+        let request = next_account_info(accounts_iter)?;
+        let owner = next_account_info(accounts_iter)?;
+        let destination = next_account_info(accounts_iter)?;
+
+        let bookkeeper_data = Self::require_authority(program_id, authority, bookkeeper)?;
+
+        //the owner's key only has to derive the request PDA, not sign
+        let request_data = Self::load_request(program_id, owner.key, request)?;
+
+        //the lamports would stay behind in the closed account
+        if destination.key == request.key {
+            msg!(
+                "Error: InvalidAccount: destination {} is the request account",
+                destination.key
+            );
+            return Err(InvalidAccount.into());
+        }
+
+        Self::release_client(
+            bookkeeper,
+            bookkeeper_data,
+            request,
+            request_data,
+            destination,
+            client_id,
+        )
+    }
+
+    fn require_signer(account: &AccountInfo) -> ProgramResult {
+        if !account.is_signer {
+            msg!(
+                "Error: MissingRequiredSignature: {} did not sign",
+                account.key
+            );
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        Ok(())
+    }
+
+    //loads the bookkeeper for the instructions only its authority may send
+    fn require_authority(
+        program_id: &Pubkey,
+        authority: &AccountInfo,
+        bookkeeper: &AccountInfo,
+    ) -> Result<BookKeeper, ProgramError> {
+        Self::require_signer(authority)?;
+        let bookkeeper_data = Self::load_bookkeeper(program_id, bookkeeper)?;
+        if bookkeeper_data.authority != *authority.key {
+            msg!(
+                "Error: Unauthorized: expected authority {}, got {}",
+                bookkeeper_data.authority,
+                authority.key
+            );
+            return Err(Unauthorized.into());
+        }
+        Ok(bookkeeper_data)
+    }
+
+    //loads the bookkeeper for the instructions only its admin may send
+    //checks the signer is the upgrade authority recorded in the program's
+    //ProgramData account, the one key that provably deployed this program
+    fn require_upgrade_authority(
+        program_id: &Pubkey,
+        authority: &AccountInfo,
+        program_data: &AccountInfo,
+    ) -> ProgramResult {
+        Self::require_signer(authority)?;
+        let expected = find_program_data_address(program_id);
+        if *program_data.key != expected || *program_data.owner != bpf_loader_upgradeable::id() {
+            msg!(
+                "Error: InvalidAccount: expected program data {}, got {}",
+                expected,
+                program_data.key
+            );
             return Err(InvalidAccount.into());
+        }
+        //ProgramData { slot: u64, upgrade_authority_address: Option<Pubkey> },
+        //bincode encoded behind a u32 variant tag
+        let data = program_data.try_borrow_data()?;
+        let upgrade_authority = match data
+            .get(..UpgradeableLoaderState::size_of_programdata_metadata())
+        {
+            Some([3, 0, 0, 0, _, _, _, _, _, _, _, _, 1, key @ ..]) => Pubkey::try_from(key).ok(),
+            _ => None,
         };
+        if upgrade_authority != Some(*authority.key) {
+            msg!(
+                "Error: Unauthorized: {} is not the upgrade authority",
+                authority.key
+            );
+            return Err(Unauthorized.into());
+        }
+        Ok(())
+    }
+
+    fn require_admin(
+        program_id: &Pubkey,
+        admin: &AccountInfo,
+        bookkeeper: &AccountInfo,
+    ) -> Result<BookKeeper, ProgramError> {
+        Self::require_signer(admin)?;
+        let bookkeeper_data = Self::load_bookkeeper(program_id, bookkeeper)?;
+        if bookkeeper_data.admin != *admin.key {
+            msg!(
+                "Error: InvalidAccount: {} is not the admin {}",
+                admin.key,
+                bookkeeper_data.admin
+            );
+            return Err(InvalidAccount.into());
+        }
+        Ok(bookkeeper_data)
+    }
+
+    //loads the bookkeeper for the instructions only a registered agent may send
+    fn require_agent(
+        program_id: &Pubkey,
+        agent: &AccountInfo,
+        bookkeeper: &AccountInfo,
+    ) -> Result<BookKeeper, ProgramError> {
+        Self::require_signer(agent)?;
+        let bookkeeper_data = Self::load_bookkeeper(program_id, bookkeeper)?;
+        if !bookkeeper_data.is_agent(agent.key) {
+            msg!(
+                "Error: UnauthorizedAgent: {} is not a registered agent",
+                agent.key
+            );
+            return Err(UnauthorizedAgent.into());
+        }
+        Ok(bookkeeper_data)
+    }
+
+    //unpacks the initialized bookkeeper, checking its address with the bump stored in it
+    fn load_bookkeeper(
+        program_id: &Pubkey,
+        bookkeeper: &AccountInfo,
+    ) -> Result<BookKeeper, ProgramError> {
+        if bookkeeper.owner != program_id {
+            msg!(
+                "Error: InvalidAccount: {} is owned by {}",
+                bookkeeper.key,
+                bookkeeper.owner
+            );
+            return Err(InvalidAccount.into());
+        }
+
+        let bookkeeper_data = BookKeeper::unpack_unchecked(&bookkeeper.try_borrow_data()?)?;
+        if !bookkeeper_data.is_initialized {
+            msg!("Error: UninitializedAccount: bookkeeper {}", bookkeeper.key);
+            return Err(ProgramError::UninitializedAccount);
+        }
+        //the program only creates PDAs with the canonical bump, so a program-owned
+        //account at the address derived from the stored bump is the real one
+        let bookkeeper_key = bookkeeper_pda(program_id, bookkeeper_data.bump)?;
+        if bookkeeper_key != *bookkeeper.key {
+            msg!(
+                "Error: InvalidAccount: expected bookkeeper {}, got {}",
+                bookkeeper_key,
+                bookkeeper.key
+            );
+            return Err(InvalidAccount.into());
+        }
+
+        Ok(bookkeeper_data)
+    }
 
-        let (request_key, _bump) =
-            Pubkey::find_program_address(&["request".as_ref(), fee_payer.key.as_ref()], program_id);
+    //unpacks the initialized request account of `owner`, checking its address with the
+    //bump stored in it
+    fn load_request(
+        program_id: &Pubkey,
+        owner: &Pubkey,
+        request: &AccountInfo,
+    ) -> Result<RequestData, ProgramError> {
+        if request.owner != program_id {
+            msg!(
+                "Error: InvalidAccount: {} is owned by {}",
+                request.key,
+                request.owner
+            );
+            return Err(InvalidAccount.into());
+        }
 
+        let request_data = RequestData::unpack_account(&request.try_borrow_data()?)?;
+        if !request_data.is_initialized {
+            msg!(
+                "Error: UninitializedAccount: request account {}",
+                request.key
+            );
+            return Err(ProgramError::UninitializedAccount);
+        }
+        let request_key = request_pda(owner, request_data.bump, program_id)?;
         if request_key != *request.key {
+            msg!(
+                "Error: InvalidAccount: expected request account {} of {}, got {}",
+                request_key,
+                owner,
+                request.key
+            );
             return Err(InvalidAccount.into());
-        };
+        }
 
-        let mut bookkeeper_data = BookKeeper::unpack_unchecked(&bookkeeper.try_borrow_data()?)?;
+        Ok(request_data)
+    }
+
+    //unpacks an initialized ClientInfo account, checking its address with the owner
+    //and bump stored in it
+    fn load_client_info(
+        program_id: &Pubkey,
+        client_info: &AccountInfo,
+    ) -> Result<ClientInfo, ProgramError> {
+        if client_info.owner != program_id {
+            msg!(
+                "Error: InvalidAccount: {} is owned by {}",
+                client_info.key,
+                client_info.owner
+            );
+            return Err(InvalidAccount.into());
+        }
+
+        let client_info_data = ClientInfo::unpack_unchecked(&client_info.try_borrow_data()?)?;
+        if !client_info_data.is_initialized {
+            msg!(
+                "Error: UninitializedAccount: client info {}",
+                client_info.key
+            );
+            return Err(ProgramError::UninitializedAccount);
+        }
+        let client_info_key =
+            client_info_pda(&client_info_data.owner, client_info_data.bump, program_id)?;
+        if client_info_key != *client_info.key {
+            msg!(
+                "Error: InvalidAccount: expected client info {} of {}, got {}",
+                client_info_key,
+                client_info_data.owner,
+                client_info.key
+            );
+            return Err(InvalidAccount.into());
+        }
+
+        Ok(client_info_data)
+    }
+
+    //frees `client_id` and closes its request account, sending the lamports to `destination`
+    fn release_client(
+        bookkeeper: &AccountInfo,
+        mut bookkeeper_data: BookKeeper,
+        request: &AccountInfo,
+        request_data: RequestData,
+        destination: &AccountInfo,
+        client_id: u8,
+    ) -> ProgramResult {
+        //the request PDA is the proof of which id its owner holds
+        if request_data.client_id != client_id {
+            msg!(
+                "Error: ClientMismatch: request account holds client {}, got {}",
+                request_data.client_id,
+                client_id
+            );
+            return Err(ClientMismatch.into());
+        }
 
-        let bool = isel(bookkeeper_data.status, client_id);
-        if !bool {
+        if !bookkeeper_data.is_registered(client_id) {
+            msg!("Error: UnregisteredClient: client {}", client_id);
             return Err(UnregisteredClient.into());
         }
 
-        delel(&mut bookkeeper_data.status, client_id);
+        bookkeeper_data.unregister_client(client_id);
 
         BookKeeper::pack(bookkeeper_data, &mut bookkeeper.try_borrow_mut_data()?)?;
 
@@ -204,17 +897,17 @@ impl Processor {
         let current_lamps = request.lamports();
         let account_data_size = request.data_len();
 
-        //Transferring lamports to the fee_payer account
+        //Transferring lamports to the destination account
         **request.lamports.borrow_mut() = 0;
-        **fee_payer.lamports.borrow_mut() = fee_payer
+        **destination.lamports.borrow_mut() = destination
             .lamports()
             .checked_add(current_lamps)
-            .ok_or(Overflow)?;
+            .ok_or_else(|| overflow("destination lamports"))?;
 
         //zeroing the stored data in the account
         sol_memset(&mut *request.try_borrow_mut_data()?, 0, account_data_size);
 
-        Ok(())
+        BitokuEvent::from(ClientRemoved { client_id }).log()
     }
 
     fn process_send_request(
@@ -222,38 +915,5014 @@ impl Processor {
         program_id: &Pubkey,
         request: Request,
         client_id: u8,
+        submission_ref: [u8; 32],
+        overwrite: bool,
     ) -> ProgramResult {
         let accounts_iter = &mut accounts.iter();
 
         let fee_payer = next_account_info(accounts_iter)?;
-        let req = next_account_info(accounts_iter)?;
 
-        let (request_key, _bump) =
-            Pubkey::find_program_address(&["request".as_ref(), fee_payer.key.as_ref()], program_id);
+        if !fee_payer.is_signer {
+            msg!(
+                "Error: MissingRequiredSignature: {} did not sign",
+                fee_payer.key
+            );
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        Self::submit_request(
+            program_id,
+            fee_payer,
+            fee_payer.key,
+            accounts_iter,
+            request,
+            client_id,
+            submission_ref,
+            None,
+            overwrite,
+        )
+    }
+
+    fn process_submit_signed_request(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        envelope: SignedRequestEnvelope,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
 
-        if request_key != *req.key {
+        let relayer = next_account_info(accounts_iter)?;
+        let instructions = next_account_info(accounts_iter)?;
+
+        if !relayer.is_signer {
+            msg!(
+                "Error: MissingRequiredSignature: {} did not sign",
+                relayer.key
+            );
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if !sysvar::instructions::check_id(instructions.key) {
+            msg!(
+                "Error: InvalidAccount: expected the instructions sysvar, got {}",
+                instructions.key
+            );
             return Err(InvalidAccount.into());
-        };
+        }
+
+        //the runtime has already run the ed25519 program over the previous instruction,
+        //so it only remains to check that it verified this envelope
+        let ed25519 = get_instruction_relative(-1, instructions).map_err(|_| {
+            msg!("Error: InvalidSignature: no instruction before SubmitSignedRequest");
+            InvalidSignature
+        })?;
+        envelope
+            .check_ed25519_instruction(program_id, &ed25519)
+            .inspect_err(|_| {
+                msg!(
+                    "Error: InvalidSignature: previous instruction does not verify {}'s envelope",
+                    envelope.signer
+                )
+            })?;
+
+        Self::submit_request(
+            program_id,
+            relayer,
+            &envelope.signer,
+            accounts_iter,
+            envelope.request,
+            envelope.client_id,
+            [0; 32],
+            Some(envelope.nonce),
+            false,
+        )
+    }
+
+    //stores `request` in the request account of `owner`, with `fee_payer` paying for
+    //any account growth; `nonce` is set for requests submitted in a signed envelope,
+    //and `overwrite` lets it replace a request the agent hasn't acknowledged
+    #[allow(clippy::too_many_arguments)]
+    fn submit_request<'a, 'b: 'a>(
+        program_id: &Pubkey,
+        fee_payer: &'a AccountInfo<'b>,
+        owner: &Pubkey,
+        accounts_iter: &mut core::slice::Iter<'a, AccountInfo<'b>>,
+        request: Request,
+        client_id: u8,
+        submission_ref: [u8; 32],
+        nonce: Option<u64>,
+        overwrite: bool,
+    ) -> ProgramResult {
+        let req = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+        let clock_sysvar = next_account_info(accounts_iter)?;
+        let client_info = next_account_info(accounts_iter)?;
+
+        let mut request_data = Self::load_request(program_id, owner, req)?;
+        let bookkeeper_data = Self::load_bookkeeper(program_id, bookkeeper)?;
+        if bookkeeper_data.paused {
+            msg!("Error: ProgramPaused: requests are suspended");
+            return Err(ProgramPaused.into());
+        }
 
-        let mut request_data = RequestData::unpack_unchecked(&req.try_borrow_data()?)?;
+        //Validating the names of the request against the deployment's policy
+        request.check_names(&NamePolicyKind::from_u8(bookkeeper_data.name_policy)?)?;
 
-        //Validating the name of the request
-        let s = request.name();
-        let name = String::from_utf8(s.to_vec()).unwrap();
-        if validate_name(&name.as_bytes()) == false {
-            return Err(InvalidName.into());
+        //leases are settled here, so they don't depend on the agent
+        if !request.is_lease() && !bookkeeper_data.supports(request.kind()) {
+            msg!("Error: UnsupportedRequestKind: kind {}", request.kind());
+            return Err(UnsupportedRequestKind.into());
+        }
+        if bookkeeper_data.authority != *owner {
+            for name in [request.name(), request.new_name(), request.dst_name()]
+                .into_iter()
+                .flatten()
+            {
+                if bookkeeper_data.is_reserved(&name) {
+                    msg!(
+                        "Error: ReservedName: {}",
+                        name_to_str(&name).unwrap_or("<invalid utf-8>")
+                    );
+                    return Err(ReservedName.into());
+                }
+            }
         }
 
         if request_data.client_id != client_id {
-            msg!("{} != {}", request_data.client_id, client_id);
+            msg!(
+                "Error: ClientMismatch: request account holds client {}, got {}",
+                request_data.client_id,
+                client_id
+            );
             return Err(ClientMismatch.into());
         }
+        let client_info_data = Self::load_client_info(program_id, client_info)?;
+        if client_info_data.owner != *owner {
+            msg!(
+                "Error: InvalidAccount: client info {} belongs to {}, not {}",
+                client_info.key,
+                client_info_data.owner,
+                owner
+            );
+            return Err(InvalidAccount.into());
+        }
+        if !client_info_data.permits(&request) {
+            msg!(
+                "Error: PermissionDenied: client {} has permissions {:#06b}, kind {} needs {:#06b}",
+                client_id,
+                client_info_data.permissions,
+                request.kind(),
+                request.required_permission()
+            );
+            return Err(PermissionDenied.into());
+        }
+
+        //lease requests leave the stored request alone
+        if !overwrite && !request.is_lease() && request_data.is_pending() {
+            msg!(
+                "Error: RequestPending: request {} of client {} is still pending",
+                request_data.sequence,
+                client_id
+            );
+            return Err(RequestPending.into());
+        }
+        if !request.is_lease()
+            && request_data.requests_this_epoch >= bookkeeper_data.max_client_requests_per_epoch
+        {
+            msg!(
+                "Error: RateLimitExceeded: client {} stored {} requests this epoch",
+                client_id,
+                request_data.requests_this_epoch
+            );
+            return Err(RateLimitExceeded.into());
+        }
+
+        if let Some(nonce) = nonce {
+            if nonce <= request_data.nonce {
+                msg!(
+                    "Error: StaleNonce: nonce {} after {}",
+                    nonce,
+                    request_data.nonce
+                );
+                return Err(StaleNonce.into());
+            }
+            request_data.nonce = nonce;
+        }
 
-        request_data.requester = *fee_payer.key;
+        let clock = Clock::from_account_info(clock_sysvar)?;
+        let system_program = accounts_iter.next();
+        let rent_sysvar = accounts_iter.next();
+
+        if let Some(bucket) = request
+            .bucket()
+            .filter(|_| request.is_mutation() || request.is_lease())
+        {
+            let lease = next_account_info(accounts_iter)?;
+            Self::apply_lease(
+                program_id,
+                fee_payer,
+                lease,
+                system_program,
+                rent_sysvar,
+                &bucket,
+                &request,
+                owner,
+                clock.slot,
+            )?;
+            //lease requests never reach the agent
+            if request.is_lease() {
+                if nonce.is_some() {
+                    request_data.pack_account(&mut req.try_borrow_mut_data()?)?;
+                }
+                return Ok(());
+            }
+        }
+
+        request_data.track_position(&request)?;
+        request_data.requester = *owner;
         request_data.request = request;
+        request_data.submission_ref = submission_ref;
+        request_data.submitted_at = clock.unix_timestamp;
+        request_data.status = RequestStatus::Pending;
+        request_data.sequence = request_data
+            .sequence
+            .checked_add(1)
+            .ok_or_else(|| overflow("request sequence"))?;
+        request_data.requests_this_epoch += 1;
+        //clearing the previous answer so it can't be read as this request's
+        request_data.response = [0; RESPONSE_LEN];
+        request_data.response_ready = false;
+        request_data.response_len = 0;
+        request_data.response_data = [0; 512];
+
+        //a compact account grows to fit a larger request, topped up to stay rent exempt
+        let is_compact = RequestData::is_compact(&req.try_borrow_data()?);
+        let needed = request_data.compact_len();
+        if is_compact && needed > req.data_len() {
+            let (Some(system_program), Some(rent_sysvar)) = (system_program, rent_sysvar) else {
+                msg!("Error: NotEnoughAccountKeys: system program and rent sysvar are needed");
+                return Err(ProgramError::NotEnoughAccountKeys);
+            };
+            let rent = Rent::from_account_info(rent_sysvar)?;
+
+            let top_up = rent.minimum_balance(needed).saturating_sub(req.lamports());
+            if top_up > 0 {
+                invoke(
+                    &transfer(fee_payer.key, req.key, top_up),
+                    &[fee_payer.clone(), req.clone(), system_program.clone()],
+                )?;
+            }
+            req.realloc(needed, false)?;
+        }
+
+        let event = RequestSubmitted::new(client_id, &request_data.request, request_data.sequence);
+        request_data.pack_account(&mut req.try_borrow_mut_data()?)?;
+        BitokuEvent::from(event).log()?;
+
+        Ok(())
+    }
+
+    //rejects writes to a bucket leased to another client, and takes or gives up
+    //the lease for `AcquireBucketLease`/`ReleaseBucketLease`
+    #[allow(clippy::too_many_arguments)]
+    fn apply_lease<'a>(
+        program_id: &Pubkey,
+        fee_payer: &AccountInfo<'a>,
+        lease: &AccountInfo<'a>,
+        system_program: Option<&AccountInfo<'a>>,
+        rent_sysvar: Option<&AccountInfo<'a>>,
+        bucket: &[u8; 128],
+        request: &Request,
+        requester: &Pubkey,
+        slot: u64,
+    ) -> ProgramResult {
+        let (lease_key, bump) = find_lease_pda(bucket, program_id);
+
+        if lease_key != *lease.key {
+            msg!(
+                "Error: InvalidAccount: expected lease {}, got {}",
+                lease_key,
+                lease.key
+            );
+            return Err(InvalidAccount.into());
+        };
+
+        if lease.data_is_empty() {
+            //nobody has leased the bucket yet
+            if !matches!(request, Request::AcquireBucketLease { .. }) {
+                return Ok(());
+            }
+            //creating the lease account on the bucket's first lease
+            let (Some(system_program), Some(rent_sysvar)) = (system_program, rent_sysvar) else {
+                msg!("Error: NotEnoughAccountKeys: system program and rent sysvar are needed");
+                return Err(ProgramError::NotEnoughAccountKeys);
+            };
+            let rent = Rent::from_account_info(rent_sysvar)?;
+            let init_lease = create_account(
+                fee_payer.key,
+                &lease_key,
+                rent.minimum_balance(BucketLease::LEN),
+                BucketLease::LEN as u64,
+                program_id,
+            );
+
+            invoke_signed(
+                &init_lease,
+                &[system_program.clone(), fee_payer.clone(), lease.clone()],
+                &[&[LEASE_SEED, hash(bucket).as_ref(), &[bump]]],
+            )?;
+        }
+
+        if lease.owner != program_id {
+            msg!(
+                "Error: InvalidAccount: {} is owned by {}",
+                lease.key,
+                lease.owner
+            );
+            return Err(InvalidAccount.into());
+        }
+
+        let mut lease_data = BucketLease::unpack_unchecked(&lease.try_borrow_data()?)?;
+        if lease_data.blocks(requester, slot) {
+            msg!(
+                "Error: BucketLeased: held by {} until slot {}",
+                lease_data.holder,
+                lease_data.expiry_slot
+            );
+            return Err(BucketLeased.into());
+        }
 
-        RequestData::pack(request_data, &mut req.try_borrow_mut_data()?)?;
+        match *request {
+            Request::AcquireBucketLease { ttl_slots, .. } => {
+                lease_data.holder = *requester;
+                lease_data.expiry_slot = slot
+                    .checked_add(ttl_slots as u64)
+                    .ok_or_else(|| overflow("lease expiry slot"))?;
+            }
+            Request::ReleaseBucketLease { .. } => lease_data = BucketLease::default(),
+            _ => return Ok(()),
+        }
+        BucketLease::pack(lease_data, &mut lease.try_borrow_mut_data()?)?;
 
         Ok(())
     }
+
+    fn process_set_reserved_prefix(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        index: u8,
+        prefix: [u8; PREFIX_LEN],
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+
+        let mut bookkeeper_data = Self::require_authority(program_id, authority, bookkeeper)?;
+
+        let slot = bookkeeper_data
+            .reserved_prefixes
+            .get_mut(index as usize)
+            .ok_or_else(|| {
+                msg!("Error: InvalidInstructionData: prefix index {}", index);
+                InvalidInstructionData
+            })?;
+        *slot = prefix;
+        BookKeeper::pack(bookkeeper_data, &mut bookkeeper.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_set_supported_kinds(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        supported_kinds: u32,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let agent = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+
+        let mut bookkeeper_data = Self::require_agent(program_id, agent, bookkeeper)?;
+
+        bookkeeper_data.supported_kinds = supported_kinds;
+        BookKeeper::pack(bookkeeper_data, &mut bookkeeper.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_update_request_status(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        client_id: u8,
+        status: RequestStatus,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let agent = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+        let req = next_account_info(accounts_iter)?;
+
+        Self::require_agent(program_id, agent, bookkeeper)?;
+
+        Self::set_request_status(program_id, req, client_id, status)
+    }
+
+    fn process_acknowledge_request(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        client_id: u8,
+        status: RequestStatus,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let agent = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+        let req = next_account_info(accounts_iter)?;
+
+        Self::require_agent(program_id, agent, bookkeeper)?;
+
+        //acknowledging only picks a pending request up; finishing it is
+        //`UpdateRequestStatus` or `WriteResponse`
+        if status != RequestStatus::Processing {
+            msg!(
+                "Error: InvalidStatusTransition: acknowledged with {:?}",
+                status
+            );
+            return Err(InvalidStatusTransition.into());
+        }
+
+        Self::set_request_status(program_id, req, client_id, status)
+    }
+
+    fn process_reset_epoch_counter(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        client_id: u8,
+    ) -> ProgramResult {
+        Self::update_client(accounts, program_id, client_id, |request_data| {
+            request_data.requests_this_epoch = 0
+        })
+    }
+
+    fn process_set_client_permissions(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        client_id: u8,
+        permissions: u8,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let admin = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+        let req = next_account_info(accounts_iter)?;
+        let client_info = next_account_info(accounts_iter)?;
+
+        Self::require_admin(program_id, admin, bookkeeper)?;
+
+        //the ClientInfo names its owner, whose request account holds the client id
+        let mut client_info_data = Self::load_client_info(program_id, client_info)?;
+        let request_data = Self::load_request(program_id, &client_info_data.owner, req)?;
+        if request_data.client_id != client_id {
+            msg!(
+                "Error: ClientMismatch: request account holds client {}, got {}",
+                request_data.client_id,
+                client_id
+            );
+            return Err(ClientMismatch.into());
+        }
+
+        client_info_data.permissions = permissions;
+        ClientInfo::pack(client_info_data, &mut client_info.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    //applies `update` to the request account of client `client_id`, for the
+    //authority-only instructions that manage a client
+    fn update_client(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        client_id: u8,
+        update: impl FnOnce(&mut RequestData),
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+        let req = next_account_info(accounts_iter)?;
+
+        Self::require_authority(program_id, authority, bookkeeper)?;
+
+        if req.owner != program_id {
+            msg!(
+                "Error: InvalidAccount: {} is owned by {}",
+                req.key,
+                req.owner
+            );
+            return Err(InvalidAccount.into());
+        }
+
+        let mut request_data = RequestData::unpack_account(&req.try_borrow_data()?)?;
+        if request_data.client_id != client_id {
+            msg!(
+                "Error: ClientMismatch: request account holds client {}, got {}",
+                request_data.client_id,
+                client_id
+            );
+            return Err(ClientMismatch.into());
+        }
+        update(&mut request_data);
+
+        request_data.pack_account(&mut req.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    //moves the request held by `req` to `status`, for `UpdateRequestStatus` and
+    //`AcknowledgeRequest` once they have checked their signer
+    fn set_request_status(
+        program_id: &Pubkey,
+        req: &AccountInfo,
+        client_id: u8,
+        status: RequestStatus,
+    ) -> ProgramResult {
+        if req.owner != program_id {
+            msg!(
+                "Error: InvalidAccount: {} is owned by {}",
+                req.key,
+                req.owner
+            );
+            return Err(InvalidAccount.into());
+        }
+
+        let mut request_data = RequestData::unpack_account(&req.try_borrow_data()?)?;
+
+        if request_data.client_id != client_id {
+            msg!(
+                "Error: ClientMismatch: request account holds client {}, got {}",
+                request_data.client_id,
+                client_id
+            );
+            return Err(ClientMismatch.into());
+        }
+
+        //a freshly registered slot has nothing for the agent to report on
+        if request_data.sequence == 0 {
+            msg!(
+                "Error: NoRequest: client {} has not sent a request",
+                client_id
+            );
+            return Err(NoRequest.into());
+        }
+
+        if !request_data.status.can_transition_to(status) {
+            msg!(
+                "Error: InvalidStatusTransition: {:?} to {:?}",
+                request_data.status,
+                status
+            );
+            return Err(InvalidStatusTransition.into());
+        }
+        request_data.status = status;
+
+        request_data.pack_account(&mut req.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_write_response(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        client_id: u8,
+        data: [u8; 512],
+        len: u16,
+        status: RequestStatus,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let agent = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+        let req = next_account_info(accounts_iter)?;
+
+        Self::require_agent(program_id, agent, bookkeeper)?;
+
+        if req.owner != program_id {
+            msg!(
+                "Error: InvalidAccount: {} is owned by {}",
+                req.key,
+                req.owner
+            );
+            return Err(InvalidAccount.into());
+        }
+
+        let mut request_data = RequestData::unpack_account(&req.try_borrow_data()?)?;
+
+        if request_data.client_id != client_id {
+            msg!(
+                "Error: ClientMismatch: request account holds client {}, got {}",
+                request_data.client_id,
+                client_id
+            );
+            return Err(ClientMismatch.into());
+        }
+
+        if request_data.sequence == 0 {
+            msg!(
+                "Error: NoRequest: client {} has not sent a request",
+                client_id
+            );
+            return Err(NoRequest.into());
+        }
+
+        //a finished request keeps its response until the next SendRequest
+        if !request_data.status.can_respond_with(status) {
+            msg!(
+                "Error: InvalidStatusTransition: {:?} to {:?}",
+                request_data.status,
+                status
+            );
+            return Err(InvalidStatusTransition.into());
+        }
+        request_data.response_data = data;
+        request_data.response_len = len;
+        request_data.response_ready = true;
+        request_data.status = status;
+
+        request_data.pack_account(&mut req.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_set_agent(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        agent: Pubkey,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+
+        let mut bookkeeper_data = Self::require_authority(program_id, authority, bookkeeper)?;
+
+        bookkeeper_data.agent = agent;
+        BookKeeper::pack(bookkeeper_data, &mut bookkeeper.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_add_agent(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        pubkey: Pubkey,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+
+        let mut bookkeeper_data = Self::require_authority(program_id, authority, bookkeeper)?;
+
+        //the default key marks a free slot
+        if pubkey == Pubkey::default() {
+            msg!("Error: InvalidArgument: the default key can't be an agent");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if bookkeeper_data.agents.contains(&pubkey) {
+            return Ok(());
+        }
+        let slot = bookkeeper_data
+            .agents
+            .iter_mut()
+            .find(|agent| **agent == Pubkey::default())
+            .ok_or_else(|| {
+                msg!(
+                    "Error: AgentLimitReached: all {} agent slots are taken",
+                    MAX_AGENTS
+                );
+                AgentLimitReached
+            })?;
+        *slot = pubkey;
+        BookKeeper::pack(bookkeeper_data, &mut bookkeeper.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_remove_agent(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        pubkey: Pubkey,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+
+        let mut bookkeeper_data = Self::require_authority(program_id, authority, bookkeeper)?;
+
+        let slot = bookkeeper_data
+            .agents
+            .iter_mut()
+            .find(|agent| pubkey != Pubkey::default() && **agent == pubkey)
+            .ok_or_else(|| {
+                msg!(
+                    "Error: UnauthorizedAgent: {} is not a registered agent",
+                    pubkey
+                );
+                UnauthorizedAgent
+            })?;
+        *slot = Pubkey::default();
+        BookKeeper::pack(bookkeeper_data, &mut bookkeeper.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_set_authority(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        new_authority: Pubkey,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+
+        let mut bookkeeper_data = Self::require_authority(program_id, authority, bookkeeper)?;
+
+        bookkeeper_data.authority = new_authority;
+        BookKeeper::pack(bookkeeper_data, &mut bookkeeper.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_update_admin(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        new_admin: Pubkey,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let admin = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+
+        let mut bookkeeper_data = Self::require_admin(program_id, admin, bookkeeper)?;
+
+        bookkeeper_data.admin = new_admin;
+        BookKeeper::pack(bookkeeper_data, &mut bookkeeper.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_set_paused(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        paused: bool,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+
+        let mut bookkeeper_data = Self::require_authority(program_id, authority, bookkeeper)?;
+
+        bookkeeper_data.paused = paused;
+        BookKeeper::pack(bookkeeper_data, &mut bookkeeper.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_set_name_policy(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        policy: NamePolicyKind,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+
+        let mut bookkeeper_data = Self::require_authority(program_id, authority, bookkeeper)?;
+
+        bookkeeper_data.name_policy = policy as u8;
+        BookKeeper::pack(bookkeeper_data, &mut bookkeeper.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_publish_load(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        max_requests_per_epoch: u32,
+        current_backlog: u16,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let agent = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+        let clock_sysvar = next_account_info(accounts_iter)?;
+
+        let mut bookkeeper_data = Self::require_agent(program_id, agent, bookkeeper)?;
+
+        let clock = Clock::from_account_info(clock_sysvar)?;
+        bookkeeper_data.max_requests_per_epoch = max_requests_per_epoch;
+        bookkeeper_data.current_backlog = current_backlog;
+        bookkeeper_data.last_heartbeat_slot = clock.slot;
+        BookKeeper::pack(bookkeeper_data, &mut bookkeeper.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_cancel_request(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+        client_id: u8,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let fee_payer = next_account_info(accounts_iter)?;
+        let req = next_account_info(accounts_iter)?;
+
+        if !fee_payer.is_signer {
+            msg!(
+                "Error: MissingRequiredSignature: {} did not sign",
+                fee_payer.key
+            );
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mut request_data = Self::load_request(program_id, fee_payer.key, req)?;
+
+        if request_data.client_id != client_id {
+            msg!(
+                "Error: ClientMismatch: request account holds client {}, got {}",
+                request_data.client_id,
+                client_id
+            );
+            return Err(ClientMismatch.into());
+        }
+
+        if request_data.sequence == 0 {
+            msg!(
+                "Error: NoRequest: client {} has not sent a request",
+                client_id
+            );
+            return Err(NoRequest.into());
+        }
+
+        //once the agent has started, the request runs to its end
+        if !request_data.status.can_cancel() {
+            msg!(
+                "Error: InvalidStatusTransition: {:?} to {:?}",
+                request_data.status,
+                RequestStatus::Cancelled
+            );
+            return Err(InvalidStatusTransition.into());
+        }
+        //packing a cancelled request overwrites it with the sentinel
+        request_data.status = RequestStatus::Cancelled;
+        request_data.request = RequestData::CANCELLED_REQUEST;
+
+        request_data.pack_account(&mut req.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_shrink_request_account(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let fee_payer = next_account_info(accounts_iter)?;
+        let req = next_account_info(accounts_iter)?;
+        let rent_sysvar = next_account_info(accounts_iter)?;
+
+        if !fee_payer.is_signer {
+            msg!(
+                "Error: MissingRequiredSignature: {} did not sign",
+                fee_payer.key
+            );
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let request_data = Self::load_request(program_id, fee_payer.key, req)?;
+
+        //only compact accounts can be resized
+        if !RequestData::is_compact(&req.try_borrow_data()?) {
+            msg!(
+                "Error: InvalidAccount: {} is not a compact request account",
+                req.key
+            );
+            return Err(InvalidAccount.into());
+        }
+
+        #[cfg(debug_assertions)]
+        let before = req.try_borrow_data()?.to_vec();
+
+        let needed = request_data.compact_len();
+        if needed < req.data_len() {
+            req.realloc(needed, false)?;
+        }
+
+        //the resized account must still read back as the same request
+        #[cfg(debug_assertions)]
+        verify_migration(&before, &req.try_borrow_data()?)?;
+
+        //refunding the rent the smaller account no longer needs
+        let rent = Rent::from_account_info(rent_sysvar)?;
+        let excess = req
+            .lamports()
+            .saturating_sub(rent.minimum_balance(req.data_len()));
+        **req.lamports.borrow_mut() -= excess;
+        **fee_payer.lamports.borrow_mut() = fee_payer
+            .lamports()
+            .checked_add(excess)
+            .ok_or_else(|| overflow("fee payer lamports"))?;
+
+        Ok(())
+    }
+
+    fn process_migrate_account(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let fee_payer = next_account_info(accounts_iter)?;
+        let account = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+        let rent_sysvar = next_account_info(accounts_iter)?;
+        let client_info = next_account_info(accounts_iter)?;
+        let program_data = next_account_info(accounts_iter)?;
+
+        Self::require_signer(fee_payer)?;
+
+        if account.owner != program_id {
+            msg!(
+                "Error: InvalidAccount: {} is owned by {}",
+                account.key,
+                account.owner
+            );
+            return Err(InvalidAccount.into());
+        }
+
+        let rent = Rent::from_account_info(rent_sysvar)?;
+        let before = account.try_borrow_data()?.to_vec();
+        let migrated = if *account.key == find_bookkeeper_pda(program_id).0 {
+            //the first release's bookkeeper has no authority to keep, so whoever
+            //migrates it takes the role and must be the one who deployed the program
+            if is_baseline_bookkeeper(&before) {
+                Self::require_upgrade_authority(program_id, fee_payer, program_data)?;
+            }
+            let bookkeeper_data = upgrade_bookkeeper(&before, fee_payer.key, program_id)?;
+            let mut migrated = vec![0; BookKeeper::LEN];
+            BookKeeper::pack(bookkeeper_data, &mut migrated)?;
+            migrated
+        } else if *account.key == find_request_pda(fee_payer.key, program_id).0 {
+            let request_data = upgrade_request_data(&before, fee_payer.key, program_id)?;
+            //clients registered before permissions moved into ClientInfo have
+            //none, and SendRequest can't run without it
+            Self::create_client_info(
+                program_id,
+                fee_payer,
+                fee_payer.key,
+                client_info,
+                system_program,
+                &rent,
+            )?;
+            //compact accounts only exist in the current layout
+            if RequestData::is_compact(&before) {
+                return Ok(());
+            }
+            let mut migrated = vec![0; RequestData::LEN];
+            RequestData::pack(request_data, &mut migrated)?;
+            migrated
+        } else {
+            msg!(
+                "Error: InvalidAccount: {} is neither the bookkeeper nor {}'s request account",
+                account.key,
+                fee_payer.key
+            );
+            return Err(InvalidAccount.into());
+        };
+        if migrated == before {
+            return Ok(());
+        }
+
+        //every field of a request account must read back from the current layout
+        #[cfg(debug_assertions)]
+        if migrated.len() == RequestData::LEN {
+            verify_migration(&before, &migrated)?;
+        }
+
+        //older layouts are shorter, so the account grows and is topped up to stay rent exempt
+        let top_up = rent
+            .minimum_balance(migrated.len())
+            .saturating_sub(account.lamports());
+        if top_up > 0 {
+            invoke(
+                &transfer(fee_payer.key, account.key, top_up),
+                &[fee_payer.clone(), account.clone(), system_program.clone()],
+            )?;
+        }
+        account.realloc(migrated.len(), false)?;
+        account.try_borrow_mut_data()?.copy_from_slice(&migrated);
+
+        Ok(())
+    }
+
+    fn process_publish_stats(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let publisher = next_account_info(accounts_iter)?;
+        let bookkeeper = next_account_info(accounts_iter)?;
+        let stats = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+        let clock_sysvar = next_account_info(accounts_iter)?;
+        let rent_sys_var = next_account_info(accounts_iter)?;
+
+        Self::require_signer(publisher)?;
+        let bookkeeper_data = Self::load_bookkeeper(program_id, bookkeeper)?;
+        //agents publish as part of their upkeep, like `PublishLoad`
+        if bookkeeper_data.authority != *publisher.key && !bookkeeper_data.is_agent(publisher.key) {
+            msg!(
+                "Error: Unauthorized: {} is neither the authority nor an agent",
+                publisher.key
+            );
+            return Err(Unauthorized.into());
+        }
+
+        let clock = Clock::from_account_info(clock_sysvar)?;
+        let (stats_key, bump) = find_stats_pda(clock.epoch, program_id);
+
+        if stats_key != *stats.key {
+            msg!(
+                "Error: InvalidAccount: expected stats {}, got {}",
+                stats_key,
+                stats.key
+            );
+            return Err(InvalidAccount.into());
+        };
+
+        //creating the snapshot account on the first publish of the epoch
+        if stats.data_is_empty() {
+            let rent = Rent::from_account_info(rent_sys_var)?;
+            let init_stats = create_account(
+                publisher.key,
+                &stats_key,
+                rent.minimum_balance(StatsSnapshot::LEN),
+                StatsSnapshot::LEN as u64,
+                program_id,
+            );
+
+            invoke_signed(
+                &init_stats,
+                &[system_program.clone(), publisher.clone(), stats.clone()],
+                &[&[STATS_SEED, &clock.epoch.to_le_bytes(), &[bump]]],
+            )?;
+        }
+
+        let snapshot = StatsSnapshot {
+            epoch: clock.epoch,
+            slot: clock.slot,
+            published_at: clock.unix_timestamp,
+            client_count: bookkeeper_data.client_count(),
+            next_id: bookkeeper_data.next_id,
+            last_heartbeat_slot: bookkeeper_data.last_heartbeat_slot,
+        };
+        StatsSnapshot::pack(snapshot, &mut stats.try_borrow_mut_data()?)?;
+
+        Ok(())
+    }
+
+    fn process_get_version(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+
+        let bookkeeper = next_account_info(accounts_iter)?;
+
+        let bookkeeper_data = Self::load_bookkeeper(program_id, bookkeeper)?;
+        let capabilities = Capabilities::new(&bookkeeper_data);
+
+        sol_log_data(&[&capabilities.try_to_vec()?]);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::error::BitokuError::{EmptyName, InvalidName};
+    use crate::events::parse_event;
+    use crate::instruction::{
+        acknowledge_request, bookkeeper_address, client_info_address, force_remove_client,
+        get_version, init_bitoku, init_bitoku_auto, lease_address, publish_load, publish_stats,
+        register_client_auto, register_client_for, remove_client, request_address,
+        send_request_auto, set_agent, set_authority, set_name_policy, set_paused,
+        set_reserved_prefix, set_supported_kinds, stats_snapshot_address, submit_signed_request,
+        update_request_status, write_response,
+    };
+    use crate::migration::verify_migration;
+    use crate::pool::{ClientPool, PoolStep};
+    use crate::state::{
+        first_free, first_set_bit, AccountType, ThrottleAdvice, ACCOUNT_HEADER_LEN, CANCELLED_TAG,
+        ENCODED_REQUEST_MAX_LEN, LEGACY_REQUEST_LEN, PERMISSION_CREATE, PERMISSION_DELETE,
+        PERMISSION_READ, PERMISSION_WRITE,
+    };
+    use solana_program::entrypoint::{deserialize, MAX_PERMITTED_DATA_INCREASE, NON_DUP_MARKER};
+    use solana_program::instruction::{AccountMeta, Instruction};
+    use solana_program::program_stubs::{set_syscall_stubs, SyscallStubs};
+    use solana_program::sysvar::instructions::{
+        construct_instructions_data, store_current_index, BorrowedAccountMeta, BorrowedInstruction,
+    };
+    use solana_program::{system_program, sysvar};
+    use std::{cell::RefCell, sync::Once};
+
+    struct TestAccount {
+        key: Pubkey,
+        is_signer: bool,
+        is_writable: bool,
+        lamports: u64,
+        data: Vec<u8>,
+        owner: Pubkey,
+    }
+
+    impl TestAccount {
+        fn new(key: Pubkey, data: Vec<u8>, owner: Pubkey) -> Self {
+            Self {
+                key,
+                is_signer: false,
+                is_writable: true,
+                lamports: 1_000_000_000,
+                data,
+                owner,
+            }
+        }
+
+        fn wallet() -> Self {
+            let mut wallet = Self::new(Pubkey::new_unique(), vec![], system_program::ID);
+            wallet.is_signer = true;
+            wallet
+        }
+
+        fn request_data(&self) -> RequestData {
+            RequestData::unpack_account(&self.data).unwrap()
+        }
+
+        fn bookkeeper_data(&self) -> BookKeeper {
+            BookKeeper::unpack_unchecked(&self.data).unwrap()
+        }
+    }
+
+    fn process(
+        program_id: &Pubkey,
+        accounts: &mut [&mut TestAccount],
+        instruction: BitokuInstructions,
+    ) -> ProgramResult {
+        run(program_id, accounts, &instruction.pack())
+    }
+
+    //lays the accounts out the way the runtime serializes them, so realloc works
+    fn serialize(program_id: &Pubkey, accounts: &[&mut TestAccount], data: &[u8]) -> Vec<u64> {
+        let mut input = Vec::new();
+        input.extend_from_slice(&(accounts.len() as u64).to_le_bytes());
+        for account in accounts {
+            input.extend_from_slice(&[NON_DUP_MARKER, account.is_signer as u8]);
+            input.extend_from_slice(&[account.is_writable as u8, 0, 0, 0, 0, 0]);
+            input.extend_from_slice(account.key.as_ref());
+            input.extend_from_slice(account.owner.as_ref());
+            input.extend_from_slice(&account.lamports.to_le_bytes());
+            input.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+            input.extend_from_slice(&account.data);
+            input.resize(input.len() + MAX_PERMITTED_DATA_INCREASE, 0);
+            input.resize(input.len() + (8 - input.len() % 8) % 8, 0);
+            input.extend_from_slice(&0u64.to_le_bytes());
+        }
+        input.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        input.extend_from_slice(data);
+        input.extend_from_slice(program_id.as_ref());
+
+        let mut aligned = vec![0u64; input.len().div_ceil(8)];
+        for (word, chunk) in aligned.iter_mut().zip(input.chunks(8)) {
+            let mut bytes = [0u8; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            *word = u64::from_le_bytes(bytes);
+        }
+        aligned
+    }
+
+    //runs the processor and copies the resulting lamports and data back
+    fn run(program_id: &Pubkey, accounts: &mut [&mut TestAccount], data: &[u8]) -> ProgramResult {
+        let mut input = serialize(program_id, accounts, data);
+        let (_, infos, data) = unsafe { deserialize(input.as_mut_ptr() as *mut u8) };
+        let result = Processor::process(program_id, &infos, data);
+        for (account, info) in accounts.iter_mut().zip(&infos) {
+            account.lamports = info.lamports();
+            account.data = info.data.borrow().to_vec();
+        }
+        result
+    }
+
+    thread_local! {
+        static LOGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+        static DATA: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+    }
+
+    //keeps `msg!` output per thread, so tests running in parallel don't see each other's
+    struct CapturedLogs;
+
+    impl SyscallStubs for CapturedLogs {
+        fn sol_log(&self, message: &str) {
+            LOGS.with(|logs| logs.borrow_mut().push(message.to_string()));
+        }
+
+        fn sol_log_data(&self, fields: &[&[u8]]) {
+            DATA.with(|data| {
+                data.borrow_mut()
+                    .extend(fields.iter().map(|field| field.to_vec()))
+            });
+        }
+    }
+
+    //the messages logged while `f` runs
+    fn logs_of(f: impl FnOnce()) -> Vec<String> {
+        capture_logs();
+        LOGS.with(|logs| logs.borrow_mut().clear());
+        f();
+        LOGS.with(|logs| logs.take())
+    }
+
+    //the `sol_log_data` fields logged while `f` runs
+    fn log_data_of(f: impl FnOnce()) -> Vec<Vec<u8>> {
+        capture_logs();
+        DATA.with(|data| data.borrow_mut().clear());
+        f();
+        DATA.with(|data| data.take())
+    }
+
+    fn capture_logs() {
+        static STUBS: Once = Once::new();
+        STUBS.call_once(|| {
+            set_syscall_stubs(Box::new(CapturedLogs));
+        });
+    }
+
+    struct Fixture {
+        program_id: Pubkey,
+        authority: TestAccount,
+        bookkeeper: TestAccount,
+        system_program: TestAccount,
+        rent: TestAccount,
+        clock: TestAccount,
+        //the program's ProgramData, naming the authority as its upgrade authority
+        program_data: TestAccount,
+        //lease PDAs of the buckets requests were sent for, created on first use
+        leases: Vec<TestAccount>,
+        //ClientInfo PDAs of the wallets registered so far, created on first use
+        client_infos: Vec<TestAccount>,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let program_id = Pubkey::new_unique();
+            let authority = TestAccount::wallet();
+
+            let (bookkeeper_key, bump) =
+                Pubkey::find_program_address(&["bookkeeper".as_ref()], &program_id);
+            let mut bookkeeper_data = vec![0u8; BookKeeper::LEN];
+            let bookkeeper = BookKeeper {
+                is_initialized: true,
+                status: [0; 32],
+                next_id: 0,
+                authority: authority.key,
+                reserved_prefixes: [[0; PREFIX_LEN]; RESERVED_PREFIXES],
+                supported_kinds: ALL_REQUEST_KINDS,
+                agent: authority.key,
+                free_slots: [0; 32],
+                name_policy: NamePolicyKind::Default as u8,
+                max_requests_per_epoch: 0,
+                current_backlog: 0,
+                paused: false,
+                bump,
+                agents: [Pubkey::default(); MAX_AGENTS],
+                max_client_requests_per_epoch: DEFAULT_MAX_CLIENT_REQUESTS_PER_EPOCH,
+                last_heartbeat_slot: 0,
+                admin: authority.key,
+            };
+            BookKeeper::pack(bookkeeper, &mut bookkeeper_data).unwrap();
+
+            let rent = Rent::default();
+            let mut rent_data = Vec::new();
+            rent_data.extend_from_slice(&rent.lamports_per_byte_year.to_le_bytes());
+            rent_data.extend_from_slice(&rent.exemption_threshold.to_le_bytes());
+            rent_data.push(rent.burn_percent);
+
+            let program_data = program_data_account(&program_id, Some(authority.key));
+            Self {
+                program_id,
+                authority,
+                bookkeeper: TestAccount::new(bookkeeper_key, bookkeeper_data, program_id),
+                system_program: TestAccount::new(system_program::ID, vec![], Pubkey::default()),
+                rent: TestAccount::new(sysvar::rent::ID, rent_data, sysvar::ID),
+                clock: TestAccount::new(sysvar::clock::ID, clock_data(0), sysvar::ID),
+                program_data,
+                leases: Vec::new(),
+                client_infos: Vec::new(),
+            }
+        }
+
+        //key of the ClientInfo PDA of `owner`, added to `client_infos` on first use
+        fn add_client_info(&mut self, owner: &Pubkey) -> Pubkey {
+            let (client_info_key, _) = find_client_info_pda(owner, &self.program_id);
+            if !self
+                .client_infos
+                .iter()
+                .any(|account| account.key == client_info_key)
+            {
+                self.client_infos.push(self.client_info_account(owner));
+            }
+            client_info_key
+        }
+
+        //an empty ClientInfo PDA for `owner`, as left behind by the create_account CPI
+        fn client_info_account(&self, owner: &Pubkey) -> TestAccount {
+            let (client_info_key, _) = find_client_info_pda(owner, &self.program_id);
+            TestAccount::new(client_info_key, vec![0; ClientInfo::LEN], self.program_id)
+        }
+
+        fn client_info_data(&self, owner: &Pubkey) -> ClientInfo {
+            let (client_info_key, _) = find_client_info_pda(owner, &self.program_id);
+            let client_info = self
+                .client_infos
+                .iter()
+                .find(|account| account.key == client_info_key);
+            ClientInfo::unpack(&client_info.unwrap().data).unwrap()
+        }
+
+        //key of the lease PDA of `req`'s bucket, added to `leases` on first use
+        fn add_lease(&mut self, req: &Request) -> Pubkey {
+            let (lease_key, _) = lease_address(&self.program_id, req);
+            if !self.leases.iter().any(|account| account.key == lease_key) {
+                self.leases.push(self.lease_account(req));
+            }
+            lease_key
+        }
+
+        fn lease_data(&self, req: &Request) -> BucketLease {
+            let (lease_key, _) = lease_address(&self.program_id, req);
+            let lease = self.leases.iter().find(|account| account.key == lease_key);
+            BucketLease::unpack_unchecked(&lease.unwrap().data).unwrap()
+        }
+
+        //an empty lease PDA for `req`'s bucket, as left behind by the create_account CPI
+        fn lease_account(&self, req: &Request) -> TestAccount {
+            let (lease_key, _) = lease_address(&self.program_id, req);
+            TestAccount::new(lease_key, vec![0; BucketLease::LEN], self.program_id)
+        }
+
+        //an empty request PDA for `owner`, as left behind by the create_account CPI
+        fn request_account(&self, owner: &Pubkey) -> TestAccount {
+            let (request_key, _) = Pubkey::find_program_address(
+                &["request".as_ref(), owner.as_ref()],
+                &self.program_id,
+            );
+            TestAccount::new(request_key, vec![0; RequestData::LEN], self.program_id)
+        }
+
+        fn register(
+            &mut self,
+            wallet: &mut TestAccount,
+            request: &mut TestAccount,
+        ) -> ProgramResult {
+            self.process_register(wallet, request, false)
+        }
+
+        fn process_register(
+            &mut self,
+            wallet: &mut TestAccount,
+            request: &mut TestAccount,
+            compact: bool,
+        ) -> ProgramResult {
+            let client_info_key = self.add_client_info(&wallet.key);
+            let client_info = self
+                .client_infos
+                .iter_mut()
+                .find(|account| account.key == client_info_key)
+                .unwrap();
+            process(
+                &self.program_id,
+                &mut [
+                    wallet,
+                    &mut self.bookkeeper,
+                    request,
+                    &mut self.system_program,
+                    &mut self.rent,
+                    client_info,
+                ],
+                BitokuInstructions::RegisterClient { compact },
+            )
+        }
+
+        fn remove(
+            &mut self,
+            wallet: &mut TestAccount,
+            request: &mut TestAccount,
+            client_id: u8,
+        ) -> ProgramResult {
+            process(
+                &self.program_id,
+                &mut [wallet, &mut self.bookkeeper, request],
+                BitokuInstructions::RemoveClient { client_id },
+            )
+        }
+
+        fn force_remove(
+            &mut self,
+            authority: Option<&mut TestAccount>,
+            request: &mut TestAccount,
+            owner: &mut TestAccount,
+            destination: &mut TestAccount,
+            client_id: u8,
+        ) -> ProgramResult {
+            let authority = match authority {
+                Some(authority) => authority,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [authority, &mut self.bookkeeper, request, owner, destination],
+                BitokuInstructions::ForceRemoveClient { client_id },
+            )
+        }
+
+        fn register_for(
+            &mut self,
+            authority: Option<&mut TestAccount>,
+            request: &mut TestAccount,
+            target: Pubkey,
+        ) -> ProgramResult {
+            let client_info_key = self.add_client_info(&target);
+            let client_info = self
+                .client_infos
+                .iter_mut()
+                .find(|account| account.key == client_info_key)
+                .unwrap();
+            let authority = match authority {
+                Some(authority) => authority,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [
+                    authority,
+                    &mut self.bookkeeper,
+                    request,
+                    &mut self.system_program,
+                    &mut self.rent,
+                    client_info,
+                ],
+                BitokuInstructions::RegisterClientFor { target },
+            )
+        }
+
+        fn reserve(
+            &mut self,
+            authority: Option<&mut TestAccount>,
+            index: u8,
+            prefix: &str,
+        ) -> ProgramResult {
+            let authority = match authority {
+                Some(authority) => authority,
+                None => &mut self.authority,
+            };
+            let mut padded = [0u8; PREFIX_LEN];
+            padded[..prefix.len()].copy_from_slice(prefix.as_bytes());
+            process(
+                &self.program_id,
+                &mut [authority, &mut self.bookkeeper],
+                BitokuInstructions::SetReservedPrefix {
+                    index,
+                    prefix: padded,
+                },
+            )
+        }
+
+        fn update_status(
+            &mut self,
+            agent: Option<&mut TestAccount>,
+            request: &mut TestAccount,
+            client_id: u8,
+            status: RequestStatus,
+        ) -> ProgramResult {
+            let agent = match agent {
+                Some(agent) => agent,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [agent, &mut self.bookkeeper, request],
+                BitokuInstructions::UpdateRequestStatus { client_id, status },
+            )
+        }
+
+        fn reset_epoch_counter(
+            &mut self,
+            authority: Option<&mut TestAccount>,
+            request: &mut TestAccount,
+            client_id: u8,
+        ) -> ProgramResult {
+            let authority = match authority {
+                Some(authority) => authority,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [authority, &mut self.bookkeeper, request],
+                BitokuInstructions::ResetEpochCounter { client_id },
+            )
+        }
+
+        fn set_permissions(
+            &mut self,
+            admin: Option<&mut TestAccount>,
+            request: &mut TestAccount,
+            owner: &Pubkey,
+            client_id: u8,
+            permissions: u8,
+        ) -> ProgramResult {
+            let client_info_key = self.add_client_info(owner);
+            let client_info = self
+                .client_infos
+                .iter_mut()
+                .find(|account| account.key == client_info_key)
+                .unwrap();
+            let admin = match admin {
+                Some(admin) => admin,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [admin, &mut self.bookkeeper, request, client_info],
+                BitokuInstructions::SetClientPermissions {
+                    client_id,
+                    permissions,
+                },
+            )
+        }
+
+        fn cancel(
+            &self,
+            wallet: &mut TestAccount,
+            request: &mut TestAccount,
+            client_id: u8,
+        ) -> ProgramResult {
+            process(
+                &self.program_id,
+                &mut [wallet, request],
+                BitokuInstructions::CancelRequest { client_id },
+            )
+        }
+
+        fn set_name_policy(
+            &mut self,
+            authority: Option<&mut TestAccount>,
+            policy: NamePolicyKind,
+        ) -> ProgramResult {
+            let authority = match authority {
+                Some(authority) => authority,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [authority, &mut self.bookkeeper],
+                BitokuInstructions::SetNamePolicy { policy },
+            )
+        }
+
+        fn set_supported_kinds(
+            &mut self,
+            agent: Option<&mut TestAccount>,
+            supported_kinds: u32,
+        ) -> ProgramResult {
+            let agent = match agent {
+                Some(agent) => agent,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [agent, &mut self.bookkeeper],
+                BitokuInstructions::SetSupportedKinds { supported_kinds },
+            )
+        }
+
+        fn write_response(
+            &mut self,
+            agent: Option<&mut TestAccount>,
+            request: &mut TestAccount,
+            client_id: u8,
+            status: RequestStatus,
+            response: &[u8],
+        ) -> ProgramResult {
+            let agent = match agent {
+                Some(agent) => agent,
+                None => &mut self.authority,
+            };
+            let mut data = [0u8; 512];
+            data[..response.len()].copy_from_slice(response);
+            process(
+                &self.program_id,
+                &mut [agent, &mut self.bookkeeper, request],
+                BitokuInstructions::WriteResponse {
+                    client_id,
+                    data,
+                    len: response.len() as u16,
+                    status,
+                },
+            )
+        }
+
+        fn set_agent(
+            &mut self,
+            authority: Option<&mut TestAccount>,
+            agent: Pubkey,
+        ) -> ProgramResult {
+            let authority = match authority {
+                Some(authority) => authority,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [authority, &mut self.bookkeeper],
+                BitokuInstructions::SetAgent { agent },
+            )
+        }
+
+        fn add_agent(
+            &mut self,
+            authority: Option<&mut TestAccount>,
+            pubkey: Pubkey,
+        ) -> ProgramResult {
+            let authority = match authority {
+                Some(authority) => authority,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [authority, &mut self.bookkeeper],
+                BitokuInstructions::AddAgent { pubkey },
+            )
+        }
+
+        fn remove_agent(
+            &mut self,
+            authority: Option<&mut TestAccount>,
+            pubkey: Pubkey,
+        ) -> ProgramResult {
+            let authority = match authority {
+                Some(authority) => authority,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [authority, &mut self.bookkeeper],
+                BitokuInstructions::RemoveAgent { pubkey },
+            )
+        }
+
+        //InitBitoku over the fixture's bookkeeper, with a signing `admin`
+        //defaulting to the authority
+        fn init_bitoku(&mut self, admin: Option<Pubkey>) -> ProgramResult {
+            let admin = admin.unwrap_or(self.authority.key);
+            let mut admin = TestAccount::new(admin, vec![], system_program::ID);
+            admin.is_signer = true;
+            self.init_bitoku_with(&mut admin)
+        }
+
+        fn init_bitoku_with(&mut self, admin: &mut TestAccount) -> ProgramResult {
+            process(
+                &self.program_id,
+                &mut [
+                    &mut self.authority,
+                    &mut self.bookkeeper,
+                    &mut self.system_program,
+                    &mut self.rent,
+                    admin,
+                    &mut self.program_data,
+                ],
+                BitokuInstructions::InitBitoku,
+            )
+        }
+
+        fn update_admin(&mut self, admin: &mut TestAccount, new_admin: Pubkey) -> ProgramResult {
+            process(
+                &self.program_id,
+                &mut [admin, &mut self.bookkeeper],
+                BitokuInstructions::UpdateAdmin { new_admin },
+            )
+        }
+
+        fn set_authority(
+            &mut self,
+            authority: Option<&mut TestAccount>,
+            new_authority: Pubkey,
+        ) -> ProgramResult {
+            let authority = match authority {
+                Some(authority) => authority,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [authority, &mut self.bookkeeper],
+                BitokuInstructions::SetAuthority { new_authority },
+            )
+        }
+
+        fn set_paused(
+            &mut self,
+            authority: Option<&mut TestAccount>,
+            paused: bool,
+        ) -> ProgramResult {
+            let authority = match authority {
+                Some(authority) => authority,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [authority, &mut self.bookkeeper],
+                BitokuInstructions::SetPaused { paused },
+            )
+        }
+
+        fn publish_load(
+            &mut self,
+            agent: Option<&mut TestAccount>,
+            max_requests_per_epoch: u32,
+            current_backlog: u16,
+        ) -> ProgramResult {
+            let agent = match agent {
+                Some(agent) => agent,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [agent, &mut self.bookkeeper, &mut self.clock],
+                BitokuInstructions::PublishLoad {
+                    max_requests_per_epoch,
+                    current_backlog,
+                },
+            )
+        }
+
+        //an empty snapshot PDA for `epoch`, as left behind by the create_account CPI
+        fn stats_account(&self, epoch: u64) -> TestAccount {
+            let (stats_key, _) = Pubkey::find_program_address(
+                &["stats".as_ref(), &epoch.to_le_bytes()],
+                &self.program_id,
+            );
+            TestAccount::new(stats_key, vec![0; StatsSnapshot::LEN], self.program_id)
+        }
+
+        fn publish_stats(
+            &mut self,
+            publisher: Option<&mut TestAccount>,
+            stats: &mut TestAccount,
+        ) -> ProgramResult {
+            let publisher = match publisher {
+                Some(publisher) => publisher,
+                None => &mut self.authority,
+            };
+            process(
+                &self.program_id,
+                &mut [
+                    publisher,
+                    &mut self.bookkeeper,
+                    stats,
+                    &mut self.system_program,
+                    &mut self.clock,
+                    &mut self.rent,
+                ],
+                BitokuInstructions::PublishStats,
+            )
+        }
+
+        //a wallet with a registered request PDA
+        fn client(&mut self) -> (TestAccount, TestAccount) {
+            let mut wallet = TestAccount::wallet();
+            let mut request = self.request_account(&wallet.key);
+            self.register(&mut wallet, &mut request).unwrap();
+            (wallet, request)
+        }
+
+        //a client whose request account uses the compact layout
+        fn compact_client(&mut self) -> (TestAccount, TestAccount) {
+            let mut wallet = TestAccount::wallet();
+            let mut request = self.request_account(&wallet.key);
+            request.data = vec![0; COMPACT_BASE_LEN];
+            request.lamports = Rent::default().minimum_balance(COMPACT_BASE_LEN);
+            self.process_register(&mut wallet, &mut request, true)
+                .unwrap();
+            (wallet, request)
+        }
+
+        fn shrink(&mut self, wallet: &mut TestAccount, request: &mut TestAccount) -> ProgramResult {
+            process(
+                &self.program_id,
+                &mut [wallet, request, &mut self.rent],
+                BitokuInstructions::ShrinkRequestAccount,
+            )
+        }
+
+        //MigrateAccount of `account`, creating `fee_payer`'s ClientInfo if it has none
+        fn migrate(
+            &mut self,
+            fee_payer: &mut TestAccount,
+            account: &mut TestAccount,
+        ) -> ProgramResult {
+            let client_info_key = self.add_client_info(&fee_payer.key);
+            let client_info = self
+                .client_infos
+                .iter_mut()
+                .find(|account| account.key == client_info_key)
+                .unwrap();
+            process(
+                &self.program_id,
+                &mut [
+                    fee_payer,
+                    account,
+                    &mut self.system_program,
+                    &mut self.rent,
+                    client_info,
+                    &mut self.program_data,
+                ],
+                BitokuInstructions::MigrateAccount,
+            )
+        }
+
+        //MigrateAccount of the bookkeeper, paid by the authority unless `fee_payer` is given
+        fn migrate_bookkeeper(&mut self, fee_payer: Option<&mut TestAccount>) -> ProgramResult {
+            let payer_key = fee_payer
+                .as_ref()
+                .map_or(self.authority.key, |payer| payer.key);
+            let mut client_info = self.client_info_account(&payer_key);
+            let fee_payer = fee_payer.unwrap_or(&mut self.authority);
+            process(
+                &self.program_id,
+                &mut [
+                    fee_payer,
+                    &mut self.bookkeeper,
+                    &mut self.system_program,
+                    &mut self.rent,
+                    &mut client_info,
+                    &mut self.program_data,
+                ],
+                BitokuInstructions::MigrateAccount,
+            )
+        }
+
+        fn send(
+            &mut self,
+            wallet: &mut TestAccount,
+            request: &mut TestAccount,
+            client_id: u8,
+            req: Request,
+        ) -> ProgramResult {
+            self.send_with_ref(wallet, request, client_id, [0; 32], req)
+        }
+
+        //the agent acknowledges the request `request` holds, as it does before the
+        //client may send the next one; accounts the processor rejects are left to
+        //the instruction under test
+        fn acknowledge(&mut self, request: &mut TestAccount) {
+            if let Ok(data) = RequestData::unpack_account(&request.data) {
+                if data.is_pending() {
+                    self.update_status(None, request, data.client_id, RequestStatus::Processing)
+                        .ok();
+                }
+            }
+        }
+
+        //sends `req` once the previous request has been acknowledged
+        fn send_with_ref(
+            &mut self,
+            wallet: &mut TestAccount,
+            request: &mut TestAccount,
+            client_id: u8,
+            submission_ref: [u8; 32],
+            req: Request,
+        ) -> ProgramResult {
+            self.acknowledge(request);
+            self.process_send(
+                wallet,
+                request,
+                BitokuInstructions::SendRequest {
+                    client_id,
+                    submission_ref,
+                    request: req,
+                },
+            )
+        }
+
+        //runs a `SendRequest` or `OverwriteRequest` as it is, pending request or not
+        fn process_send(
+            &mut self,
+            wallet: &mut TestAccount,
+            request: &mut TestAccount,
+            ix: BitokuInstructions,
+        ) -> ProgramResult {
+            let (BitokuInstructions::SendRequest { request: req, .. }
+            | BitokuInstructions::OverwriteRequest { request: req, .. }) = &ix
+            else {
+                panic!("not a send");
+            };
+            let lease_key = self.add_lease(req);
+            let client_info_key = self.add_client_info(&wallet.key);
+            let lease = self
+                .leases
+                .iter_mut()
+                .find(|account| account.key == lease_key)
+                .unwrap();
+            let client_info = self
+                .client_infos
+                .iter_mut()
+                .find(|account| account.key == client_info_key)
+                .unwrap();
+            process(
+                &self.program_id,
+                &mut [
+                    wallet,
+                    request,
+                    &mut self.bookkeeper,
+                    &mut self.clock,
+                    client_info,
+                    &mut self.system_program,
+                    &mut self.rent,
+                    lease,
+                ],
+                ix,
+            )
+        }
+
+        //relays `envelope` in a transaction where `ed25519` comes right before it
+        fn submit_signed(
+            &mut self,
+            relayer: &mut TestAccount,
+            request: &mut TestAccount,
+            envelope: &SignedRequestEnvelope,
+            ed25519: &Instruction,
+        ) -> ProgramResult {
+            self.acknowledge(request);
+            let ix = submit_signed_request(relayer.key, self.program_id, envelope).unwrap();
+            let mut instructions = instructions_sysvar(&[ed25519, &ix], 1);
+            let lease_key = self.add_lease(&envelope.request);
+            let client_info_key = self.add_client_info(&envelope.signer);
+            let Fixture {
+                program_id,
+                bookkeeper,
+                clock,
+                system_program,
+                rent,
+                leases,
+                client_infos,
+                ..
+            } = self;
+            let lease = leases
+                .iter_mut()
+                .find(|account| account.key == lease_key)
+                .unwrap();
+            let client_info = client_infos
+                .iter_mut()
+                .find(|account| account.key == client_info_key)
+                .unwrap();
+            run(
+                program_id,
+                &mut [
+                    relayer,
+                    &mut instructions,
+                    request,
+                    bookkeeper,
+                    clock,
+                    client_info,
+                    system_program,
+                    rent,
+                    lease,
+                ],
+                &ix.data,
+            )
+        }
+    }
+
+    //the instructions sysvar of a transaction made of `instructions`, executing
+    //the one at `current`
+    fn instructions_sysvar(instructions: &[&Instruction], current: u16) -> TestAccount {
+        let borrowed: Vec<BorrowedInstruction> = instructions
+            .iter()
+            .map(|ix| BorrowedInstruction {
+                program_id: &ix.program_id,
+                accounts: ix
+                    .accounts
+                    .iter()
+                    .map(|meta| BorrowedAccountMeta {
+                        pubkey: &meta.pubkey,
+                        is_signer: meta.is_signer,
+                        is_writable: meta.is_writable,
+                    })
+                    .collect(),
+                data: &ix.data,
+            })
+            .collect();
+        let mut data = construct_instructions_data(&borrowed);
+        store_current_index(&mut data, current);
+        TestAccount::new(sysvar::instructions::ID, data, sysvar::ID)
+    }
+
+    //the ProgramData account the upgradeable loader keeps for `program_id`
+    fn program_data_account(program_id: &Pubkey, upgrade_authority: Option<Pubkey>) -> TestAccount {
+        let state = UpgradeableLoaderState::ProgramData {
+            slot: 0,
+            upgrade_authority_address: upgrade_authority,
+        };
+        let mut data = bincode::serialize(&state).unwrap();
+        data.resize(UpgradeableLoaderState::size_of_programdata_metadata(), 0);
+        TestAccount::new(
+            find_program_data_address(program_id),
+            data,
+            bpf_loader_upgradeable::id(),
+        )
+    }
+
+    fn clock_data(unix_timestamp: i64) -> Vec<u8> {
+        epoch_clock_data(0, 0, unix_timestamp)
+    }
+
+    fn epoch_clock_data(epoch: u64, slot: u64, unix_timestamp: i64) -> Vec<u8> {
+        let clock = Clock {
+            slot,
+            epoch,
+            unix_timestamp,
+            ..Clock::default()
+        };
+        let mut clock_data = Vec::new();
+        clock_data.extend_from_slice(&clock.slot.to_le_bytes());
+        clock_data.extend_from_slice(&clock.epoch_start_timestamp.to_le_bytes());
+        clock_data.extend_from_slice(&clock.epoch.to_le_bytes());
+        clock_data.extend_from_slice(&clock.leader_schedule_epoch.to_le_bytes());
+        clock_data.extend_from_slice(&clock.unix_timestamp.to_le_bytes());
+        clock_data
+    }
+
+    fn name(s: &str) -> [u8; 128] {
+        let mut name = [0u8; 128];
+        name[..s.len()].copy_from_slice(s.as_bytes());
+        name
+    }
+
+    #[test]
+    fn test_init_bitoku_builder_accounts() {
+        let mut f = Fixture::new();
+        f.bookkeeper.data = vec![0; BookKeeper::LEN];
+        let ix = init_bitoku_auto(f.authority.key, f.program_id).unwrap();
+
+        let keys: Vec<Pubkey> = ix.accounts.iter().map(|meta| meta.pubkey).collect();
+        assert_eq!(
+            keys,
+            vec![
+                f.authority.key,
+                f.bookkeeper.key,
+                f.system_program.key,
+                f.rent.key,
+                f.authority.key,
+                f.program_data.key
+            ]
+        );
+
+        let mut admin = TestAccount::new(f.authority.key, vec![], system_program::ID);
+        admin.is_signer = true;
+        let mut accounts = [
+            &mut f.authority,
+            &mut f.bookkeeper,
+            &mut f.system_program,
+            &mut f.rent,
+            &mut admin,
+            &mut f.program_data,
+        ];
+        run(&f.program_id, &mut accounts, &ix.data).unwrap();
+
+        let bookkeeper = f.bookkeeper.bookkeeper_data();
+        assert_eq!(bookkeeper.authority, f.authority.key);
+        assert_eq!(bookkeeper.admin, f.authority.key);
+        assert_eq!(
+            bookkeeper.max_client_requests_per_epoch,
+            DEFAULT_MAX_CLIENT_REQUESTS_PER_EPOCH
+        );
+    }
+
+    #[test]
+    fn test_reinitialization_rejected() {
+        let mut f = Fixture::new();
+        let initialized = f.bookkeeper.data.clone();
+        let attacker = TestAccount::wallet().key;
+        assert_eq!(
+            f.init_bitoku(Some(attacker)),
+            Err(AccountAlreadyInitialized.into())
+        );
+        assert_eq!(f.bookkeeper.data, initialized);
+
+        let (mut wallet, mut request) = f.client();
+        assert_eq!(
+            f.register(&mut wallet, &mut request),
+            Err(AccountAlreadyInitialized.into())
+        );
+    }
+
+    #[test]
+    fn test_init_bitoku_requires_upgrade_authority() {
+        let mut f = Fixture::new();
+        f.bookkeeper.data = vec![0; BookKeeper::LEN];
+
+        //whoever lands InitBitoku first on a fresh deployment doesn't get it
+        let deployer = f.program_data.data.clone();
+        let attacker = TestAccount::wallet().key;
+        for upgrade_authority in [Some(attacker), None] {
+            f.program_data = program_data_account(&f.program_id, upgrade_authority);
+            assert_eq!(f.init_bitoku(None), Err(Unauthorized.into()));
+        }
+
+        //nor can it point the check at a ProgramData it controls
+        f.program_data = program_data_account(&Pubkey::new_unique(), Some(f.authority.key));
+        assert_eq!(f.init_bitoku(None), Err(InvalidAccount.into()));
+        f.program_data = program_data_account(&f.program_id, Some(f.authority.key));
+        f.program_data.owner = f.program_id;
+        assert_eq!(f.init_bitoku(None), Err(InvalidAccount.into()));
+        f.program_data.owner = bpf_loader_upgradeable::id();
+        assert_eq!(f.program_data.data, deployer);
+
+        //the admin has to sign for the role it is given
+        let mut admin = TestAccount::new(attacker, vec![], system_program::ID);
+        assert_eq!(
+            f.init_bitoku_with(&mut admin),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(f.bookkeeper.data, vec![0; BookKeeper::LEN]);
+
+        admin.is_signer = true;
+        f.init_bitoku_with(&mut admin).unwrap();
+        assert_eq!(f.bookkeeper.bookkeeper_data().admin, attacker);
+    }
+
+    #[test]
+    fn test_stored_bumps_checked() {
+        let mut f = Fixture::new();
+        let initialized = f.bookkeeper.data.clone();
+        f.bookkeeper.data = vec![0; BookKeeper::LEN];
+        f.init_bitoku(None).unwrap();
+        assert_eq!(f.bookkeeper.data, initialized);
+        assert_eq!(
+            f.bookkeeper.bookkeeper_data().bump,
+            find_bookkeeper_pda(&f.program_id).1
+        );
+
+        let (mut wallet, mut request) = f.client();
+        assert_eq!(
+            request.request_data().bump,
+            find_request_pda(&wallet.key, &f.program_id).1
+        );
+
+        //any other bump derives another address, or none at all
+        let mut request_data = request.request_data();
+        request_data.bump = request_data.bump.wrapping_sub(1);
+        request_data.pack_account(&mut request.data).unwrap();
+        assert_eq!(
+            f.send(
+                &mut wallet,
+                &mut request,
+                0,
+                Request::CreateBucket { name: name("b") }
+            ),
+            Err(InvalidAccount.into())
+        );
+        assert_eq!(
+            f.remove(&mut wallet, &mut request, 0),
+            Err(InvalidAccount.into())
+        );
+
+        let mut bookkeeper_data = f.bookkeeper.bookkeeper_data();
+        bookkeeper_data.bump = bookkeeper_data.bump.wrapping_sub(1);
+        BookKeeper::pack(bookkeeper_data, &mut f.bookkeeper.data).unwrap();
+        let mut wallet = TestAccount::wallet();
+        let mut request = f.request_account(&wallet.key);
+        assert_eq!(
+            f.register(&mut wallet, &mut request),
+            Err(InvalidAccount.into())
+        );
+    }
+
+    #[test]
+    fn test_uninitialized_accounts_rejected() {
+        let mut f = Fixture::new();
+        let (mut wallet, _) = f.client();
+        let mut request = f.request_account(&wallet.key);
+        assert_eq!(
+            f.send(
+                &mut wallet,
+                &mut request,
+                0,
+                Request::CreateBucket { name: name("b") }
+            ),
+            Err(ProgramError::UninitializedAccount)
+        );
+        assert_eq!(
+            f.remove(&mut wallet, &mut request, 0),
+            Err(ProgramError::UninitializedAccount)
+        );
+
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        f.bookkeeper.data = vec![0; BookKeeper::LEN];
+        assert_eq!(
+            f.send(
+                &mut wallet,
+                &mut request,
+                0,
+                Request::CreateBucket { name: name("b") }
+            ),
+            Err(ProgramError::UninitializedAccount)
+        );
+        let mut request = f.request_account(&wallet.key);
+        assert_eq!(
+            f.register(&mut wallet, &mut request),
+            Err(ProgramError::UninitializedAccount)
+        );
+    }
+
+    #[test]
+    fn test_account_header_checked() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        request.data[0] = AccountType::BookKeeper as u8;
+        assert_eq!(
+            f.send(
+                &mut wallet,
+                &mut request,
+                0,
+                Request::CreateBucket { name: name("b") }
+            ),
+            Err(ProgramError::InvalidAccountData)
+        );
+
+        //a bookkeeper from before the header has to be re-created
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        f.bookkeeper.data.remove(0);
+        f.bookkeeper.data.remove(0);
+        assert_eq!(
+            f.send(
+                &mut wallet,
+                &mut request,
+                0,
+                Request::CreateBucket { name: name("b") }
+            ),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn test_legacy_request_account() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        request.data.drain(..ACCOUNT_HEADER_LEN);
+        assert_eq!(request.data.len(), LEGACY_REQUEST_LEN);
+        assert!(request.request_data().is_initialized);
+
+        let req = Request::CreateBucket { name: name("b") };
+        f.send(&mut wallet, &mut request, 0, req.clone()).unwrap();
+        assert_eq!(request.data.len(), LEGACY_REQUEST_LEN);
+        assert_eq!(request.request_data().request, req);
+
+        f.remove(&mut wallet, &mut request, 0).unwrap();
+        assert_eq!(request.data.len(), LEGACY_REQUEST_LEN);
+        assert_eq!(f.bookkeeper.bookkeeper_data().status, [0; 32]);
+    }
+
+    #[test]
+    fn test_accounts_must_be_program_owned() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+        f.send(&mut wallet, &mut request, 0, bucket.clone())
+            .unwrap();
+        let invalid = Err(InvalidAccount.into());
+
+        //right address, right size and contents, wrong owner
+        request.owner = system_program::ID;
+        let stored = request.data.clone();
+        assert_eq!(
+            f.send(&mut wallet, &mut request, 0, bucket.clone()),
+            invalid
+        );
+        assert_eq!(
+            f.update_status(None, &mut request, 0, RequestStatus::Processing),
+            invalid
+        );
+        assert_eq!(
+            f.write_response(None, &mut request, 0, RequestStatus::Completed, b"x"),
+            invalid
+        );
+        assert_eq!(f.remove(&mut wallet, &mut request, 0), invalid);
+        assert_eq!(request.data, stored);
+        request.owner = f.program_id;
+
+        f.bookkeeper.owner = system_program::ID;
+        let stored = f.bookkeeper.data.clone();
+        let mut other = TestAccount::wallet();
+        let mut other_request = f.request_account(&other.key);
+        let mut stats = f.stats_account(0);
+        assert_eq!(f.register(&mut other, &mut other_request), invalid);
+        assert_eq!(f.register_for(None, &mut other_request, other.key), invalid);
+        assert_eq!(f.send(&mut wallet, &mut request, 0, bucket), invalid);
+        assert_eq!(f.reserve(None, 0, "sys/"), invalid);
+        assert_eq!(f.set_supported_kinds(None, 0), invalid);
+        assert_eq!(
+            f.update_status(None, &mut request, 0, RequestStatus::Processing),
+            invalid
+        );
+        assert_eq!(
+            f.write_response(None, &mut request, 0, RequestStatus::Completed, b"x"),
+            invalid
+        );
+        assert_eq!(f.set_agent(None, other.key), invalid);
+        assert_eq!(f.set_authority(None, other.key), invalid);
+        assert_eq!(f.set_name_policy(None, NamePolicyKind::Utf8), invalid);
+        assert_eq!(f.publish_load(None, 1, 1), invalid);
+        assert_eq!(f.publish_stats(None, &mut stats), invalid);
+        assert_eq!(f.remove(&mut wallet, &mut request, 0), invalid);
+        assert_eq!(
+            process(
+                &f.program_id,
+                &mut [&mut f.bookkeeper],
+                BitokuInstructions::GetVersion
+            ),
+            invalid
+        );
+        assert_eq!(f.bookkeeper.data, stored);
+
+        f.bookkeeper.owner = f.program_id;
+        f.remove(&mut wallet, &mut request, 0).unwrap();
+    }
+
+    #[test]
+    fn test_fee_payer_must_sign() {
+        let mut f = Fixture::new();
+        let initialized = f.bookkeeper.data.clone();
+
+        f.bookkeeper.data = vec![0; BookKeeper::LEN];
+        f.authority.is_signer = false;
+        assert_eq!(
+            f.init_bitoku(None),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(f.bookkeeper.data, vec![0; BookKeeper::LEN]);
+        f.authority.is_signer = true;
+        f.bookkeeper.data = initialized;
+
+        let mut wallet = TestAccount::wallet();
+        let mut request = f.request_account(&wallet.key);
+        wallet.is_signer = false;
+        assert_eq!(
+            f.register(&mut wallet, &mut request),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(f.bookkeeper.bookkeeper_data().client_count(), 0);
+        wallet.is_signer = true;
+        f.register(&mut wallet, &mut request).unwrap();
+        f.send(
+            &mut wallet,
+            &mut request,
+            0,
+            Request::CreateBucket { name: name("mine") },
+        )
+        .unwrap();
+
+        //someone passing the owner's key without its signature cannot
+        //replace the acknowledged request or deregister the client
+        f.acknowledge(&mut request);
+        let stored = request.data.clone();
+        wallet.is_signer = false;
+        assert_eq!(
+            f.send(
+                &mut wallet,
+                &mut request,
+                0,
+                Request::DeleteBucket { name: name("mine") },
+            ),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(
+            f.remove(&mut wallet, &mut request, 0),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(request.data, stored);
+        assert_eq!(f.bookkeeper.bookkeeper_data().client_count(), 1);
+    }
+
+    //runs `ix` with each account's flags taken from the builder's metas and fails if
+    //the handler changes an account the instruction declares readonly
+    fn replay(pool: &mut [TestAccount], ix: &Instruction) -> ProgramResult {
+        let decoded = BitokuInstructions::unpack(&ix.data)?;
+        let declared: Vec<AccountMeta> = decoded
+            .accounts()
+            .iter()
+            .zip(&ix.accounts)
+            .map(|(spec, meta)| spec.meta(meta.pubkey))
+            .collect();
+        assert_eq!(declared, ix.accounts, "instruction {}", ix.data[0]);
+
+        let mut accounts: Vec<&mut TestAccount> = pool
+            .iter_mut()
+            .filter(|account| ix.accounts.iter().any(|meta| meta.pubkey == account.key))
+            .collect();
+        accounts.sort_by_key(|account| {
+            ix.accounts
+                .iter()
+                .position(|meta| meta.pubkey == account.key)
+        });
+        assert_eq!(accounts.len(), ix.accounts.len());
+
+        let before: Vec<(u64, Vec<u8>)> = accounts
+            .iter_mut()
+            .zip(&ix.accounts)
+            .map(|(account, meta)| {
+                account.is_signer = meta.is_signer;
+                account.is_writable = meta.is_writable;
+                (account.lamports, account.data.clone())
+            })
+            .collect();
+
+        let result = run(&ix.program_id, &mut accounts, &ix.data);
+
+        for ((account, meta), (lamports, data)) in accounts.iter().zip(&ix.accounts).zip(before) {
+            if !meta.is_writable {
+                assert_eq!(account.lamports, lamports, "instruction {}", ix.data[0]);
+                assert_eq!(account.data, data, "instruction {}", ix.data[0]);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_builder_addresses_match_processor() {
+        //the fixture derives its accounts from the raw seeds, the way the processor checks them
+        for epoch in 0..4 {
+            let f = Fixture::new();
+            let owner = Pubkey::new_unique();
+            assert_eq!(bookkeeper_address(&f.program_id).0, f.bookkeeper.key);
+            assert_eq!(
+                request_address(&f.program_id, &owner).0,
+                f.request_account(&owner).key
+            );
+            assert_eq!(
+                client_info_address(&f.program_id, &owner).0,
+                f.client_info_account(&owner).key
+            );
+            assert_eq!(
+                stats_snapshot_address(&f.program_id, epoch).0,
+                f.stats_account(epoch).key
+            );
+        }
+    }
+
+    #[test]
+    fn test_handlers_honour_declared_accounts() {
+        let f = Fixture::new();
+        let program_id = f.program_id;
+        let authority = f.authority.key;
+        let bookkeeper = f.bookkeeper.key;
+        let program_data_key = f.program_data.key;
+        let wallet = TestAccount::wallet();
+        let owner = wallet.key;
+        let target = Pubkey::new_unique();
+        let request = f.request_account(&owner);
+        let target_request = f.request_account(&target);
+        let client_info = f.client_info_account(&owner);
+        let target_client_info = f.client_info_account(&target);
+        let stats = f.stats_account(0);
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+        let lease = f.lease_account(&bucket);
+        let (request_key, target_request_key, stats_key) =
+            (request.key, target_request.key, stats.key);
+        let envelope = SignedRequestEnvelope::new(bucket.clone(), 0, 1, owner, [0; 64]);
+        let signed = submit_signed_request(authority, program_id, &envelope).unwrap();
+        let ed25519 = envelope.ed25519_instruction(&program_id);
+
+        let mut pool = vec![
+            f.authority,
+            TestAccount::new(bookkeeper, vec![0; BookKeeper::LEN], program_id),
+            f.system_program,
+            f.rent,
+            f.clock,
+            wallet,
+            request,
+            target_request,
+            client_info,
+            target_client_info,
+            TestAccount::new(target, vec![], system_program::ID),
+            stats,
+            lease,
+            instructions_sysvar(&[&ed25519, &signed], 1),
+            f.program_data,
+        ];
+
+        let instructions = [
+            init_bitoku(
+                authority,
+                bookkeeper,
+                system_program::ID,
+                sysvar::rent::ID,
+                target,
+                program_data_key,
+                program_id,
+            )
+            .unwrap(),
+            register_client_auto(owner, program_id).unwrap().0,
+            register_client_for(
+                authority,
+                bookkeeper,
+                target_request_key,
+                system_program::ID,
+                sysvar::rent::ID,
+                program_id,
+                target,
+            )
+            .unwrap(),
+            set_reserved_prefix(authority, bookkeeper, program_id, 0, "sys/").unwrap(),
+            set_supported_kinds(authority, program_id, ALL_REQUEST_KINDS).unwrap(),
+            send_request_auto(owner, program_id, 0, bucket).unwrap().0,
+            update_request_status(
+                authority,
+                request_key,
+                program_id,
+                0,
+                RequestStatus::Processing,
+            )
+            .unwrap(),
+            signed,
+            write_response(
+                authority,
+                request_key,
+                program_id,
+                0,
+                RequestStatus::Completed,
+                b"done",
+            )
+            .unwrap(),
+            set_agent(authority, program_id, authority).unwrap(),
+            set_authority(authority, program_id, authority).unwrap(),
+            set_paused(authority, program_id, false).unwrap(),
+            set_name_policy(authority, program_id, NamePolicyKind::Utf8).unwrap(),
+            publish_load(authority, program_id, 100, 3).unwrap(),
+            publish_stats(authority, program_id, 0).unwrap(),
+            get_version(bookkeeper, program_id).unwrap(),
+            remove_client(owner, bookkeeper, request_key, program_id, 0).unwrap(),
+            force_remove_client(authority, program_id, target, owner, 1).unwrap(),
+        ];
+
+        let mut covered: Vec<u8> = instructions.iter().map(|ix| ix.data[0]).collect();
+        covered.sort_unstable();
+        covered.dedup();
+        //ShrinkRequestAccount needs a compact request account
+        assert_eq!(
+            covered,
+            (0..19).filter(|&tag| tag != 14).collect::<Vec<_>>()
+        );
+
+        for ix in &instructions {
+            replay(&mut pool, ix).unwrap();
+        }
+        assert_ne!(
+            pool.iter().find(|a| a.key == stats_key).unwrap().data,
+            vec![0; StatsSnapshot::LEN]
+        );
+    }
+
+    #[test]
+    fn test_auto_builders_match_processor() {
+        let mut f = Fixture::new();
+        let mut wallet = TestAccount::wallet();
+        let mut request = f.request_account(&wallet.key);
+        let mut client_info = f.client_info_account(&wallet.key);
+
+        let (ix, request_key) = register_client_auto(wallet.key, f.program_id).unwrap();
+        assert_eq!(request_key, request.key);
+        let keys: Vec<Pubkey> = ix.accounts.iter().map(|meta| meta.pubkey).collect();
+        assert_eq!(
+            keys,
+            vec![
+                wallet.key,
+                f.bookkeeper.key,
+                request.key,
+                f.system_program.key,
+                f.rent.key,
+                client_info.key
+            ]
+        );
+
+        let mut accounts = [
+            &mut wallet,
+            &mut f.bookkeeper,
+            &mut request,
+            &mut f.system_program,
+            &mut f.rent,
+            &mut client_info,
+        ];
+        run(&f.program_id, &mut accounts, &ix.data).unwrap();
+
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+        let (ix, request_key) =
+            send_request_auto(wallet.key, f.program_id, 0, bucket.clone()).unwrap();
+        assert_eq!(request_key, request.key);
+
+        let mut lease = f.lease_account(&bucket);
+        let mut accounts = [
+            &mut wallet,
+            &mut request,
+            &mut f.bookkeeper,
+            &mut f.clock,
+            &mut client_info,
+            &mut f.system_program,
+            &mut f.rent,
+            &mut lease,
+        ];
+        run(&f.program_id, &mut accounts, &ix.data).unwrap();
+
+        assert_eq!(request.request_data().request, bucket);
+    }
+
+    #[test]
+    fn test_client_pool() {
+        let f = Fixture::new();
+        let program_id = f.program_id;
+        let bookkeeper = f.bookkeeper.key;
+        let agent = f.authority.key;
+        let wallets = [
+            TestAccount::wallet(),
+            TestAccount::wallet(),
+            TestAccount::wallet(),
+        ];
+        let authorities: Vec<Pubkey> = wallets.iter().map(|wallet| wallet.key).collect();
+
+        let bucket = |i: usize| Request::CreateBucket {
+            name: name(&format!("bucket{}", i)),
+        };
+        let requests: Vec<TestAccount> = authorities
+            .iter()
+            .map(|authority| f.request_account(authority))
+            .collect();
+        let client_infos: Vec<TestAccount> = authorities
+            .iter()
+            .map(|authority| f.client_info_account(authority))
+            .collect();
+        let leases: Vec<TestAccount> = (0..6).map(|i| f.lease_account(&bucket(i))).collect();
+
+        let mut accounts = vec![f.authority, f.bookkeeper, f.system_program, f.rent, f.clock];
+        accounts.extend(wallets);
+        accounts.extend(requests);
+        accounts.extend(client_infos);
+        accounts.extend(leases);
+        let data = |accounts: &[TestAccount], key: Pubkey| {
+            accounts.iter().find(|a| a.key == key).unwrap().data.clone()
+        };
+
+        let mut pool = ClientPool::new(program_id);
+        for (i, authority) in authorities.iter().enumerate() {
+            let PoolStep::Register(ix) = pool.send_for(authority, bucket(i)).unwrap() else {
+                panic!("expected a registration");
+            };
+            replay(&mut accounts, &ix).unwrap();
+            let request = pool.client(authority).unwrap().request;
+            let client_id = pool.confirm(authority, &data(&accounts, request)).unwrap();
+            assert_eq!(client_id, i as u8);
+        }
+
+        //sends from all three authorities interleave
+        for round in 0..2 {
+            for (i, authority) in authorities.iter().enumerate() {
+                let PoolStep::Send(ix) = pool.send_for(authority, bucket(round * 3 + i)).unwrap()
+                else {
+                    panic!("expected a send");
+                };
+                replay(&mut accounts, &ix).unwrap();
+            }
+            //the agent picks the requests up before the next round
+            for (i, authority) in authorities.iter().enumerate() {
+                let request = pool.client(authority).unwrap().request;
+                let ix = acknowledge_request(
+                    agent,
+                    request,
+                    program_id,
+                    i as u8,
+                    RequestStatus::Processing,
+                )
+                .unwrap();
+                replay(&mut accounts, &ix).unwrap();
+            }
+        }
+        for (i, authority) in authorities.iter().enumerate() {
+            let request = pool.client(authority).unwrap().request;
+            let stored = RequestData::unpack_account(&data(&accounts, request)).unwrap();
+            assert_eq!(stored.request, bucket(3 + i));
+        }
+
+        let mut restored = ClientPool::from_snapshot(&pool.snapshot()).unwrap();
+        assert_eq!(restored, pool);
+
+        let ix = restored.remove(&authorities[1]).unwrap();
+        replay(&mut accounts, &ix).unwrap();
+        assert!(restored.client(&authorities[1]).is_none());
+        assert_eq!(
+            restored.authorities().collect::<Vec<_>>(),
+            pool.authorities()
+                .filter(|&authority| *authority != authorities[1])
+                .collect::<Vec<_>>()
+        );
+        let status = BookKeeper::unpack_unchecked(&data(&accounts, bookkeeper))
+            .unwrap()
+            .status;
+        assert_eq!(first_free(&status), Some(1));
+    }
+
+    #[test]
+    fn test_rename_file_validates_new_name() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+
+        let bad = Request::RenameFile {
+            name: name("file-1"),
+            file_id: 1,
+            new_name: name("bad name!"),
+        };
+        assert_eq!(
+            f.send(&mut wallet, &mut request, 0, bad),
+            Err(InvalidName.into())
+        );
+
+        let good = Request::RenameFile {
+            name: name("file-1"),
+            file_id: 1,
+            new_name: name("file-2"),
+        };
+        f.send(&mut wallet, &mut request, 0, good.clone()).unwrap();
+
+        let stored = request.request_data();
+        assert_eq!(stored.request, good);
+        assert_eq!(stored.requester, wallet.key);
+    }
+
+    #[test]
+    fn test_name_policy_switch() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let borderline = Request::CreateBucket {
+            name: name("photos(2023)"),
+        };
+        let unicode = Request::CreateBucket {
+            name: name("f\u{f6}rsta"),
+        };
+
+        assert_eq!(
+            f.send(&mut wallet, &mut request, 0, borderline.clone()),
+            Err(InvalidName.into())
+        );
+
+        f.set_name_policy(None, NamePolicyKind::S3Compat).unwrap();
+        f.send(&mut wallet, &mut request, 0, borderline.clone())
+            .unwrap();
+        assert_eq!(request.request_data().request, borderline);
+        assert_eq!(
+            f.send(&mut wallet, &mut request, 0, unicode.clone()),
+            Err(InvalidName.into())
+        );
+
+        f.set_name_policy(None, NamePolicyKind::Utf8).unwrap();
+        f.send(&mut wallet, &mut request, 0, unicode.clone())
+            .unwrap();
+        assert_eq!(request.request_data().request, unicode);
+
+        let mut impostor = TestAccount::wallet();
+        assert_eq!(
+            f.set_name_policy(Some(&mut impostor), NamePolicyKind::Default),
+            Err(Unauthorized.into())
+        );
+        assert_eq!(
+            f.bookkeeper.bookkeeper_data().name_policy,
+            NamePolicyKind::Utf8 as u8
+        );
+    }
+
+    #[test]
+    fn test_send_request_rejects_malformed_names() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+
+        let mut not_utf8 = name("bucket");
+        not_utf8[6] = 0xff;
+        let mut hidden_tail = name("bucket");
+        hidden_tail[100] = b'x';
+        let cases = [
+            (not_utf8, InvalidName),
+            (hidden_tail, InvalidName),
+            ([0; 128], EmptyName),
+        ];
+
+        for policy in [NamePolicyKind::Default, NamePolicyKind::Utf8] {
+            f.set_name_policy(None, policy).unwrap();
+            for (bad, error) in cases.clone() {
+                assert_eq!(
+                    f.send(
+                        &mut wallet,
+                        &mut request,
+                        0,
+                        Request::CreateBucket { name: bad }
+                    ),
+                    Err(error.into())
+                );
+            }
+        }
+        assert_eq!(request.request_data().sequence, 0);
+    }
+
+    #[test]
+    fn test_copy_file_validates_both_names() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+
+        let bad_src = Request::CopyFile {
+            src_name: name("dir 1/file"),
+            src_file_id: 1,
+            dst_name: name("dir-2/file"),
+        };
+        assert_eq!(
+            f.send(&mut wallet, &mut request, 0, bad_src),
+            Err(InvalidName.into())
+        );
+
+        let bad_dst = Request::CopyFile {
+            src_name: name("dir-1/file"),
+            src_file_id: 1,
+            dst_name: name("dir 2/file"),
+        };
+        assert_eq!(
+            f.send(&mut wallet, &mut request, 0, bad_dst),
+            Err(InvalidName.into())
+        );
+
+        let good = Request::CopyFile {
+            src_name: name("dir-1/file"),
+            src_file_id: 1,
+            dst_name: name("dir-2/file"),
+        };
+        f.send(&mut wallet, &mut request, 0, good.clone()).unwrap();
+        assert_eq!(request.request_data().request, good);
+    }
+
+    #[test]
+    fn test_submission_ref_replaced_by_next_submission() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+
+        f.send_with_ref(&mut wallet, &mut request, 0, [1; 32], bucket.clone())
+            .unwrap();
+        assert_eq!(request.request_data().submission_ref, [1; 32]);
+
+        let bad = Request::CreateBucket {
+            name: name("bad name"),
+        };
+        assert!(f
+            .send_with_ref(&mut wallet, &mut request, 0, [2; 32], bad)
+            .is_err());
+        assert_eq!(request.request_data().submission_ref, [1; 32]);
+
+        f.send_with_ref(&mut wallet, &mut request, 0, [3; 32], bucket)
+            .unwrap();
+        assert_eq!(request.request_data().submission_ref, [3; 32]);
+    }
+
+    #[test]
+    fn test_new_request_clears_response() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let exists = Request::FileExists {
+            name: name("bucket/file"),
+            file_id: 1,
+        };
+        f.send(&mut wallet, &mut request, 0, exists.clone())
+            .unwrap();
+        assert_eq!(request.request_data().exists_response(), None);
+
+        //the agent answers
+        let mut answered = request.request_data();
+        answered.response[0] = 1;
+        answered.response_ready = true;
+        RequestData::pack(answered, &mut request.data).unwrap();
+        assert_eq!(request.request_data().exists_response(), Some(true));
+
+        f.send(&mut wallet, &mut request, 0, exists).unwrap();
+        let stored = request.request_data();
+        assert_eq!(stored.exists_response(), None);
+        assert_eq!(stored.response, [0; RESPONSE_LEN]);
+    }
+
+    #[test]
+    fn test_send_request_records_submission_time() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+
+        f.clock.data = clock_data(1_700_000_000);
+        f.send(&mut wallet, &mut request, 0, bucket.clone())
+            .unwrap();
+        assert_eq!(request.request_data().submitted_at, 1_700_000_000);
+
+        f.clock.data = clock_data(1_700_000_060);
+        f.send(&mut wallet, &mut request, 0, bucket).unwrap();
+        assert_eq!(request.request_data().submitted_at, 1_700_000_060);
+    }
+
+    #[test]
+    fn test_sequence_increments_per_request() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+
+        f.send(&mut wallet, &mut request, 0, bucket.clone())
+            .unwrap();
+        assert_eq!(request.request_data().sequence, 1);
+
+        let logged = log_data_of(|| {
+            f.send(&mut wallet, &mut request, 0, bucket.clone())
+                .unwrap()
+        });
+        assert_eq!(request.request_data().sequence, 2);
+        assert_eq!(
+            logged
+                .iter()
+                .map(|data| parse_event(data).unwrap())
+                .collect::<Vec<_>>(),
+            vec![BitokuEvent::RequestSubmitted(RequestSubmitted {
+                client_id: 0,
+                request_tag: bucket.kind(),
+                name_hash: hash(b"bucket").to_bytes(),
+                sequence: 2,
+            })]
+        );
+
+        let bad = Request::CreateBucket {
+            name: name("bad name"),
+        };
+        let logged = log_data_of(|| {
+            assert!(f.send(&mut wallet, &mut request, 0, bad).is_err());
+        });
+        assert_eq!(logged, Vec::<Vec<u8>>::new());
+        assert_eq!(request.request_data().sequence, 2);
+
+        //the counter never wraps back to a sequence the agent has seen
+        let mut data = request.request_data();
+        data.sequence = u64::MAX;
+        data.status = RequestStatus::Completed;
+        data.pack_account(&mut request.data).unwrap();
+        assert_eq!(
+            f.send(&mut wallet, &mut request, 0, bucket),
+            Err(Overflow.into())
+        );
+        assert_eq!(request.request_data().sequence, u64::MAX);
+    }
+
+    #[test]
+    fn test_set_reserved_prefix_requires_authority() {
+        let mut f = Fixture::new();
+
+        let mut impostor = TestAccount::wallet();
+        assert_eq!(
+            f.reserve(Some(&mut impostor), 0, "system/"),
+            Err(Unauthorized.into())
+        );
+
+        f.authority.is_signer = false;
+        assert_eq!(
+            f.reserve(None, 0, "system/"),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(
+            f.bookkeeper.bookkeeper_data().reserved_prefixes,
+            [[0; PREFIX_LEN]; RESERVED_PREFIXES]
+        );
+
+        f.authority.is_signer = true;
+        f.reserve(None, 1, "system/").unwrap();
+        assert!(f.bookkeeper.bookkeeper_data().is_reserved(b"system/file"));
+
+        f.reserve(None, 1, "").unwrap();
+        assert!(!f.bookkeeper.bookkeeper_data().is_reserved(b"system/file"));
+    }
+
+    #[test]
+    fn test_reserved_prefix_rejects_clients() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        f.reserve(None, 0, "system/").unwrap();
+        f.reserve(None, 3, ".bitoku/").unwrap();
+
+        let reserved = Request::CreateBucket {
+            name: name("system/bucket"),
+        };
+        assert_eq!(
+            f.send(&mut wallet, &mut request, 0, reserved.clone()),
+            Err(ReservedName.into())
+        );
+
+        let rename = Request::RenameFile {
+            name: name("dir-1/file"),
+            file_id: 1,
+            new_name: name(".bitoku/file"),
+        };
+        assert_eq!(
+            f.send(&mut wallet, &mut request, 0, rename),
+            Err(ReservedName.into())
+        );
+
+        let near_miss = Request::CreateBucket {
+            name: name("systems/bucket"),
+        };
+        f.send(&mut wallet, &mut request, 0, near_miss).unwrap();
+
+        //the authority itself may use reserved names
+        let authority_key = f.authority.key;
+        let mut authority_request = f.request_account(&authority_key);
+        f.register_for(None, &mut authority_request, authority_key)
+            .unwrap();
+        let mut authority = std::mem::replace(&mut f.authority, TestAccount::wallet());
+        f.send(&mut authority, &mut authority_request, 1, reserved.clone())
+            .unwrap();
+        assert_eq!(authority_request.request_data().request, reserved);
+    }
+
+    #[test]
+    fn test_unsupported_request_kind() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+        let exists = Request::FileExists {
+            name: name("bucket/file"),
+            file_id: 1,
+        };
+
+        f.set_supported_kinds(None, ALL_REQUEST_KINDS & !(1 << exists.kind()))
+            .unwrap();
+        f.send(&mut wallet, &mut request, 0, bucket.clone())
+            .unwrap();
+        assert_eq!(
+            f.send(&mut wallet, &mut request, 0, exists.clone()),
+            Err(UnsupportedRequestKind.into())
+        );
+        assert_eq!(request.request_data().request, bucket);
+
+        f.set_supported_kinds(None, ALL_REQUEST_KINDS).unwrap();
+        f.send(&mut wallet, &mut request, 0, exists.clone())
+            .unwrap();
+        assert_eq!(request.request_data().request, exists);
+
+        let mut impostor = TestAccount::wallet();
+        assert_eq!(
+            f.set_supported_kinds(Some(&mut impostor), 0),
+            Err(UnauthorizedAgent.into())
+        );
+        assert_eq!(
+            f.bookkeeper.bookkeeper_data().supported_kinds,
+            ALL_REQUEST_KINDS
+        );
+
+        //it is the agent's call, not the authority's
+        let mut agent = TestAccount::wallet();
+        f.set_agent(None, agent.key).unwrap();
+        f.set_supported_kinds(Some(&mut agent), 1).unwrap();
+        assert_eq!(
+            f.set_supported_kinds(None, 0),
+            Err(UnauthorizedAgent.into())
+        );
+        assert_eq!(f.bookkeeper.bookkeeper_data().supported_kinds, 1);
+    }
+
+    #[test]
+    fn test_two_phase_delete() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let file = name("bucket/file");
+
+        for req in [
+            Request::TrashFile {
+                name: file,
+                file_id: 1,
+            },
+            Request::RestoreFile {
+                name: file,
+                file_id: 1,
+            },
+            Request::TrashFile {
+                name: file,
+                file_id: 1,
+            },
+            Request::EmptyTrash {},
+        ] {
+            f.send(&mut wallet, &mut request, 0, req.clone()).unwrap();
+            assert_eq!(request.request_data().request, req);
+        }
+        assert_eq!(request.request_data().sequence, 4);
+
+        //trashing is still subject to reserved prefixes; emptying names nothing
+        f.reserve(None, 0, "bucket").unwrap();
+        assert_eq!(
+            f.send(
+                &mut wallet,
+                &mut request,
+                0,
+                Request::TrashFile {
+                    name: file,
+                    file_id: 1
+                }
+            ),
+            Err(ReservedName.into())
+        );
+        f.send(&mut wallet, &mut request, 0, Request::EmptyTrash {})
+            .unwrap();
+
+        //deployments that predate the trash keep refusing it until the agent opts in
+        f.set_supported_kinds(None, (1 << 19) - 1).unwrap();
+        assert_eq!(
+            f.send(&mut wallet, &mut request, 0, Request::EmptyTrash {}),
+            Err(UnsupportedRequestKind.into())
+        );
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let read = Request::ReadFile {
+            name: name("bucket/file"),
+            file_id: 1,
+            min_sequence: 0,
+        };
+        f.send(&mut wallet, &mut request, 0, read.clone()).unwrap();
+
+        f.write_response(
+            None,
+            &mut request,
+            0,
+            RequestStatus::Completed,
+            b"file contents",
+        )
+        .unwrap();
+        let data = request.request_data();
+        assert!(data.response_ready);
+        assert_eq!(data.response_bytes(), b"file contents");
+        assert_eq!(data.request, read);
+
+        assert_eq!(
+            f.write_response(None, &mut request, 1, RequestStatus::Completed, b"x"),
+            Err(ClientMismatch.into())
+        );
+
+        //a new request must not expose the previous payload
+        f.send(&mut wallet, &mut request, 0, read).unwrap();
+        let data = request.request_data();
+        assert!(!data.response_ready);
+        assert_eq!(data.response_len, 0);
+        assert_eq!(data.response_data, [0; 512]);
+    }
+
+    #[test]
+    fn test_set_authority() {
+        let mut f = Fixture::new();
+        let mut successor = TestAccount::wallet();
+        let successor_key = successor.key;
+
+        let mut impostor = TestAccount::wallet();
+        let impostor_key = impostor.key;
+        assert_eq!(
+            f.set_authority(Some(&mut impostor), impostor_key),
+            Err(Unauthorized.into())
+        );
+        f.authority.is_signer = false;
+        assert_eq!(
+            f.set_authority(None, impostor_key),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        f.authority.is_signer = true;
+        assert_eq!(f.bookkeeper.bookkeeper_data().authority, f.authority.key);
+
+        f.set_authority(None, successor_key).unwrap();
+        assert_eq!(f.bookkeeper.bookkeeper_data().authority, successor_key);
+
+        //the previous authority has lost its admin rights
+        assert_eq!(
+            f.set_authority(None, impostor_key),
+            Err(Unauthorized.into())
+        );
+        assert_eq!(f.set_agent(None, impostor_key), Err(Unauthorized.into()));
+        f.set_agent(Some(&mut successor), successor_key).unwrap();
+        assert_eq!(f.bookkeeper.bookkeeper_data().agent, successor_key);
+    }
+
+    #[test]
+    fn test_update_admin() {
+        let mut f = Fixture::new();
+        f.bookkeeper.data = vec![0; BookKeeper::LEN];
+        let mut admin = TestAccount::wallet();
+        f.init_bitoku(Some(admin.key)).unwrap();
+        assert_eq!(f.bookkeeper.bookkeeper_data().admin, admin.key);
+        assert_eq!(f.bookkeeper.bookkeeper_data().authority, f.authority.key);
+
+        //the authority doesn't stand in for the admin
+        let mut authority = TestAccount::new(f.authority.key, vec![], system_program::ID);
+        authority.is_signer = true;
+        let mut successor = TestAccount::wallet();
+        assert_eq!(
+            f.update_admin(&mut authority, successor.key),
+            Err(InvalidAccount.into())
+        );
+        admin.is_signer = false;
+        assert_eq!(
+            f.update_admin(&mut admin, successor.key),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        admin.is_signer = true;
+
+        f.update_admin(&mut admin, successor.key).unwrap();
+        assert_eq!(f.bookkeeper.bookkeeper_data().admin, successor.key);
+        let admin_key = admin.key;
+        assert_eq!(
+            f.update_admin(&mut admin, admin_key),
+            Err(InvalidAccount.into())
+        );
+        f.update_admin(&mut successor, admin_key).unwrap();
+        assert_eq!(f.bookkeeper.bookkeeper_data().admin, admin_key);
+    }
+
+    #[test]
+    fn test_set_paused() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+
+        let mut impostor = TestAccount::wallet();
+        assert_eq!(
+            f.set_paused(Some(&mut impostor), true),
+            Err(Unauthorized.into())
+        );
+        f.authority.is_signer = false;
+        assert_eq!(
+            f.set_paused(None, true),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        f.authority.is_signer = true;
+        assert!(!f.bookkeeper.bookkeeper_data().paused);
+
+        f.set_paused(None, true).unwrap();
+        assert!(f.bookkeeper.bookkeeper_data().paused);
+        assert_eq!(
+            f.send(&mut wallet, &mut request, 0, bucket.clone()),
+            Err(ProgramPaused.into())
+        );
+        let mut other = TestAccount::wallet();
+        let mut other_request = f.request_account(&other.key);
+        assert_eq!(
+            f.register(&mut other, &mut other_request),
+            Err(ProgramPaused.into())
+        );
+
+        //clients can still leave and reclaim their rent
+        f.remove(&mut wallet, &mut request, 0).unwrap();
+        assert_eq!(f.bookkeeper.bookkeeper_data().status, [0; 32]);
+
+        f.set_paused(None, false).unwrap();
+        f.register(&mut other, &mut other_request).unwrap();
+        f.send(&mut other, &mut other_request, 0, bucket).unwrap();
+    }
+
+    #[test]
+    fn test_submit_signed_request() {
+        let mut f = Fixture::new();
+        let (owner, mut request) = f.client();
+        let mut relayer = TestAccount::wallet();
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+
+        let envelope = SignedRequestEnvelope::new(bucket.clone(), 0, 1, owner.key, [7; 64]);
+        let ed25519 = envelope.ed25519_instruction(&f.program_id);
+        envelope
+            .check_request_account(&f.program_id, &request.key, &request.data)
+            .unwrap();
+        f.submit_signed(&mut relayer, &mut request, &envelope, &ed25519)
+            .unwrap();
+        let data = request.request_data();
+        assert_eq!(data.request, bucket);
+        assert_eq!(data.requester, owner.key);
+        assert_eq!(data.status, RequestStatus::Pending);
+        assert_eq!((data.sequence, data.nonce), (1, 1));
+
+        //replayed
+        assert_eq!(
+            f.submit_signed(&mut relayer, &mut request, &envelope, &ed25519),
+            Err(StaleNonce.into())
+        );
+        assert_eq!(
+            envelope.check_request_account(&f.program_id, &request.key, &request.data),
+            Err(StaleNonce.into())
+        );
+
+        //forged: the ed25519 instruction verified another envelope, or none at all
+        let delete = Request::DeleteBucket {
+            name: name("bucket"),
+        };
+        let forged = SignedRequestEnvelope::new(delete.clone(), 0, 2, owner.key, [7; 64]);
+        assert_eq!(
+            f.submit_signed(&mut relayer, &mut request, &forged, &ed25519),
+            Err(InvalidSignature.into())
+        );
+        let not_ed25519 = Instruction {
+            program_id: f.program_id,
+            ..forged.ed25519_instruction(&f.program_id)
+        };
+        assert_eq!(
+            f.submit_signed(&mut relayer, &mut request, &forged, &not_ed25519),
+            Err(InvalidSignature.into())
+        );
+        let other_deployment = forged.ed25519_instruction(&Pubkey::new_unique());
+        assert_eq!(
+            f.submit_signed(&mut relayer, &mut request, &forged, &other_deployment),
+            Err(InvalidSignature.into())
+        );
+        //signed by a key that doesn't own the request account
+        let impostor = SignedRequestEnvelope {
+            signer: relayer.key,
+            ..forged.clone()
+        };
+        let ed25519 = impostor.ed25519_instruction(&f.program_id);
+        assert_eq!(
+            f.submit_signed(&mut relayer, &mut request, &impostor, &ed25519),
+            Err(InvalidAccount.into())
+        );
+        assert_eq!(request.request_data().nonce, 1);
+
+        let ed25519 = forged.ed25519_instruction(&f.program_id);
+        f.submit_signed(&mut relayer, &mut request, &forged, &ed25519)
+            .unwrap();
+        assert_eq!(request.request_data().request, delete);
+
+        //lease requests, which are not stored, still use up their nonce
+        let lease = Request::AcquireBucketLease {
+            name: name("bucket"),
+            ttl_slots: 10,
+        };
+        let envelope = SignedRequestEnvelope::new(lease.clone(), 0, 5, owner.key, [7; 64]);
+        let ed25519 = envelope.ed25519_instruction(&f.program_id);
+        f.submit_signed(&mut relayer, &mut request, &envelope, &ed25519)
+            .unwrap();
+        assert_eq!(f.lease_data(&lease).holder, owner.key);
+        assert_eq!(request.request_data().nonce, 5);
+        assert_eq!(request.request_data().request, delete);
+    }
+
+    #[test]
+    fn test_error_logs() {
+        let mut f = Fixture::new();
+        let program_id = f.program_id;
+        let mut wrong =
+            TestAccount::new(Pubkey::new_unique(), f.bookkeeper.data.clone(), program_id);
+        let logs = logs_of(|| {
+            let result = process(
+                &program_id,
+                &mut [&mut f.authority, &mut wrong],
+                BitokuInstructions::SetPaused { paused: true },
+            );
+            assert_eq!(result, Err(InvalidAccount.into()));
+        });
+        assert_eq!(
+            logs,
+            [
+                "Instruction : SetPaused".to_string(),
+                format!(
+                    "Error: InvalidAccount: expected bookkeeper {}, got {}",
+                    f.bookkeeper.key, wrong.key
+                ),
+            ]
+        );
+
+        let mut impostor = TestAccount::wallet();
+        let logs = logs_of(|| {
+            let result = f.set_agent(Some(&mut impostor), Pubkey::new_unique());
+            assert_eq!(result, Err(Unauthorized.into()));
+        });
+        assert_eq!(
+            logs.last().unwrap(),
+            &format!(
+                "Error: Unauthorized: expected authority {}, got {}",
+                f.authority.key, impostor.key
+            )
+        );
+
+        let (_, mut request) = f.client();
+        let logs = logs_of(|| {
+            let result = f.write_response(None, &mut request, 0, RequestStatus::Completed, b"x");
+            assert_eq!(result, Err(NoRequest.into()));
+        });
+        assert_eq!(
+            logs.last().unwrap(),
+            "Error: NoRequest: client 0 has not sent a request"
+        );
+    }
+
+    #[test]
+    fn test_write_response_requires_agent() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let mut agent = TestAccount::wallet();
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+
+        assert_eq!(
+            f.write_response(None, &mut request, 0, RequestStatus::Completed, b"x"),
+            Err(NoRequest.into())
+        );
+        f.send(&mut wallet, &mut request, 0, bucket).unwrap();
+
+        assert_eq!(
+            f.write_response(
+                Some(&mut agent),
+                &mut request,
+                0,
+                RequestStatus::Completed,
+                b"x"
+            ),
+            Err(UnauthorizedAgent.into())
+        );
+
+        let mut impostor = TestAccount::wallet();
+        let impostor_key = impostor.key;
+        assert_eq!(
+            f.set_agent(Some(&mut impostor), impostor_key),
+            Err(Unauthorized.into())
+        );
+
+        f.set_agent(None, agent.key).unwrap();
+        assert_eq!(f.bookkeeper.bookkeeper_data().agent, agent.key);
+        f.write_response(
+            Some(&mut agent),
+            &mut request,
+            0,
+            RequestStatus::Completed,
+            b"x",
+        )
+        .unwrap();
+        assert_eq!(request.request_data().response_bytes(), b"x");
+
+        //the authority is no longer the agent
+        assert_eq!(
+            f.write_response(None, &mut request, 0, RequestStatus::Completed, b"y"),
+            Err(UnauthorizedAgent.into())
+        );
+    }
+
+    #[test]
+    fn test_agent_lifecycle() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let mut agent = TestAccount::wallet();
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+        f.send(&mut wallet, &mut request, 0, bucket).unwrap();
+
+        let mut impostor = TestAccount::wallet();
+        assert_eq!(
+            f.add_agent(Some(&mut impostor), agent.key),
+            Err(Unauthorized.into())
+        );
+        f.authority.is_signer = false;
+        assert_eq!(
+            f.add_agent(None, agent.key),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        f.authority.is_signer = true;
+        assert_eq!(
+            f.add_agent(None, Pubkey::default()),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        //add
+        f.add_agent(None, agent.key).unwrap();
+        f.add_agent(None, agent.key).unwrap();
+        let bookkeeper = f.bookkeeper.bookkeeper_data();
+        assert_eq!(bookkeeper.agents[0], agent.key);
+        assert_eq!(bookkeeper.agents[1], Pubkey::default());
+        assert!(bookkeeper.is_agent(&agent.key));
+        assert!(!bookkeeper.is_agent(&Pubkey::default()));
+
+        //use, alongside the primary agent
+        f.update_status(None, &mut request, 0, RequestStatus::Processing)
+            .unwrap();
+        f.publish_load(Some(&mut agent), 10, 1).unwrap();
+        f.write_response(
+            Some(&mut agent),
+            &mut request,
+            0,
+            RequestStatus::Completed,
+            b"x",
+        )
+        .unwrap();
+        assert_eq!(request.request_data().response(), Some(&b"x"[..]));
+        f.publish_load(None, 10, 0).unwrap();
+
+        for _ in 1..MAX_AGENTS {
+            f.add_agent(None, Pubkey::new_unique()).unwrap();
+        }
+        assert_eq!(
+            f.add_agent(None, Pubkey::new_unique()),
+            Err(AgentLimitReached.into())
+        );
+
+        //remove
+        assert_eq!(
+            f.remove_agent(Some(&mut impostor), agent.key),
+            Err(Unauthorized.into())
+        );
+        f.remove_agent(None, agent.key).unwrap();
+        assert_eq!(
+            f.remove_agent(None, agent.key),
+            Err(UnauthorizedAgent.into())
+        );
+        assert_eq!(
+            f.remove_agent(None, Pubkey::default()),
+            Err(UnauthorizedAgent.into())
+        );
+        assert!(!f.bookkeeper.bookkeeper_data().is_agent(&agent.key));
+        assert_eq!(
+            f.publish_load(Some(&mut agent), 10, 1),
+            Err(UnauthorizedAgent.into())
+        );
+
+        //the freed slot can be reused
+        f.add_agent(None, impostor.key).unwrap();
+        assert_eq!(f.bookkeeper.bookkeeper_data().agents[0], impostor.key);
+    }
+
+    #[test]
+    fn test_publish_load() {
+        let mut f = Fixture::new();
+        let mut agent = TestAccount::wallet();
+
+        f.clock.data = epoch_clock_data(0, 42, 0);
+        f.publish_load(None, 500, 40).unwrap();
+        let bookkeeper = f.bookkeeper.bookkeeper_data();
+        assert_eq!(bookkeeper.max_requests_per_epoch, 500);
+        assert_eq!(bookkeeper.current_backlog, 40);
+        assert_eq!(bookkeeper.last_heartbeat_slot, 42);
+        assert_eq!(bookkeeper.throttle_advice(50), ThrottleAdvice::Proceed);
+        assert_eq!(bookkeeper.throttle_advice(40), ThrottleAdvice::Delay);
+
+        assert_eq!(
+            f.publish_load(Some(&mut agent), 500, 500),
+            Err(UnauthorizedAgent.into())
+        );
+        f.set_agent(None, agent.key).unwrap();
+        agent.is_signer = false;
+        assert_eq!(
+            f.publish_load(Some(&mut agent), 500, 500),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        agent.is_signer = true;
+        f.publish_load(Some(&mut agent), 500, 500).unwrap();
+        assert_eq!(
+            f.bookkeeper.bookkeeper_data().throttle_advice(50),
+            ThrottleAdvice::Hold
+        );
+
+        //the authority no longer speaks for the agent
+        assert_eq!(f.publish_load(None, 500, 0), Err(UnauthorizedAgent.into()));
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    enum RaceOp {
+        Send(&'static str),
+        Cancel,
+        Acknowledge,
+        Status(RequestStatus),
+        Respond(&'static [u8], RequestStatus),
+    }
+
+    //every order of `lanes` that keeps each lane's own order
+    fn interleavings(lanes: &[&[RaceOp]]) -> Vec<Vec<RaceOp>> {
+        if lanes.iter().all(|lane| lane.is_empty()) {
+            return vec![vec![]];
+        }
+        let mut orders = Vec::new();
+        for (i, lane) in lanes.iter().enumerate() {
+            if let Some((op, rest)) = lane.split_first() {
+                let mut next = lanes.to_vec();
+                next[i] = rest;
+                for mut order in interleavings(&next) {
+                    order.insert(0, *op);
+                    orders.push(order);
+                }
+            }
+        }
+        orders
+    }
+
+    #[test]
+    fn test_shared_wallet_races() {
+        //two submitters sharing one wallet, one of them withdrawing its first
+        //request, and the agent working through the slot
+        let first: &[RaceOp] = &[
+            RaceOp::Send("first/a"),
+            RaceOp::Cancel,
+            RaceOp::Send("first/b"),
+        ];
+        let second: &[RaceOp] = &[RaceOp::Send("second/a")];
+        let agent: &[RaceOp] = &[
+            RaceOp::Acknowledge,
+            RaceOp::Status(RequestStatus::Processing),
+            RaceOp::Respond(b"partial", RequestStatus::Processing),
+            RaceOp::Respond(b"answer", RequestStatus::Completed),
+        ];
+
+        let orders = interleavings(&[first, second, agent]);
+        assert_eq!(orders.len(), 280);
+
+        for order in orders {
+            let mut f = Fixture::new();
+            let (mut wallet, mut request) = f.client();
+            let start = request.request_data();
+
+            //what the documented state machine says the slot holds
+            let mut sequence = start.sequence;
+            let mut stored = start.request;
+            let mut status = start.status;
+            let mut response: Option<&[u8]> = None;
+            let mut submitted = false;
+
+            for op in &order {
+                match *op {
+                    RaceOp::Send(file) => {
+                        let req = Request::CreateBucket { name: name(file) };
+                        let send = BitokuInstructions::SendRequest {
+                            client_id: 0,
+                            submission_ref: [0; 32],
+                            request: req.clone(),
+                        };
+                        let result = f.process_send(&mut wallet, &mut request, send);
+                        if submitted && status == RequestStatus::Pending {
+                            assert_eq!(result, Err(RequestPending.into()), "{:?}", order);
+                        } else {
+                            assert_eq!(result, Ok(()), "{:?}", order);
+                            sequence += 1;
+                            stored = req;
+                            status = RequestStatus::Pending;
+                            response = None;
+                            submitted = true;
+                        }
+                    }
+                    RaceOp::Cancel => {
+                        let result = f.cancel(&mut wallet, &mut request, 0);
+                        if !submitted {
+                            assert_eq!(result, Err(NoRequest.into()), "{:?}", order);
+                        } else if status.can_cancel() {
+                            assert_eq!(result, Ok(()), "{:?}", order);
+                            stored = RequestData::CANCELLED_REQUEST;
+                            status = RequestStatus::Cancelled;
+                        } else {
+                            assert_eq!(result, Err(InvalidStatusTransition.into()), "{:?}", order);
+                        }
+                    }
+                    RaceOp::Acknowledge => {
+                        let result = process(
+                            &f.program_id,
+                            &mut [&mut f.authority, &mut f.bookkeeper, &mut request],
+                            BitokuInstructions::AcknowledgeRequest {
+                                client_id: 0,
+                                status: RequestStatus::Processing,
+                            },
+                        );
+                        if !submitted {
+                            assert_eq!(result, Err(NoRequest.into()), "{:?}", order);
+                        } else if status.can_transition_to(RequestStatus::Processing) {
+                            assert_eq!(result, Ok(()), "{:?}", order);
+                            status = RequestStatus::Processing;
+                        } else {
+                            assert_eq!(result, Err(InvalidStatusTransition.into()), "{:?}", order);
+                        }
+                    }
+                    RaceOp::Status(next) => {
+                        let result = f.update_status(None, &mut request, 0, next);
+                        if !submitted {
+                            assert_eq!(result, Err(NoRequest.into()), "{:?}", order);
+                        } else if status.can_transition_to(next) {
+                            assert_eq!(result, Ok(()), "{:?}", order);
+                            status = next;
+                        } else {
+                            assert_eq!(result, Err(InvalidStatusTransition.into()), "{:?}", order);
+                        }
+                    }
+                    RaceOp::Respond(bytes, next) => {
+                        let result = f.write_response(None, &mut request, 0, next, bytes);
+                        if !submitted {
+                            assert_eq!(result, Err(NoRequest.into()), "{:?}", order);
+                        } else if status.can_respond_with(next) {
+                            assert_eq!(result, Ok(()), "{:?}", order);
+                            status = next;
+                            response = Some(bytes);
+                        } else {
+                            assert_eq!(result, Err(InvalidStatusTransition.into()), "{:?}", order);
+                        }
+                    }
+                }
+
+                let data = request.request_data();
+                assert_eq!(data.client_id, 0, "{:?}", order);
+                let requester = if submitted {
+                    wallet.key
+                } else {
+                    Pubkey::default()
+                };
+                assert_eq!(data.requester, requester, "{:?}", order);
+                assert_eq!(data.sequence, sequence, "{:?}", order);
+                assert_eq!(data.request, stored, "{:?}", order);
+                assert_eq!(data.status, status, "{:?}", order);
+                assert_eq!(data.response(), response, "{:?}", order);
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_response_overwrite_ordering() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+        f.send(&mut wallet, &mut request, 0, bucket.clone())
+            .unwrap();
+        assert_eq!(request.request_data().response(), None);
+
+        //a shorter final answer replaces the whole partial one
+        f.write_response(
+            None,
+            &mut request,
+            0,
+            RequestStatus::Processing,
+            b"partial answer",
+        )
+        .unwrap();
+        assert_eq!(
+            request.request_data().response(),
+            Some(&b"partial answer"[..])
+        );
+        assert_eq!(request.request_data().status, RequestStatus::Processing);
+        f.write_response(None, &mut request, 0, RequestStatus::Completed, b"done")
+            .unwrap();
+        assert_eq!(request.request_data().response(), Some(&b"done"[..]));
+        assert_eq!(request.request_data().status, RequestStatus::Completed);
+
+        //the final answer stays until the next request
+        for status in [RequestStatus::Completed, RequestStatus::Failed] {
+            assert_eq!(
+                f.write_response(None, &mut request, 0, status, b"late"),
+                Err(InvalidStatusTransition.into())
+            );
+        }
+        assert_eq!(
+            f.write_response(None, &mut request, 0, RequestStatus::Pending, b"late"),
+            Err(InvalidStatusTransition.into())
+        );
+        assert_eq!(request.request_data().response(), Some(&b"done"[..]));
+
+        f.send(&mut wallet, &mut request, 0, bucket).unwrap();
+        let data = request.request_data();
+        assert_eq!(
+            (data.status, data.response()),
+            (RequestStatus::Pending, None)
+        );
+
+        //an error message goes out with Failed straight from Pending
+        f.write_response(
+            None,
+            &mut request,
+            0,
+            RequestStatus::Failed,
+            b"no such bucket",
+        )
+        .unwrap();
+        let data = request.request_data();
+        assert_eq!(data.status, RequestStatus::Failed);
+        assert_eq!(data.response(), Some(&b"no such bucket"[..]));
+        assert_eq!(
+            f.write_response(None, &mut request, 0, RequestStatus::Processing, b"retry"),
+            Err(InvalidStatusTransition.into())
+        );
+    }
+
+    #[test]
+    fn test_request_status_lifecycle() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+
+        f.send(&mut wallet, &mut request, 0, bucket.clone())
+            .unwrap();
+        assert_eq!(request.request_data().status, RequestStatus::Pending);
+
+        f.update_status(None, &mut request, 0, RequestStatus::Processing)
+            .unwrap();
+        assert_eq!(request.request_data().status, RequestStatus::Processing);
+        assert_eq!(
+            f.update_status(None, &mut request, 0, RequestStatus::Pending),
+            Err(InvalidStatusTransition.into())
+        );
+
+        f.update_status(None, &mut request, 0, RequestStatus::Completed)
+            .unwrap();
+        assert_eq!(request.request_data().status, RequestStatus::Completed);
+        assert_eq!(
+            f.update_status(None, &mut request, 0, RequestStatus::Failed),
+            Err(InvalidStatusTransition.into())
+        );
+
+        //a new submission starts over
+        f.send(&mut wallet, &mut request, 0, bucket).unwrap();
+        assert_eq!(request.request_data().status, RequestStatus::Pending);
+        f.update_status(None, &mut request, 0, RequestStatus::Failed)
+            .unwrap();
+        assert_eq!(request.request_data().status, RequestStatus::Failed);
+    }
+
+    #[test]
+    fn test_pending_request_is_not_replaced() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+        let other = Request::CreateBucket {
+            name: name("other"),
+        };
+        f.send(&mut wallet, &mut request, 0, bucket.clone())
+            .unwrap();
+        assert!(request.request_data().is_pending());
+
+        let resend = BitokuInstructions::SendRequest {
+            client_id: 0,
+            submission_ref: [0; 32],
+            request: other.clone(),
+        };
+        assert_eq!(
+            f.process_send(&mut wallet, &mut request, resend.clone()),
+            Err(RequestPending.into())
+        );
+        assert_eq!(request.request_data().request, bucket);
+
+        //the client can still replace it on purpose
+        let overwrite = BitokuInstructions::OverwriteRequest {
+            client_id: 0,
+            submission_ref: [0; 32],
+            request: other.clone(),
+        };
+        f.process_send(&mut wallet, &mut request, overwrite)
+            .unwrap();
+        let data = request.request_data();
+        assert_eq!((data.sequence, data.request), (2, other));
+
+        //only agents acknowledge
+        let acknowledge = BitokuInstructions::AcknowledgeRequest {
+            client_id: 0,
+            status: RequestStatus::Processing,
+        };
+        let mut impostor = TestAccount::wallet();
+        assert_eq!(
+            process(
+                &f.program_id,
+                &mut [&mut impostor, &mut f.bookkeeper, &mut request],
+                acknowledge.clone(),
+            ),
+            Err(UnauthorizedAgent.into())
+        );
+        let mut agent = TestAccount::wallet();
+        f.add_agent(None, agent.key).unwrap();
+        //acknowledging can't finish the request
+        for status in [RequestStatus::Completed, RequestStatus::Failed] {
+            assert_eq!(
+                process(
+                    &f.program_id,
+                    &mut [&mut agent, &mut f.bookkeeper, &mut request],
+                    BitokuInstructions::AcknowledgeRequest {
+                        client_id: 0,
+                        status,
+                    },
+                ),
+                Err(InvalidStatusTransition.into())
+            );
+        }
+        assert!(request.request_data().is_pending());
+        process(
+            &f.program_id,
+            &mut [&mut agent, &mut f.bookkeeper, &mut request],
+            acknowledge.clone(),
+        )
+        .unwrap();
+        let data = request.request_data();
+        assert!(data.is_acknowledged() && !data.is_pending());
+        assert_eq!(
+            process(
+                &f.program_id,
+                &mut [&mut agent, &mut f.bookkeeper, &mut request],
+                acknowledge,
+            ),
+            Err(InvalidStatusTransition.into())
+        );
+
+        f.process_send(&mut wallet, &mut request, resend.clone())
+            .unwrap();
+        assert_eq!(request.request_data().sequence, 3);
+    }
+
+    #[test]
+    fn test_client_request_limit() {
+        let mut f = Fixture::new();
+        let mut bookkeeper = f.bookkeeper.bookkeeper_data();
+        bookkeeper.max_client_requests_per_epoch = 2;
+        BookKeeper::pack(bookkeeper, &mut f.bookkeeper.data).unwrap();
+        let (mut wallet, mut request) = f.client();
+        let (mut other, mut other_request) = f.client();
+        let bucket = |n: &str| Request::CreateBucket { name: name(n) };
+
+        f.send(&mut wallet, &mut request, 0, bucket("a")).unwrap();
+        f.send(&mut wallet, &mut request, 0, bucket("b")).unwrap();
+        let data = request.request_data();
+        assert_eq!((data.requests_this_epoch, data.sequence), (2, 2));
+
+        let stored = request.request_data().request;
+        assert_eq!(
+            f.send(&mut wallet, &mut request, 0, bucket("c")),
+            Err(RateLimitExceeded.into())
+        );
+        assert_eq!(request.request_data().request, stored);
+        //the limit is per client
+        f.send(&mut other, &mut other_request, 1, bucket("c"))
+            .unwrap();
+        //each client counts its own requests, the bookkeeper stays untouched
+        assert_eq!(other_request.request_data().sequence, 1);
+
+        let mut impostor = TestAccount::wallet();
+        assert_eq!(
+            f.reset_epoch_counter(Some(&mut impostor), &mut request, 0),
+            Err(Unauthorized.into())
+        );
+        assert_eq!(
+            f.reset_epoch_counter(None, &mut request, 1),
+            Err(ClientMismatch.into())
+        );
+        f.reset_epoch_counter(None, &mut request, 0).unwrap();
+        let data = request.request_data();
+        assert_eq!((data.requests_this_epoch, data.sequence), (0, 2));
+
+        f.send(&mut wallet, &mut request, 0, bucket("c")).unwrap();
+        assert_eq!(request.request_data().requests_this_epoch, 1);
+    }
+
+    #[test]
+    fn test_client_permissions() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let (compact_wallet, _) = f.compact_client();
+        assert_eq!(f.client_info_data(&wallet.key).permissions, ALL_PERMISSIONS);
+        assert_eq!(
+            f.client_info_data(&compact_wallet.key).permissions,
+            ALL_PERMISSIONS
+        );
+        let owner = wallet.key;
+
+        let cases = [
+            (PERMISSION_CREATE, Request::create_bucket("photos").unwrap()),
+            (
+                PERMISSION_WRITE,
+                Request::write_file("photos/a", 1, 0, b"abc").unwrap(),
+            ),
+            (
+                PERMISSION_DELETE,
+                Request::delete_file("photos/a", 1).unwrap(),
+            ),
+            (PERMISSION_READ, Request::read_file("photos/a", 1).unwrap()),
+        ];
+        for (bit, req) in cases {
+            assert_eq!(req.required_permission(), bit);
+
+            //every other permission is not enough
+            f.set_permissions(None, &mut request, &owner, 0, ALL_PERMISSIONS & !bit)
+                .unwrap();
+            let stored = request.request_data();
+            assert_eq!(
+                f.send(&mut wallet, &mut request, 0, req.clone()),
+                Err(PermissionDenied.into())
+            );
+            assert_eq!(request.request_data().request, stored.request);
+            assert_eq!(request.request_data().sequence, stored.sequence);
+
+            //the bit on its own is
+            f.set_permissions(None, &mut request, &owner, 0, bit)
+                .unwrap();
+            f.send(&mut wallet, &mut request, 0, req.clone()).unwrap();
+            assert_eq!(request.request_data().request, req);
+        }
+
+        //a client without any permission can still give up a lease
+        let lease = Request::AcquireBucketLease {
+            name: name("photos"),
+            ttl_slots: 10,
+        };
+        f.set_permissions(None, &mut request, &owner, 0, PERMISSION_WRITE)
+            .unwrap();
+        f.send(&mut wallet, &mut request, 0, lease).unwrap();
+        f.set_permissions(None, &mut request, &owner, 0, 0).unwrap();
+        let release = Request::ReleaseBucketLease {
+            name: name("photos"),
+        };
+        f.send(&mut wallet, &mut request, 0, release).unwrap();
+
+        let mut impostor = TestAccount::wallet();
+        assert_eq!(
+            f.set_permissions(
+                Some(&mut impostor),
+                &mut request,
+                &owner,
+                0,
+                ALL_PERMISSIONS
+            ),
+            Err(InvalidAccount.into())
+        );
+        assert_eq!(
+            f.set_permissions(None, &mut request, &owner, 1, ALL_PERMISSIONS),
+            Err(ClientMismatch.into())
+        );
+        assert_eq!(
+            f.set_permissions(None, &mut request, &owner, 0, 0b10000),
+            Err(InvalidInstructionData.into())
+        );
+        assert_eq!(f.client_info_data(&owner).permissions, 0);
+
+        //registering again keeps the permissions the admin set
+        f.remove(&mut wallet, &mut request, 0).unwrap();
+        let mut request = f.request_account(&owner);
+        f.register(&mut wallet, &mut request).unwrap();
+        assert_eq!(f.client_info_data(&owner).permissions, 0);
+    }
+
+    #[test]
+    fn test_cancel_request() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+
+        assert_eq!(
+            f.cancel(&mut wallet, &mut request, 0),
+            Err(NoRequest.into())
+        );
+        f.send(&mut wallet, &mut request, 0, bucket.clone())
+            .unwrap();
+
+        let mut impostor = TestAccount::wallet();
+        assert_eq!(
+            f.cancel(&mut impostor, &mut request, 0),
+            Err(InvalidAccount.into())
+        );
+        assert_eq!(
+            f.cancel(&mut wallet, &mut request, 1),
+            Err(ClientMismatch.into())
+        );
+
+        f.cancel(&mut wallet, &mut request, 0).unwrap();
+        let data = request.request_data();
+        assert_eq!(data.status, RequestStatus::Cancelled);
+        assert_eq!(data.request, RequestData::CANCELLED_REQUEST);
+        //client id and requester come before the request area
+        let area = &request.data[ACCOUNT_HEADER_LEN + 33..][..ENCODED_REQUEST_MAX_LEN];
+        assert_eq!(area[0], CANCELLED_TAG);
+        assert!(area[1..].iter().all(|&byte| byte == 0));
+        //the agent can no longer act on it
+        assert_eq!(
+            f.update_status(None, &mut request, 0, RequestStatus::Processing),
+            Err(InvalidStatusTransition.into())
+        );
+        assert_eq!(
+            f.write_response(None, &mut request, 0, RequestStatus::Cancelled, b"x"),
+            Err(InvalidStatusTransition.into())
+        );
+        assert_eq!(
+            f.cancel(&mut wallet, &mut request, 0),
+            Err(InvalidStatusTransition.into())
+        );
+
+        //the slot takes a new request straight away
+        let file = Request::CreateBucket {
+            name: name("other"),
+        };
+        f.send(&mut wallet, &mut request, 0, file.clone()).unwrap();
+        let data = request.request_data();
+        assert_eq!((data.status, data.request), (RequestStatus::Pending, file));
+
+        //too late once the agent has started
+        f.update_status(None, &mut request, 0, RequestStatus::Processing)
+            .unwrap();
+        assert_eq!(
+            f.cancel(&mut wallet, &mut request, 0),
+            Err(InvalidStatusTransition.into())
+        );
+        assert_eq!(request.request_data().status, RequestStatus::Processing);
+    }
+
+    #[test]
+    fn test_update_request_status_checks() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+
+        let mut impostor = TestAccount::wallet();
+        assert_eq!(
+            f.update_status(
+                Some(&mut impostor),
+                &mut request,
+                0,
+                RequestStatus::Processing
+            ),
+            Err(UnauthorizedAgent.into())
+        );
+        impostor.is_signer = false;
+        f.add_agent(None, impostor.key).unwrap();
+        assert_eq!(
+            f.update_status(
+                Some(&mut impostor),
+                &mut request,
+                0,
+                RequestStatus::Processing
+            ),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(
+            f.update_status(None, &mut request, 1, RequestStatus::Processing),
+            Err(ClientMismatch.into())
+        );
+
+        let mut foreign = TestAccount::new(request.key, request.data.clone(), Pubkey::new_unique());
+        assert_eq!(
+            f.update_status(None, &mut foreign, 0, RequestStatus::Processing),
+            Err(InvalidAccount.into())
+        );
+        assert_eq!(request.request_data().status, RequestStatus::Pending);
+
+        //any registered agent can move the request on, not only the authority
+        f.send(&mut wallet, &mut request, 0, write_file(8)).unwrap();
+        impostor.is_signer = true;
+        f.update_status(
+            Some(&mut impostor),
+            &mut request,
+            0,
+            RequestStatus::Processing,
+        )
+        .unwrap();
+        assert_eq!(request.request_data().status, RequestStatus::Processing);
+
+        //the authority alone isn't enough once someone else is the agent
+        f.set_agent(None, Pubkey::new_unique()).unwrap();
+        assert_eq!(
+            f.update_status(None, &mut request, 0, RequestStatus::Completed),
+            Err(UnauthorizedAgent.into())
+        );
+    }
+
+    #[test]
+    fn test_publish_stats_across_epochs() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        f.send(&mut wallet, &mut request, 0, write_file(8)).unwrap();
+
+        f.clock.data = epoch_clock_data(4, 1_727_990, 1_699_999_990);
+        f.publish_load(None, 500, 1).unwrap();
+        f.clock.data = epoch_clock_data(4, 1_728_000, 1_700_000_000);
+        let mut epoch_4 = f.stats_account(4);
+        f.publish_stats(None, &mut epoch_4).unwrap();
+
+        let (_, _) = f.client();
+        let (_, _) = f.client();
+
+        f.clock.data = epoch_clock_data(5, 2_160_000, 1_700_172_800);
+        let mut epoch_5 = f.stats_account(5);
+        //the epoch 4 snapshot can't be overwritten once the clock moves on
+        assert_eq!(
+            f.publish_stats(None, &mut epoch_4),
+            Err(InvalidAccount.into())
+        );
+        f.publish_stats(None, &mut epoch_5).unwrap();
+
+        let first = StatsSnapshot::unpack_unchecked(&epoch_4.data).unwrap();
+        assert_eq!(
+            first,
+            StatsSnapshot {
+                epoch: 4,
+                slot: 1_728_000,
+                published_at: 1_700_000_000,
+                client_count: 1,
+                next_id: 1,
+                last_heartbeat_slot: 1_727_990,
+            }
+        );
+        let second = StatsSnapshot::unpack_unchecked(&epoch_5.data).unwrap();
+        assert_eq!(second.epoch, 5);
+        assert_eq!(second.slot, 2_160_000);
+        assert_eq!(second.client_count, 3);
+        assert_eq!(second.next_id, 3);
+
+        //publishing again in the same epoch refreshes the snapshot
+        f.clock.data = epoch_clock_data(5, 2_160_500, 1_700_173_000);
+        f.publish_stats(None, &mut epoch_5).unwrap();
+        assert_eq!(
+            StatsSnapshot::unpack_unchecked(&epoch_5.data).unwrap().slot,
+            2_160_500
+        );
+    }
+
+    #[test]
+    fn test_publish_stats_requires_authority_or_agent() {
+        let mut f = Fixture::new();
+        let mut stats = f.stats_account(0);
+
+        let mut impostor = TestAccount::wallet();
+        assert_eq!(
+            f.publish_stats(Some(&mut impostor), &mut stats),
+            Err(Unauthorized.into())
+        );
+
+        f.authority.is_signer = false;
+        assert_eq!(
+            f.publish_stats(None, &mut stats),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(stats.data, vec![0; StatsSnapshot::LEN]);
+
+        f.authority.is_signer = true;
+        let mut agent = TestAccount::wallet();
+        f.add_agent(None, agent.key).unwrap();
+        f.clock.data = epoch_clock_data(0, 7, 1_700_000_000);
+        f.publish_stats(Some(&mut agent), &mut stats).unwrap();
+        assert_eq!(
+            StatsSnapshot::unpack_unchecked(&stats.data).unwrap().slot,
+            7
+        );
+    }
+
+    #[test]
+    fn test_removed_client_ids_are_reused() {
+        let mut f = Fixture::new();
+        let mut clients: Vec<_> = (0..4).map(|_| f.client()).collect();
+        for (id, (_, request)) in clients.iter().enumerate() {
+            assert_eq!(request.request_data().client_id, id as u8);
+        }
+
+        for id in [2, 1] {
+            let (wallet, request) = &mut clients[id];
+            f.remove(wallet, request, id as u8).unwrap();
+        }
+        let bookkeeper = f.bookkeeper.bookkeeper_data();
+        assert_eq!(first_set_bit(&bookkeeper.free_slots), Some(1));
+        assert_eq!(bookkeeper.client_count(), 2);
+
+        //lowest freed id first, then the other one, then fresh ids again
+        for expected in [1, 2, 4] {
+            let (_, request) = f.client();
+            assert_eq!(request.request_data().client_id, expected);
+        }
+        let bookkeeper = f.bookkeeper.bookkeeper_data();
+        assert_eq!(bookkeeper.free_slots, [0; 32]);
+        assert_eq!(bookkeeper.next_id, 5);
+        assert_eq!(bookkeeper.client_count(), 5);
+    }
+
+    #[test]
+    fn test_remove_client_rejects_foreign_id() {
+        let mut f = Fixture::new();
+        let (mut victim, mut victim_request) = f.client();
+        let (mut attacker, mut attacker_request) = f.client();
+        assert_eq!(victim_request.request_data().client_id, 0);
+        let bookkeeper = f.bookkeeper.data.clone();
+
+        //the attacker's own PDA, but the victim's id
+        assert_eq!(
+            f.remove(&mut attacker, &mut attacker_request, 0),
+            Err(ClientMismatch.into())
+        );
+        //the victim's PDA does not derive from the attacker's key
+        assert_eq!(
+            f.remove(&mut attacker, &mut victim_request, 0),
+            Err(InvalidAccount.into())
+        );
+        assert_eq!(f.bookkeeper.data, bookkeeper);
+
+        let req = Request::CreateBucket {
+            name: name("bucket"),
+        };
+        f.send(&mut victim, &mut victim_request, 0, req).unwrap();
+
+        victim.is_signer = false;
+        assert_eq!(
+            f.remove(&mut victim, &mut victim_request, 0),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        victim.is_signer = true;
+        f.remove(&mut victim, &mut victim_request, 0).unwrap();
+        assert_eq!(f.bookkeeper.bookkeeper_data().client_count(), 1);
+        f.remove(&mut attacker, &mut attacker_request, 1).unwrap();
+    }
+
+    #[test]
+    fn test_force_remove_client() {
+        let mut f = Fixture::new();
+        let (_, mut kept_request) = f.client();
+        let (mut owner, mut request) = f.client();
+        let mut destination = TestAccount::new(Pubkey::new_unique(), vec![], system_program::ID);
+        let mut stranger = TestAccount::wallet();
+        let bookkeeper = f.bookkeeper.data.clone();
+        owner.is_signer = false;
+
+        assert_eq!(
+            f.force_remove(
+                Some(&mut stranger),
+                &mut request,
+                &mut owner,
+                &mut destination,
+                1
+            ),
+            Err(Unauthorized.into())
+        );
+        f.authority.is_signer = false;
+        assert_eq!(
+            f.force_remove(None, &mut request, &mut owner, &mut destination, 1),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        f.authority.is_signer = true;
+        //the request PDA has to derive from the owner passed alongside it
+        assert_eq!(
+            f.force_remove(None, &mut kept_request, &mut owner, &mut destination, 0),
+            Err(InvalidAccount.into())
+        );
+        assert_eq!(
+            f.force_remove(None, &mut request, &mut owner, &mut destination, 0),
+            Err(ClientMismatch.into())
+        );
+        assert_eq!(f.bookkeeper.data, bookkeeper);
+
+        let lamports = request.lamports + destination.lamports;
+        f.force_remove(None, &mut request, &mut owner, &mut destination, 1)
+            .unwrap();
+        assert_eq!(destination.lamports, lamports);
+        assert_eq!(request.lamports, 0);
+        assert!(request.data.iter().all(|&b| b == 0));
+
+        let bookkeeper = f.bookkeeper.bookkeeper_data();
+        assert!(!bookkeeper.is_registered(1));
+        assert!(bookkeeper.is_registered(0));
+        assert_eq!(first_set_bit(&bookkeeper.free_slots), Some(1));
+        assert_eq!(f.client().1.request_data().client_id, 1);
+    }
+
+    #[test]
+    fn test_client_events() {
+        let mut f = Fixture::new();
+        let events = |logged: Vec<Vec<u8>>| {
+            logged
+                .iter()
+                .map(|data| parse_event(data).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let mut wallet = TestAccount::wallet();
+        let mut request = f.request_account(&wallet.key);
+        let logged = log_data_of(|| f.register(&mut wallet, &mut request).unwrap());
+        assert_eq!(
+            events(logged),
+            vec![BitokuEvent::ClientRegistered(ClientRegistered {
+                client_id: 0,
+                requester: wallet.key,
+            })]
+        );
+
+        let (mut owner, mut owner_request) = f.client();
+        let logged = log_data_of(|| {
+            assert!(f.remove(&mut wallet, &mut request, 1).is_err());
+            f.remove(&mut wallet, &mut request, 0).unwrap();
+        });
+        assert_eq!(
+            events(logged),
+            vec![BitokuEvent::ClientRemoved(ClientRemoved { client_id: 0 })]
+        );
+
+        let mut destination = TestAccount::wallet();
+        let logged = log_data_of(|| {
+            f.force_remove(None, &mut owner_request, &mut owner, &mut destination, 1)
+                .unwrap()
+        });
+        assert_eq!(
+            events(logged),
+            vec![BitokuEvent::ClientRemoved(ClientRemoved { client_id: 1 })]
+        );
+    }
+
+    #[test]
+    fn test_bucket_lease() {
+        let mut f = Fixture::new();
+        let (mut first, mut first_request) = f.client();
+        let (mut second, mut second_request) = f.client();
+        let acquire = |ttl_slots| Request::AcquireBucketLease {
+            name: name("shared"),
+            ttl_slots,
+        };
+        let release = Request::ReleaseBucketLease {
+            name: name("shared"),
+        };
+        let write = |file: &str| Request::CreateFile {
+            name: name(file),
+            sparse: false,
+            data: [0; 512],
+        };
+
+        //releasing a lease nobody took is a no-op
+        f.send(&mut second, &mut second_request, 1, release.clone())
+            .unwrap();
+
+        f.clock.data = epoch_clock_data(0, 100, 0);
+        f.send(&mut first, &mut first_request, 0, acquire(50))
+            .unwrap();
+        assert_eq!(
+            f.lease_data(&release),
+            BucketLease {
+                holder: first.key,
+                expiry_slot: 150
+            }
+        );
+        //lease requests are not left for the agent
+        assert_eq!(first_request.request_data().sequence, 0);
+
+        //contention: only the holder writes the bucket until the lease expires
+        f.send(&mut first, &mut first_request, 0, write("shared/a"))
+            .unwrap();
+        for req in [
+            write("shared/b"),
+            Request::DeleteBucket {
+                name: name("shared"),
+            },
+            Request::CopyFile {
+                src_name: name("other/x"),
+                src_file_id: 1,
+                dst_name: name("shared/x"),
+            },
+            acquire(1),
+            release.clone(),
+        ] {
+            assert_eq!(
+                f.send(&mut second, &mut second_request, 1, req),
+                Err(BucketLeased.into())
+            );
+        }
+        f.send(&mut second, &mut second_request, 1, write("other/b"))
+            .unwrap();
+        let read = Request::ReadFile {
+            name: name("shared/a"),
+            file_id: 1,
+            min_sequence: 0,
+        };
+        f.send(&mut second, &mut second_request, 1, read).unwrap();
+
+        //the holder renews from the current slot
+        f.clock.data = epoch_clock_data(0, 120, 0);
+        f.send(&mut first, &mut first_request, 0, acquire(10))
+            .unwrap();
+        assert_eq!(f.lease_data(&release).expiry_slot, 130);
+
+        //expiry: the bucket is open again and can be taken over
+        f.clock.data = epoch_clock_data(0, 130, 0);
+        f.send(&mut second, &mut second_request, 1, write("shared/b"))
+            .unwrap();
+        f.send(&mut second, &mut second_request, 1, acquire(5))
+            .unwrap();
+        assert_eq!(
+            f.send(&mut first, &mut first_request, 0, write("shared/a")),
+            Err(BucketLeased.into())
+        );
+
+        //release
+        f.send(&mut second, &mut second_request, 1, release.clone())
+            .unwrap();
+        assert_eq!(f.lease_data(&release), BucketLease::default());
+        f.send(&mut first, &mut first_request, 0, write("shared/a"))
+            .unwrap();
+
+        assert_eq!(
+            f.send(&mut first, &mut first_request, 0, acquire(0)),
+            Err(InvalidInstructionData.into())
+        );
+        //leases are settled by the program, whatever the agent supports
+        f.set_supported_kinds(None, 0).unwrap();
+        f.send(&mut first, &mut first_request, 0, acquire(1))
+            .unwrap();
+
+        //the lease stays with the holder's key, not its recycled client id
+        f.set_supported_kinds(None, ALL_REQUEST_KINDS).unwrap();
+        f.remove(&mut first, &mut first_request, 0).unwrap();
+        let (mut third, mut third_request) = f.client();
+        assert_eq!(third_request.request_data().client_id, 0);
+        assert_eq!(
+            f.send(&mut third, &mut third_request, 0, write("shared/c")),
+            Err(BucketLeased.into())
+        );
+    }
+
+    #[test]
+    fn test_bucket_lease_account_checked() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let write = Request::CreateBucket {
+            name: name("bucket"),
+        };
+        let ix = BitokuInstructions::SendRequest {
+            client_id: 0,
+            submission_ref: [0; 32],
+            request: write.clone(),
+        };
+        //the ClientInfo the registration initialized
+        let mut client_info = f.client_infos.pop().unwrap();
+
+        let mut accounts = [
+            &mut wallet,
+            &mut request,
+            &mut f.bookkeeper,
+            &mut f.clock,
+            &mut client_info,
+            &mut f.system_program,
+            &mut f.rent,
+        ];
+        assert_eq!(
+            run(&f.program_id, &mut accounts, &ix.pack()),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        let mut other = f.lease_account(&Request::CreateBucket {
+            name: name("other"),
+        });
+        let mut lease = f.lease_account(&write);
+        lease.owner = Pubkey::new_unique();
+        for lease in [&mut other, &mut lease] {
+            let mut accounts = [
+                &mut wallet,
+                &mut request,
+                &mut f.bookkeeper,
+                &mut f.clock,
+                &mut client_info,
+                &mut f.system_program,
+                &mut f.rent,
+                lease,
+            ];
+            assert_eq!(
+                run(&f.program_id, &mut accounts, &ix.pack()),
+                Err(InvalidAccount.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_all_256_ids_recycled() {
+        let mut f = Fixture::new();
+        let mut clients: Vec<_> = (0..256).map(|_| f.client()).collect();
+        let (_, last) = &clients[255];
+        assert_eq!(last.request_data().client_id, 255);
+        let bookkeeper = f.bookkeeper.bookkeeper_data();
+        assert_eq!(bookkeeper.client_count(), 256);
+        assert_eq!(bookkeeper.next_id, 255);
+
+        let mut newcomer = TestAccount::wallet();
+        let mut newcomer_request = f.request_account(&newcomer.key);
+        assert_eq!(
+            f.register(&mut newcomer, &mut newcomer_request),
+            Err(NoAvailableClients.into())
+        );
+
+        for id in [200, 7, 255] {
+            let (wallet, request) = &mut clients[id];
+            f.remove(wallet, request, id as u8).unwrap();
+        }
+        assert_eq!(f.bookkeeper.bookkeeper_data().client_count(), 253);
+
+        //freed slots are refilled lowest first, then the bitmap is full again
+        for expected in [7, 200, 255] {
+            let (_, request) = f.client();
+            assert_eq!(request.request_data().client_id, expected);
+        }
+        let bookkeeper = f.bookkeeper.bookkeeper_data();
+        assert_eq!(bookkeeper.status, [u8::MAX; 32]);
+        assert_eq!(bookkeeper.free_slots, [0; 32]);
+        assert_eq!(
+            f.register(&mut newcomer, &mut newcomer_request),
+            Err(NoAvailableClients.into())
+        );
+    }
+
+    #[test]
+    fn test_ids_not_bounded_by_next_id() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        //a bookkeeper written when next_id gated registration
+        let mut bookkeeper = f.bookkeeper.bookkeeper_data();
+        bookkeeper.next_id = 255;
+        BookKeeper::pack(bookkeeper, &mut f.bookkeeper.data).unwrap();
+
+        let (_, newcomer_request) = f.client();
+        assert_eq!(newcomer_request.request_data().client_id, 1);
+
+        f.remove(&mut wallet, &mut request, 0).unwrap();
+        let (_, request) = f.client();
+        assert_eq!(request.request_data().client_id, 0);
+        assert_eq!(f.bookkeeper.bookkeeper_data().next_id, 255);
+    }
+
+    #[test]
+    fn test_register_client_for_then_first_use_by_target() {
+        let mut f = Fixture::new();
+        let (_, _) = f.client();
+
+        let mut target = TestAccount::wallet();
+        let mut request = f.request_account(&target.key);
+        f.register_for(None, &mut request, target.key).unwrap();
+
+        assert_eq!(request.request_data().client_id, 1);
+        let bookkeeper = f.bookkeeper.bookkeeper_data();
+        assert!(bookkeeper.is_registered(1));
+        assert_eq!(bookkeeper.next_id, 2);
+
+        let bucket = Request::CreateBucket {
+            name: name("bucket"),
+        };
+        f.send(&mut target, &mut request, 1, bucket.clone())
+            .unwrap();
+        assert_eq!(request.request_data().request, bucket);
+        assert_eq!(request.request_data().requester, target.key);
+    }
+
+    #[test]
+    fn test_register_client_for_requires_authority() {
+        let mut f = Fixture::new();
+        let target = Pubkey::new_unique();
+        let mut request = f.request_account(&target);
+
+        let mut impostor = TestAccount::wallet();
+        assert_eq!(
+            f.register_for(Some(&mut impostor), &mut request, target),
+            Err(Unauthorized.into())
+        );
+
+        f.authority.is_signer = false;
+        assert_eq!(
+            f.register_for(None, &mut request, target),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(f.bookkeeper.bookkeeper_data().next_id, 0);
+    }
+
+    #[test]
+    fn test_register_client_for_target_pda_only() {
+        let mut f = Fixture::new();
+        let target = TestAccount::wallet();
+
+        let authority_key = f.authority.key;
+        let mut authority_request = f.request_account(&authority_key);
+        assert_eq!(
+            f.register_for(None, &mut authority_request, target.key),
+            Err(InvalidAccount.into())
+        );
+
+        let mut request = f.request_account(&target.key);
+        f.register_for(None, &mut request, target.key).unwrap();
+
+        //only the target's signature can drive its request PDA
+        let mut unsigned_target = TestAccount::new(target.key, vec![], system_program::ID);
+        assert_eq!(
+            f.send(
+                &mut unsigned_target,
+                &mut request,
+                0,
+                Request::CreateBucket {
+                    name: name("bucket")
+                }
+            ),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    fn write_file(len: usize) -> Request {
+        let mut data = [0u8; 512];
+        data[..len].fill(b'd');
+        Request::WriteFile {
+            name: name("file"),
+            file_id: 1,
+            offset: 0,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_compact_account_grows_for_larger_request() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.compact_client();
+        assert_eq!(request.data.len(), COMPACT_BASE_LEN);
+        assert_eq!(request.request_data().client_id, 0);
+
+        f.send(&mut wallet, &mut request, 0, write_file(300))
+            .unwrap();
+        assert_eq!(request.data.len(), COMPACT_BASE_LEN + 1 + 137 + 300);
+        assert!(RequestData::is_compact(&request.data));
+        assert_eq!(request.request_data().request, write_file(300));
+        assert_eq!(request.request_data().sequence, 1);
+
+        //the grow path needs the system program and rent sysvar
+        f.acknowledge(&mut request);
+        let mut accounts = [&mut wallet, &mut request, &mut f.bookkeeper, &mut f.clock];
+        let ix = BitokuInstructions::SendRequest {
+            client_id: 0,
+            submission_ref: [0; 32],
+            request: write_file(512),
+        };
+        assert_eq!(
+            run(&f.program_id, &mut accounts, &ix.pack()),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn test_compact_account_alternating_sizes() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.compact_client();
+
+        let mut largest = COMPACT_BASE_LEN;
+        for len in [512, 4, 200, 0, 512, 17] {
+            f.send(&mut wallet, &mut request, 0, write_file(len))
+                .unwrap();
+            //growing happens on demand, shrinking only on request
+            largest = largest.max(request.request_data().compact_len());
+            assert_eq!(request.data.len(), largest);
+            assert_eq!(request.request_data().request, write_file(len));
+        }
+
+        f.shrink(&mut wallet, &mut request).unwrap();
+        assert_eq!(request.data.len(), COMPACT_BASE_LEN + 1 + 137 + 17);
+        f.send(&mut wallet, &mut request, 0, write_file(64))
+            .unwrap();
+        assert_eq!(request.data.len(), COMPACT_BASE_LEN + 1 + 137 + 64);
+        assert_eq!(request.request_data().sequence, 7);
+    }
+
+    #[test]
+    fn test_shrink_request_account_refunds_rent() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.compact_client();
+        f.send(&mut wallet, &mut request, 0, write_file(512))
+            .unwrap();
+        //stands in for the top-up transfer, which the test runtime doesn't execute
+        let rent = Rent::default();
+        request.lamports = rent.minimum_balance(request.data.len());
+
+        f.send(&mut wallet, &mut request, 0, write_file(8)).unwrap();
+        let before = (wallet.lamports, request.lamports);
+        let before_data = request.data.clone();
+        f.shrink(&mut wallet, &mut request).unwrap();
+
+        let len = COMPACT_BASE_LEN + 1 + 137 + 8;
+        assert_eq!(request.data.len(), len);
+        assert_eq!(request.lamports, rent.minimum_balance(len));
+        let refund = before.1 - request.lamports;
+        assert!(refund > 0);
+        assert_eq!(wallet.lamports, before.0 + refund);
+        assert_eq!(request.request_data().request, write_file(8));
+        assert_eq!(verify_migration(&before_data, &request.data), Ok(()));
+
+        //nothing left to refund
+        f.shrink(&mut wallet, &mut request).unwrap();
+        assert_eq!(request.lamports, rent.minimum_balance(len));
+    }
+
+    #[test]
+    fn test_shrink_request_account_checks() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        assert_eq!(
+            f.shrink(&mut wallet, &mut request),
+            Err(InvalidAccount.into())
+        );
+        f.send(&mut wallet, &mut request, 0, write_file(512))
+            .unwrap();
+        assert_eq!(request.data.len(), RequestData::LEN);
+
+        let (mut other, _) = f.compact_client();
+        let (_, mut compact) = f.compact_client();
+        assert_eq!(
+            f.shrink(&mut other, &mut compact),
+            Err(InvalidAccount.into())
+        );
+
+        let (mut wallet, mut compact) = f.compact_client();
+        wallet.is_signer = false;
+        assert_eq!(
+            f.shrink(&mut wallet, &mut compact),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    #[test]
+    fn test_migrate_request_account() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        f.send(&mut wallet, &mut request, 0, write_file(64))
+            .unwrap();
+        let current = request.data.clone();
+        let original = request.request_data();
+
+        //every older length, with the header and from before it
+        for len in [1522, 1530, 1531] {
+            for older in [&current[..len], &current[ACCOUNT_HEADER_LEN..len]] {
+                request.data = older.to_vec();
+                f.migrate(&mut wallet, &mut request).unwrap();
+                assert_eq!(request.data.len(), RequestData::LEN);
+
+                let migrated = request.request_data();
+                assert_eq!(migrated.client_id, original.client_id);
+                assert_eq!(migrated.request, original.request);
+                assert_eq!(migrated.sequence, original.sequence);
+                assert_eq!(migrated.bump, original.bump);
+                assert_eq!(verify_migration(older, &request.data), Ok(()));
+
+                //a second migration finds nothing to do
+                let once = request.data.clone();
+                f.migrate(&mut wallet, &mut request).unwrap();
+                assert_eq!(request.data, once);
+            }
+        }
+
+        //the migrated account takes requests again
+        f.send(&mut wallet, &mut request, 0, write_file(8)).unwrap();
+        assert_eq!(request.request_data().request, write_file(8));
+
+        //a CreateBucket stored by the first release
+        let mut baseline = vec![0; 675];
+        baseline[1..33].copy_from_slice(wallet.key.as_ref());
+        baseline[34..40].copy_from_slice(b"bucket");
+        request.data = baseline.clone();
+        f.migrate(&mut wallet, &mut request).unwrap();
+        assert_eq!(request.data.len(), RequestData::LEN);
+        let migrated = request.request_data();
+        assert_eq!((migrated.client_id, migrated.requester), (0, wallet.key));
+        assert_eq!(
+            migrated.request,
+            Request::CreateBucket {
+                name: name("bucket")
+            }
+        );
+        assert_eq!(migrated.bump, original.bump);
+        assert_eq!(verify_migration(&baseline, &request.data), Ok(()));
+        f.send(&mut wallet, &mut request, 0, write_file(8)).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_creates_client_info() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        //a client registered before permissions moved into ClientInfo has none
+        f.client_infos.clear();
+        let mut baseline = vec![0; 675];
+        baseline[1..33].copy_from_slice(wallet.key.as_ref());
+        request.data = baseline;
+
+        f.migrate(&mut wallet, &mut request).unwrap();
+        assert_eq!(f.client_info_data(&wallet.key).owner, wallet.key);
+        assert_eq!(f.client_info_data(&wallet.key).permissions, ALL_PERMISSIONS);
+        f.send(&mut wallet, &mut request, 0, write_file(8)).unwrap();
+        assert_eq!(request.request_data().request, write_file(8));
+
+        //permissions the admin set survive a later migration
+        f.set_permissions(None, &mut request, &wallet.key, 0, PERMISSION_READ)
+            .unwrap();
+        f.migrate(&mut wallet, &mut request).unwrap();
+        assert_eq!(f.client_info_data(&wallet.key).permissions, PERMISSION_READ);
+    }
+
+    #[test]
+    fn test_migrate_bookkeeper() {
+        let mut f = Fixture::new();
+        let original = f.bookkeeper.bookkeeper_data();
+        let mut payer = TestAccount::wallet();
+
+        for len in [270, 271, 272, 400, 408, 416] {
+            f.bookkeeper.data.truncate(len);
+            f.migrate_bookkeeper(Some(&mut payer)).unwrap();
+            assert_eq!(f.bookkeeper.data.len(), BookKeeper::LEN);
+
+            let migrated = f.bookkeeper.bookkeeper_data();
+            assert_eq!(migrated.authority, original.authority);
+            assert_eq!(migrated.agent, original.agent);
+            assert_eq!(migrated.bump, original.bump);
+            assert_eq!(migrated.admin, original.authority);
+            assert_eq!(
+                migrated.max_client_requests_per_epoch,
+                DEFAULT_MAX_CLIENT_REQUESTS_PER_EPOCH
+            );
+        }
+
+        let current = f.bookkeeper.data.clone();
+        f.migrate_bookkeeper(Some(&mut payer)).unwrap();
+        assert_eq!(f.bookkeeper.data, current);
+        let (mut wallet, mut request) = f.client();
+        f.send(&mut wallet, &mut request, 0, write_file(8)).unwrap();
+
+        //the first release's bookkeeper has no authority, so the payer takes it,
+        //which only the program's upgrade authority may do
+        let mut baseline = [0u8; 33];
+        baseline[0] = 0b1;
+        baseline[32] = 1;
+        f.bookkeeper.data = baseline.to_vec();
+        assert_eq!(
+            f.migrate_bookkeeper(Some(&mut payer)),
+            Err(Unauthorized.into())
+        );
+        assert_eq!(f.bookkeeper.data, baseline);
+        f.migrate_bookkeeper(None).unwrap();
+        let migrated = f.bookkeeper.bookkeeper_data();
+        assert_eq!(migrated.registered_clients().collect::<Vec<_>>(), [0]);
+        let authority = f.authority.key;
+        assert_eq!(
+            (migrated.authority, migrated.agent, migrated.admin),
+            (authority, authority, authority)
+        );
+        assert_eq!(migrated.bump, original.bump);
+        let (_, request) = f.client();
+        assert_eq!(request.request_data().client_id, 1);
+    }
+
+    #[test]
+    fn test_migrate_account_checks() {
+        let mut f = Fixture::new();
+        let (mut wallet, mut request) = f.client();
+        let (mut other, _) = f.client();
+        assert_eq!(
+            f.migrate(&mut other, &mut request),
+            Err(InvalidAccount.into())
+        );
+
+        request.data.truncate(1000);
+        assert_eq!(
+            f.migrate(&mut wallet, &mut request),
+            Err(ProgramError::InvalidAccountData)
+        );
+
+        wallet.is_signer = false;
+        assert_eq!(
+            f.migrate(&mut wallet, &mut request),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        //compact accounts only exist in the current layout
+        let (mut wallet, mut compact) = f.compact_client();
+        let before = compact.data.clone();
+        f.migrate(&mut wallet, &mut compact).unwrap();
+        assert_eq!(compact.data, before);
+    }
 }
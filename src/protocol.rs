@@ -0,0 +1,458 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Wire layout of `BitokuInstructions` and `Request`, which are encoded with
+//! Borsh: the tag is the variant index and the fields follow in declaration
+//! order. `PROTOCOL.md` is rendered from these tables, and the legacy decoder
+//! walks them field by field, so its tests catch a table that no longer
+//! describes the Borsh encoding.
+
+use crate::state::PREFIX_LEN;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldKind {
+    U8,
+    Bool,
+    U16,
+    U32,
+    U64,
+    I64,
+    Key,
+    /// 128-byte name, zero padded after its text.
+    Name,
+    /// Fixed-size text, zero padded after its text.
+    Text(usize),
+    Bytes(usize),
+    /// A u8 holding a discriminant of the named enum.
+    Enum(&'static str),
+    /// Bool that legacy decoding also accepts left out at the end of the input,
+    /// reading as false.
+    Flag,
+    /// 512 bytes. Legacy decoding also accepts fewer running to the end of the
+    /// input, zero padding them to 512.
+    Data,
+    /// A nested `Request`, laid out as in `REQUESTS`.
+    Request,
+}
+
+impl FieldKind {
+    /// Encoded size, or `None` if the field runs to the end of the input.
+    pub const fn size(self) -> Option<usize> {
+        match self {
+            Self::U8 | Self::Bool | Self::Enum(_) | Self::Flag => Some(1),
+            Self::U16 => Some(2),
+            Self::U32 => Some(4),
+            Self::U64 | Self::I64 => Some(8),
+            Self::Key => Some(32),
+            Self::Name => Some(128),
+            Self::Text(len) | Self::Bytes(len) => Some(len),
+            Self::Data | Self::Request => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Field {
+    pub name: &'static str,
+    pub kind: FieldKind,
+}
+
+#[derive(Debug)]
+pub struct Variant {
+    pub tag: u8,
+    pub name: &'static str,
+    pub fields: &'static [Field],
+}
+
+const fn field(name: &'static str, kind: FieldKind) -> Field {
+    Field { name, kind }
+}
+
+const fn variant(tag: u8, name: &'static str, fields: &'static [Field]) -> Variant {
+    Variant { tag, name, fields }
+}
+
+const CLIENT_ID: Field = field("client_id", FieldKind::U8);
+const NAME: Field = field("name", FieldKind::Name);
+const FILE_ID: Field = field("file_id", FieldKind::U8);
+const DATA: Field = field("data", FieldKind::Data);
+const OFFSET: Field = field("offset", FieldKind::U64);
+const MIN_SEQUENCE: Field = field("min_sequence", FieldKind::U64);
+
+/// `BitokuInstructions`, indexed by tag. Every instruction starts with its tag.
+pub const INSTRUCTIONS: &[Variant] = &[
+    variant(0, "InitBitoku", &[]),
+    variant(1, "RegisterClient", &[field("compact", FieldKind::Flag)]),
+    variant(2, "RemoveClient", &[CLIENT_ID]),
+    variant(
+        3,
+        "SendRequest",
+        &[
+            CLIENT_ID,
+            field("submission_ref", FieldKind::Bytes(32)),
+            field("request", FieldKind::Request),
+        ],
+    ),
+    variant(4, "GetVersion", &[]),
+    variant(5, "RegisterClientFor", &[field("target", FieldKind::Key)]),
+    variant(
+        6,
+        "SetReservedPrefix",
+        &[
+            field("index", FieldKind::U8),
+            field("prefix", FieldKind::Text(PREFIX_LEN)),
+        ],
+    ),
+    variant(7, "PublishStats", &[]),
+    variant(
+        8,
+        "UpdateRequestStatus",
+        &[CLIENT_ID, field("status", FieldKind::Enum("RequestStatus"))],
+    ),
+    variant(
+        9,
+        "SetSupportedKinds",
+        &[field("supported_kinds", FieldKind::U32)],
+    ),
+    variant(
+        10,
+        "WriteResponse",
+        &[
+            CLIENT_ID,
+            field("data", FieldKind::Bytes(512)),
+            field("len", FieldKind::U16),
+            field("status", FieldKind::Enum("RequestStatus")),
+        ],
+    ),
+    variant(11, "SetAgent", &[field("agent", FieldKind::Key)]),
+    variant(
+        12,
+        "SetNamePolicy",
+        &[field("policy", FieldKind::Enum("NamePolicyKind"))],
+    ),
+    variant(
+        13,
+        "PublishLoad",
+        &[
+            field("max_requests_per_epoch", FieldKind::U32),
+            field("current_backlog", FieldKind::U16),
+        ],
+    ),
+    variant(14, "ShrinkRequestAccount", &[]),
+    variant(
+        15,
+        "SetAuthority",
+        &[field("new_authority", FieldKind::Key)],
+    ),
+    variant(16, "ForceRemoveClient", &[CLIENT_ID]),
+    variant(17, "SetPaused", &[field("paused", FieldKind::Bool)]),
+    variant(
+        18,
+        "SubmitSignedRequest",
+        &[
+            CLIENT_ID,
+            field("nonce", FieldKind::U64),
+            field("signer", FieldKind::Key),
+            field("signature", FieldKind::Bytes(64)),
+            field("request", FieldKind::Request),
+        ],
+    ),
+    variant(19, "CancelRequest", &[CLIENT_ID]),
+    variant(20, "AddAgent", &[field("pubkey", FieldKind::Key)]),
+    variant(21, "RemoveAgent", &[field("pubkey", FieldKind::Key)]),
+    variant(
+        22,
+        "AcknowledgeRequest",
+        &[CLIENT_ID, field("status", FieldKind::Enum("RequestStatus"))],
+    ),
+    variant(
+        23,
+        "OverwriteRequest",
+        &[
+            CLIENT_ID,
+            field("submission_ref", FieldKind::Bytes(32)),
+            field("request", FieldKind::Request),
+        ],
+    ),
+    variant(24, "ResetEpochCounter", &[CLIENT_ID]),
+    variant(
+        25,
+        "SetClientPermissions",
+        &[CLIENT_ID, field("permissions", FieldKind::U8)],
+    ),
+    variant(26, "MigrateAccount", &[]),
+    variant(27, "UpdateAdmin", &[field("new_admin", FieldKind::Key)]),
+];
+
+/// `Request` kinds, indexed by tag. Every request starts with its tag.
+pub const REQUESTS: &[Variant] = &[
+    variant(0, "CreateBucket", &[NAME]),
+    variant(
+        1,
+        "CreateFile",
+        &[NAME, field("sparse", FieldKind::Bool), DATA],
+    ),
+    variant(2, "WriteFile", &[NAME, FILE_ID, OFFSET, DATA]),
+    variant(3, "CloseFile", &[NAME, FILE_ID]),
+    variant(4, "DeleteFile", &[NAME, FILE_ID]),
+    variant(
+        5,
+        "SetPosition",
+        &[NAME, FILE_ID, field("position", FieldKind::U64)],
+    ),
+    variant(6, "OpenFile", &[NAME, FILE_ID]),
+    variant(7, "ReadFile", &[NAME, FILE_ID, MIN_SEQUENCE]),
+    variant(8, "DeleteBucket", &[NAME]),
+    variant(
+        9,
+        "RenameFile",
+        &[NAME, FILE_ID, field("new_name", FieldKind::Name)],
+    ),
+    variant(
+        10,
+        "CopyFile",
+        &[
+            field("src_name", FieldKind::Name),
+            field("src_file_id", FieldKind::U8),
+            field("dst_name", FieldKind::Name),
+        ],
+    ),
+    variant(11, "StatFile", &[NAME, FILE_ID, MIN_SEQUENCE]),
+    variant(
+        12,
+        "TruncateFile",
+        &[NAME, FILE_ID, field("new_length", FieldKind::U64)],
+    ),
+    variant(13, "ListFiles", &[NAME, field("page", FieldKind::U8)]),
+    variant(14, "AppendFile", &[NAME, FILE_ID, DATA]),
+    variant(
+        15,
+        "WriteByReference",
+        &[NAME, FILE_ID, field("chunk_hash", FieldKind::Bytes(32))],
+    ),
+    variant(16, "WriteAt", &[NAME, FILE_ID, OFFSET, DATA]),
+    variant(
+        17,
+        "ReadRange",
+        &[
+            NAME,
+            FILE_ID,
+            OFFSET,
+            field("len", FieldKind::U64),
+            MIN_SEQUENCE,
+        ],
+    ),
+    variant(18, "FileExists", &[NAME, FILE_ID]),
+    variant(19, "TrashFile", &[NAME, FILE_ID]),
+    variant(20, "RestoreFile", &[NAME, FILE_ID]),
+    variant(21, "EmptyTrash", &[]),
+    variant(
+        22,
+        "ReadFileRange",
+        &[NAME, FILE_ID, OFFSET, field("length", FieldKind::U32)],
+    ),
+    variant(
+        23,
+        "AcquireBucketLease",
+        &[NAME, field("ttl_slots", FieldKind::U32)],
+    ),
+    variant(24, "ReleaseBucketLease", &[NAME]),
+];
+
+impl Variant {
+    /// Encoded size with every `Data` and `Request` field at its largest.
+    pub const fn max_len(&self) -> usize {
+        let mut len = 1;
+        let mut i = 0;
+        while i < self.fields.len() {
+            len += match self.fields[i].kind {
+                FieldKind::Data => 512,
+                FieldKind::Request => max_len(REQUESTS),
+                kind => match kind.size() {
+                    Some(len) => len,
+                    None => 0,
+                },
+            };
+            i += 1;
+        }
+        len
+    }
+}
+
+/// Size of the largest variant in `table`.
+pub const fn max_len(table: &[Variant]) -> usize {
+    let mut max = 0;
+    let mut i = 0;
+    while i < table.len() {
+        if table[i].max_len() > max {
+            max = table[i].max_len();
+        }
+        i += 1;
+    }
+    max
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction::MAX_PACKED_LEN;
+    use crate::state::{ACCOUNT_HEADER_LEN, CANCELLED_TAG, ENCODED_REQUEST_MAX_LEN};
+    use std::fmt::Write;
+
+    const SEND_REQUEST_OFFSET: usize = 1 + 1 + 32;
+    const ACCOUNT_REQUEST_OFFSET: usize = ACCOUNT_HEADER_LEN + 1 + 32;
+
+    fn type_name(kind: FieldKind) -> String {
+        match kind {
+            FieldKind::U8 => "u8".to_string(),
+            FieldKind::Bool => "bool".to_string(),
+            FieldKind::U16 => "u16".to_string(),
+            FieldKind::U32 => "u32".to_string(),
+            FieldKind::U64 => "u64".to_string(),
+            FieldKind::I64 => "i64".to_string(),
+            FieldKind::Key => "pubkey".to_string(),
+            FieldKind::Name => "name".to_string(),
+            FieldKind::Text(len) => format!("text [u8; {}]", len),
+            FieldKind::Bytes(len) => format!("[u8; {}]", len),
+            FieldKind::Enum(ty) => format!("`{}` as u8", ty),
+            FieldKind::Flag => "optional bool".to_string(),
+            FieldKind::Data => "data".to_string(),
+            FieldKind::Request => "request".to_string(),
+        }
+    }
+
+    fn endianness(kind: FieldKind) -> &'static str {
+        match kind {
+            FieldKind::U16 | FieldKind::U32 | FieldKind::U64 | FieldKind::I64 => "little",
+            _ => "-",
+        }
+    }
+
+    fn render_table(out: &mut String, table: &[Variant]) {
+        for variant in table {
+            writeln!(out, "### {} `{}`\n", variant.tag, variant.name).unwrap();
+            writeln!(out, "| Offset | Field | Type | Length | Endianness |").unwrap();
+            writeln!(out, "|---|---|---|---|---|").unwrap();
+            writeln!(out, "| 0 | tag = {} | u8 | 1 | - |", variant.tag).unwrap();
+            let mut offset = 1;
+            for field in variant.fields {
+                let len = match field.kind {
+                    FieldKind::Data => "512".to_string(),
+                    FieldKind::Request => "rest".to_string(),
+                    kind => kind.size().unwrap().to_string(),
+                };
+                writeln!(
+                    out,
+                    "| {} | {} | {} | {} | {} |",
+                    offset,
+                    field.name,
+                    type_name(field.kind),
+                    len,
+                    endianness(field.kind)
+                )
+                .unwrap();
+                offset += field.kind.size().unwrap_or(0);
+            }
+            writeln!(out).unwrap();
+        }
+    }
+
+    fn render() -> String {
+        let mut out = String::new();
+        out.push_str(
+            "# Bitoku wire protocol\n\
+             \n\
+             <!-- Generated from src/protocol.rs by `cargo test`; run it with\n\
+             UPDATE_PROTOCOL=1 to rewrite this file after changing the table. -->\n\
+             \n\
+             Instructions and requests are Borsh encoded: the tag is the variant index\n\
+             and the fields follow in declaration order. Offsets and lengths are in\n\
+             bytes. Field types:\n\
+             \n\
+             - `name`: 128 bytes of UTF-8 text, zero padded; no byte after the first\n\
+             \x20 zero may be set.\n\
+             - `text`: the same, at the given length.\n\
+             - `optional bool`: 0 or 1.\n\
+             - `data`: 512 bytes.\n\
+             - `request`: a request as described below, running to the end of the\n\
+             \x20 instruction.\n\
+             \n\
+             Bytes after the last field are rejected.\n\
+             \n\
+             Builds with the `legacy-wire` feature, which is on by default, fall back to\n\
+             the pre-Borsh rules for input that does not decode: an `optional bool` may\n\
+             be left out, reading as 0; `data` may be shorter, running to the end of the\n\
+             instruction and read as zero padded to 512; any non-zero `bool` reads as 1;\n\
+             and bytes after the last field are ignored.\n\
+             \n\
+             ## Instructions\n\
+             \n",
+        );
+        render_table(&mut out, INSTRUCTIONS);
+        writeln!(
+            out,
+            "## Requests\n\n\
+             Offsets are from the request's tag, which is at offset {} of a `SendRequest`\n\
+             instruction and at offset {} of a `RequestData` account. In an account the\n\
+             request area is {} bytes and zero padded after the request, so `data` always\n\
+             takes 512 bytes there. A cancelled request is overwritten with tag {} and\n\
+             zeros.\n",
+            SEND_REQUEST_OFFSET, ACCOUNT_REQUEST_OFFSET, ENCODED_REQUEST_MAX_LEN, CANCELLED_TAG
+        )
+        .unwrap();
+        render_table(&mut out, REQUESTS);
+        out.truncate(out.trim_end().len() + 1);
+        out
+    }
+
+    #[test]
+    fn test_tables_indexed_by_tag() {
+        for table in [INSTRUCTIONS, REQUESTS] {
+            for (i, variant) in table.iter().enumerate() {
+                assert_eq!(variant.tag as usize, i, "{}", variant.name);
+                //only the last field may be left out or run to the end of the input
+                let fixed = variant.fields.len().saturating_sub(1);
+                for field in &variant.fields[..fixed] {
+                    assert!(field.kind.size().is_some() && field.kind != FieldKind::Flag);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_len() {
+        assert_eq!(max_len(REQUESTS), REQUESTS[16].max_len());
+        assert_eq!(ENCODED_REQUEST_MAX_LEN, 1 + 128 + 1 + 8 + 512);
+        assert_eq!(
+            MAX_PACKED_LEN,
+            1 + 1 + 8 + 32 + 64 + ENCODED_REQUEST_MAX_LEN
+        );
+    }
+
+    #[test]
+    fn test_protocol_md_is_current() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/PROTOCOL.md");
+        let rendered = render();
+        if std::env::var_os("UPDATE_PROTOCOL").is_some() {
+            std::fs::write(path, &rendered).unwrap();
+        }
+        let committed = std::fs::read_to_string(path).unwrap_or_default();
+        assert!(
+            committed == rendered,
+            "PROTOCOL.md is stale; rerun the tests with UPDATE_PROTOCOL=1"
+        );
+    }
+}
@@ -14,250 +14,2702 @@
    limitations under the License.
 */
 
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
 use borsh::{BorshDeserialize, BorshSerialize};
+use core::mem::size_of;
 use solana_program::{
     program_error::ProgramError,
-    program_pack::{Pack, Sealed},
+    program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
 };
 
-use crate::instruction::{unpack_request, Request};
+use crate::{
+    error::BitokuError::{self, BitmapOverflow, InvalidPosition, Overflow},
+    instruction::{pack_request, unpack_stored_request, Request},
+    policy::{DefaultPolicy, NamePolicy, NamePolicyKind},
+    protocol::{max_len, REQUESTS},
+};
+
+/// Size of the `data` chunk carried by a single `CreateFile`/`WriteFile` request.
+pub const CHUNK_LEN: u64 = 512;
+
+/// Largest `len` accepted in a single `ReadRange` request.
+pub const MAX_READ: u64 = 16 * CHUNK_LEN;
+
+/// Largest `ttl_slots` accepted in an `AcquireBucketLease` request, about a day
+/// at 400ms slots; a client that needs longer renews its lease.
+pub const MAX_LEASE_SLOTS: u32 = 216_000;
+
+/// Largest encoded `Request`, reached by `WriteAt`: tag, name, file id, offset
+/// and a full data chunk. `RequestData` reserves this much for its request.
+pub const ENCODED_REQUEST_MAX_LEN: usize = max_len(REQUESTS);
+
+/// Version of the instruction wire format accepted by this program.
+pub const WIRE_FORMAT_VERSION: u8 = 0;
+
+/// Number of `Request` kinds understood by `unpack_request` (tags `0..REQUEST_KINDS`).
+pub const REQUEST_KINDS: u8 = REQUESTS.len() as u8;
+
+/// `BookKeeper::supported_kinds` with every kind known to this program version set.
+pub const ALL_REQUEST_KINDS: u32 = (1 << REQUEST_KINDS) - 1;
+
+/// Size of the response area the agent fills in `RequestData`.
+pub const RESPONSE_LEN: usize = 256;
+
+/// `StatFile` response: file size in bytes, little-endian u64.
+pub const STAT_SIZE_OFFSET: usize = 0;
+/// `StatFile` response: last write time as a unix timestamp, little-endian u64.
+pub const STAT_MTIME_OFFSET: usize = 8;
+
+/// Number of reserved name prefix slots in `BookKeeper`.
+pub const RESERVED_PREFIXES: usize = 4;
+/// Size of a reserved name prefix; shorter prefixes are zero-padded, and an
+/// all-zero slot is unused.
+pub const PREFIX_LEN: usize = 32;
+
+/// Number of agent key slots in `BookKeeper`, besides `BookKeeper::agent`.
+pub const MAX_AGENTS: usize = 4;
+
+/// `Capabilities::feature_flags` bit set while `BookKeeper::paused` holds
+/// requests back.
+pub const FEATURE_PAUSED: u32 = 1 << 0;
+/// `Capabilities::feature_flags` bit set when `BookKeeper::name_policy` isn't
+/// `NamePolicyKind::Default`.
+pub const FEATURE_CUSTOM_NAME_POLICY: u32 = 1 << 1;
+
+/// `BookKeeper::max_client_requests_per_epoch` of a fresh deployment.
+pub const DEFAULT_MAX_CLIENT_REQUESTS_PER_EPOCH: u64 = 1000;
+
+/// `ClientInfo::permissions` bit for requests that create buckets and files.
+pub const PERMISSION_CREATE: u8 = 1 << 0;
+/// `ClientInfo::permissions` bit for requests that change existing files.
+pub const PERMISSION_WRITE: u8 = 1 << 1;
+/// `ClientInfo::permissions` bit for requests that delete or trash buckets and files.
+pub const PERMISSION_DELETE: u8 = 1 << 2;
+/// `ClientInfo::permissions` bit for requests that query stored data.
+pub const PERMISSION_READ: u8 = 1 << 3;
+/// `ClientInfo::permissions` of a newly registered client.
+pub const ALL_PERMISSIONS: u8 =
+    PERMISSION_CREATE | PERMISSION_WRITE | PERMISSION_DELETE | PERMISSION_READ;
+
+/// `FileExists` response: 1 if the file exists, 0 otherwise.
+pub const EXISTS_OFFSET: usize = 0;
+
+/// `ListFiles` response: number of entries on the page.
+pub const LIST_COUNT_OFFSET: usize = 0;
+/// `ListFiles` response: first entry; each entry is a file_id byte followed by
+/// `LIST_ENTRY_LEN - 1` bytes of zero-padded, possibly truncated file name.
+pub const LIST_ENTRIES_OFFSET: usize = 1;
+pub const LIST_ENTRY_LEN: usize = 32;
+/// Maximum number of entries on one `ListFiles` page.
+pub const LIST_PAGE_LEN: usize = (RESPONSE_LEN - LIST_ENTRIES_OFFSET) / LIST_ENTRY_LEN;
 
 #[repr(C)]
 #[derive(Clone, BorshSerialize, BorshDeserialize, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BookKeeper {
+    /// Set by `InitBitoku`; stored in the account header.
+    pub is_initialized: bool,
     pub status: [u8; 32],
+    /// One past the highest client id handed out so far, saturating at 255.
+    pub next_id: u8,
+    /// Key that initialized the program; signs admin-only instructions.
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::pubkey_serde"))]
+    pub authority: Pubkey,
+    /// Name prefixes only `authority` may use in requests.
+    pub reserved_prefixes: [[u8; PREFIX_LEN]; RESERVED_PREFIXES],
+    /// Bit `n` is set when the agent handles requests of kind `n` (see `Request::kind`).
+    pub supported_kinds: u32,
+    /// Key of the primary agent, set by `SetAgent`; see `is_agent` for the keys
+    /// accepted by agent-only instructions.
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::pubkey_serde"))]
+    pub agent: Pubkey,
+    /// Client ids released by `RemoveClient` and not yet handed out again;
+    /// `first_free` allocates from here before any fresh id.
+    pub free_slots: [u8; 32],
+    /// `NamePolicyKind` that `SendRequest` checks names against.
+    pub name_policy: u8,
+    /// Requests the agent can handle per epoch, as advertised with `PublishLoad`;
+    /// zero until the agent first publishes.
+    pub max_requests_per_epoch: u32,
+    /// Requests waiting for the agent when it last published its load.
+    pub current_backlog: u16,
+    /// Set by `SetPaused`; new clients and requests are refused while set.
+    pub paused: bool,
+    /// Bump of the account's PDA, stored by `InitBitoku` so later instructions
+    /// can check the address without searching for it.
+    pub bump: u8,
+    /// Keys registered with `AddAgent`, accepted alongside `agent`; unused slots
+    /// hold the default key.
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::pubkeys_serde"))]
+    pub agents: [Pubkey; MAX_AGENTS],
+    /// Requests each client may store before `ResetEpochCounter` clears its
+    /// `RequestData::requests_this_epoch`; `InitBitoku` sets
+    /// `DEFAULT_MAX_CLIENT_REQUESTS_PER_EPOCH`.
+    pub max_client_requests_per_epoch: u64,
+    /// Slot of the agent's latest `PublishLoad`, zero until it first publishes.
+    pub last_heartbeat_slot: u64,
+    /// Key that may hand the program to a new admin with `UpdateAdmin`; set by
+    /// `InitBitoku`.
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::pubkey_serde"))]
+    pub admin: Pubkey,
+}
+
+/// What a client should do before submitting, derived from the load the agent
+/// advertised in `BookKeeper`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ThrottleAdvice {
+    /// Backlog is below the caller's threshold.
+    Proceed,
+    /// Backlog is at or above the threshold; submit after a delay.
+    Delay,
+    /// Backlog already covers a full epoch of agent throughput.
+    Hold,
+}
+
+/// Usage counters copied from `BookKeeper` by `PublishStats`, one PDA per epoch
+/// at `["stats", epoch.to_le_bytes()]`. Request totals aren't kept on chain, as
+/// counting them would make the bookkeeper writable in every `SendRequest`;
+/// count the `RequestSubmitted` events, or sum `RequestData::sequence`.
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StatsSnapshot {
+    pub epoch: u64,
+    /// Slot of the latest publish within `epoch`.
+    pub slot: u64,
+    pub published_at: i64,
+    pub client_count: u16,
     pub next_id: u8,
+    pub last_heartbeat_slot: u64,
+}
+
+/// Exclusive write access to a bucket, one PDA per bucket at
+/// `["lease", sha256(bucket)]` (see `find_lease_pda`). An all-zero account is
+/// a lease that has already expired.
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BucketLease {
+    /// Requester key of the holder. Client ids are recycled, so the lease
+    /// follows the key, and a client removed while holding it keeps it until it
+    /// expires rather than passing it on to the next one registered.
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::pubkey_serde"))]
+    pub holder: Pubkey,
+    /// First slot at which the lease no longer applies.
+    pub expiry_slot: u64,
+}
+
+impl BucketLease {
+    /// Whether the lease keeps `requester` from writing the bucket at `slot`.
+    pub fn blocks(&self, requester: &Pubkey, slot: u64) -> bool {
+        self.holder != *requester && slot < self.expiry_slot
+    }
+}
+
+/// What a client may do, one PDA per client at `["client_info", owner]` (see
+/// `find_client_info_pda`). Created by the client's first `RegisterClient` and
+/// kept when it is removed, so registering again doesn't reset its permissions.
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClientInfo {
+    /// Set by `RegisterClient`; stored in the account header.
+    pub is_initialized: bool,
+    /// Key the PDA is derived from.
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::pubkey_serde"))]
+    pub owner: Pubkey,
+    /// `PERMISSION_*` bits of the request kinds the client may send (see
+    /// `Request::required_permission`); `RegisterClient` grants `ALL_PERMISSIONS`
+    /// and the admin changes them with `SetClientPermissions`.
+    pub permissions: u8,
+    /// Bump of the account's PDA, stored by `RegisterClient`.
+    pub bump: u8,
+}
+
+impl ClientInfo {
+    /// Whether the client may send `request`.
+    pub fn permits(&self, request: &Request) -> bool {
+        let required = request.required_permission();
+        self.permissions & required == required
+    }
+}
+
+/// Payload of the event logged by `GetVersion`, emitted with `sol_log_data`.
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Capabilities {
+    pub version: String,
+    pub wire_format_version: u8,
+    pub request_kinds: u8,
+    pub feature_flags: u32,
+}
+
+impl Capabilities {
+    pub fn new(bookkeeper: &BookKeeper) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            wire_format_version: WIRE_FORMAT_VERSION,
+            request_kinds: REQUEST_KINDS,
+            feature_flags: bookkeeper.feature_flags(),
+        }
+    }
+
+    /// Decodes the `Program data:` entry logged by `GetVersion` (already base64-decoded).
+    pub fn from_log_data(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::try_from_slice(data).map_err(|_| ProgramError::InvalidAccountData)
+    }
 }
 
 #[repr(C)]
 #[derive(Clone, BorshSerialize, BorshDeserialize, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RequestData {
+    /// Set by `RegisterClient`; stored in the account header.
+    pub is_initialized: bool,
     pub client_id: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::pubkey_serde"))]
     pub requester: Pubkey,
+    /// Packed as the `CANCELLED_TAG` sentinel while `status` is `Cancelled`, and
+    /// unpacked from it as `RequestData::CANCELLED_REQUEST`.
     pub request: Request,
+    /// Best-effort write pointer of the last file touched through this account.
+    pub position: u64,
+    /// Highest offset reached by a write since the file was created.
+    pub max_written_offset: u64,
+    /// Whether the file was created with the sparse flag, exempting it from seek checks.
+    pub sparse: bool,
+    /// Set by the agent once `response` or `response_data` holds the answer to `request`.
+    pub response_ready: bool,
+    /// Result area written by the agent for query requests such as `StatFile`.
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::data_serde"))]
+    pub response: [u8; RESPONSE_LEN],
+    /// Client-supplied reference of the submission that stored `request`.
+    pub submission_ref: [u8; 32],
+    /// Number of requests stored through this account; the latest one has this sequence.
+    pub sequence: u64,
+    /// Cluster unix timestamp at which `request` was stored.
+    pub submitted_at: i64,
+    /// Progress of `request` as reported by the agent.
+    pub status: RequestStatus,
+    /// Number of meaningful bytes in `response_data`.
+    pub response_len: u16,
+    /// Payload written by the agent with `WriteResponse`, e.g. the contents for `ReadFile`.
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::data_serde"))]
+    pub response_data: [u8; 512],
+    /// Nonce of the last request submitted with `SubmitSignedRequest`; each
+    /// envelope must carry a higher one.
+    pub nonce: u64,
+    /// Bump of the account's PDA, stored by `RegisterClient`.
+    pub bump: u8,
+    /// Requests stored since the authority last sent `ResetEpochCounter`;
+    /// `sequence` keeps the all-time count.
+    pub requests_this_epoch: u64,
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RequestStatus {
+    Pending = 0,
+    Processing = 1,
+    Completed = 2,
+    Failed = 3,
+    /// Withdrawn by the client with `CancelRequest` before the agent picked it up.
+    Cancelled = 4,
+}
+
+impl RequestStatus {
+    pub fn from_u8(value: u8) -> Result<Self, ProgramError> {
+        Ok(match value {
+            0 => Self::Pending,
+            1 => Self::Processing,
+            2 => Self::Completed,
+            3 => Self::Failed,
+            4 => Self::Cancelled,
+            _ => return Err(ProgramError::InvalidAccountData),
+        })
+    }
+
+    /// Whether the agent may move a request from `self` to `next`. Requests only move
+    /// forward, and `Completed`/`Failed` are final until the next `SendRequest`.
+    /// Only the client can cancel a request, see `can_cancel`.
+    pub fn can_transition_to(self, next: Self) -> bool {
+        matches!(
+            (self, next),
+            (Self::Pending, Self::Processing)
+                | (Self::Pending, Self::Completed)
+                | (Self::Pending, Self::Failed)
+                | (Self::Processing, Self::Completed)
+                | (Self::Processing, Self::Failed)
+        )
+    }
+
+    /// Whether `WriteResponse` may leave a request in `next` when it is in `self`.
+    /// Besides the moves `can_transition_to` allows, a request that isn't finished
+    /// may keep its status, so the agent can overwrite a partial response.
+    pub fn can_respond_with(self, next: Self) -> bool {
+        (self == next && !self.is_final()) || self.can_transition_to(next)
+    }
+
+    /// Whether `CancelRequest` may withdraw a request in `self`: only one the agent
+    /// hasn't started on.
+    pub fn can_cancel(self) -> bool {
+        self == Self::Pending
+    }
+
+    /// Whether the request is over until the next `SendRequest`.
+    pub fn is_final(self) -> bool {
+        matches!(self, Self::Completed | Self::Failed | Self::Cancelled)
+    }
+}
+
+/// Bytes in front of every `BookKeeper`, `RequestData` and `ClientInfo` account: the
+/// `AccountType`, then the layout version. Both are zero until the account is set up.
+pub const ACCOUNT_HEADER_LEN: usize = 2;
+
+/// Layout version written in the header of initialized accounts. The byte was
+/// a plain initialized flag before, so accounts written in the older layouts
+/// listed in `migration` carry 1 too and are told apart by their length;
+/// `MigrateAccount` rewrites them in the current layout.
+pub const ACCOUNT_VERSION: u8 = 1;
+
+/// First byte of an initialized state account, so that one kind of account
+/// can't be passed off as another.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AccountType {
+    Uninitialized = 0,
+    BookKeeper = 1,
+    RequestData = 2,
+    ClientInfo = 3,
+}
+
+//splits off the header, returning whether the account is initialized and its body;
+//accounts of a later version were written by a newer program and are rejected
+fn unpack_header(src: &[u8], account_type: AccountType) -> Result<(bool, &[u8]), ProgramError> {
+    let (header, body) = src.split_at(ACCOUNT_HEADER_LEN);
+    match *header {
+        [0, 0] => Ok((false, body)),
+        [kind, ACCOUNT_VERSION] if kind == account_type as u8 => Ok((true, body)),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+fn pack_header(dst: &mut [u8], account_type: AccountType, is_initialized: bool) -> &mut [u8] {
+    let (header, body) = dst.split_at_mut(ACCOUNT_HEADER_LEN);
+    if is_initialized {
+        header.copy_from_slice(&[account_type as u8, ACCOUNT_VERSION]);
+    } else {
+        header.fill(0);
+    }
+    body
+}
+
+//offsets of the packed fields, after the account header
+impl BookKeeper {
+    const STATUS_OFFSET: usize = 0;
+    const NEXT_ID_OFFSET: usize = Self::STATUS_OFFSET + 32;
+    const AUTHORITY_OFFSET: usize = Self::NEXT_ID_OFFSET + 1;
+    const PREFIXES_OFFSET: usize = Self::AUTHORITY_OFFSET + 32;
+    const SUPPORTED_KINDS_OFFSET: usize = Self::PREFIXES_OFFSET + PREFIX_LEN * RESERVED_PREFIXES;
+    const AGENT_OFFSET: usize = Self::SUPPORTED_KINDS_OFFSET + 4;
+    const FREE_SLOTS_OFFSET: usize = Self::AGENT_OFFSET + 32;
+    const NAME_POLICY_OFFSET: usize = Self::FREE_SLOTS_OFFSET + 32;
+    const MAX_REQUESTS_OFFSET: usize = Self::NAME_POLICY_OFFSET + 1;
+    const BACKLOG_OFFSET: usize = Self::MAX_REQUESTS_OFFSET + 4;
+    const PAUSED_OFFSET: usize = Self::BACKLOG_OFFSET + 2;
+    const BUMP_OFFSET: usize = Self::PAUSED_OFFSET + 1;
+    const AGENTS_OFFSET: usize = Self::BUMP_OFFSET + 1;
+    const CLIENT_LIMIT_OFFSET: usize = Self::AGENTS_OFFSET + 32 * MAX_AGENTS;
+    const HEARTBEAT_OFFSET: usize = Self::CLIENT_LIMIT_OFFSET + 8;
+    const ADMIN_OFFSET: usize = Self::HEARTBEAT_OFFSET + 8;
+}
+
+//the `N` bytes of `src` at `offset`
+fn array<const N: usize>(src: &[u8], offset: usize) -> [u8; N] {
+    src[offset..offset + N].try_into().unwrap()
+}
+
+//copies `bytes` into `dst` at `offset`
+fn put(dst: &mut [u8], offset: usize, bytes: &[u8]) {
+    dst[offset..offset + bytes.len()].copy_from_slice(bytes);
 }
 
+//a field added to the struct but not to LEN fails the build here
+const _: () = assert!(
+    BookKeeper::LEN
+        == size_of::<AccountType>()
+            + size_of::<bool>()
+            + size_of::<[u8; 32]>()
+            + size_of::<u8>()
+            + size_of::<Pubkey>()
+            + size_of::<[[u8; PREFIX_LEN]; RESERVED_PREFIXES]>()
+            + size_of::<u32>()
+            + size_of::<Pubkey>()
+            + size_of::<[u8; 32]>()
+            + size_of::<u8>()
+            + size_of::<u32>()
+            + size_of::<u16>()
+            + size_of::<bool>()
+            + size_of::<u8>()
+            + size_of::<[Pubkey; MAX_AGENTS]>()
+            + size_of::<u64>()
+            + size_of::<u64>()
+            + size_of::<Pubkey>()
+);
+
 impl Sealed for BookKeeper {}
 
+impl IsInitialized for BookKeeper {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
 impl Pack for BookKeeper {
-    const LEN: usize = 33;
+    const LEN: usize = ACCOUNT_HEADER_LEN + Self::ADMIN_OFFSET + 32;
 
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-        if src.len() < 33 {
+        if src.len() != BookKeeper::LEN {
             return Err(ProgramError::InvalidAccountData);
         }
+        let (is_initialized, src) = unpack_header(src, AccountType::BookKeeper)?;
 
-        let status: [u8; 32] = src[..32].try_into().unwrap();
-        let next_id = u8::from_le_bytes(src[32..33].try_into().unwrap())
-            .try_into()
-            .unwrap();
+        let mut reserved_prefixes = [[0u8; PREFIX_LEN]; RESERVED_PREFIXES];
+        for (prefix, bytes) in reserved_prefixes
+            .iter_mut()
+            .zip(src[Self::PREFIXES_OFFSET..Self::SUPPORTED_KINDS_OFFSET].chunks_exact(PREFIX_LEN))
+        {
+            prefix.copy_from_slice(bytes);
+        }
+
+        let mut agents = [Pubkey::default(); MAX_AGENTS];
+        for (i, agent) in agents.iter_mut().enumerate() {
+            *agent = Pubkey::new_from_array(array(src, Self::AGENTS_OFFSET + i * 32));
+        }
 
-        Ok(Self { status, next_id })
+        Ok(Self {
+            is_initialized,
+            status: array(src, Self::STATUS_OFFSET),
+            next_id: src[Self::NEXT_ID_OFFSET],
+            authority: Pubkey::new_from_array(array(src, Self::AUTHORITY_OFFSET)),
+            reserved_prefixes,
+            supported_kinds: u32::from_le_bytes(array(src, Self::SUPPORTED_KINDS_OFFSET)),
+            agent: Pubkey::new_from_array(array(src, Self::AGENT_OFFSET)),
+            free_slots: array(src, Self::FREE_SLOTS_OFFSET),
+            name_policy: src[Self::NAME_POLICY_OFFSET],
+            max_requests_per_epoch: u32::from_le_bytes(array(src, Self::MAX_REQUESTS_OFFSET)),
+            current_backlog: u16::from_le_bytes(array(src, Self::BACKLOG_OFFSET)),
+            paused: src[Self::PAUSED_OFFSET] != 0,
+            bump: src[Self::BUMP_OFFSET],
+            agents,
+            max_client_requests_per_epoch: u64::from_le_bytes(array(
+                src,
+                Self::CLIENT_LIMIT_OFFSET,
+            )),
+            last_heartbeat_slot: u64::from_le_bytes(array(src, Self::HEARTBEAT_OFFSET)),
+            admin: Pubkey::new_from_array(array(src, Self::ADMIN_OFFSET)),
+        })
     }
 
     fn pack_into_slice(&self, dst: &mut [u8]) {
-        let status = self.status;
-        let next_id = self.next_id.to_le_bytes();
+        let dst = pack_header(dst, AccountType::BookKeeper, self.is_initialized);
+
+        put(dst, Self::STATUS_OFFSET, &self.status);
+        dst[Self::NEXT_ID_OFFSET] = self.next_id;
+        put(dst, Self::AUTHORITY_OFFSET, self.authority.as_ref());
+        for (i, prefix) in self.reserved_prefixes.iter().enumerate() {
+            put(dst, Self::PREFIXES_OFFSET + i * PREFIX_LEN, prefix);
+        }
+        put(
+            dst,
+            Self::SUPPORTED_KINDS_OFFSET,
+            &self.supported_kinds.to_le_bytes(),
+        );
+        put(dst, Self::AGENT_OFFSET, self.agent.as_ref());
+        put(dst, Self::FREE_SLOTS_OFFSET, &self.free_slots);
+        dst[Self::NAME_POLICY_OFFSET] = self.name_policy;
+        put(
+            dst,
+            Self::MAX_REQUESTS_OFFSET,
+            &self.max_requests_per_epoch.to_le_bytes(),
+        );
+        put(
+            dst,
+            Self::BACKLOG_OFFSET,
+            &self.current_backlog.to_le_bytes(),
+        );
+        dst[Self::PAUSED_OFFSET] = self.paused as u8;
+        dst[Self::BUMP_OFFSET] = self.bump;
+        for (i, agent) in self.agents.iter().enumerate() {
+            put(dst, Self::AGENTS_OFFSET + i * 32, agent.as_ref());
+        }
+        put(
+            dst,
+            Self::CLIENT_LIMIT_OFFSET,
+            &self.max_client_requests_per_epoch.to_le_bytes(),
+        );
+        put(
+            dst,
+            Self::HEARTBEAT_OFFSET,
+            &self.last_heartbeat_slot.to_le_bytes(),
+        );
+        put(dst, Self::ADMIN_OFFSET, self.admin.as_ref());
+    }
+}
+
+impl BookKeeper {
+    /// Whether `name` starts with one of the reserved prefixes.
+    pub fn is_reserved(&self, name: &[u8]) -> bool {
+        self.reserved_prefixes
+            .iter()
+            .any(|prefix| has_prefix(name, prefix))
+    }
+
+    /// Whether `key` may sign agent-only instructions: the `agent` or one of the
+    /// `agents` registered with `AddAgent`.
+    pub fn is_agent(&self, key: &Pubkey) -> bool {
+        *key == self.agent || (*key != Pubkey::default() && self.agents.contains(key))
+    }
+
+    /// Whether the agent has acknowledged requests of `kind`.
+    pub fn supports(&self, kind: u8) -> bool {
+        kind < 32 && self.supported_kinds & (1 << kind) != 0
+    }
+
+    /// Marks `id` as registered, taking it out of `free_slots`.
+    pub fn register_client(&mut self, id: u8) {
+        set_bit(&mut self.status, id);
+        clear_bit(&mut self.free_slots, id);
+    }
+
+    /// Marks `id` as no longer registered and adds it to `free_slots`.
+    pub fn unregister_client(&mut self, id: u8) {
+        clear_bit(&mut self.status, id);
+        set_bit(&mut self.free_slots, id);
+    }
+
+    pub fn is_registered(&self, id: u8) -> bool {
+        bit(&self.status, id)
+    }
+
+    /// Registered client ids in ascending order.
+    pub fn registered_clients(&self) -> impl Iterator<Item = u8> + '_ {
+        self.status
+            .iter()
+            .enumerate()
+            .filter(|(_, &byte)| byte != 0)
+            .flat_map(|(byte_index, &byte)| {
+                (0..8u8)
+                    .filter(move |bit_offset| byte >> bit_offset & 1 == 1)
+                    .map(move |bit_offset| byte_index as u8 * 8 + bit_offset)
+            })
+    }
+
+    /// Number of client ids currently registered.
+    pub fn count(&self) -> usize {
+        self.status.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    /// Client id the next registration takes: the lowest one `RemoveClient` put in
+    /// `free_slots`, else the lowest never handed out; `None` once all 256 are
+    /// registered.
+    pub fn first_free(&self) -> Option<u8> {
+        first_set_bit(&self.free_slots).or_else(|| first_free(&self.status))
+    }
+
+    /// `count` as a `u16`.
+    pub fn client_count(&self) -> u16 {
+        self.count() as u16
+    }
+
+    /// `client_count` as a `u8`, saturating at 255 once all 256 ids are taken.
+    pub fn count_active_clients(&self) -> u8 {
+        self.client_count().min(u8::MAX.into()) as u8
+    }
+
+    /// Registered client ids in ascending order; same as `registered_clients`.
+    pub fn iter_active_clients(&self) -> impl Iterator<Item = u8> + '_ {
+        self.registered_clients()
+    }
 
-        for i in 0..32 {
-            dst[i] = status[i];
+    /// Advice for a client that wants to keep the agent's backlog below `threshold`.
+    pub fn throttle_advice(&self, threshold: u16) -> ThrottleAdvice {
+        if self.current_backlog < threshold {
+            ThrottleAdvice::Proceed
+        } else if self.max_requests_per_epoch == 0
+            || u32::from(self.current_backlog) < self.max_requests_per_epoch
+        {
+            ThrottleAdvice::Delay
+        } else {
+            ThrottleAdvice::Hold
         }
+    }
 
-        for i in 32..33 {
-            dst[i] = next_id[0];
+    /// Bitmask of optional behaviours enabled on this deployment, made of the
+    /// `FEATURE_*` bits.
+    pub fn feature_flags(&self) -> u32 {
+        let mut flags = 0;
+        if self.paused {
+            flags |= FEATURE_PAUSED;
+        }
+        if self.name_policy != NamePolicyKind::Default as u8 {
+            flags |= FEATURE_CUSTOM_NAME_POLICY;
         }
+        flags
     }
 }
 
-impl Sealed for RequestData {}
+impl<'a> IntoIterator for &'a BookKeeper {
+    type Item = u8;
+    type IntoIter = Box<dyn Iterator<Item = u8> + 'a>;
 
-impl Pack for RequestData {
-    const LEN: usize = 1 + 32 + 1 + 128 + 1 + 512;
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter_active_clients())
+    }
+}
+
+impl Sealed for StatsSnapshot {}
+
+impl Pack for StatsSnapshot {
+    const LEN: usize = 8 + 8 + 8 + 2 + 1 + 8;
 
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-        if src.len() < RequestData::LEN {
+        if src.len() != StatsSnapshot::LEN {
             return Err(ProgramError::InvalidAccountData);
         }
 
-        let client_id = u8::from_le_bytes(src[..1].try_into().unwrap())
-            .try_into()
-            .unwrap();
-        let requester = Pubkey::new(&src[1..33]);
-        let request_bytes = &src[33..];
+        Ok(Self {
+            epoch: u64::from_le_bytes(src[0..8].try_into().unwrap()),
+            slot: u64::from_le_bytes(src[8..16].try_into().unwrap()),
+            published_at: i64::from_le_bytes(src[16..24].try_into().unwrap()),
+            client_count: u16::from_le_bytes(src[24..26].try_into().unwrap()),
+            next_id: src[26],
+            last_heartbeat_slot: u64::from_le_bytes(src[27..35].try_into().unwrap()),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        dst[0..8].copy_from_slice(&self.epoch.to_le_bytes());
+        dst[8..16].copy_from_slice(&self.slot.to_le_bytes());
+        dst[16..24].copy_from_slice(&self.published_at.to_le_bytes());
+        dst[24..26].copy_from_slice(&self.client_count.to_le_bytes());
+        dst[26] = self.next_id;
+        dst[27..35].copy_from_slice(&self.last_heartbeat_slot.to_le_bytes());
+    }
+}
 
-        let request = unpack_request(request_bytes)?;
+impl Sealed for ClientInfo {}
+
+impl IsInitialized for ClientInfo {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for ClientInfo {
+    const LEN: usize = ACCOUNT_HEADER_LEN + 32 + 1 + 1;
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        if src.len() != ClientInfo::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let (is_initialized, src) = unpack_header(src, AccountType::ClientInfo)?;
 
         Ok(Self {
-            client_id,
-            requester,
-            request,
+            is_initialized,
+            owner: Pubkey::new_from_array(array(src, 0)),
+            permissions: src[32],
+            bump: src[33],
         })
     }
 
     fn pack_into_slice(&self, dst: &mut [u8]) {
-        let client_id = self.client_id;
-        let requester = self.requester.to_bytes();
+        let dst = pack_header(dst, AccountType::ClientInfo, self.is_initialized);
 
-        dst[0] = client_id;
+        put(dst, 0, self.owner.as_ref());
+        dst[32] = self.permissions;
+        dst[33] = self.bump;
+    }
+}
 
-        for i in 1..33 {
-            dst[i] = requester[i - 1]
-        }
+impl Sealed for BucketLease {}
 
-        match &self.request {
-            Request::CreateBucket { name } => {
-                dst[33] = 0;
-                for i in 34..162 {
-                    dst[i] = name[i - 34];
-                }
-            }
-            Request::CreateFile { name, data } => {
-                dst[33] = 1;
-                for i in 34..162 {
-                    dst[i] = name[i - 34];
-                }
-                dst[162] = 0;
-                for i in 163..675 {
-                    dst[i] = data[i - 163]
-                }
-            }
-            Request::WriteFile {
-                name,
-                file_id,
-                data,
-            } => {
-                dst[33] = 2;
-                for i in 34..162 {
-                    dst[i] = name[i - 34];
-                }
-                dst[162] = *file_id;
-                for i in 163..675 {
-                    dst[i] = data[i - 163]
-                }
-            }
-            Request::CloseFile { name, file_id } => {
-                dst[33] = 3;
-                for i in 34..162 {
-                    dst[i] = name[i - 34];
-                }
-                dst[162] = *file_id;
-            }
-            Request::DeleteFile { name, file_id } => {
-                dst[33] = 4;
-                for i in 34..162 {
-                    dst[i] = name[i - 34];
-                }
-                dst[162] = *file_id;
-            }
-            Request::SetPosition {
-                name,
-                file_id,
-                position,
-            } => {
-                dst[33] = 5;
-                for i in 34..162 {
-                    dst[i] = name[i - 34];
-                }
-                dst[162] = *file_id;
-
-                let position_bytes = position.to_le_bytes();
-
-                for i in 163..171 {
-                    dst[i] = position_bytes[i - 163]
-                }
-            }
-            Request::OpenFile { name, file_id } => {
-                dst[33] = 6;
-                for i in 34..162 {
-                    dst[i] = name[i - 34];
-                }
-                dst[162] = *file_id;
-            }
-            Request::ReadFile { name, file_id } => {
-                dst[33] = 7;
-                for i in 34..162 {
-                    dst[i] = name[i - 34];
-                }
-                dst[162] = *file_id;
-            }
+impl Pack for BucketLease {
+    const LEN: usize = 32 + 8;
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        if src.len() != BucketLease::LEN {
+            return Err(ProgramError::InvalidAccountData);
         }
+
+        Ok(Self {
+            holder: Pubkey::new_from_array(src[0..32].try_into().unwrap()),
+            expiry_slot: u64::from_le_bytes(src[32..40].try_into().unwrap()),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        dst[0..32].copy_from_slice(self.holder.as_ref());
+        dst[32..40].copy_from_slice(&self.expiry_slot.to_le_bytes());
     }
 }
 
-pub fn addel(src: &mut [u8; 32], element: u8) {
-    let byte_index = element / 8;
-    let bit_offset = element % 8;
-    src[byte_index as usize] |= 1 << bit_offset;
+//`request` is enum-encoded, so it counts as its largest encoding
+const _: () = assert!(
+    RequestData::LEN
+        == size_of::<AccountType>()
+            + size_of::<bool>()
+            + size_of::<u8>()
+            + size_of::<Pubkey>()
+            + ENCODED_REQUEST_MAX_LEN
+            + size_of::<u64>()
+            + size_of::<u64>()
+            + size_of::<bool>()
+            + size_of::<bool>()
+            + size_of::<[u8; RESPONSE_LEN]>()
+            + size_of::<[u8; 32]>()
+            + size_of::<u64>()
+            + size_of::<i64>()
+            + size_of::<RequestStatus>()
+            + size_of::<u16>()
+            + size_of::<[u8; 512]>()
+            + size_of::<u64>()
+            + size_of::<u8>()
+            + size_of::<u64>()
+);
+const _: () = assert!(RequestData::LEN >= ACCOUNT_HEADER_LEN + 1 + 32 + ENCODED_REQUEST_MAX_LEN);
+
+impl Sealed for RequestData {}
+
+impl IsInitialized for RequestData {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
 }
 
-pub fn isel(src: [u8; 32], element: u8) -> bool {
-    let byte_index = element / 8;
-    let bit_offset = element % 8;
-    let value = (src[byte_index as usize] >> bit_offset) & 1;
+//offsets of the packed fields, after the account header
+impl RequestData {
+    const CLIENT_ID_OFFSET: usize = 0;
+    const REQUESTER_OFFSET: usize = Self::CLIENT_ID_OFFSET + 1;
+    const REQUEST_OFFSET: usize = Self::REQUESTER_OFFSET + 32;
+    const POSITION_OFFSET: usize = Self::REQUEST_OFFSET + ENCODED_REQUEST_MAX_LEN;
+    const MAX_WRITTEN_OFFSET_OFFSET: usize = Self::POSITION_OFFSET + 8;
+    const SPARSE_OFFSET: usize = Self::MAX_WRITTEN_OFFSET_OFFSET + 8;
+    const RESPONSE_READY_OFFSET: usize = Self::SPARSE_OFFSET + 1;
+    const RESPONSE_OFFSET: usize = Self::RESPONSE_READY_OFFSET + 1;
+    const SUBMISSION_REF_OFFSET: usize = Self::RESPONSE_OFFSET + RESPONSE_LEN;
+    const SEQUENCE_OFFSET: usize = Self::SUBMISSION_REF_OFFSET + 32;
+    const SUBMITTED_AT_OFFSET: usize = Self::SEQUENCE_OFFSET + 8;
+    const STATUS_OFFSET: usize = Self::SUBMITTED_AT_OFFSET + 8;
+    const RESPONSE_LEN_OFFSET: usize = Self::STATUS_OFFSET + 1;
+    const RESPONSE_DATA_OFFSET: usize = Self::RESPONSE_LEN_OFFSET + 2;
+    const NONCE_OFFSET: usize = Self::RESPONSE_DATA_OFFSET + 512;
+    const BUMP_OFFSET: usize = Self::NONCE_OFFSET + 8;
+    const REQUESTS_THIS_EPOCH_OFFSET: usize = Self::BUMP_OFFSET + 1;
+}
+
+impl RequestData {
+    /// `request` of a cancelled request, read back from the sentinel. It is what
+    /// the all-zero area of a fresh account holds too.
+    pub const CANCELLED_REQUEST: Request = Request::CreateBucket { name: [0; 128] };
+}
+
+impl Pack for RequestData {
+    const LEN: usize = ACCOUNT_HEADER_LEN + Self::REQUESTS_THIS_EPOCH_OFFSET + 8;
+
+    //the fields are Borsh encoded in declaration order, except that the header
+    //holds `is_initialized` and `request` is zero padded to a fixed-size area
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        if src.len() != RequestData::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let (is_initialized, src) = unpack_header(src, AccountType::RequestData)?;
+        let (mut head, rest) = src.split_at(Self::REQUEST_OFFSET);
+        let (area, mut tail) = rest.split_at(ENCODED_REQUEST_MAX_LEN);
+        let cancelled = area[0] == CANCELLED_TAG;
+        let request = match cancelled {
+            true if area[1..].iter().all(|&byte| byte == 0) => Self::CANCELLED_REQUEST,
+            true => return Err(ProgramError::InvalidAccountData),
+            false => unpack_stored_request(area)?,
+        };
+
+        let data = Self {
+            is_initialized,
+            client_id: read(&mut head)?,
+            requester: read(&mut head)?,
+            request,
+            position: read(&mut tail)?,
+            max_written_offset: read(&mut tail)?,
+            sparse: read(&mut tail)?,
+            response_ready: read(&mut tail)?,
+            response: read(&mut tail)?,
+            submission_ref: read(&mut tail)?,
+            sequence: read(&mut tail)?,
+            submitted_at: read(&mut tail)?,
+            status: read(&mut tail)?,
+            response_len: read(&mut tail)?,
+            response_data: read(&mut tail)?,
+            nonce: read(&mut tail)?,
+            bump: read(&mut tail)?,
+            requests_this_epoch: read(&mut tail)?,
+        };
+        //only `CancelRequest` writes the sentinel
+        if cancelled && data.status != RequestStatus::Cancelled {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(data)
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = pack_header(dst, AccountType::RequestData, self.is_initialized);
+        //requests shorter than the previous one must not leave its bytes behind
+        dst.fill(0);
+        let (mut head, rest) = dst.split_at_mut(Self::REQUEST_OFFSET);
+        let (area, mut tail) = rest.split_at_mut(ENCODED_REQUEST_MAX_LEN);
 
-    if value == 1 {
-        return true;
+        write(&mut head, &(self.client_id, self.requester));
+        if self.status == RequestStatus::Cancelled {
+            area[0] = CANCELLED_TAG;
+        } else {
+            pack_request(&self.request, area).expect("the request area fits every request");
+        }
+        write(
+            &mut tail,
+            &(
+                self.position,
+                self.max_written_offset,
+                self.sparse,
+                self.response_ready,
+                self.response,
+                self.submission_ref,
+                self.sequence,
+                self.submitted_at,
+                self.status,
+                self.response_len,
+                self.response_data,
+                self.nonce,
+                self.bump,
+                self.requests_this_epoch,
+            ),
+        );
     }
-    false
 }
 
-pub fn delel(src: &mut [u8; 32], element: u8) {
-    let byte_index = element / 8;
-    let bit_offset = element % 8;
-    src[byte_index as usize] &= !(1 << bit_offset);
+//Borsh decodes the next field of a packed account
+fn read<T: BorshDeserialize>(src: &mut &[u8]) -> Result<T, ProgramError> {
+    T::deserialize(src).map_err(|_| ProgramError::InvalidAccountData)
 }
 
-pub fn validate_name(name: &[u8]) -> bool {
-    if name.len() > 128 as usize {
-        return false;
+//Borsh encodes fields into a packed account, whose length was checked by `Pack`
+fn write(dst: &mut &mut [u8], value: &impl BorshSerialize) {
+    value.serialize(dst).expect("the account fits every field");
+}
+
+/// Byte of a compact request account where the full layout stores the request
+/// tag, which is always lower.
+pub const COMPACT_MARKER: u8 = 0xff;
+
+/// Request tag that `CancelRequest` overwrites the stored request with, so the
+/// agent can't act on it. 255, the first tag no request uses, is taken by
+/// `COMPACT_MARKER`.
+pub const CANCELLED_TAG: u8 = 0xfe;
+
+/// Size of a request account created before the account header; such accounts
+/// are read as initialized and keep their headerless layout when written.
+pub const LEGACY_REQUEST_LEN: usize = RequestData::LEN - ACCOUNT_HEADER_LEN;
+
+//the request area of the full layout, which the compact layout stores trimmed
+const REQUEST_AREA_START: usize = ACCOUNT_HEADER_LEN + RequestData::REQUEST_OFFSET;
+const REQUEST_AREA_END: usize = REQUEST_AREA_START + ENCODED_REQUEST_MAX_LEN;
+const TAIL_LEN: usize = RequestData::LEN - REQUEST_AREA_END;
+
+/// Size of a compact request account holding an all-zero request: the full
+/// layout without its request area, plus the marker and a u16 request length.
+/// Compact accounts store their fields in full-layout order, except that the
+/// request moves to the end and keeps only its bytes up to the last non-zero one.
+pub const COMPACT_BASE_LEN: usize = RequestData::LEN - (REQUEST_AREA_END - REQUEST_AREA_START) + 3;
+
+impl RequestData {
+    /// Whether `src` is a request account using the compact layout.
+    pub fn is_compact(src: &[u8]) -> bool {
+        src.get(REQUEST_AREA_START) == Some(&COMPACT_MARKER)
+    }
+
+    //a legacy account has a name byte where the marker goes, and names never hold 0xff
+    fn is_legacy(src: &[u8]) -> bool {
+        src.len() == LEGACY_REQUEST_LEN && !Self::is_compact(src)
+    }
+
+    /// Turns a freshly created compact-sized account into an empty compact account.
+    pub fn mark_compact(dst: &mut [u8]) {
+        dst[REQUEST_AREA_START] = COMPACT_MARKER;
     }
 
-    let non_zero_bytes: Vec<u8> = name.iter().take_while(|&b| *b != 0).copied().collect();
+    /// Unpacks a request account in any layout.
+    pub fn unpack_account(src: &[u8]) -> Result<Self, ProgramError> {
+        Self::unpack_unchecked(&Self::full_bytes(src)?)
+    }
 
-    for b in non_zero_bytes {
-        if !(b >= b'a' && b <= b'z')
-            && !(b >= b'A' && b <= b'Z')
-            && !(b >= b'0' && b <= b'9')
-            && !(b == b'.' || b == b'/' || b == b'_' || b == b'+' || b == b'-')
+    /// The bytes of a request account in any layout, rearranged into the full layout.
+    pub fn full_bytes(src: &[u8]) -> Result<Cow<'_, [u8]>, ProgramError> {
+        if Self::is_legacy(src) {
+            let mut full = vec![AccountType::RequestData as u8, ACCOUNT_VERSION];
+            full.extend_from_slice(src);
+            return Ok(Cow::Owned(full));
+        }
+        if !Self::is_compact(src) {
+            return Ok(Cow::Borrowed(src));
+        }
+        let len = src
+            .get(COMPACT_BASE_LEN - 2..COMPACT_BASE_LEN)
+            .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()) as usize)
+            .filter(|&len| len <= REQUEST_AREA_END - REQUEST_AREA_START)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        let request = src
+            .get(COMPACT_BASE_LEN..COMPACT_BASE_LEN + len)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        //the stored request ends at its last non-zero byte, so anything else means
+        //the length itself is off
+        if request.last() == Some(&0) || src[COMPACT_BASE_LEN + len..].iter().any(|&byte| byte != 0)
         {
-            return false;
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let mut full = vec![0u8; Self::LEN];
+        full[..REQUEST_AREA_START].copy_from_slice(&src[..REQUEST_AREA_START]);
+        full[REQUEST_AREA_START..REQUEST_AREA_START + len].copy_from_slice(request);
+        full[REQUEST_AREA_END..]
+            .copy_from_slice(&src[REQUEST_AREA_START + 1..REQUEST_AREA_START + 1 + TAIL_LEN]);
+        Ok(Cow::Owned(full))
+    }
+
+    /// Packs into a request account, keeping the layout it already uses. A compact
+    /// account has to be grown to `compact_len` first.
+    pub fn pack_account(self, dst: &mut [u8]) -> Result<(), ProgramError> {
+        if Self::is_legacy(dst) {
+            dst.copy_from_slice(&self.full_layout()[ACCOUNT_HEADER_LEN..]);
+            return Ok(());
+        }
+        if !Self::is_compact(dst) {
+            return Self::pack(self, dst);
         }
+        let full = self.full_layout();
+        let len = used_request_len(&full);
+        if dst.len() < COMPACT_BASE_LEN + len {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+
+        dst[..REQUEST_AREA_START].copy_from_slice(&full[..REQUEST_AREA_START]);
+        dst[REQUEST_AREA_START] = COMPACT_MARKER;
+        dst[REQUEST_AREA_START + 1..REQUEST_AREA_START + 1 + TAIL_LEN]
+            .copy_from_slice(&full[REQUEST_AREA_END..]);
+        dst[COMPACT_BASE_LEN - 2..COMPACT_BASE_LEN].copy_from_slice(&(len as u16).to_le_bytes());
+        dst[COMPACT_BASE_LEN..COMPACT_BASE_LEN + len]
+            .copy_from_slice(&full[REQUEST_AREA_START..REQUEST_AREA_START + len]);
+        dst[COMPACT_BASE_LEN + len..].fill(0);
+        Ok(())
+    }
+
+    /// Size a compact account needs to hold `self`.
+    pub fn compact_len(&self) -> usize {
+        COMPACT_BASE_LEN + used_request_len(&self.full_layout())
     }
 
-    true
+    pub(crate) fn full_layout(&self) -> Vec<u8> {
+        let mut full = vec![0u8; Self::LEN];
+        self.pack_into_slice(&mut full);
+        full
+    }
 }
 
-#[cfg(test)]
-mod test {
+//length of the full layout's request area without its trailing zeros
+fn used_request_len(full: &[u8]) -> usize {
+    full[REQUEST_AREA_START..REQUEST_AREA_END]
+        .iter()
+        .rposition(|&b| b != 0)
+        .map_or(0, |i| i + 1)
+}
 
-    use super::*;
-    use super::{Request, RequestData};
-    #[test]
-    fn test_pack() {
-        let mut name: [u8; 128] = [0; 128];
-        let bytes = "test".as_bytes();
-        name[..bytes.len()].copy_from_slice(bytes);
+impl RequestData {
+    /// File size reported by the agent for a `StatFile` request.
+    pub fn stat_size(&self) -> u64 {
+        let bytes = &self.response[STAT_SIZE_OFFSET..STAT_SIZE_OFFSET + 8];
+        u64::from_le_bytes(bytes.try_into().unwrap())
+    }
 
-        let requester = Pubkey::new_unique();
-        print!("name {:?}", String::from_utf8(name.to_vec()).unwrap());
-        let src = RequestData {
-            client_id: 85,
-            requester: requester,
-            request: Request::CloseFile { name, file_id: 69 },
-        };
-        let mut dst = [0u8; 163];
-        println!("{:?}", src);
+    /// Entries written by the agent for a `ListFiles` request, as `(file_id, name)`.
+    /// Entries whose name is not valid UTF-8 are skipped.
+    pub fn list_entries(&self) -> impl Iterator<Item = (u8, &str)> + '_ {
+        let count = (self.response[LIST_COUNT_OFFSET] as usize).min(LIST_PAGE_LEN);
 
-        let res = RequestData::pack(src, &mut dst);
-        print!("packed {:?}", res.unwrap());
+        self.response[LIST_ENTRIES_OFFSET..]
+            .chunks_exact(LIST_ENTRY_LEN)
+            .take(count)
+            .filter_map(|entry| {
+                let (file_id, name) = entry.split_first().unwrap();
+                let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                core::str::from_utf8(&name[..len])
+                    .ok()
+                    .map(|name| (*file_id, name))
+            })
     }
-    #[test]
-    fn test_name_validation() {
-        let name = "test".as_bytes();
 
-        let bool = validate_name(&name);
+    /// Bytes written by the agent with `WriteResponse`.
+    pub fn response_bytes(&self) -> &[u8] {
+        &self.response_data[..(self.response_len as usize).min(512)]
+    }
 
-        assert!(bool);
+    /// `response_bytes`, or `None` until the agent has answered the current request.
+    pub fn response(&self) -> Option<&[u8]> {
+        self.response_ready.then(|| self.response_bytes())
+    }
+
+    /// Whether a request was sent and the agent hasn't acknowledged it yet, so a
+    /// `SendRequest` would fail with `RequestPending`.
+    pub fn is_pending(&self) -> bool {
+        self.sequence > 0 && self.status == RequestStatus::Pending
+    }
+
+    /// Whether the agent has acknowledged the current request and is working on it.
+    pub fn is_acknowledged(&self) -> bool {
+        self.sequence > 0 && self.status == RequestStatus::Processing
+    }
+
+    /// Answer to a `FileExists` request, or `None` until the agent has set `response_ready`.
+    pub fn exists_response(&self) -> Option<bool> {
+        if !self.response_ready {
+            return None;
+        }
+        Some(self.response[EXISTS_OFFSET] != 0)
+    }
+
+    /// Last write time reported by the agent for a `StatFile` request.
+    pub fn stat_mtime(&self) -> u64 {
+        let bytes = &self.response[STAT_MTIME_OFFSET..STAT_MTIME_OFFSET + 8];
+        u64::from_le_bytes(bytes.try_into().unwrap())
+    }
+
+    /// Updates the write pointer bookkeeping for `request` and rejects seeks that land
+    /// more than one chunk past anything written so far, unless the file is sparse.
+    pub fn track_position(&mut self, request: &Request) -> Result<(), ProgramError> {
+        match request {
+            Request::CreateFile { sparse, .. } => {
+                self.position = CHUNK_LEN;
+                self.max_written_offset = CHUNK_LEN;
+                self.sparse = *sparse;
+            }
+            Request::WriteByReference { .. } => {
+                self.position = self.position.checked_add(CHUNK_LEN).ok_or(Overflow)?;
+                self.max_written_offset = self.max_written_offset.max(self.position);
+            }
+            Request::AppendFile { .. } => {
+                self.position = self
+                    .max_written_offset
+                    .checked_add(CHUNK_LEN)
+                    .ok_or(Overflow)?;
+                self.max_written_offset = self.position;
+            }
+            Request::SetPosition { position, .. } => {
+                self.seek(*position)?;
+            }
+            Request::WriteFile { offset, .. } | Request::WriteAt { offset, .. } => {
+                self.seek(*offset)?;
+                self.position = self.position.checked_add(CHUNK_LEN).ok_or(Overflow)?;
+                self.max_written_offset = self.max_written_offset.max(self.position);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn seek(&mut self, position: u64) -> Result<(), ProgramError> {
+        let limit = self.max_written_offset.saturating_add(CHUNK_LEN);
+        if !self.sparse && position > limit {
+            return Err(InvalidPosition.into());
+        }
+        self.position = position;
+        Ok(())
+    }
+}
+
+#[deprecated(note = "use `BookKeeper::register_client`")]
+pub fn addel(src: &mut [u8; 32], element: u8) {
+    set_bit(src, element);
+}
+
+#[deprecated(note = "use `BookKeeper::is_registered`")]
+pub fn isel(src: [u8; 32], element: u8) -> bool {
+    bit(&src, element)
+}
+
+#[deprecated(note = "use `BookKeeper::unregister_client`")]
+pub fn delel(src: &mut [u8; 32], element: u8) {
+    clear_bit(src, element);
+}
+
+//a 32-byte bitmap holds every u8, so these can't go out of bounds
+fn set_bit(src: &mut [u8; 32], element: u8) {
+    src[element as usize / 8] |= 1 << (element % 8);
+}
+
+fn clear_bit(src: &mut [u8; 32], element: u8) {
+    src[element as usize / 8] &= !(1 << (element % 8));
+}
+
+fn bit(src: &[u8; 32], element: u8) -> bool {
+    src[element as usize / 8] >> (element % 8) & 1 == 1
+}
+
+//byte and bit of `element` in a bitmap of `len` bytes
+fn bit_position(len: usize, element: u8) -> Result<(usize, u8), BitokuError> {
+    let byte_index = element as usize / 8;
+    if byte_index >= len {
+        return Err(BitmapOverflow);
+    }
+    Ok((byte_index, element % 8))
+}
+
+/// `addel`, failing instead of panicking if `element` is outside `src`.
+pub fn addel_checked(src: &mut [u8; 32], element: u8) -> Result<(), BitokuError> {
+    let (byte_index, bit_offset) = bit_position(src.len(), element)?;
+    src[byte_index] |= 1 << bit_offset;
+    Ok(())
+}
+
+/// `isel`, failing instead of panicking if `element` is outside `src`.
+pub fn isel_checked(src: &[u8; 32], element: u8) -> Result<bool, BitokuError> {
+    let (byte_index, bit_offset) = bit_position(src.len(), element)?;
+    Ok(src[byte_index] >> bit_offset & 1 == 1)
+}
+
+/// `delel`, failing instead of panicking if `element` is outside `src`.
+pub fn delel_checked(src: &mut [u8; 32], element: u8) -> Result<(), BitokuError> {
+    let (byte_index, bit_offset) = bit_position(src.len(), element)?;
+    src[byte_index] &= !(1 << bit_offset);
+    Ok(())
+}
+
+/// Lowest element present in `src`, if any.
+pub fn first_set_bit(src: &[u8; 32]) -> Option<u8> {
+    let byte_index = src.iter().position(|&byte| byte != 0)?;
+    Some(byte_index as u8 * 8 + src[byte_index].trailing_zeros() as u8)
+}
+
+/// Lowest element missing from `src`; `None` once all 256 are present.
+pub fn first_free(status: &[u8; 32]) -> Option<u8> {
+    let byte_index = status.iter().position(|&byte| byte != u8::MAX)?;
+    Some(byte_index as u8 * 8 + status[byte_index].trailing_ones() as u8)
+}
+
+/// Whether `name` starts with the zero-padded `prefix`; an empty prefix matches nothing.
+pub fn has_prefix(name: &[u8], prefix: &[u8; PREFIX_LEN]) -> bool {
+    let len = prefix.iter().position(|&b| b == 0).unwrap_or(PREFIX_LEN);
+    len != 0 && name.get(..len) == Some(&prefix[..len])
+}
+
+/// Checks `name` against `DefaultPolicy`.
+pub fn validate_name(name: &[u8]) -> bool {
+    DefaultPolicy.validate(name)
+}
+
+/// The part of `name` before the first NUL byte.
+pub fn name_to_str(name: &[u8; 128]) -> Result<&str, core::str::Utf8Error> {
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    core::str::from_utf8(&name[..len])
+}
+
+/// Zero-padded name array for `s`, if `validate_name` accepts it.
+pub fn str_to_name(s: &str) -> Result<[u8; 128], BitokuError> {
+    let bytes = s.as_bytes();
+    if !validate_name(bytes) || bytes.contains(&0) {
+        return Err(BitokuError::InvalidName);
+    }
+    let mut name = [0u8; 128];
+    name[..bytes.len()].copy_from_slice(bytes);
+    Ok(name)
+}
+
+/// Serde for name arrays as the text of the name. Text is read back through
+/// `str_to_name`, except that the empty string is the all-zero name of a field
+/// that was never set.
+#[cfg(feature = "serde")]
+pub mod name_serde {
+    use super::{name_to_str, str_to_name};
+    use alloc::string::String;
+    use serde::{de, ser, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(name: &[u8; 128], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(name_to_str(name).map_err(ser::Error::custom)?)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 128], D::Error> {
+        let text = String::deserialize(deserializer)?;
+        if text.is_empty() {
+            return Ok([0; 128]);
+        }
+        str_to_name(&text).map_err(de::Error::custom)
+    }
+}
+
+/// Serde for byte arrays too long for serde's own array support, such as the
+/// 512-byte payloads, as standard base64.
+#[cfg(feature = "serde")]
+pub mod data_serde {
+    use alloc::{format, string::String};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        data: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let bytes = STANDARD.decode(text).map_err(de::Error::custom)?;
+        let len = bytes.len();
+        bytes
+            .try_into()
+            .map_err(|_| de::Error::invalid_length(len, &format!("{} bytes", N).as_str()))
+    }
+}
+
+/// Serde for keys as base58 text, the form explorers and the CLI show.
+#[cfg(feature = "serde")]
+pub mod pubkey_serde {
+    use alloc::string::{String, ToString};
+    use core::str::FromStr;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use solana_program::pubkey::Pubkey;
+
+    pub fn serialize<S: Serializer>(key: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&key.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pubkey, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Pubkey::from_str(&text).map_err(de::Error::custom)
+    }
+}
+
+/// Serde for fixed lists of keys, such as `BookKeeper::agents`, as base58 text.
+#[cfg(feature = "serde")]
+pub mod pubkeys_serde {
+    use alloc::{
+        format,
+        string::{String, ToString},
+        vec::Vec,
+    };
+    use core::str::FromStr;
+    use serde::{de, ser::SerializeSeq, Deserialize, Deserializer, Serializer};
+    use solana_program::pubkey::Pubkey;
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        keys: &[Pubkey; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(N))?;
+        for key in keys {
+            seq.serialize_element(&key.to_string())?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[Pubkey; N], D::Error> {
+        let texts = Vec::<String>::deserialize(deserializer)?;
+        let len = texts.len();
+        let keys = texts
+            .iter()
+            .map(|text| Pubkey::from_str(text).map_err(de::Error::custom))
+            .collect::<Result<Vec<_>, _>>()?;
+        keys.try_into()
+            .map_err(|_| de::Error::invalid_length(len, &format!("{} keys", N).as_str()))
+    }
+}
+
+/// Serde for maps keyed by `Pubkey`, such as the clients of a `ClientPool`, with
+/// the keys as base58 text so they can be JSON object keys.
+#[cfg(feature = "serde")]
+pub mod pubkey_map_serde {
+    use alloc::{
+        collections::BTreeMap,
+        string::{String, ToString},
+    };
+    use core::str::FromStr;
+    use serde::{de, ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
+    use solana_program::pubkey::Pubkey;
+
+    pub fn serialize<S: Serializer, V: Serialize>(
+        map: &BTreeMap<Pubkey, V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut entries = serializer.serialize_map(Some(map.len()))?;
+        for (key, value) in map {
+            entries.serialize_entry(&key.to_string(), value)?;
+        }
+        entries.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, V: Deserialize<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<Pubkey, V>, D::Error> {
+        BTreeMap::<String, V>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(text, value)| Ok((Pubkey::from_str(&text).map_err(de::Error::custom)?, value)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use super::{Request, RequestData};
+    use crate::instruction::unpack_request;
+    #[test]
+    fn test_pack() {
+        let request = Request::close_file("test", 69).unwrap();
+        let requester = Pubkey::new_unique();
+        print!("name {:?}", request.name_str().unwrap());
+        let src = RequestData {
+            client_id: 85,
+            requester,
+            ..request_data(request)
+        };
+        let mut dst = [0u8; RequestData::LEN];
+        println!("{:?}", src);
+
+        let res = RequestData::pack(src, &mut dst);
+        print!("packed {:?}", res.unwrap());
+    }
+    #[test]
+    fn test_pack_clears_previous_request() {
+        let mut data = request_data(Request::CreateFile {
+            name: [7; 128],
+            sparse: true,
+            data: [0xee; 512],
+        });
+        let mut dst = [0u8; RequestData::LEN];
+        RequestData::pack(data.clone(), &mut dst).unwrap();
+
+        data.request = Request::CloseFile {
+            name: [7; 128],
+            file_id: 1,
+        };
+        RequestData::pack(data.clone(), &mut dst).unwrap();
+        let body = &dst[ACCOUNT_HEADER_LEN..];
+        assert!(body[163..675].iter().all(|&b| b == 0));
+        assert_eq!(
+            RequestData::unpack_unchecked(&dst).unwrap().request,
+            data.request
+        );
+    }
+
+    #[test]
+    fn test_every_request_pack_roundtrip() {
+        for request in crate::instruction::test::every_request() {
+            let src = request_data(request.clone());
+            let mut dst = [0u8; RequestData::LEN];
+            RequestData::pack(src, &mut dst).unwrap();
+            assert_eq!(dst[ACCOUNT_HEADER_LEN + 33], request.kind());
+            assert_eq!(
+                RequestData::unpack_unchecked(&dst).unwrap().request,
+                request
+            );
+        }
+    }
+
+    #[test]
+    fn test_field_offsets_roundtrip() {
+        for request in crate::instruction::test::every_request() {
+            let src = RequestData {
+                is_initialized: true,
+                client_id: 1,
+                requester: Pubkey::new_unique(),
+                request: request.clone(),
+                position: 2,
+                max_written_offset: 3,
+                sparse: true,
+                response_ready: true,
+                response: [4; RESPONSE_LEN],
+                submission_ref: [5; 32],
+                sequence: 6,
+                submitted_at: -7,
+                status: RequestStatus::Failed,
+                response_len: 8,
+                response_data: [9; 512],
+                nonce: 10,
+                bump: 11,
+                requests_this_epoch: 12,
+            };
+            let mut dst = [0u8; RequestData::LEN];
+            RequestData::pack(src.clone(), &mut dst).unwrap();
+
+            let body = &dst[ACCOUNT_HEADER_LEN..];
+            assert_eq!(body[RequestData::CLIENT_ID_OFFSET], 1);
+            assert_eq!(
+                body[RequestData::REQUESTER_OFFSET..][..32],
+                src.requester.to_bytes()
+            );
+            assert_eq!(body[RequestData::REQUEST_OFFSET], request.kind());
+            assert_eq!(body[RequestData::POSITION_OFFSET], 2);
+            assert_eq!(body[RequestData::MAX_WRITTEN_OFFSET_OFFSET], 3);
+            assert_eq!(body[RequestData::SPARSE_OFFSET], 1);
+            assert_eq!(body[RequestData::RESPONSE_READY_OFFSET], 1);
+            assert_eq!(body[RequestData::RESPONSE_OFFSET], 4);
+            assert_eq!(body[RequestData::SUBMISSION_REF_OFFSET], 5);
+            assert_eq!(body[RequestData::SEQUENCE_OFFSET], 6);
+            assert_eq!(
+                body[RequestData::SUBMITTED_AT_OFFSET..][..8],
+                (-7i64).to_le_bytes()
+            );
+            assert_eq!(
+                body[RequestData::STATUS_OFFSET],
+                RequestStatus::Failed as u8
+            );
+            assert_eq!(body[RequestData::RESPONSE_LEN_OFFSET], 8);
+            assert_eq!(body[RequestData::RESPONSE_DATA_OFFSET..][..512], [9; 512]);
+            assert_eq!(body[RequestData::NONCE_OFFSET], 10);
+            assert_eq!(body[RequestData::BUMP_OFFSET], 11);
+            assert_eq!(body[RequestData::REQUESTS_THIS_EPOCH_OFFSET], 12);
+
+            let unpacked = RequestData::unpack_unchecked(&dst).unwrap();
+            assert_eq!(unpacked.request, request);
+            assert_eq!(format!("{:?}", unpacked), format!("{:?}", src));
+            let mut repacked = [0u8; RequestData::LEN];
+            RequestData::pack(unpacked, &mut repacked).unwrap();
+            assert_eq!(repacked, dst);
+        }
+
+        let mut reserved_prefixes = [[0; PREFIX_LEN]; RESERVED_PREFIXES];
+        reserved_prefixes[1] = [b'p'; PREFIX_LEN];
+        let src = BookKeeper {
+            is_initialized: true,
+            status: [1; 32],
+            next_id: 2,
+            authority: Pubkey::new_unique(),
+            reserved_prefixes,
+            supported_kinds: 3,
+            agent: Pubkey::new_unique(),
+            free_slots: [4; 32],
+            name_policy: 5,
+            max_requests_per_epoch: 6,
+            current_backlog: 7,
+            paused: true,
+            bump: 8,
+            agents: [
+                Pubkey::default(),
+                Pubkey::new_unique(),
+                Pubkey::default(),
+                Pubkey::new_unique(),
+            ],
+            max_client_requests_per_epoch: 9,
+            last_heartbeat_slot: 11,
+            admin: Pubkey::new_unique(),
+        };
+        let mut dst = [0u8; BookKeeper::LEN];
+        BookKeeper::pack(src.clone(), &mut dst).unwrap();
+
+        let body = &dst[ACCOUNT_HEADER_LEN..];
+        assert_eq!(body[BookKeeper::STATUS_OFFSET..][..32], [1; 32]);
+        assert_eq!(body[BookKeeper::NEXT_ID_OFFSET], 2);
+        assert_eq!(
+            body[BookKeeper::AUTHORITY_OFFSET..][..32],
+            src.authority.to_bytes()
+        );
+        assert_eq!(
+            body[BookKeeper::PREFIXES_OFFSET + PREFIX_LEN..][..PREFIX_LEN],
+            [b'p'; PREFIX_LEN]
+        );
+        assert_eq!(body[BookKeeper::SUPPORTED_KINDS_OFFSET], 3);
+        assert_eq!(body[BookKeeper::AGENT_OFFSET..][..32], src.agent.to_bytes());
+        assert_eq!(body[BookKeeper::FREE_SLOTS_OFFSET..][..32], [4; 32]);
+        assert_eq!(body[BookKeeper::NAME_POLICY_OFFSET], 5);
+        assert_eq!(body[BookKeeper::MAX_REQUESTS_OFFSET], 6);
+        assert_eq!(body[BookKeeper::BACKLOG_OFFSET], 7);
+        assert_eq!(body[BookKeeper::PAUSED_OFFSET], 1);
+        assert_eq!(body[BookKeeper::BUMP_OFFSET], 8);
+        assert_eq!(
+            body[BookKeeper::AGENTS_OFFSET + 32..][..32],
+            src.agents[1].to_bytes()
+        );
+        assert_eq!(body[BookKeeper::CLIENT_LIMIT_OFFSET], 9);
+        assert_eq!(body[BookKeeper::HEARTBEAT_OFFSET], 11);
+        assert_eq!(body[BookKeeper::ADMIN_OFFSET..][..32], src.admin.to_bytes());
+        assert_eq!(body.len(), BookKeeper::ADMIN_OFFSET + 32);
+
+        let unpacked = BookKeeper::unpack_unchecked(&dst).unwrap();
+        assert_eq!(format!("{:?}", unpacked), format!("{:?}", src));
+        let mut repacked = [0u8; BookKeeper::LEN];
+        BookKeeper::pack(unpacked, &mut repacked).unwrap();
+        assert_eq!(repacked, dst);
+    }
+
+    #[test]
+    fn test_delete_bucket_pack() {
+        let mut name = [0u8; 128];
+        name[..6].copy_from_slice(b"bucket");
+
+        let src = request_data(Request::DeleteBucket { name });
+        let mut dst = [0u8; RequestData::LEN];
+        RequestData::pack(src.clone(), &mut dst).unwrap();
+
+        let unpacked = RequestData::unpack_unchecked(&dst).unwrap();
+        assert_eq!(unpacked.request, src.request);
+        assert_eq!(unpacked.requester, src.requester);
+    }
+
+    #[test]
+    fn test_rename_file_pack() {
+        let mut name = [0u8; 128];
+        name[..6].copy_from_slice(b"file-1");
+        let new_name = [b'x'; 128];
+
+        let src = request_data(Request::RenameFile {
+            name,
+            file_id: 9,
+            new_name,
+        });
+        let mut dst = [0u8; RequestData::LEN];
+        RequestData::pack(src.clone(), &mut dst).unwrap();
+
+        let unpacked = RequestData::unpack_unchecked(&dst).unwrap();
+        assert_eq!(unpacked.request, src.request);
+    }
+
+    #[test]
+    fn test_copy_file_pack() {
+        let mut src_name = [0u8; 128];
+        src_name[..12].copy_from_slice(b"dir-1/file-1");
+        let dst_name = [b'z'; 128];
+
+        let src = request_data(Request::CopyFile {
+            src_name,
+            src_file_id: 4,
+            dst_name,
+        });
+        let mut dst = [0u8; RequestData::LEN];
+        RequestData::pack(src.clone(), &mut dst).unwrap();
+
+        let body = &dst[ACCOUNT_HEADER_LEN..];
+        assert_eq!(body[33], 10);
+        assert_eq!(&body[34..162], &src_name[..]);
+        assert_eq!(body[162], 4);
+        assert_eq!(&body[163..291], &dst_name[..]);
+
+        let unpacked = RequestData::unpack_unchecked(&dst).unwrap();
+        assert_eq!(unpacked.request, src.request);
+    }
+
+    #[test]
+    fn test_capabilities_track_constants() {
+        let bookkeeper = empty_bookkeeper();
+        let capabilities = Capabilities::new(&bookkeeper);
+
+        assert_eq!(capabilities.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(capabilities.wire_format_version, WIRE_FORMAT_VERSION);
+        assert_eq!(capabilities.request_kinds, REQUEST_KINDS);
+
+        //the newest kind, ReleaseBucketLease, carries only a name
+        let mut request = [0u8; 1 + 128];
+        request[0] = REQUEST_KINDS - 1;
+        request[1] = b'a';
+        assert!(unpack_request(&request).is_ok());
+        request[0] = REQUEST_KINDS;
+        assert!(unpack_request(&request).is_err());
+
+        let logged = capabilities.try_to_vec().unwrap();
+        assert_eq!(Capabilities::from_log_data(&logged).unwrap(), capabilities);
+    }
+
+    #[test]
+    fn test_stat_file_response() {
+        let mut name = [0u8; 128];
+        name[..4].copy_from_slice(b"stat");
+
+        let mut response = [0u8; RESPONSE_LEN];
+        response[STAT_SIZE_OFFSET..STAT_SIZE_OFFSET + 8].copy_from_slice(&1234u64.to_le_bytes());
+        response[STAT_MTIME_OFFSET..STAT_MTIME_OFFSET + 8]
+            .copy_from_slice(&1_690_000_000u64.to_le_bytes());
+
+        let src = RequestData {
+            response_ready: true,
+            response,
+            ..request_data(Request::StatFile {
+                name,
+                file_id: 2,
+                min_sequence: 11,
+            })
+        };
+        let mut dst = [0u8; RequestData::LEN];
+        RequestData::pack(src.clone(), &mut dst).unwrap();
+
+        let unpacked = RequestData::unpack_unchecked(&dst).unwrap();
+        assert_eq!(unpacked.request, src.request);
+        assert_eq!(unpacked.stat_size(), 1234);
+        assert_eq!(unpacked.stat_mtime(), 1_690_000_000);
+    }
+
+    #[test]
+    fn test_truncate_file_pack() {
+        let src = request_data(Request::TruncateFile {
+            name: [b'f'; 128],
+            file_id: 3,
+            new_length: u64::MAX,
+        });
+        let mut dst = [0u8; RequestData::LEN];
+        RequestData::pack(src.clone(), &mut dst).unwrap();
+
+        let unpacked = RequestData::unpack_unchecked(&dst).unwrap();
+        assert_eq!(unpacked.request, src.request);
+        assert_eq!(unpacked.request.new_length(), Some(u64::MAX));
+    }
+
+    fn list_response(entries: &[(u8, &[u8])]) -> RequestData {
+        let mut response = [0u8; RESPONSE_LEN];
+        response[LIST_COUNT_OFFSET] = entries.len() as u8;
+        for (i, (file_id, name)) in entries.iter().enumerate() {
+            let start = LIST_ENTRIES_OFFSET + i * LIST_ENTRY_LEN;
+            response[start] = *file_id;
+            response[start + 1..start + 1 + name.len()].copy_from_slice(name);
+        }
+
+        RequestData {
+            response_ready: true,
+            response,
+            ..request_data(Request::ListFiles {
+                name: [b'b'; 128],
+                page: 0,
+            })
+        }
+    }
+
+    #[test]
+    fn test_list_entries_empty_page() {
+        let data = list_response(&[]);
+        assert_eq!(data.list_entries().count(), 0);
+    }
+
+    #[test]
+    fn test_list_entries_full_page() {
+        let names: Vec<String> = (0..LIST_PAGE_LEN).map(|i| format!("file-{}", i)).collect();
+        let entries: Vec<(u8, &[u8])> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (i as u8, name.as_bytes()))
+            .collect();
+
+        let src = list_response(&entries);
+        let mut dst = [0u8; RequestData::LEN];
+        RequestData::pack(src, &mut dst).unwrap();
+        let data = RequestData::unpack_unchecked(&dst).unwrap();
+
+        let listed: Vec<(u8, &str)> = data.list_entries().collect();
+        assert_eq!(listed.len(), LIST_PAGE_LEN);
+        for (i, (file_id, name)) in listed.into_iter().enumerate() {
+            assert_eq!(file_id, i as u8);
+            assert_eq!(name, names[i]);
+        }
+
+        let long_name = [b'n'; LIST_ENTRY_LEN - 1];
+        let data = list_response(&[(9, &long_name)]);
+        let listed: Vec<(u8, &str)> = data.list_entries().collect();
+        assert_eq!(listed, vec![(9, "n".repeat(LIST_ENTRY_LEN - 1).as_str())]);
+    }
+
+    #[test]
+    fn test_list_entries_invalid_utf8() {
+        let data = list_response(&[(1, b"ok"), (2, &[0xff, 0xfe]), (3, b"fine")]);
+        let listed: Vec<(u8, &str)> = data.list_entries().collect();
+        assert_eq!(listed, vec![(1, "ok"), (3, "fine")]);
+    }
+
+    #[test]
+    fn test_bookkeeper_pack() {
+        let mut status = [0u8; 32];
+        set_bit(&mut status, 3);
+        let mut free_slots = [0u8; 32];
+        set_bit(&mut free_slots, 255);
+        let mut reserved_prefixes = [[0; PREFIX_LEN]; RESERVED_PREFIXES];
+        reserved_prefixes[RESERVED_PREFIXES - 1] = [b'p'; PREFIX_LEN];
+        let src = BookKeeper {
+            is_initialized: true,
+            status,
+            next_id: 4,
+            authority: Pubkey::new_unique(),
+            reserved_prefixes,
+            supported_kinds: 0x8000_0001,
+            agent: Pubkey::new_unique(),
+            free_slots,
+            name_policy: crate::policy::NamePolicyKind::Utf8 as u8,
+            max_requests_per_epoch: 0x0102_0304,
+            current_backlog: 0x0506,
+            paused: true,
+            bump: 0,
+            agents: [Pubkey::default(); MAX_AGENTS],
+            max_client_requests_per_epoch: 0,
+            last_heartbeat_slot: 0,
+            admin: Pubkey::new_unique(),
+        };
+        let mut dst = [0u8; BookKeeper::LEN];
+        BookKeeper::pack(src.clone(), &mut dst).unwrap();
+
+        assert_eq!(
+            dst[..ACCOUNT_HEADER_LEN],
+            [AccountType::BookKeeper as u8, 1]
+        );
+        let unpacked = BookKeeper::unpack_unchecked(&dst).unwrap();
+        assert!(unpacked.is_initialized);
+        assert_eq!(unpacked.status, src.status);
+        assert_eq!(unpacked.next_id, 4);
+        assert_eq!(unpacked.authority, src.authority);
+        assert_eq!(unpacked.reserved_prefixes, reserved_prefixes);
+        assert_eq!(unpacked.supported_kinds, 0x8000_0001);
+        assert_eq!(unpacked.agent, src.agent);
+        assert_eq!(unpacked.free_slots, free_slots);
+        assert_eq!(
+            unpacked.name_policy,
+            crate::policy::NamePolicyKind::Utf8 as u8
+        );
+        assert_eq!(unpacked.max_requests_per_epoch, 0x0102_0304);
+        assert_eq!(unpacked.current_backlog, 0x0506);
+        assert!(unpacked.paused);
+    }
+
+    #[test]
+    fn test_unpack_exact_length() {
+        //all zeros is an uninitialized account, and a trailing zero is as much a
+        //layout mismatch as a missing byte
+        let bookkeeper = vec![0u8; BookKeeper::LEN + 1];
+        assert!(BookKeeper::unpack_from_slice(&bookkeeper[..BookKeeper::LEN]).is_ok());
+        for len in [BookKeeper::LEN - 1, BookKeeper::LEN + 1] {
+            assert_eq!(
+                BookKeeper::unpack_from_slice(&bookkeeper[..len]).err(),
+                Some(ProgramError::InvalidAccountData),
+                "{}",
+                len
+            );
+        }
+
+        let request = vec![0u8; RequestData::LEN + 1];
+        assert!(RequestData::unpack_from_slice(&request[..RequestData::LEN]).is_ok());
+        for len in [RequestData::LEN - 1, RequestData::LEN + 1] {
+            assert_eq!(
+                RequestData::unpack_from_slice(&request[..len]).err(),
+                Some(ProgramError::InvalidAccountData),
+                "{}",
+                len
+            );
+        }
+        //an oversized account is not mistaken for one in another layout either
+        assert_eq!(
+            RequestData::unpack_account(&request).err(),
+            Some(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn test_request_status_transitions() {
+        use RequestStatus::*;
+
+        let all = [Pending, Processing, Completed, Failed];
+        let allowed = [
+            (Pending, Processing),
+            (Pending, Completed),
+            (Pending, Failed),
+            (Processing, Completed),
+            (Processing, Failed),
+        ];
+        for from in all {
+            for to in all {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    allowed.contains(&(from, to)),
+                    "{:?} -> {:?}",
+                    from,
+                    to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_request_status_pack() {
+        let mut data = request_data(Request::CreateBucket { name: [0; 128] });
+        for status in [
+            RequestStatus::Pending,
+            RequestStatus::Processing,
+            RequestStatus::Completed,
+            RequestStatus::Failed,
+            RequestStatus::Cancelled,
+        ] {
+            data.status = status;
+            let mut dst = [0u8; RequestData::LEN];
+            RequestData::pack(data.clone(), &mut dst).unwrap();
+            assert_eq!(dst[ACCOUNT_HEADER_LEN + 749 + RESPONSE_LEN], status as u8);
+            assert_eq!(RequestData::unpack_unchecked(&dst).unwrap().status, status);
+
+            dst[ACCOUNT_HEADER_LEN + 749 + RESPONSE_LEN] = 5;
+            assert_eq!(
+                RequestData::unpack_unchecked(&dst).err(),
+                Some(ProgramError::InvalidAccountData)
+            );
+        }
+    }
+
+    #[test]
+    fn test_response_data_pack() {
+        let mut data = request_data(Request::CreateBucket { name: [0; 128] });
+        data.response_data[..5].copy_from_slice(b"hello");
+        data.response_data[511] = 0xff;
+        data.response_len = 5;
+
+        let mut dst = [0u8; RequestData::LEN];
+        RequestData::pack(data.clone(), &mut dst).unwrap();
+        let unpacked = RequestData::unpack_unchecked(&dst).unwrap();
+        assert_eq!(unpacked.response_len, 5);
+        assert_eq!(unpacked.response_data, data.response_data);
+        assert_eq!(unpacked.response_bytes(), b"hello");
+        assert_eq!(unpacked.status, data.status);
+
+        data.response_len = u16::MAX;
+        assert_eq!(data.response_bytes().len(), 512);
+    }
+
+    #[test]
+    fn test_stats_snapshot_pack() {
+        let src = StatsSnapshot {
+            epoch: 7,
+            slot: 3_024_001,
+            published_at: 1_700_000_000,
+            client_count: 256,
+            next_id: 255,
+            last_heartbeat_slot: 3_023_999,
+        };
+        let mut dst = [0u8; StatsSnapshot::LEN];
+        StatsSnapshot::pack(src.clone(), &mut dst).unwrap();
+        assert_eq!(StatsSnapshot::unpack_unchecked(&dst).unwrap(), src);
+
+        assert_eq!(
+            StatsSnapshot::unpack_unchecked(&dst[..StatsSnapshot::LEN - 1]),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn test_bucket_lease() {
+        let (holder, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let lease = BucketLease {
+            holder,
+            expiry_slot: 100,
+        };
+        let mut dst = [0u8; BucketLease::LEN];
+        BucketLease::pack(lease.clone(), &mut dst).unwrap();
+        assert_eq!(BucketLease::unpack_unchecked(&dst).unwrap(), lease);
+
+        assert!(!lease.blocks(&holder, 0));
+        assert!(lease.blocks(&other, 99));
+        assert!(!lease.blocks(&other, 100));
+        assert!(!BucketLease::unpack_unchecked(&[0; BucketLease::LEN])
+            .unwrap()
+            .blocks(&other, 0));
+    }
+
+    #[test]
+    fn test_client_info() {
+        let info = ClientInfo {
+            is_initialized: true,
+            owner: Pubkey::new_unique(),
+            permissions: PERMISSION_READ | PERMISSION_WRITE,
+            bump: 254,
+        };
+        let mut dst = [0u8; ClientInfo::LEN];
+        ClientInfo::pack(info.clone(), &mut dst).unwrap();
+        assert_eq!(dst[..ACCOUNT_HEADER_LEN], [3, ACCOUNT_VERSION]);
+        assert_eq!(ClientInfo::unpack_unchecked(&dst).unwrap(), info);
+        assert_eq!(
+            ClientInfo::unpack_unchecked(&[0; ClientInfo::LEN]).unwrap(),
+            ClientInfo::default()
+        );
+
+        //a request account can't be passed off as one
+        dst[0] = AccountType::RequestData as u8;
+        assert_eq!(
+            ClientInfo::unpack_unchecked(&dst),
+            Err(ProgramError::InvalidAccountData)
+        );
+
+        assert!(info.permits(&Request::read_file("b/f", 1).unwrap()));
+        assert!(!info.permits(&Request::create_bucket("b").unwrap()));
+    }
+
+    #[test]
+    fn test_supports() {
+        let mut bookkeeper = BookKeeper {
+            supported_kinds: ALL_REQUEST_KINDS,
+            ..empty_bookkeeper()
+        };
+        assert!((0..REQUEST_KINDS).all(|kind| bookkeeper.supports(kind)));
+        assert!(!bookkeeper.supports(REQUEST_KINDS));
+        assert!(!bookkeeper.supports(u8::MAX));
+
+        bookkeeper.supported_kinds &= !(1 << 7);
+        assert!(!bookkeeper.supports(7));
+        assert!(bookkeeper.supports(8));
+    }
+
+    #[test]
+    fn test_active_clients() {
+        let mut bookkeeper = empty_bookkeeper();
+        assert_eq!(bookkeeper.count_active_clients(), 0);
+        assert_eq!(bookkeeper.iter_active_clients().next(), None);
+
+        for id in [0, 7, 8, 100, 254] {
+            bookkeeper.register_client(id);
+        }
+        assert_eq!(bookkeeper.count_active_clients(), 5);
+        assert_eq!(
+            bookkeeper.iter_active_clients().collect::<Vec<_>>(),
+            vec![0, 7, 8, 100, 254]
+        );
+
+        bookkeeper.unregister_client(8);
+        assert_eq!(bookkeeper.count_active_clients(), 4);
+        assert_eq!(bookkeeper.client_count(), 4);
+        let ids: Vec<u8> = (&bookkeeper).into_iter().collect();
+        assert_eq!(ids, vec![0, 7, 100, 254]);
+        let mut seen = Vec::new();
+        for id in &bookkeeper {
+            seen.push(id);
+        }
+        assert_eq!(seen, ids);
+    }
+
+    #[test]
+    fn test_throttle_advice() {
+        let mut bookkeeper = empty_bookkeeper();
+        //nothing published yet
+        assert_eq!(bookkeeper.throttle_advice(10), ThrottleAdvice::Proceed);
+        assert_eq!(bookkeeper.throttle_advice(0), ThrottleAdvice::Delay);
+
+        bookkeeper.max_requests_per_epoch = 100;
+        for (backlog, advice) in [
+            (0, ThrottleAdvice::Proceed),
+            (9, ThrottleAdvice::Proceed),
+            (10, ThrottleAdvice::Delay),
+            (99, ThrottleAdvice::Delay),
+            (100, ThrottleAdvice::Hold),
+            (u16::MAX, ThrottleAdvice::Hold),
+        ] {
+            bookkeeper.current_backlog = backlog;
+            assert_eq!(
+                bookkeeper.throttle_advice(10),
+                advice,
+                "backlog {}",
+                backlog
+            );
+        }
+
+        //a threshold above the agent's capacity never delays before holding
+        bookkeeper.current_backlog = 150;
+        assert_eq!(bookkeeper.throttle_advice(200), ThrottleAdvice::Proceed);
+        bookkeeper.current_backlog = 200;
+        assert_eq!(bookkeeper.throttle_advice(200), ThrottleAdvice::Hold);
+    }
+
+    #[test]
+    fn test_checked_bitmap_boundaries() {
+        let mut src = [0u8; 32];
+        for element in [0, 127, 254, 255] {
+            assert_eq!(isel_checked(&src, element), Ok(false));
+            addel_checked(&mut src, element).unwrap();
+            assert_eq!(isel_checked(&src, element), Ok(true));
+            assert_eq!(isel_checked(&src, element), Ok(bit(&src, element)));
+        }
+        assert_eq!(src[0], 0b1);
+        assert_eq!(src[15], 0b1000_0000);
+        assert_eq!(src[31], 0b1100_0000);
+
+        delel_checked(&mut src, 254).unwrap();
+        assert_eq!(src[31], 0b1000_0000);
+        for element in [0, 127, 255] {
+            delel_checked(&mut src, element).unwrap();
+        }
+        assert_eq!(src, [0; 32]);
+
+        assert_eq!(bit_position(32, 255), Ok((31, 7)));
+        assert_eq!(bit_position(31, 255), Err(BitmapOverflow));
+    }
+
+    #[test]
+    fn test_first_set_bit() {
+        let mut src = [0u8; 32];
+        assert_eq!(first_set_bit(&src), None);
+        set_bit(&mut src, 255);
+        assert_eq!(first_set_bit(&src), Some(255));
+        set_bit(&mut src, 9);
+        set_bit(&mut src, 17);
+        assert_eq!(first_set_bit(&src), Some(9));
+        clear_bit(&mut src, 9);
+        assert_eq!(first_set_bit(&src), Some(17));
+        set_bit(&mut src, 0);
+        assert_eq!(first_set_bit(&src), Some(0));
+    }
+
+    #[test]
+    fn test_first_free() {
+        let mut src = [0u8; 32];
+        assert_eq!(first_free(&src), Some(0));
+        set_bit(&mut src, 0);
+        set_bit(&mut src, 1);
+        set_bit(&mut src, 3);
+        assert_eq!(first_free(&src), Some(2));
+
+        src = [u8::MAX; 32];
+        assert_eq!(first_free(&src), None);
+        clear_bit(&mut src, 255);
+        assert_eq!(first_free(&src), Some(255));
+        clear_bit(&mut src, 130);
+        assert_eq!(first_free(&src), Some(130));
+    }
+
+    #[test]
+    fn test_client_count() {
+        let mut bookkeeper = empty_bookkeeper();
+        assert_eq!(bookkeeper.client_count(), 0);
+
+        bookkeeper.register_client(0);
+        bookkeeper.register_client(9);
+        bookkeeper.register_client(255);
+        assert_eq!(bookkeeper.client_count(), 3);
+        assert_eq!(bookkeeper.count(), 3);
+
+        bookkeeper.status = [0xff; 32];
+        assert_eq!(bookkeeper.client_count(), 256);
+        assert_eq!(bookkeeper.count_active_clients(), u8::MAX);
+    }
+
+    fn empty_bookkeeper() -> BookKeeper {
+        BookKeeper {
+            is_initialized: true,
+            status: [0; 32],
+            next_id: 0,
+            authority: Pubkey::new_unique(),
+            reserved_prefixes: [[0; PREFIX_LEN]; RESERVED_PREFIXES],
+            supported_kinds: 0,
+            agent: Pubkey::default(),
+            free_slots: [0; 32],
+            name_policy: 0,
+            max_requests_per_epoch: 0,
+            current_backlog: 0,
+            paused: false,
+            bump: 0,
+            agents: [Pubkey::default(); MAX_AGENTS],
+            max_client_requests_per_epoch: 0,
+            last_heartbeat_slot: 0,
+            admin: Pubkey::new_unique(),
+        }
+    }
+
+    #[test]
+    fn test_feature_flags() {
+        let mut bookkeeper = empty_bookkeeper();
+        assert_eq!(bookkeeper.feature_flags(), 0);
+
+        bookkeeper.paused = true;
+        assert_eq!(bookkeeper.feature_flags(), FEATURE_PAUSED);
+        bookkeeper.name_policy = NamePolicyKind::Utf8 as u8;
+        assert_eq!(
+            Capabilities::new(&bookkeeper).feature_flags,
+            FEATURE_PAUSED | FEATURE_CUSTOM_NAME_POLICY
+        );
+        bookkeeper.paused = false;
+        assert_eq!(bookkeeper.feature_flags(), FEATURE_CUSTOM_NAME_POLICY);
+    }
+
+    #[test]
+    fn test_register_client() {
+        let mut bookkeeper = empty_bookkeeper();
+        assert_eq!(bookkeeper.first_free(), Some(0));
+
+        bookkeeper.register_client(0);
+        bookkeeper.register_client(1);
+        assert!(bookkeeper.is_registered(1));
+        assert_eq!(bookkeeper.first_free(), Some(2));
+
+        bookkeeper.unregister_client(0);
+        assert!(!bookkeeper.is_registered(0));
+        assert!(bit(&bookkeeper.free_slots, 0));
+        assert_eq!(bookkeeper.first_free(), Some(0));
+
+        //handing a released id out again takes it off the free list
+        bookkeeper.register_client(0);
+        assert!(bookkeeper.is_registered(0));
+        assert_eq!(bookkeeper.free_slots, [0; 32]);
+        assert_eq!(bookkeeper.count(), 2);
+
+        //released ids come off `free_slots` lowest first, then fresh ones follow
+        bookkeeper.register_client(2);
+        bookkeeper.register_client(3);
+        bookkeeper.unregister_client(3);
+        bookkeeper.unregister_client(1);
+        for expected in [1, 3, 4] {
+            let id = bookkeeper.first_free().unwrap();
+            assert_eq!(id, expected);
+            bookkeeper.register_client(id);
+        }
+        assert_eq!(bookkeeper.free_slots, [0; 32]);
+
+        bookkeeper.status = [u8::MAX; 32];
+        assert_eq!(bookkeeper.first_free(), None);
+        bookkeeper.unregister_client(255);
+        assert_eq!(bookkeeper.first_free(), Some(255));
+    }
+
+    #[test]
+    fn test_registered_clients() {
+        let mut bookkeeper = empty_bookkeeper();
+        assert_eq!(bookkeeper.registered_clients().next(), None);
+
+        //sparse: the first and last id of a byte, the bitmap's ends, and ids
+        //with empty bytes between them
+        let sparse = [0, 7, 8, 15, 64, 200, 255];
+        for id in sparse {
+            bookkeeper.register_client(id);
+        }
+        assert_eq!(bookkeeper.registered_clients().collect::<Vec<_>>(), sparse);
+        assert_eq!(bookkeeper.count(), sparse.len());
+
+        //dense: every id, then every id but a few
+        bookkeeper.status = [u8::MAX; 32];
+        assert!(bookkeeper.registered_clients().eq(0..=u8::MAX));
+        assert_eq!(bookkeeper.count(), 256);
+        for id in [0, 31, 32, 255] {
+            bookkeeper.unregister_client(id);
+        }
+        let expected: Vec<u8> = (0..=u8::MAX)
+            .filter(|id| ![0, 31, 32, 255].contains(id))
+            .collect();
+        assert_eq!(
+            bookkeeper.registered_clients().collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(bookkeeper.count(), 252);
+
+        //the iterator agrees with `is_registered` on every id
+        for id in 0..=u8::MAX {
+            assert_eq!(
+                bookkeeper
+                    .registered_clients()
+                    .any(|registered| registered == id),
+                bookkeeper.is_registered(id)
+            );
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_bitmap_shims() {
+        let mut src = [0u8; 32];
+        addel(&mut src, 130);
+        assert!(isel(src, 130));
+        assert_eq!(src[16], 0b100);
+        delel(&mut src, 130);
+        assert!(!isel(src, 130));
+        assert_eq!(src, [0; 32]);
+    }
+
+    fn prefix(s: &[u8]) -> [u8; PREFIX_LEN] {
+        let mut prefix = [0u8; PREFIX_LEN];
+        prefix[..s.len()].copy_from_slice(s);
+        prefix
+    }
+
+    #[test]
+    fn test_has_prefix_boundaries() {
+        let mut name = [0u8; 128];
+        name[..14].copy_from_slice(b"system/file-01");
+
+        assert!(has_prefix(&name, &prefix(b"system/")));
+        assert!(has_prefix(&name, &prefix(b"s")));
+        assert!(!has_prefix(&name, &prefix(b"systems/")));
+        assert!(!has_prefix(&name, &prefix(b"")));
+
+        //a full-length prefix has no terminating zero
+        let full = [b'x'; PREFIX_LEN];
+        let mut long_name = [0u8; 128];
+        long_name[..PREFIX_LEN + 1].copy_from_slice(&[b'x'; PREFIX_LEN + 1]);
+        assert!(has_prefix(&long_name, &full));
+        assert!(has_prefix(&full, &full));
+        assert!(!has_prefix(&full[..PREFIX_LEN - 1], &full));
+    }
+
+    #[test]
+    fn test_is_reserved() {
+        let mut bookkeeper = empty_bookkeeper();
+        assert!(!bookkeeper.is_reserved(b".bitoku/config"));
+
+        bookkeeper.reserved_prefixes[2] = prefix(b".bitoku/");
+        assert!(bookkeeper.is_reserved(b".bitoku/config"));
+        assert!(!bookkeeper.is_reserved(b"bitoku/config"));
+    }
+
+    #[test]
+    fn test_write_by_reference_pack() {
+        let src = request_data(Request::WriteByReference {
+            name: [b'w'; 128],
+            file_id: 5,
+            chunk_hash: [0xab; 32],
+        });
+        let mut dst = [0u8; RequestData::LEN];
+        RequestData::pack(src.clone(), &mut dst).unwrap();
+
+        assert_eq!(
+            &dst[ACCOUNT_HEADER_LEN + 163..ACCOUNT_HEADER_LEN + 195],
+            &[0xab; 32]
+        );
+        assert_eq!(
+            RequestData::unpack_unchecked(&dst).unwrap().request,
+            src.request
+        );
+    }
+
+    #[test]
+    fn test_write_at_pack() {
+        let mut data = [0u8; 512];
+        data[511] = 0xcd;
+        let src = RequestData {
+            position: 7,
+            max_written_offset: 9,
+            sparse: true,
+            submission_ref: [0xee; 32],
+            ..request_data(Request::WriteAt {
+                name: [b'w'; 128],
+                file_id: 5,
+                offset: 0x0102030405060708,
+                data,
+            })
+        };
+        let mut dst = [0u8; RequestData::LEN];
+        RequestData::pack(src.clone(), &mut dst).unwrap();
+
+        let body = &dst[ACCOUNT_HEADER_LEN..];
+        assert_eq!(&body[163..171], &0x0102030405060708u64.to_le_bytes());
+        assert_eq!(body[682], 0xcd);
+
+        let unpacked = RequestData::unpack_unchecked(&dst).unwrap();
+        assert_eq!(unpacked.request, src.request);
+        assert_eq!(unpacked.position, 7);
+        assert_eq!(unpacked.max_written_offset, 9);
+        assert!(unpacked.sparse);
+        assert_eq!(unpacked.submission_ref, [0xee; 32]);
+    }
+
+    #[test]
+    fn test_read_range_pack_roundtrip() {
+        let ranges = [
+            (0, 1),
+            (0, MAX_READ),
+            (CHUNK_LEN, 3),
+            (u64::MAX - MAX_READ, MAX_READ),
+            (u64::MAX - 1, 1),
+        ];
+        for (offset, len) in ranges {
+            let src = RequestData {
+                position: offset,
+                max_written_offset: len,
+                ..request_data(Request::ReadRange {
+                    name: [b'r'; 128],
+                    file_id: 3,
+                    offset,
+                    len,
+                    min_sequence: offset,
+                })
+            };
+            let mut dst = [0u8; RequestData::LEN];
+            RequestData::pack(src.clone(), &mut dst).unwrap();
+
+            let unpacked = RequestData::unpack_unchecked(&dst).unwrap();
+            assert_eq!(unpacked.request, src.request);
+            assert_eq!(unpacked.position, offset);
+            assert_eq!(unpacked.max_written_offset, len);
+        }
+    }
+
+    #[test]
+    fn test_exists_response() {
+        let mut src = request_data(Request::FileExists {
+            name: [b'e'; 128],
+            file_id: 4,
+        });
+        src.response[EXISTS_OFFSET] = 1;
+        assert_eq!(src.exists_response(), None);
+
+        src.response_ready = true;
+        let mut dst = [0u8; RequestData::LEN];
+        RequestData::pack(src.clone(), &mut dst).unwrap();
+        let unpacked = RequestData::unpack_unchecked(&dst).unwrap();
+        assert_eq!(unpacked.request, src.request);
+        assert_eq!(unpacked.exists_response(), Some(true));
+
+        src.response[EXISTS_OFFSET] = 0;
+        assert_eq!(src.exists_response(), Some(false));
+    }
+
+    #[test]
+    fn test_name_validation() {
+        let name = "test".as_bytes();
+
+        let bool = validate_name(&name);
+
+        assert!(bool);
+    }
+
+    #[test]
+    fn test_empty_name_validation() {
+        assert!(!validate_name(&[0u8; 128]));
+        assert!(!validate_name(&[]));
+        assert!(validate_name(b"a"));
+    }
+
+    #[test]
+    fn test_name_str_conversion() {
+        let name = str_to_name("bucket/file.txt").unwrap();
+        assert_eq!(&name[..15], b"bucket/file.txt");
+        assert!(name[15..].iter().all(|&b| b == 0));
+        assert_eq!(name_to_str(&name), Ok("bucket/file.txt"));
+
+        //non-ASCII reads back but does not pass validate_name
+        let mut name = [0u8; 128];
+        name[..6].copy_from_slice("héllo".as_bytes());
+        assert_eq!(name_to_str(&name), Ok("héllo"));
+        assert_eq!(str_to_name("héllo"), Err(BitokuError::InvalidName));
+        name[0] = 0xff;
+        assert!(name_to_str(&name).is_err());
+
+        assert_eq!(name_to_str(&[0; 128]), Ok(""));
+        assert_eq!(str_to_name(""), Err(BitokuError::InvalidName));
+        assert_eq!(str_to_name("\0"), Err(BitokuError::InvalidName));
+        assert_eq!(str_to_name("a\0b"), Err(BitokuError::InvalidName));
+
+        let longest = "a".repeat(128);
+        let name = str_to_name(&longest).unwrap();
+        assert_eq!(name, [b'a'; 128]);
+        assert_eq!(name_to_str(&name), Ok(longest.as_str()));
+        assert_eq!(str_to_name(&"a".repeat(129)), Err(BitokuError::InvalidName));
+    }
+
+    fn request_data(request: Request) -> RequestData {
+        RequestData {
+            is_initialized: true,
+            client_id: 0,
+            requester: Pubkey::new_unique(),
+            request,
+            position: 0,
+            max_written_offset: 0,
+            sparse: false,
+            response_ready: false,
+            response: [0; RESPONSE_LEN],
+            submission_ref: [0; 32],
+            sequence: 0,
+            submitted_at: 0,
+            status: RequestStatus::Pending,
+            response_len: 0,
+            response_data: [0; 512],
+            nonce: 0,
+            bump: 0,
+            requests_this_epoch: 0,
+        }
+    }
+
+    fn create_file(sparse: bool) -> Request {
+        Request::CreateFile {
+            name: [0; 128],
+            sparse,
+            data: [0; 512],
+        }
+    }
+
+    fn write_file(offset: u64) -> Request {
+        Request::WriteFile {
+            name: [0; 128],
+            file_id: 1,
+            offset,
+            data: [0; 512],
+        }
+    }
+
+    fn set_position(position: u64) -> Request {
+        Request::SetPosition {
+            name: [0; 128],
+            file_id: 1,
+            position,
+        }
+    }
+
+    #[test]
+    fn test_track_sequential_writes() {
+        let mut data = request_data(Request::CreateBucket { name: [0; 128] });
+        data.track_position(&create_file(false)).unwrap();
+        data.track_position(&write_file(CHUNK_LEN)).unwrap();
+        data.track_position(&write_file(2 * CHUNK_LEN)).unwrap();
+
+        assert_eq!(data.position, 3 * CHUNK_LEN);
+        assert_eq!(data.max_written_offset, 3 * CHUNK_LEN);
+
+        data.track_position(&set_position(4 * CHUNK_LEN)).unwrap();
+        assert_eq!(
+            data.track_position(&set_position(5 * CHUNK_LEN)),
+            Err(InvalidPosition.into())
+        );
+    }
+
+    #[test]
+    fn test_track_seek_backwards() {
+        let mut data = request_data(Request::CreateBucket { name: [0; 128] });
+        data.track_position(&create_file(false)).unwrap();
+        data.track_position(&write_file(CHUNK_LEN)).unwrap();
+        data.track_position(&write_file(0)).unwrap();
+
+        assert_eq!(data.position, CHUNK_LEN);
+        assert_eq!(data.max_written_offset, 2 * CHUNK_LEN);
+        assert!(data.track_position(&set_position(3 * CHUNK_LEN)).is_ok());
+    }
+
+    #[test]
+    fn test_track_sparse_exemption() {
+        let mut data = request_data(Request::CreateBucket { name: [0; 128] });
+        data.track_position(&create_file(true)).unwrap();
+        data.track_position(&set_position(u64::MAX)).unwrap();
+        assert_eq!(data.position, u64::MAX);
+        assert_eq!(data.max_written_offset, CHUNK_LEN);
+
+        data.track_position(&create_file(false)).unwrap();
+        assert_eq!(
+            data.track_position(&set_position(u64::MAX)),
+            Err(InvalidPosition.into())
+        );
+    }
+
+    #[test]
+    fn test_track_append() {
+        let mut data = request_data(Request::CreateBucket { name: [0; 128] });
+        data.track_position(&create_file(false)).unwrap();
+        data.track_position(&write_file(CHUNK_LEN)).unwrap();
+        data.track_position(&set_position(0)).unwrap();
+        data.track_position(&Request::AppendFile {
+            name: [0; 128],
+            file_id: 1,
+            data: [0; 512],
+        })
+        .unwrap();
+
+        assert_eq!(data.position, 3 * CHUNK_LEN);
+        assert_eq!(data.max_written_offset, 3 * CHUNK_LEN);
+    }
+
+    #[test]
+    fn test_track_write_at() {
+        let write_at = |offset| Request::WriteAt {
+            name: [0; 128],
+            file_id: 1,
+            offset,
+            data: [0; 512],
+        };
+
+        let mut data = request_data(Request::CreateBucket { name: [0; 128] });
+        data.track_position(&create_file(false)).unwrap();
+        data.track_position(&write_at(CHUNK_LEN)).unwrap();
+        assert_eq!(data.position, 2 * CHUNK_LEN);
+        assert_eq!(data.max_written_offset, 2 * CHUNK_LEN);
+
+        data.track_position(&write_at(0)).unwrap();
+        assert_eq!(data.position, CHUNK_LEN);
+        assert_eq!(data.max_written_offset, 2 * CHUNK_LEN);
+
+        assert_eq!(
+            data.track_position(&write_at(4 * CHUNK_LEN)),
+            Err(InvalidPosition.into())
+        );
+    }
+
+    #[test]
+    fn test_tracking_survives_pack() {
+        let mut data = request_data(Request::CreateBucket { name: [0; 128] });
+        data.track_position(&create_file(true)).unwrap();
+        data.track_position(&write_file(CHUNK_LEN)).unwrap();
+
+        let mut dst = [0u8; RequestData::LEN];
+        RequestData::pack(data, &mut dst).unwrap();
+        let unpacked = RequestData::unpack_unchecked(&dst).unwrap();
+
+        assert_eq!(unpacked.position, 2 * CHUNK_LEN);
+        assert_eq!(unpacked.max_written_offset, 2 * CHUNK_LEN);
+        assert!(unpacked.sparse);
+    }
+
+    //a freshly created compact account, as RegisterClient leaves it
+    fn compact_account() -> Vec<u8> {
+        let mut dst = vec![0u8; COMPACT_BASE_LEN];
+        RequestData::mark_compact(&mut dst);
+        dst
+    }
+
+    #[test]
+    fn test_compact_pack_roundtrip() {
+        for request in crate::instruction::test::every_request() {
+            let mut src = request_data(Request::CreateBucket { name: [0; 128] });
+            src.client_id = 9;
+            src.sequence = 77;
+            src.response_data = [b'r'; 512];
+            src.request = request.clone();
+
+            let mut dst = compact_account();
+            dst.resize(src.compact_len(), 0);
+            src.clone().pack_account(&mut dst).unwrap();
+            assert!(RequestData::is_compact(&dst));
+
+            let unpacked = RequestData::unpack_account(&dst).unwrap();
+            assert_eq!(unpacked.request, request);
+            assert_eq!(unpacked.client_id, 9);
+            assert_eq!(unpacked.sequence, 77);
+            assert_eq!(unpacked.response_data, [b'r'; 512]);
+            assert_eq!(unpacked.requester, src.requester);
+        }
+    }
+
+    #[test]
+    fn test_compact_len() {
+        let mut src = request_data(Request::CreateBucket { name: [0; 128] });
+        assert_eq!(src.compact_len(), COMPACT_BASE_LEN);
+        assert_eq!(
+            RequestData::unpack_account(&compact_account())
+                .unwrap()
+                .request,
+            Request::CreateBucket { name: [0; 128] }
+        );
+
+        let mut name = [0u8; 128];
+        name[..6].copy_from_slice(b"bucket");
+        src.request = Request::CreateBucket { name };
+        assert_eq!(src.compact_len(), COMPACT_BASE_LEN + 1 + 6);
+
+        let mut data = [0u8; 512];
+        data[511] = 1;
+        src.request = Request::WriteFile {
+            name,
+            file_id: 1,
+            offset: 0,
+            data,
+        };
+        assert_eq!(src.compact_len(), COMPACT_BASE_LEN + 1 + 137 + 512);
+
+        src.request = Request::AppendFile {
+            name,
+            file_id: 1,
+            data,
+        };
+        assert_eq!(src.compact_len(), COMPACT_BASE_LEN + 1 + 129 + 512);
+        assert!(src.compact_len() < RequestData::LEN);
+    }
+
+    #[test]
+    fn test_compact_account_too_small() {
+        let src = request_data(Request::CreateBucket { name: [b'b'; 128] });
+
+        let mut dst = compact_account();
+        assert_eq!(
+            src.clone().pack_account(&mut dst),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+
+        //a larger account is zero-filled past the request
+        dst.resize(src.compact_len() + 8, 0xaa);
+        src.clone().pack_account(&mut dst).unwrap();
+        assert!(dst[src.compact_len()..].iter().all(|&b| b == 0));
+        assert_eq!(
+            RequestData::unpack_account(&dst).unwrap().request,
+            src.request
+        );
+    }
+
+    #[test]
+    fn test_full_layout_account_unchanged() {
+        let src = request_data(Request::CreateBucket { name: [b'b'; 128] });
+
+        let mut full = [0u8; RequestData::LEN];
+        src.clone().pack_account(&mut full).unwrap();
+        assert!(!RequestData::is_compact(&full));
+
+        let mut packed = [0u8; RequestData::LEN];
+        RequestData::pack(src.clone(), &mut packed).unwrap();
+        assert_eq!(full, packed);
+        assert_eq!(
+            RequestData::unpack_account(&full).unwrap().request,
+            src.request
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_request_data_json_roundtrip() {
+        for request in crate::instruction::test::every_request() {
+            let mut src = request_data(Request::CreateBucket { name: [0; 128] });
+            src.requester = Pubkey::new_unique();
+            src.request = request;
+            src.response_data = [b'r'; 512];
+
+            let json = serde_json::to_string(&src).unwrap();
+            let requester = format!(r#""requester":"{}""#, src.requester);
+            assert!(json.contains(&requester), "{}", json);
+            let decoded: RequestData = serde_json::from_str(&json).unwrap();
+            assert_eq!(decoded.try_to_vec().unwrap(), src.try_to_vec().unwrap());
+        }
+
+        //keys must be base58 text
+        let src = request_data(Request::CreateBucket { name: [0; 128] });
+        let json = serde_json::to_string(&src).unwrap();
+        let bytes = serde_json::to_string(&[1u8; 32]).unwrap();
+        let invalid = json.replacen(&format!("\"{}\"", src.requester), &bytes, 1);
+        assert!(serde_json::from_str::<RequestData>(&invalid).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_bookkeeper_json_roundtrip() {
+        let mut src = empty_bookkeeper();
+        src.agent = Pubkey::new_unique();
+        src.agents[1] = Pubkey::new_unique();
+        src.register_client(3);
+
+        let json = serde_json::to_string(&src).unwrap();
+        assert!(json.contains(&format!(r#""authority":"{}""#, src.authority)));
+        let agents = format!(
+            r#""agents":["{}","{}","{}","{}"]"#,
+            Pubkey::default(),
+            src.agents[1],
+            Pubkey::default(),
+            Pubkey::default()
+        );
+        assert!(json.contains(&agents), "{}", json);
+        let decoded: BookKeeper = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.try_to_vec().unwrap(), src.try_to_vec().unwrap());
+
+        //the agent list keeps its fixed length
+        let short = json.replace(&format!(r#","{}"]"#, Pubkey::default()), "]");
+        assert!(serde_json::from_str::<BookKeeper>(&short).is_err());
     }
 }
@@ -0,0 +1,51 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! `wasm-bindgen` exports for browser clients that build or read requests off
+//! chain. Built with `wasm-pack build -- --no-default-features --features wasm`;
+//! `tests/wasm` drives the package from node.
+
+use alloc::{string::ToString, vec::Vec};
+use core::fmt::Display;
+
+use borsh::BorshSerialize;
+use wasm_bindgen::prelude::*;
+
+use crate::instruction::{unpack_request, Request};
+
+fn js_error(err: impl Display) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+/// Borsh encoding of the request built by `Request::from_parts`.
+#[wasm_bindgen]
+pub fn wasm_pack_request(
+    tag: u8,
+    name: &str,
+    file_id: u8,
+    data: &[u8],
+) -> Result<Vec<u8>, JsError> {
+    let request = Request::from_parts(tag, name, file_id, data).map_err(js_error)?;
+    request.try_to_vec().map_err(js_error)
+}
+
+/// The request encoded in `bytes` as a plain object in the serde layout, e.g.
+/// `{ CreateBucket: { name: "photos" } }`.
+#[wasm_bindgen]
+pub fn wasm_unpack_request(bytes: &[u8]) -> Result<JsValue, JsError> {
+    let request = unpack_request(bytes).map_err(js_error)?;
+    serde_wasm_bindgen::to_value(&request).map_err(js_error)
+}
@@ -0,0 +1,236 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Decodes accounts captured byte-for-byte from the current layout, version 1 of
+//! `ACCOUNT_VERSION`, and from the first release's headerless layout. The
+//! fixtures are never regenerated: a layout change that misreads them needs a
+//! new version and a `MigrateAccount` upgrade instead.
+
+use bitoku_sdk_agent_native::{
+    instruction::Request,
+    migration::{
+        upgrade_bookkeeper, upgrade_request_data, verify_migration, BOOKKEEPER_APPENDED,
+        BOOKKEEPER_BASELINE, BOOKKEEPER_V0, REQUEST_DATA_APPENDED, REQUEST_DATA_BASELINE,
+        REQUEST_DATA_V0,
+    },
+    pda::{find_bookkeeper_pda, find_request_pda},
+    state::{
+        str_to_name, BookKeeper, RequestData, RequestStatus, ACCOUNT_HEADER_LEN, ACCOUNT_VERSION,
+        DEFAULT_MAX_CLIENT_REQUESTS_PER_EPOCH, MAX_AGENTS,
+    },
+};
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+
+const REQUEST_DATA_FIXTURE: &str = include_str!("fixtures/request_data.hex");
+const BOOKKEEPER_FIXTURE: &str = include_str!("fixtures/bookkeeper.hex");
+//packed by the first release's `Pack` impls, a ReadFile written over a WriteFile
+const REQUEST_DATA_BASELINE_FIXTURE: &str = include_str!("fixtures/request_data_baseline.hex");
+const BOOKKEEPER_BASELINE_FIXTURE: &str = include_str!("fixtures/bookkeeper_baseline.hex");
+
+fn decode_hex(hex: &str) -> Vec<u8> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect()
+}
+
+//the values the fixtures were captured with
+fn request_data() -> RequestData {
+    RequestData {
+        is_initialized: true,
+        client_id: 3,
+        requester: Pubkey::new_from_array([7; 32]),
+        request: Request::CreateBucket {
+            name: str_to_name("photos").unwrap(),
+        },
+        position: 11,
+        max_written_offset: 12,
+        sparse: true,
+        response_ready: true,
+        response: [13; 256],
+        submission_ref: [14; 32],
+        sequence: 15,
+        submitted_at: 16,
+        status: RequestStatus::Completed,
+        response_len: 17,
+        response_data: [18; 512],
+        nonce: 19,
+        bump: 254,
+        requests_this_epoch: 20,
+    }
+}
+
+fn bookkeeper() -> BookKeeper {
+    let mut agents = [Pubkey::default(); MAX_AGENTS];
+    agents[0] = Pubkey::new_from_array([9; 32]);
+    BookKeeper {
+        is_initialized: true,
+        status: [1; 32],
+        next_id: 4,
+        authority: Pubkey::new_from_array([8; 32]),
+        reserved_prefixes: [[2; 32]; 4],
+        supported_kinds: 0x0fff,
+        agent: Pubkey::new_from_array([9; 32]),
+        free_slots: [3; 32],
+        name_policy: 1,
+        max_requests_per_epoch: 500,
+        current_backlog: 6,
+        paused: true,
+        bump: 253,
+        agents,
+        max_client_requests_per_epoch: 64,
+        last_heartbeat_slot: 0,
+        admin: Pubkey::new_from_array([8; 32]),
+    }
+}
+
+//`RequestData` has no `PartialEq`, so accounts are compared packed
+fn assert_request_eq(decoded: &RequestData, expected: &RequestData) {
+    let mut packed = vec![0; RequestData::LEN];
+    RequestData::pack(decoded.clone(), &mut packed).unwrap();
+    let mut expected_packed = vec![0; RequestData::LEN];
+    RequestData::pack(expected.clone(), &mut expected_packed).unwrap();
+    assert_eq!(packed, expected_packed);
+}
+
+#[test]
+fn test_fixtures_decode() {
+    let bytes = decode_hex(REQUEST_DATA_FIXTURE);
+    assert_eq!(bytes.len(), RequestData::LEN);
+    assert_eq!(bytes[1], ACCOUNT_VERSION);
+    let decoded = RequestData::unpack(&bytes).unwrap();
+    assert_request_eq(&decoded, &request_data());
+
+    //the current layout packs back to the same bytes
+    let mut repacked = vec![0; RequestData::LEN];
+    RequestData::pack(decoded, &mut repacked).unwrap();
+    assert_eq!(repacked, bytes);
+
+    //captured before `last_heartbeat_slot` and `admin` were appended
+    let bytes = decode_hex(BOOKKEEPER_FIXTURE);
+    assert_eq!(bytes[1], ACCOUNT_VERSION);
+    let decoded = upgrade_bookkeeper(&bytes, &Pubkey::new_unique(), &Pubkey::new_unique()).unwrap();
+    let mut repacked = vec![0; BookKeeper::LEN];
+    BookKeeper::pack(decoded.clone(), &mut repacked).unwrap();
+    assert_eq!(repacked[..bytes.len()], bytes);
+    assert_eq!(decoded.last_heartbeat_slot, 0);
+    assert_eq!(decoded.authority, bookkeeper().authority);
+    assert_eq!(decoded.agents, bookkeeper().agents);
+    assert_eq!(decoded.max_client_requests_per_epoch, 64);
+}
+
+#[test]
+fn test_fixtures_upgrade_from_older_layouts() {
+    let owner = Pubkey::new_unique();
+    let program_id = Pubkey::new_unique();
+
+    //accounts written before a field was appended end where it starts
+    let bytes = decode_hex(REQUEST_DATA_FIXTURE);
+    let v0_end = REQUEST_DATA_V0.last().map(|l| l.offset + l.len).unwrap();
+    for len in [v0_end]
+        .into_iter()
+        .chain(REQUEST_DATA_APPENDED.iter().map(|l| l.offset + l.len))
+    {
+        for older in [&bytes[..len], &bytes[ACCOUNT_HEADER_LEN..len]] {
+            let upgraded = upgrade_request_data(older, &owner, &program_id).unwrap();
+            let mut expected = request_data();
+            for layout in REQUEST_DATA_APPENDED.iter().filter(|l| l.offset >= len) {
+                match layout.name {
+                    "nonce" => expected.nonce = 0,
+                    "bump" => expected.bump = find_request_pda(&owner, &program_id).1,
+                    "requests_this_epoch" => expected.requests_this_epoch = 0,
+                    name => panic!("no default for {}", name),
+                }
+            }
+            assert_request_eq(&upgraded, &expected);
+        }
+    }
+
+    let bytes = decode_hex(BOOKKEEPER_FIXTURE);
+    let v0_end = BOOKKEEPER_V0.last().map(|l| l.offset + l.len).unwrap();
+    for len in [v0_end]
+        .into_iter()
+        .chain(BOOKKEEPER_APPENDED.iter().map(|l| l.offset + l.len))
+        .filter(|&len| len <= bytes.len())
+    {
+        let upgraded = upgrade_bookkeeper(&bytes[..len], &owner, &program_id).unwrap();
+        assert_eq!(upgraded.authority, bookkeeper().authority);
+        assert_eq!(upgraded.supported_kinds, bookkeeper().supported_kinds);
+        assert_eq!(upgraded.current_backlog, bookkeeper().current_backlog);
+        if len < bytes.len() {
+            assert_eq!(
+                upgraded.max_client_requests_per_epoch,
+                DEFAULT_MAX_CLIENT_REQUESTS_PER_EPOCH
+            );
+        }
+    }
+}
+
+#[test]
+fn test_baseline_fixtures_upgrade() {
+    let owner = Pubkey::new_unique();
+    let program_id = Pubkey::new_unique();
+
+    let bytes = decode_hex(REQUEST_DATA_BASELINE_FIXTURE);
+    assert_eq!(bytes.len(), 675);
+    assert_eq!(
+        bytes.len(),
+        REQUEST_DATA_BASELINE
+            .last()
+            .map(|l| l.offset + l.len)
+            .unwrap()
+    );
+    let upgraded = upgrade_request_data(&bytes, &owner, &program_id).unwrap();
+    assert!(upgraded.is_initialized);
+    assert_eq!(upgraded.client_id, 2);
+    assert_eq!(upgraded.requester, Pubkey::new_from_array([7; 32]));
+    //the WriteFile's data left behind the ReadFile is not part of it
+    assert_eq!(
+        upgraded.request,
+        Request::ReadFile {
+            name: str_to_name("photos/a").unwrap(),
+            file_id: 2,
+            min_sequence: 0,
+        }
+    );
+    assert_eq!(upgraded.bump, find_request_pda(&owner, &program_id).1);
+    assert_eq!((upgraded.sequence, upgraded.is_pending()), (0, false));
+    let mut migrated = vec![0; RequestData::LEN];
+    RequestData::pack(upgraded, &mut migrated).unwrap();
+    assert_eq!(verify_migration(&bytes, &migrated), Ok(()));
+
+    let bytes = decode_hex(BOOKKEEPER_BASELINE_FIXTURE);
+    assert_eq!(bytes.len(), 33);
+    assert_eq!(
+        bytes.len(),
+        BOOKKEEPER_BASELINE
+            .last()
+            .map(|l| l.offset + l.len)
+            .unwrap()
+    );
+    let upgraded = upgrade_bookkeeper(&bytes, &owner, &program_id).unwrap();
+    assert!(upgraded.is_initialized);
+    assert_eq!(upgraded.registered_clients().collect::<Vec<_>>(), [0, 2]);
+    assert_eq!(upgraded.next_id, 3);
+    assert_eq!(upgraded.first_free(), Some(1));
+    assert_eq!((upgraded.authority, upgraded.admin), (owner, owner));
+    assert_eq!(upgraded.bump, find_bookkeeper_pda(&program_id).1);
+    assert_eq!(
+        upgraded.max_client_requests_per_epoch,
+        DEFAULT_MAX_CLIENT_REQUESTS_PER_EPOCH
+    );
+}
@@ -0,0 +1,262 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+//! An in-process stand-in for a cluster with the program deployed, shared by the
+//! integration tests. Instructions go through the same input serialization the
+//! runtime uses, and the system program is emulated for the CPIs the processor
+//! makes, including its checks that new accounts are unused and that every
+//! account it moves lamports from or allocates has signed.
+//!
+//! Each test crate uses a different part of it.
+#![allow(dead_code)]
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    sync::Once,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use bitoku_sdk_agent_native::{pda::find_program_data_address, processor::Processor};
+use solana_program::{
+    account_info::AccountInfo,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    clock::Clock,
+    entrypoint::{deserialize, ProgramResult, MAX_PERMITTED_DATA_INCREASE, NON_DUP_MARKER},
+    instruction::Instruction,
+    program_error::ProgramError,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction::SystemError,
+    system_program, sysvar,
+};
+
+thread_local! {
+    //the program being executed, which the PDAs it signs for derive from
+    static CALLER: Cell<Pubkey> = Cell::new(Pubkey::default());
+    //what the program logged, formatted the way the runtime reports it
+    static LOGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+//the log messages of the instructions executed on this thread since the last call
+pub fn take_logs() -> Vec<String> {
+    LOGS.with(|logs| logs.take())
+}
+
+#[derive(Clone, Default)]
+pub struct Account {
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+}
+
+//the accounts of a cluster the program is deployed on; missing accounts are
+//empty and owned by the system program, like on a validator
+pub struct Ledger {
+    pub program_id: Pubkey,
+    pub accounts: BTreeMap<Pubkey, Account>,
+}
+
+impl Ledger {
+    pub fn new(program_id: Pubkey) -> Self {
+        let rent = Rent::default();
+        let mut rent_data = Vec::new();
+        rent_data.extend_from_slice(&rent.lamports_per_byte_year.to_le_bytes());
+        rent_data.extend_from_slice(&rent.exemption_threshold.to_le_bytes());
+        rent_data.push(rent.burn_percent);
+
+        let mut ledger = Self {
+            program_id,
+            accounts: BTreeMap::new(),
+        };
+        ledger.set(sysvar::rent::ID, rent_data, sysvar::ID);
+        ledger.set_clock(&Clock::default());
+        ledger.set(system_program::ID, vec![], Pubkey::default());
+        ledger
+    }
+
+    pub fn set(&mut self, key: Pubkey, data: Vec<u8>, owner: Pubkey) {
+        self.accounts.insert(
+            key,
+            Account {
+                lamports: 1_000_000_000,
+                data,
+                owner,
+            },
+        );
+    }
+
+    pub fn set_clock(&mut self, clock: &Clock) {
+        let mut clock_data = Vec::new();
+        clock_data.extend_from_slice(&clock.slot.to_le_bytes());
+        clock_data.extend_from_slice(&clock.epoch_start_timestamp.to_le_bytes());
+        clock_data.extend_from_slice(&clock.epoch.to_le_bytes());
+        clock_data.extend_from_slice(&clock.leader_schedule_epoch.to_le_bytes());
+        clock_data.extend_from_slice(&clock.unix_timestamp.to_le_bytes());
+        self.set(sysvar::clock::ID, clock_data, sysvar::ID);
+    }
+
+    //the program's ProgramData, recording who deployed it and may upgrade it
+    pub fn set_upgrade_authority(&mut self, authority: Option<Pubkey>) {
+        let state = UpgradeableLoaderState::ProgramData {
+            slot: 0,
+            upgrade_authority_address: authority,
+        };
+        let mut data = bincode::serialize(&state).unwrap();
+        data.resize(UpgradeableLoaderState::size_of_programdata_metadata(), 0);
+        self.set(
+            find_program_data_address(&self.program_id),
+            data,
+            bpf_loader_upgradeable::id(),
+        );
+    }
+
+    pub fn data(&self, key: &Pubkey) -> &[u8] {
+        &self.accounts[key].data
+    }
+
+    //lays the accounts out the way the runtime serializes them, so realloc works
+    fn serialize(&self, ix: &Instruction) -> Vec<u64> {
+        let mut input = Vec::new();
+        input.extend_from_slice(&(ix.accounts.len() as u64).to_le_bytes());
+        for meta in &ix.accounts {
+            let account = self.accounts.get(&meta.pubkey).cloned().unwrap_or(Account {
+                owner: system_program::ID,
+                ..Account::default()
+            });
+            input.extend_from_slice(&[NON_DUP_MARKER, meta.is_signer as u8]);
+            input.extend_from_slice(&[meta.is_writable as u8, 0, 0, 0, 0, 0]);
+            input.extend_from_slice(meta.pubkey.as_ref());
+            input.extend_from_slice(account.owner.as_ref());
+            input.extend_from_slice(&account.lamports.to_le_bytes());
+            input.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+            input.extend_from_slice(&account.data);
+            input.resize(input.len() + MAX_PERMITTED_DATA_INCREASE, 0);
+            input.resize(input.len() + (8 - input.len() % 8) % 8, 0);
+            input.extend_from_slice(&0u64.to_le_bytes());
+        }
+        input.extend_from_slice(&(ix.data.len() as u64).to_le_bytes());
+        input.extend_from_slice(&ix.data);
+        input.extend_from_slice(self.program_id.as_ref());
+
+        let mut aligned = vec![0u64; input.len().div_ceil(8)];
+        for (word, chunk) in aligned.iter_mut().zip(input.chunks(8)) {
+            let mut bytes = [0u8; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            *word = u64::from_le_bytes(bytes);
+        }
+        aligned
+    }
+
+    //runs `ix` through the processor and keeps the writable accounts it changed
+    pub fn execute(&mut self, ix: &Instruction) -> ProgramResult {
+        static STUBS: Once = Once::new();
+        STUBS.call_once(|| {
+            set_syscall_stubs(Box::new(SystemProgram));
+        });
+        assert_eq!(ix.program_id, self.program_id);
+
+        CALLER.with(|caller| caller.set(self.program_id));
+        let mut input = self.serialize(ix);
+        let (program_id, infos, data) = unsafe { deserialize(input.as_mut_ptr() as *mut u8) };
+        Processor::process(program_id, &infos, data)?;
+
+        for info in infos.iter().filter(|info| info.is_writable) {
+            //the runtime drops accounts left without lamports
+            if info.lamports() == 0 {
+                self.accounts.remove(info.key);
+                continue;
+            }
+            self.accounts.insert(
+                *info.key,
+                Account {
+                    lamports: info.lamports(),
+                    data: info.data.borrow().to_vec(),
+                    owner: *info.owner,
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+//the system program instructions the processor invokes
+struct SystemProgram;
+
+impl SyscallStubs for SystemProgram {
+    fn sol_log(&self, message: &str) {
+        LOGS.with(|logs| logs.borrow_mut().push(format!("Program log: {}", message)));
+    }
+
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        let fields: Vec<String> = fields.iter().map(|field| STANDARD.encode(field)).collect();
+        LOGS.with(|logs| {
+            logs.borrow_mut()
+                .push(format!("Program data: {}", fields.join(" ")))
+        });
+    }
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        assert_eq!(instruction.program_id, system_program::ID);
+        let info = |index: usize| {
+            let key = instruction.accounts[index].pubkey;
+            account_infos.iter().find(|info| *info.key == key).unwrap()
+        };
+        //a signer of the outer instruction, or a PDA of the caller it signs for
+        let caller = CALLER.with(Cell::get);
+        for (index, meta) in instruction.accounts.iter().enumerate() {
+            let signed = info(index).is_signer
+                || signers_seeds
+                    .iter()
+                    .any(|seeds| Pubkey::create_program_address(seeds, &caller) == Ok(meta.pubkey));
+            if meta.is_signer && !signed {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+        }
+        let u64_at = |offset: usize| {
+            u64::from_le_bytes(instruction.data[offset..offset + 8].try_into().unwrap())
+        };
+        let (from, to) = (info(0), info(1));
+
+        let lamports = match instruction.data[..4] {
+            //CreateAccount { lamports, space, owner }
+            [0, 0, 0, 0] => {
+                if to.lamports() > 0 || !to.data_is_empty() || *to.owner != system_program::ID {
+                    return Err(ProgramError::Custom(
+                        SystemError::AccountAlreadyInUse as u32,
+                    ));
+                }
+                to.realloc(u64_at(12) as usize, true)?;
+                to.assign(&Pubkey::new_from_array(
+                    instruction.data[20..52].try_into().unwrap(),
+                ));
+                u64_at(4)
+            }
+            //Transfer { lamports }
+            [2, 0, 0, 0] => u64_at(4),
+            _ => return Err(ProgramError::InvalidInstructionData),
+        };
+        **from.try_borrow_mut_lamports()? -= lamports;
+        **to.try_borrow_mut_lamports()? += lamports;
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Runs the instructions the examples build through the processor, so the
+//! examples keep working as the program changes.
+
+#[allow(dead_code)]
+#[path = "../examples/init_and_register.rs"]
+mod init_and_register;
+#[allow(dead_code)]
+#[path = "../examples/upload_file.rs"]
+mod upload_file;
+#[allow(dead_code)]
+#[path = "../examples/watch_requests.rs"]
+mod watch_requests;
+
+mod common;
+
+use bitoku_sdk_agent_native::{
+    instruction::{request_address, write_response},
+    state::RequestStatus,
+};
+use solana_program::{pubkey::Pubkey, system_program};
+
+use common::Ledger;
+
+use init_and_register::{registered_client_id, setup_instructions};
+use upload_file::upload_instructions;
+use watch_requests::{RequestEvent, RequestWatcher};
+
+#[test]
+fn test_examples() {
+    let program_id = Pubkey::new_unique();
+    let payer = Pubkey::new_unique();
+    let mut ledger = Ledger::new(program_id);
+    ledger.set(payer, vec![], system_program::ID);
+    ledger.set_upgrade_authority(Some(payer));
+
+    //init_and_register
+    for ix in setup_instructions(program_id, payer).unwrap() {
+        ledger.execute(&ix).unwrap();
+    }
+    let (request, _) = request_address(&program_id, &payer);
+    let client_id = registered_client_id(ledger.data(&request)).unwrap();
+    assert_eq!(client_id, 0);
+
+    let mut watcher = RequestWatcher::default();
+    assert_eq!(watcher.update(ledger.data(&request)).unwrap(), vec![]);
+
+    //upload_file, answered by the payer as the deployment's agent
+    let contents: Vec<u8> = (0..1100u32).map(|i| i as u8).collect();
+    let instructions = upload_instructions(
+        program_id,
+        payer,
+        client_id,
+        "docs",
+        ("a.txt", 7),
+        &contents,
+    )
+    .unwrap();
+    assert_eq!(instructions.len(), 2 + 3);
+
+    let mut uploaded = Vec::new();
+    for (sequence, ix) in (1..).zip(&instructions) {
+        ledger.execute(ix).unwrap();
+
+        let events = watcher.update(ledger.data(&request)).unwrap();
+        let [RequestEvent::Submitted {
+            sequence: submitted,
+            request: req,
+        }] = events.as_slice()
+        else {
+            panic!("unexpected events {:?}", events);
+        };
+        assert_eq!(*submitted, sequence);
+        if let (Some(offset), Some(data)) = (req.offset(), req.data()) {
+            assert_eq!(req.file_id(), Some(7));
+            assert_eq!(offset, uploaded.len() as u64);
+            uploaded.extend_from_slice(data);
+        }
+
+        let answer = write_response(
+            payer,
+            request,
+            program_id,
+            client_id,
+            RequestStatus::Completed,
+            b"ok",
+        )
+        .unwrap();
+        ledger.execute(&answer).unwrap();
+        assert_eq!(
+            watcher.update(ledger.data(&request)).unwrap(),
+            vec![
+                RequestEvent::StatusChanged {
+                    sequence,
+                    status: RequestStatus::Completed,
+                },
+                RequestEvent::Responded {
+                    sequence,
+                    data: b"ok".to_vec(),
+                },
+            ]
+        );
+    }
+
+    //the last chunk is zero-padded
+    assert_eq!(uploaded.len(), 3 * 512);
+    assert_eq!(&uploaded[..contents.len()], contents.as_slice());
+    assert!(uploaded[contents.len()..].iter().all(|&b| b == 0));
+}
@@ -0,0 +1,244 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! The life of a client, from `InitBitoku` to `RemoveClient`, run against a
+//! ledger with the program deployed.
+
+mod common;
+
+use bitoku_sdk_agent_native::{
+    error::BitokuError,
+    instruction::{
+        bookkeeper_address, init_bitoku_auto, register_client_auto, remove_client, send_request,
+        set_authority, update_admin, Request,
+    },
+    pool::{ClientPool, PoolStep},
+    state::{str_to_name, BookKeeper, RequestData},
+};
+use solana_program::{program_pack::Pack, pubkey::Pubkey, system_program};
+
+use common::Ledger;
+
+#[test]
+fn test_client_lifecycle() {
+    let program_id = Pubkey::new_unique();
+    let payer = Pubkey::new_unique();
+    let mut ledger = Ledger::new(program_id);
+    ledger.set(payer, vec![], system_program::ID);
+    ledger.set_upgrade_authority(Some(payer));
+
+    //InitBitoku creates the bookkeeper PDA
+    let (bookkeeper, _) = bookkeeper_address(&program_id);
+    ledger
+        .execute(&init_bitoku_auto(payer, program_id).unwrap())
+        .unwrap();
+    let account = &ledger.accounts[&bookkeeper];
+    assert_eq!(account.owner, program_id);
+    assert_eq!(account.data.len(), BookKeeper::LEN);
+    assert!(BookKeeper::unpack(&account.data).unwrap().is_initialized);
+
+    //RegisterClient creates the payer's request PDA with the first client id
+    let (register, request) = register_client_auto(payer, program_id).unwrap();
+    ledger.execute(&register).unwrap();
+    assert_eq!(ledger.accounts[&request].owner, program_id);
+    let request_data = RequestData::unpack_account(ledger.data(&request)).unwrap();
+    assert!(request_data.is_initialized);
+    assert_eq!(request_data.client_id, 0);
+    assert_eq!(request_data.requester, Pubkey::default());
+
+    //SendRequest stores the request for the agent
+    let name = str_to_name("photos").unwrap();
+    let send = send_request(
+        payer,
+        request,
+        program_id,
+        0,
+        Request::CreateBucket { name },
+    );
+    ledger.execute(&send.unwrap()).unwrap();
+    let request_data = RequestData::unpack_account(ledger.data(&request)).unwrap();
+    assert_eq!(request_data.requester, payer);
+    assert_eq!(request_data.request.name(), Some(name));
+    assert_eq!(request_data.sequence, 1);
+
+    //RemoveClient closes the request PDA and refunds the payer
+    let lamports = ledger.accounts[&payer].lamports + ledger.accounts[&request].lamports;
+    let remove = remove_client(payer, bookkeeper, request, program_id, 0).unwrap();
+    ledger.execute(&remove).unwrap();
+    assert!(!ledger.accounts.contains_key(&request));
+    assert_eq!(ledger.accounts[&payer].lamports, lamports);
+    let bookkeeper_data = BookKeeper::unpack(ledger.data(&bookkeeper)).unwrap();
+    assert_eq!(bookkeeper_data.client_count(), 0);
+
+    //the closed PDA can be created again
+    ledger.execute(&register).unwrap();
+    let request_data = RequestData::unpack_account(ledger.data(&request)).unwrap();
+    assert_eq!(request_data.client_id, 0);
+    assert_eq!(request_data.sequence, 0);
+    assert!(ledger.execute(&register).is_err());
+}
+
+#[test]
+fn test_bookkeeper_takeover_rejected() {
+    let program_id = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+    let attacker = Pubkey::new_unique();
+    let mut ledger = Ledger::new(program_id);
+    ledger.set(authority, vec![], system_program::ID);
+    ledger.set(attacker, vec![], system_program::ID);
+    ledger.set_upgrade_authority(Some(authority));
+
+    //racing the deployer to InitBitoku gets an attacker nothing
+    let (bookkeeper, _) = bookkeeper_address(&program_id);
+    assert_eq!(
+        ledger.execute(&init_bitoku_auto(attacker, program_id).unwrap()),
+        Err(BitokuError::Unauthorized.into())
+    );
+    ledger
+        .execute(&init_bitoku_auto(authority, program_id).unwrap())
+        .unwrap();
+    let (register, _) = register_client_auto(authority, program_id).unwrap();
+    ledger.execute(&register).unwrap();
+    let initialized = ledger.data(&bookkeeper).to_vec();
+
+    //a second InitBitoku cannot replace the authority or wipe registrations
+    assert_eq!(
+        ledger.execute(&init_bitoku_auto(authority, program_id).unwrap()),
+        Err(BitokuError::AccountAlreadyInitialized.into())
+    );
+    assert_eq!(ledger.data(&bookkeeper), &initialized[..]);
+
+    //only the stored authority can hand the bookkeeper over
+    assert_eq!(
+        ledger.execute(&set_authority(attacker, program_id, attacker).unwrap()),
+        Err(BitokuError::Unauthorized.into())
+    );
+    ledger
+        .execute(&set_authority(authority, program_id, attacker).unwrap())
+        .unwrap();
+    let bookkeeper_data = BookKeeper::unpack(ledger.data(&bookkeeper)).unwrap();
+    assert_eq!(bookkeeper_data.authority, attacker);
+    assert_eq!(bookkeeper_data.client_count(), 1);
+}
+
+#[test]
+fn test_update_admin_rejects_non_admin() {
+    let program_id = Pubkey::new_unique();
+    let admin = Pubkey::new_unique();
+    let attacker = Pubkey::new_unique();
+    let successor = Pubkey::new_unique();
+    let mut ledger = Ledger::new(program_id);
+    for key in [admin, attacker, successor] {
+        ledger.set(key, vec![], system_program::ID);
+    }
+    ledger.set_upgrade_authority(Some(admin));
+
+    let (bookkeeper, _) = bookkeeper_address(&program_id);
+    ledger
+        .execute(&init_bitoku_auto(admin, program_id).unwrap())
+        .unwrap();
+    assert_eq!(
+        BookKeeper::unpack(ledger.data(&bookkeeper)).unwrap().admin,
+        admin
+    );
+
+    assert_eq!(
+        ledger.execute(&update_admin(attacker, program_id, attacker).unwrap()),
+        Err(BitokuError::InvalidAccount.into())
+    );
+
+    //the admin hands over, after which it is refused like anyone else
+    ledger
+        .execute(&update_admin(admin, program_id, successor).unwrap())
+        .unwrap();
+    assert_eq!(
+        BookKeeper::unpack(ledger.data(&bookkeeper)).unwrap().admin,
+        successor
+    );
+    assert_eq!(
+        ledger.execute(&update_admin(admin, program_id, admin).unwrap()),
+        Err(BitokuError::InvalidAccount.into())
+    );
+}
+
+#[test]
+fn test_client_pool() {
+    let program_id = Pubkey::new_unique();
+    let deployer = Pubkey::new_unique();
+    let authorities = [
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    ];
+    let mut ledger = Ledger::new(program_id);
+    ledger.set(deployer, vec![], system_program::ID);
+    for authority in authorities {
+        ledger.set(authority, vec![], system_program::ID);
+    }
+    ledger.set_upgrade_authority(Some(deployer));
+    ledger
+        .execute(&init_bitoku_auto(deployer, program_id).unwrap())
+        .unwrap();
+    let (bookkeeper, _) = bookkeeper_address(&program_id);
+    let mut pool = ClientPool::new(program_id);
+    let bucket = |n: &str| Request::CreateBucket {
+        name: str_to_name(n).unwrap(),
+    };
+
+    //an unconfirmed authority only gets its registration; the send comes after confirm
+    for (expected, authority) in authorities.iter().enumerate() {
+        let PoolStep::Register(register) = pool.send_for(authority, bucket("a")).unwrap() else {
+            panic!("{} is not registered yet", authority);
+        };
+        ledger.execute(&register).unwrap();
+        let request = pool.client(authority).unwrap().request;
+        let client_id = pool.confirm(authority, ledger.data(&request)).unwrap();
+        assert_eq!(client_id as usize, expected);
+    }
+
+    for (n, authority) in ["a", "b", "c"].into_iter().zip(&authorities) {
+        let PoolStep::Send(send) = pool.send_for(authority, bucket(n)).unwrap() else {
+            panic!("{} is confirmed", authority);
+        };
+        ledger.execute(&send).unwrap();
+        let request = pool.client(authority).unwrap().request;
+        let request_data = RequestData::unpack_account(ledger.data(&request)).unwrap();
+        assert_eq!(request_data.requester, *authority);
+        assert_eq!(request_data.request, bucket(n));
+    }
+
+    //the snapshot carries the confirmed ids across a restart
+    let mut pool = ClientPool::from_snapshot(&pool.snapshot()).unwrap();
+    let removed = pool.client(&authorities[1]).unwrap().request;
+    let remove = pool.remove(&authorities[1]).unwrap();
+    ledger.execute(&remove).unwrap();
+    assert!(pool.client(&authorities[1]).is_none());
+    assert!(!ledger.accounts.contains_key(&removed));
+    let bookkeeper_data = BookKeeper::unpack(ledger.data(&bookkeeper)).unwrap();
+    assert_eq!(
+        bookkeeper_data.registered_clients().collect::<Vec<_>>(),
+        [0, 2]
+    );
+
+    //the released id goes to the next authority the pool registers
+    let PoolStep::Register(register) = pool.send_for(&authorities[1], bucket("b")).unwrap() else {
+        panic!("{} was removed", authorities[1]);
+    };
+    ledger.execute(&register).unwrap();
+    let request = pool.client(&authorities[1]).unwrap().request;
+    assert_eq!(pool.confirm(&authorities[1], ledger.data(&request)), Ok(1));
+    assert_eq!(pool.authorities().count(), 3);
+}
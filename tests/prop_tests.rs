@@ -0,0 +1,302 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Round trips over generated values: requests through `SendRequest`, and the
+//! `BookKeeper` and `RequestData` account layouts.
+
+use bitoku_sdk_agent_native::{
+    instruction::{BitokuInstructions, Request},
+    state::{validate_name, BookKeeper, RequestData, RequestStatus, MAX_LEASE_SLOTS, MAX_READ},
+};
+use borsh::BorshSerialize;
+use proptest::{array::uniform, prelude::*};
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+
+//names aren't checked when unpacking, so any bytes round trip
+fn name() -> impl Strategy<Value = [u8; 128]> {
+    uniform(any::<u8>())
+}
+
+fn data() -> impl Strategy<Value = [u8; 512]> {
+    uniform(any::<u8>())
+}
+
+fn key() -> impl Strategy<Value = Pubkey> {
+    uniform(any::<u8>()).prop_map(Pubkey::new_from_array)
+}
+
+//a length in `1..=MAX_READ` with an offset the read doesn't overflow from
+fn read_window() -> impl Strategy<Value = (u64, u64)> {
+    (1..=MAX_READ).prop_flat_map(|len| (0..=u64::MAX - len, Just(len)))
+}
+
+fn request() -> impl Strategy<Value = Request> {
+    prop_oneof![
+        name().prop_map(|name| Request::CreateBucket { name }),
+        (name(), any::<bool>(), data()).prop_map(|(name, sparse, data)| Request::CreateFile {
+            name,
+            sparse,
+            data
+        }),
+        (name(), any::<u8>(), any::<u64>(), data()).prop_map(|(name, file_id, offset, data)| {
+            Request::WriteFile {
+                name,
+                file_id,
+                offset,
+                data,
+            }
+        }),
+        (name(), any::<u8>()).prop_map(|(name, file_id)| Request::CloseFile { name, file_id }),
+        (name(), any::<u8>()).prop_map(|(name, file_id)| Request::DeleteFile { name, file_id }),
+        (name(), any::<u8>(), any::<u64>()).prop_map(|(name, file_id, position)| {
+            Request::SetPosition {
+                name,
+                file_id,
+                position,
+            }
+        }),
+        (name(), any::<u8>()).prop_map(|(name, file_id)| Request::OpenFile { name, file_id }),
+        (name(), any::<u8>(), any::<u64>()).prop_map(|(name, file_id, min_sequence)| {
+            Request::ReadFile {
+                name,
+                file_id,
+                min_sequence,
+            }
+        }),
+        name().prop_map(|name| Request::DeleteBucket { name }),
+        (name(), any::<u8>(), name()).prop_map(|(name, file_id, new_name)| {
+            Request::RenameFile {
+                name,
+                file_id,
+                new_name,
+            }
+        }),
+        (name(), any::<u8>(), name()).prop_map(|(src_name, src_file_id, dst_name)| {
+            Request::CopyFile {
+                src_name,
+                src_file_id,
+                dst_name,
+            }
+        }),
+        (name(), any::<u8>(), any::<u64>()).prop_map(|(name, file_id, min_sequence)| {
+            Request::StatFile {
+                name,
+                file_id,
+                min_sequence,
+            }
+        }),
+        (name(), any::<u8>(), any::<u64>()).prop_map(|(name, file_id, new_length)| {
+            Request::TruncateFile {
+                name,
+                file_id,
+                new_length,
+            }
+        }),
+        (name(), any::<u8>()).prop_map(|(name, page)| Request::ListFiles { name, page }),
+        (name(), any::<u8>(), data()).prop_map(|(name, file_id, data)| Request::AppendFile {
+            name,
+            file_id,
+            data
+        }),
+        (name(), any::<u8>(), uniform(any::<u8>())).prop_map(|(name, file_id, chunk_hash)| {
+            Request::WriteByReference {
+                name,
+                file_id,
+                chunk_hash,
+            }
+        }),
+        (name(), any::<u8>(), any::<u64>(), data()).prop_map(|(name, file_id, offset, data)| {
+            Request::WriteAt {
+                name,
+                file_id,
+                offset,
+                data,
+            }
+        }),
+        (name(), any::<u8>(), read_window(), any::<u64>()).prop_map(
+            |(name, file_id, (offset, len), min_sequence)| Request::ReadRange {
+                name,
+                file_id,
+                offset,
+                len,
+                min_sequence,
+            }
+        ),
+        (name(), any::<u8>()).prop_map(|(name, file_id)| Request::FileExists { name, file_id }),
+        (name(), any::<u8>()).prop_map(|(name, file_id)| Request::TrashFile { name, file_id }),
+        (name(), any::<u8>()).prop_map(|(name, file_id)| Request::RestoreFile { name, file_id }),
+        Just(Request::EmptyTrash {}),
+        (name(), any::<u8>(), read_window()).prop_map(|(name, file_id, (offset, length))| {
+            Request::ReadFileRange {
+                name,
+                file_id,
+                offset,
+                length: length as u32,
+            }
+        }),
+        (name(), 1..=MAX_LEASE_SLOTS)
+            .prop_map(|(name, ttl_slots)| Request::AcquireBucketLease { name, ttl_slots }),
+        name().prop_map(|name| Request::ReleaseBucketLease { name }),
+    ]
+}
+
+fn status() -> impl Strategy<Value = RequestStatus> {
+    prop_oneof![
+        Just(RequestStatus::Pending),
+        Just(RequestStatus::Processing),
+        Just(RequestStatus::Completed),
+        Just(RequestStatus::Failed),
+        Just(RequestStatus::Cancelled),
+    ]
+}
+
+fn bookkeeper() -> impl Strategy<Value = BookKeeper> {
+    (
+        (any::<bool>(), uniform(any::<u8>()), any::<u8>(), key()),
+        uniform(uniform(any::<u8>())),
+        (any::<u32>(), key(), uniform(any::<u8>()), any::<u8>()),
+        (any::<u32>(), any::<u16>(), any::<bool>(), any::<u8>()),
+        (uniform(key()), any::<u64>(), any::<u64>()),
+        key(),
+    )
+        .prop_map(
+            |(
+                (is_initialized, status, next_id, authority),
+                reserved_prefixes,
+                (supported_kinds, agent, free_slots, name_policy),
+                (max_requests_per_epoch, current_backlog, paused, bump),
+                (agents, max_client_requests_per_epoch, last_heartbeat_slot),
+                admin,
+            )| BookKeeper {
+                is_initialized,
+                status,
+                next_id,
+                authority,
+                reserved_prefixes,
+                supported_kinds,
+                agent,
+                free_slots,
+                name_policy,
+                max_requests_per_epoch,
+                current_backlog,
+                paused,
+                bump,
+                agents,
+                max_client_requests_per_epoch,
+                last_heartbeat_slot,
+                admin,
+            },
+        )
+}
+
+fn request_data() -> impl Strategy<Value = RequestData> {
+    (
+        (any::<bool>(), any::<u8>(), key(), request()),
+        (any::<u64>(), any::<u64>(), any::<bool>(), any::<bool>()),
+        (
+            uniform(any::<u8>()),
+            uniform(any::<u8>()),
+            any::<u64>(),
+            any::<i64>(),
+        ),
+        (status(), any::<u16>(), data()),
+        (any::<u64>(), any::<u8>(), any::<u64>()),
+    )
+        .prop_map(
+            |(
+                (is_initialized, client_id, requester, request),
+                (position, max_written_offset, sparse, response_ready),
+                (response, submission_ref, sequence, submitted_at),
+                (status, response_len, response_data),
+                (nonce, bump, requests_this_epoch),
+            )| RequestData {
+                is_initialized,
+                client_id,
+                requester,
+                //a cancelled request is overwritten when packed
+                request: match status {
+                    RequestStatus::Cancelled => RequestData::CANCELLED_REQUEST,
+                    _ => request,
+                },
+                position,
+                max_written_offset,
+                sparse,
+                response_ready,
+                response,
+                submission_ref,
+                sequence,
+                submitted_at,
+                status,
+                response_len,
+                response_data,
+                nonce,
+                bump,
+                requests_this_epoch,
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn send_request_roundtrip(
+        client_id in any::<u8>(),
+        submission_ref in uniform(any::<u8>()),
+        request in request(),
+    ) {
+        let packed = BitokuInstructions::SendRequest {
+            client_id,
+            submission_ref,
+            request: request.clone(),
+        }
+        .pack();
+        match BitokuInstructions::unpack(&packed) {
+            Ok(BitokuInstructions::SendRequest {
+                client_id: unpacked_id,
+                submission_ref: unpacked_ref,
+                request: unpacked,
+            }) => {
+                prop_assert_eq!(unpacked_id, client_id);
+                prop_assert_eq!(unpacked_ref, submission_ref);
+                prop_assert_eq!(unpacked, request);
+            }
+            other => prop_assert!(false, "unpacked to {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bookkeeper_roundtrip(bookkeeper in bookkeeper()) {
+        let mut dst = [0u8; BookKeeper::LEN];
+        BookKeeper::pack(bookkeeper.clone(), &mut dst).unwrap();
+        let unpacked = BookKeeper::unpack_unchecked(&dst).unwrap();
+        prop_assert_eq!(unpacked.try_to_vec().unwrap(), bookkeeper.try_to_vec().unwrap());
+    }
+
+    #[test]
+    fn request_data_roundtrip(data in request_data()) {
+        let mut dst = [0u8; RequestData::LEN];
+        RequestData::pack(data.clone(), &mut dst).unwrap();
+        let unpacked = RequestData::unpack_unchecked(&dst).unwrap();
+        prop_assert_eq!(unpacked.try_to_vec().unwrap(), data.try_to_vec().unwrap());
+    }
+
+    #[test]
+    fn default_policy_accepts_its_charset(name in "[A-Za-z0-9./_+-]{1,128}") {
+        prop_assert!(validate_name(name.as_bytes()));
+        let mut padded = [0u8; 128];
+        padded[..name.len()].copy_from_slice(name.as_bytes());
+        prop_assert!(validate_name(&padded));
+    }
+}
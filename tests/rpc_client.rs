@@ -0,0 +1,394 @@
+/*
+  Copyright 2023 Bitoku Labs
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! `BitokuClient` against an in-process RPC server that runs transactions on a
+//! ledger with the program deployed.
+
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bitoku_sdk_agent_native::{
+    client::{BitokuClient, ClientError},
+    error::BitokuError,
+    instruction::{
+        init_bitoku_auto, publish_stats, request_address, set_paused, update_request_status,
+        Request,
+    },
+    state::{str_to_name, RequestStatus, FEATURE_PAUSED, REQUEST_KINDS, WIRE_FORMAT_VERSION},
+};
+use serde_json::{json, Value};
+use solana_program::{
+    clock::Clock,
+    hash::Hash,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_rpc_client::{
+    rpc_client::{RpcClient, RpcClientConfig},
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use solana_rpc_client_api::{client_error::Result, request::RpcRequest};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+use common::Ledger;
+
+//answers the requests `BitokuClient` makes; transactions run on `ledger` and
+//are confirmed as soon as they are sent
+struct TestValidator {
+    ledger: Arc<Mutex<Ledger>>,
+}
+
+//a validator with the bookkeeper initialized by `payer`, who deployed the
+//program and is also the agent
+fn test_validator(program_id: Pubkey, payer: &Keypair) -> TestValidator {
+    let mut ledger = Ledger::new(program_id);
+    ledger.set(payer.pubkey(), vec![], system_program::ID);
+    ledger.set_upgrade_authority(Some(payer.pubkey()));
+    ledger
+        .execute(&init_bitoku_auto(payer.pubkey(), program_id).unwrap())
+        .unwrap();
+    TestValidator {
+        ledger: Arc::new(Mutex::new(ledger)),
+    }
+}
+
+//moves the payer's request to `status` the way the agent does
+fn set_status(ledger: &Mutex<Ledger>, program_id: Pubkey, agent: Pubkey, status: RequestStatus) {
+    let (request, _) = request_address(&program_id, &agent);
+    let ix = update_request_status(agent, request, program_id, 0, status).unwrap();
+    ledger.lock().unwrap().execute(&ix).unwrap();
+}
+
+//`null` for accounts that don't exist, as the RPC reports them
+fn account_json(ledger: &Ledger, key: &Pubkey) -> Value {
+    match ledger.accounts.get(key).filter(|a| a.lamports > 0) {
+        Some(account) => json!({
+            "lamports": account.lamports,
+            "data": [STANDARD.encode(&account.data), "base64"],
+            "owner": account.owner.to_string(),
+            "executable": false,
+            "rentEpoch": 0,
+            "space": account.data.len(),
+        }),
+        None => Value::Null,
+    }
+}
+
+impl TestValidator {
+    fn run(&self, transaction: &Transaction) -> std::result::Result<(), TransactionError> {
+        transaction
+            .verify()
+            .map_err(|_| TransactionError::SignatureFailure)?;
+        self.execute(transaction)
+    }
+
+    //runs `transaction` without keeping what it changed, as `simulateTransaction`
+    //does with signature checks off
+    fn simulate(&self, transaction: &Transaction) -> Value {
+        let accounts = self.ledger.lock().unwrap().accounts.clone();
+        common::take_logs();
+        let err = self.execute(transaction).err();
+        self.ledger.lock().unwrap().accounts = accounts;
+        json!({
+            "err": err,
+            "logs": common::take_logs(),
+            "accounts": null,
+            "unitsConsumed": 0,
+            "returnData": null,
+        })
+    }
+
+    fn execute(&self, transaction: &Transaction) -> std::result::Result<(), TransactionError> {
+        let message = &transaction.message;
+        let mut ledger = self.ledger.lock().unwrap();
+        for (index, ix) in message.instructions.iter().enumerate() {
+            let ix = Instruction {
+                program_id: message.account_keys[ix.program_id_index as usize],
+                accounts: ix
+                    .accounts
+                    .iter()
+                    .map(|&i| AccountMeta {
+                        pubkey: message.account_keys[i as usize],
+                        is_signer: message.is_signer(i as usize),
+                        is_writable: message.is_writable(i as usize),
+                    })
+                    .collect(),
+                data: ix.data.clone(),
+            };
+            ledger.execute(&ix).map_err(|err| {
+                TransactionError::InstructionError(
+                    index as u8,
+                    InstructionError::from(u64::from(err)),
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RpcSender for TestValidator {
+    async fn send(&self, request: RpcRequest, params: Value) -> Result<Value> {
+        let context = json!({ "slot": 1 });
+        Ok(match request {
+            RpcRequest::GetLatestBlockhash => json!({
+                "context": context,
+                "value": { "blockhash": Hash::default().to_string(), "lastValidBlockHeight": 100 },
+            }),
+            RpcRequest::SendTransaction => {
+                let encoded = STANDARD.decode(params[0].as_str().unwrap()).unwrap();
+                let transaction: Transaction = bincode::deserialize(&encoded).unwrap();
+                self.run(&transaction)?;
+                json!(transaction.signatures[0].to_string())
+            }
+            RpcRequest::SimulateTransaction => {
+                let encoded = STANDARD.decode(params[0].as_str().unwrap()).unwrap();
+                let transaction: Transaction = bincode::deserialize(&encoded).unwrap();
+                json!({ "context": context, "value": self.simulate(&transaction) })
+            }
+            RpcRequest::GetSignatureStatuses => json!({
+                "context": context,
+                "value": [{
+                    "slot": 1,
+                    "confirmations": null,
+                    "status": { "Ok": null },
+                    "err": null,
+                    "confirmationStatus": "finalized",
+                }],
+            }),
+            RpcRequest::GetAccountInfo => {
+                let key: Pubkey = params[0].as_str().unwrap().parse().unwrap();
+                let value = account_json(&self.ledger.lock().unwrap(), &key);
+                json!({ "context": context, "value": value })
+            }
+            RpcRequest::GetMultipleAccounts => {
+                let ledger = self.ledger.lock().unwrap();
+                let value: Vec<Value> = params[0]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|key| account_json(&ledger, &key.as_str().unwrap().parse().unwrap()))
+                    .collect();
+                json!({ "context": context, "value": value })
+            }
+            RpcRequest::GetVersion => json!({ "solana-core": "1.16.27", "feature-set": 0 }),
+            _ => panic!("unexpected request {}", request),
+        })
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        RpcTransportStats::default()
+    }
+
+    fn url(&self) -> String {
+        "test-validator".to_string()
+    }
+}
+
+#[test]
+fn test_register_send_read() {
+    let program_id = Pubkey::new_unique();
+    let payer = Keypair::new();
+    let validator = test_validator(program_id, &payer);
+    let ledger = validator.ledger.clone();
+    let rpc = RpcClient::new_sender(
+        validator,
+        RpcClientConfig::with_commitment(CommitmentConfig::confirmed()),
+    );
+    let client = BitokuClient::new(rpc, program_id, payer);
+    assert_eq!(client.get_bookkeeper().unwrap().client_count(), 0);
+    assert!(matches!(
+        client.get_request_data(),
+        Err(ClientError::Rpc(_))
+    ));
+
+    let registered = client.register().unwrap();
+    assert!(registered.is_initialized);
+    assert_eq!(registered.client_id, 0);
+    assert_eq!(client.get_bookkeeper().unwrap().client_count(), 1);
+
+    let name = str_to_name("photos").unwrap();
+    let stored = client.send(Request::CreateBucket { name }).unwrap();
+    assert_eq!(stored.request.name(), Some(name));
+    assert_eq!(stored.requester, client.payer.pubkey());
+    assert_eq!(stored.sequence, 1);
+
+    //the agent hasn't picked up the first request yet
+    let err = client.send(Request::CreateBucket { name }).unwrap_err();
+    assert_eq!(err.bitoku_error(), Some(BitokuError::RequestPending));
+    assert_eq!(client.get_request_data().unwrap().sequence, 1);
+
+    let ms = Duration::from_millis;
+    let err = client
+        .wait_for_status(RequestStatus::Processing, ms(1), ms(5))
+        .unwrap_err();
+    assert!(matches!(err, ClientError::Timeout));
+
+    let agent = client.payer.pubkey();
+    set_status(&ledger, program_id, agent, RequestStatus::Processing);
+    let data = client
+        .wait_for_status(RequestStatus::Processing, ms(1), ms(5))
+        .unwrap();
+    assert_eq!(data.status, RequestStatus::Processing);
+
+    //a failed request never completes, so waiting for completion stops there
+    set_status(&ledger, program_id, agent, RequestStatus::Failed);
+    let data = client
+        .wait_for_status(RequestStatus::Completed, ms(1), ms(5))
+        .unwrap();
+    assert_eq!(data.status, RequestStatus::Failed);
+
+    client.remove(0).unwrap();
+    assert!(matches!(
+        client.get_request_data(),
+        Err(ClientError::Rpc(_))
+    ));
+    assert_eq!(client.get_bookkeeper().unwrap().client_count(), 0);
+}
+
+#[test]
+fn test_get_stats_snapshots() {
+    let program_id = Pubkey::new_unique();
+    let payer = Keypair::new();
+    let validator = test_validator(program_id, &payer);
+    let ledger = validator.ledger.clone();
+    for epoch in [0, 2] {
+        let mut ledger = ledger.lock().unwrap();
+        ledger.set_clock(&Clock {
+            slot: epoch * 432_000,
+            epoch,
+            ..Clock::default()
+        });
+        let ix = publish_stats(payer.pubkey(), program_id, epoch).unwrap();
+        ledger.execute(&ix).unwrap();
+    }
+    let rpc = RpcClient::new_sender(
+        validator,
+        RpcClientConfig::with_commitment(CommitmentConfig::confirmed()),
+    );
+    let client = BitokuClient::new(rpc, program_id, payer);
+
+    let snapshots = client.get_stats_snapshots(0..4).unwrap();
+    let epochs: Vec<u64> = snapshots.iter().map(|s| s.epoch).collect();
+    assert_eq!(epochs, [0, 2]);
+    assert_eq!(snapshots[1].slot, 864_000);
+    assert!(client.get_stats_snapshots(3..5).unwrap().is_empty());
+}
+
+#[test]
+fn test_query_capabilities() {
+    let program_id = Pubkey::new_unique();
+    let payer = Keypair::new();
+    let validator = test_validator(program_id, &payer);
+    let ledger = validator.ledger.clone();
+    let ix = set_paused(payer.pubkey(), program_id, true).unwrap();
+    ledger.lock().unwrap().execute(&ix).unwrap();
+    let rpc = RpcClient::new_sender(
+        validator,
+        RpcClientConfig::with_commitment(CommitmentConfig::confirmed()),
+    );
+    let client = BitokuClient::new(rpc, program_id, payer);
+
+    let capabilities = client.query_capabilities().unwrap();
+    assert_eq!(capabilities.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(capabilities.wire_format_version, WIRE_FORMAT_VERSION);
+    assert_eq!(capabilities.request_kinds, REQUEST_KINDS);
+    assert_eq!(capabilities.feature_flags, FEATURE_PAUSED);
+
+    //a program that was never initialized has no bookkeeper to report on
+    let validator = TestValidator {
+        ledger: Arc::new(Mutex::new(Ledger::new(program_id))),
+    };
+    let rpc = RpcClient::new_sender(
+        validator,
+        RpcClientConfig::with_commitment(CommitmentConfig::confirmed()),
+    );
+    let client = BitokuClient::new(rpc, program_id, Keypair::new());
+    assert!(client.query_capabilities().is_err());
+}
+
+#[cfg(feature = "async-client")]
+mod nonblocking {
+    use bitoku_sdk_agent_native::client::nonblocking::AsyncBitokuClient;
+    use solana_rpc_client::nonblocking::rpc_client::RpcClient;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_register_send_wait() {
+        let program_id = Pubkey::new_unique();
+        let payer = Keypair::new();
+        let validator = test_validator(program_id, &payer);
+        let ledger = validator.ledger.clone();
+        let rpc = RpcClient::new_sender(
+            validator,
+            RpcClientConfig::with_commitment(CommitmentConfig::confirmed()),
+        );
+        let client = AsyncBitokuClient::new(rpc, program_id, payer);
+
+        let capabilities = client.query_capabilities().await.unwrap();
+        assert_eq!(capabilities.feature_flags, 0);
+
+        let registered = client.register().await.unwrap();
+        assert_eq!(registered.client_id, 0);
+        assert_eq!(client.get_bookkeeper().await.unwrap().client_count(), 1);
+
+        let name = str_to_name("photos").unwrap();
+        let stored = client.send(Request::CreateBucket { name }).await.unwrap();
+        assert_eq!(stored.request.name(), Some(name));
+        assert_eq!(stored.status, RequestStatus::Pending);
+
+        let err = client
+            .send(Request::CreateBucket { name })
+            .await
+            .unwrap_err();
+        assert_eq!(err.bitoku_error(), Some(BitokuError::RequestPending));
+
+        //the agent finishes the request while the client is polling
+        let agent = client.payer.pubkey();
+        let worker = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            set_status(&ledger, program_id, agent, RequestStatus::Completed);
+        });
+        let data = client
+            .wait_for_status(
+                RequestStatus::Completed,
+                Duration::from_millis(5),
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+        assert_eq!(data.status, RequestStatus::Completed);
+        worker.await.unwrap();
+
+        client.remove(0).await.unwrap();
+        assert!(matches!(
+            client.get_request_data().await,
+            Err(ClientError::Rpc(_))
+        ));
+    }
+}
@@ -0,0 +1,37 @@
+// Drives the wasm package from node. Build it first with
+//   wasm-pack build --target nodejs -- --no-default-features --features wasm
+// then run `npm run test:wasm`.
+
+import assert from "node:assert/strict";
+import { createRequire } from "node:module";
+import test from "node:test";
+
+const require = createRequire(import.meta.url);
+const { wasm_pack_request, wasm_unpack_request } = require("../../pkg/bitoku_sdk_agent_native.js");
+
+const CREATE_BUCKET = 0;
+const WRITE_FILE = 2;
+
+test("CreateBucket round trips", () => {
+  const packed = wasm_pack_request(CREATE_BUCKET, "photos", 0, new Uint8Array());
+  assert.equal(packed.length, 1 + 128);
+  assert.equal(packed[0], CREATE_BUCKET);
+  assert.deepEqual(wasm_unpack_request(packed), { CreateBucket: { name: "photos" } });
+});
+
+test("WriteFile pads its data", () => {
+  const packed = wasm_pack_request(WRITE_FILE, "photos", 3, new Uint8Array([1, 2, 3]));
+  assert.equal(packed.length, 1 + 128 + 1 + 8 + 512);
+
+  const { WriteFile } = wasm_unpack_request(packed);
+  assert.equal(WriteFile.name, "photos");
+  assert.equal(WriteFile.file_id, 3);
+  assert.equal(WriteFile.offset, 0);
+  assert.equal(Buffer.from(WriteFile.data, "base64").subarray(0, 4).toString("hex"), "01020300");
+});
+
+test("invalid input throws", () => {
+  assert.throws(() => wasm_pack_request(255, "photos", 0, new Uint8Array()));
+  assert.throws(() => wasm_pack_request(WRITE_FILE, "photos", 0, new Uint8Array(513)));
+  assert.throws(() => wasm_unpack_request(new Uint8Array([CREATE_BUCKET])));
+});